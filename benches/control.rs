@@ -0,0 +1,52 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use ws_tool::{
+    codec::{FrameConfig, FrameWriteState},
+    frame::{OpCode, OwnedFrame},
+};
+
+const FRAMES: usize = 1000;
+const MASK: [u8; 4] = [1, 2, 3, 4];
+
+/// compare control frames serialized on stack buffer by `FrameWriteState::send`
+/// with building an `OwnedFrame` for every frame
+fn control(c: &mut Criterion) {
+    // typical heartbeat payload, such as a timestamp
+    let payload = [0x42u8; 16];
+    let mut out = Vec::with_capacity(FRAMES * (payload.len() + 14));
+
+    let mut group = c.benchmark_group("send_ping_16B");
+    group.throughput(Throughput::Elements(FRAMES as u64));
+    for (name, masked) in [("masked", true), ("unmasked", false)] {
+        let config = FrameConfig::builder()
+            .mask_send_frame(masked)
+            .build()
+            .unwrap();
+        group.bench_function(format!("{name}_stack_buf"), |b| {
+            let mut state = FrameWriteState::with_config(config.clone());
+            state.set_mask_fn(|| MASK);
+            b.iter(|| {
+                out.clear();
+                for _ in 0..FRAMES {
+                    state
+                        .send(&mut out, OpCode::Ping, black_box(&payload))
+                        .unwrap();
+                }
+            })
+        });
+        group.bench_function(format!("{name}_owned_frame"), |b| {
+            let mask = masked.then_some(MASK);
+            b.iter(|| {
+                out.clear();
+                for _ in 0..FRAMES {
+                    let frame = OwnedFrame::new(OpCode::Ping, mask, black_box(&payload));
+                    out.extend_from_slice(frame.header().as_bytes());
+                    out.extend_from_slice(frame.payload());
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, control);
+criterion_main!(benches);
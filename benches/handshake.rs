@@ -0,0 +1,51 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+};
+use ws_tool::protocol::async_handle_handshake;
+
+const HANDSHAKES: usize = 100;
+
+/// server side handshake over loopback tcp, every read is a syscall, so
+/// reading request header byte by byte or in chunks makes a difference
+fn handshake(c: &mut Criterion) {
+    let request = "GET /chat HTTP/1.1\r\n\
+        Host: 127.0.0.1:9000\r\n\
+        Upgrade: websocket\r\n\
+        Connection: Upgrade\r\n\
+        Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+        Sec-WebSocket-Version: 13\r\n\
+        Sec-WebSocket-Extensions: permessage-deflate; client_max_window_bits\r\n\
+        User-Agent: ws-tool-bench\r\n\r\n";
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let (mut client, mut server) = rt.block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        client.set_nodelay(true).unwrap();
+        (client, server)
+    });
+
+    let mut group = c.benchmark_group("async_handle_handshake");
+    group.throughput(Throughput::Elements(HANDSHAKES as u64));
+    group.bench_function("loopback_tcp", |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                for _ in 0..HANDSHAKES {
+                    client.write_all(request.as_bytes()).await.unwrap();
+                    async_handle_handshake(&mut server).await.unwrap();
+                }
+            })
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, handshake);
+criterion_main!(benches);
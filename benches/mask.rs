@@ -0,0 +1,30 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use ws_tool::frame::{apply_mask, apply_mask_fast32};
+
+const SIZE: usize = 1024 * 1024;
+
+fn mask(c: &mut Criterion) {
+    let mut buf = vec![0u8; SIZE];
+    buf.iter_mut().for_each(|b| *b = fastrand::u8(..));
+    let key = [0x12, 0x34, 0x56, 0x78];
+
+    let mut group = c.benchmark_group("mask_1MiB");
+    group.throughput(Throughput::Bytes(SIZE as u64));
+    group.bench_function("apply_mask", |b| {
+        b.iter(|| apply_mask(black_box(&mut buf), black_box(key)))
+    });
+    group.bench_function("fast32", |b| {
+        b.iter(|| apply_mask_fast32(black_box(&mut buf), black_box(key)))
+    });
+    group.bench_function("scalar", |b| {
+        b.iter(|| {
+            for (i, byte) in black_box(&mut buf).iter_mut().enumerate() {
+                *byte ^= key[i & 3];
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, mask);
+criterion_main!(benches);
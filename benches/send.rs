@@ -0,0 +1,43 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use ws_tool::{
+    codec::{FrameConfig, FrameWriteState},
+    frame::OpCode,
+};
+
+const SIZE: usize = 1024;
+const FRAMES: usize = 1000;
+
+fn send(c: &mut Criterion) {
+    let payload = vec![0x42u8; SIZE];
+    let mut out = Vec::with_capacity(FRAMES * (SIZE + 14));
+    let configs = [
+        ("renew_buf", FrameConfig::builder().renew_buf_on_write(true)),
+        ("reuse_buf", FrameConfig::builder()),
+        (
+            "write_buf_capacity",
+            FrameConfig::builder().write_buf_capacity(SIZE),
+        ),
+    ];
+
+    let mut group = c.benchmark_group("send_masked_1KiB");
+    group.throughput(Throughput::Bytes((SIZE * FRAMES) as u64));
+    for (name, builder) in configs {
+        let config = builder.build().unwrap();
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                // fresh state per iteration, so first send is measured too
+                let mut state = FrameWriteState::with_config(config.clone());
+                out.clear();
+                for _ in 0..FRAMES {
+                    state
+                        .send(&mut out, OpCode::Binary, black_box(&payload))
+                        .unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, send);
+criterion_main!(benches);
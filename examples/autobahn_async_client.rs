@@ -55,11 +55,8 @@ async fn run_test(case: usize) -> Result<(), WsError> {
                 }
             }
             Err(e) => match e {
-                WsError::ProtocolError { close_code, error } => {
-                    let mut data = BytesMut::new();
-                    data.extend_from_slice(&close_code.to_be_bytes());
-                    data.extend_from_slice(error.to_string().as_bytes());
-                    if write.send(OpCode::Close, &data).await.is_err() {
+                e @ WsError::ProtocolError { .. } => {
+                    if write.close_with_error(&e).await.is_err() {
                         break;
                     }
                 }
@@ -55,15 +55,8 @@ fn run_test(case: usize) -> Result<(), WsError> {
                 }
             }
             Err(e) => match e {
-                WsError::ProtocolError { close_code, error } => {
-                    if write
-                        .send_owned_frame(OwnedFrame::close_frame(
-                            mask_key(),
-                            close_code,
-                            error.to_string().as_bytes(),
-                        ))
-                        .is_err()
-                    {
+                e @ WsError::ProtocolError { .. } => {
+                    if write.close_with_error(&e).is_err() {
                         break;
                     }
                 }
@@ -61,6 +61,8 @@ fn main() -> Result<(), ()> {
                             }
                         }
                     }
+                    #[cfg(feature = "metrics")]
+                    log_size_percentiles(r.size_histogram());
                 }
                 None => {
                     let (mut read, mut write) = ServerBuilder::accept(
@@ -77,9 +79,23 @@ fn main() -> Result<(), ()> {
                         }
                         write.send(msg).unwrap();
                     }
+                    #[cfg(feature = "metrics")]
+                    log_size_percentiles(read.size_histogram());
                 }
             }
             tracing::info!("{:?} conn down", addr);
         });
     }
 }
+
+#[cfg(feature = "metrics")]
+fn log_size_percentiles(hist: &hdrhistogram::Histogram<u64>) {
+    tracing::info!(
+        "message size count {} p50 {} p90 {} p99 {} max {}",
+        hist.len(),
+        hist.value_at_quantile(0.5),
+        hist.value_at_quantile(0.9),
+        hist.value_at_quantile(0.99),
+        hist.max()
+    );
+}
@@ -3,7 +3,7 @@ use tracing::Level;
 use tracing_subscriber::util::SubscriberInitExt;
 use ws_tool::{
     codec::AsyncStringCodec,
-    connector::{async_tcp_connect, async_wrap_rustls, get_host},
+    connector::{HttpProxyConfig, Socks5Config},
     ClientBuilder,
 };
 
@@ -37,6 +37,11 @@ struct Args {
     sp_auth: Option<String>,
 }
 
+fn parse_auth(auth: String) -> (String, String) {
+    let (user, passwd) = auth.split_once(':').expect("invalid auth format");
+    (user.trim().into(), passwd.trim().into())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), ()> {
     tracing_subscriber::fmt::fmt()
@@ -49,55 +54,22 @@ async fn main() -> Result<(), ()> {
     let uri: http::Uri = format!("wss://fstream.binance.com/stream?streams={}", channels)
         .parse()
         .unwrap();
-    let builder = ClientBuilder::new();
-    let stream = if let Some(host) = args.hp_host {
-        let auth = args
-            .hp_auth
-            .map(|auth| {
-                let (user, passwd) = auth.split_once(':').expect("invalid auth format");
-                hproxy::AuthCredential::Basic {
-                    user: user.trim().into(),
-                    passwd: passwd.trim().into(),
-                }
-            })
-            .unwrap_or(hproxy::AuthCredential::None);
-        let config = hproxy::ProxyConfig {
+    let mut builder = ClientBuilder::new();
+    if let Some(host) = args.hp_host {
+        builder = builder.http_proxy(HttpProxyConfig {
             host,
             port: args.hp_port,
-            auth,
-            keep_alive: true,
-        };
-        hproxy::async_create_conn(&config, "fstream.binance.com")
-            .await
-            .unwrap()
+            auth: args.hp_auth.map(parse_auth),
+        });
     } else if let Some(host) = args.sp_host {
-        let auth = args
-            .sp_auth
-            .map(|auth| {
-                let (user, passwd) = auth.split_once(':').expect("invalid auth format");
-                sproxy::AuthCredential::Basic {
-                    user: user.trim().into(),
-                    passwd: passwd.trim().into(),
-                }
-            })
-            .unwrap_or(sproxy::AuthCredential::None);
-        let config = sproxy::ProxyConfig {
+        builder = builder.socks5_proxy(Socks5Config {
             host,
             port: args.sp_port,
-            auth,
-        };
-        let (stream, _, _) = sproxy::async_create_conn(&config, "fstream.binance.com".into(), 443)
-            .await
-            .unwrap();
-        stream
-    } else {
-        async_tcp_connect(&uri).await.unwrap()
-    };
-    let stream = async_wrap_rustls(stream, get_host(&uri).unwrap(), vec![])
-        .await
-        .unwrap();
+            auth: args.sp_auth.map(parse_auth),
+        });
+    }
     let mut client = builder
-        .async_with_stream(uri, stream, AsyncStringCodec::check_fn)
+        .async_rustls_connect(uri, AsyncStringCodec::check_fn)
         .await
         .unwrap();
 
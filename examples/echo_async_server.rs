@@ -5,6 +5,7 @@ use std::{fs::create_dir_all, path::PathBuf};
 
 use clap::Parser;
 use tokio::io::BufStream;
+use tokio::net::TcpStream;
 use tokio_rustls::rustls::{self, Certificate, PrivateKey};
 use tokio_rustls::TlsAcceptor;
 use tracing_subscriber::util::SubscriberInitExt;
@@ -126,29 +127,28 @@ async fn main() -> Result<(), ()> {
         let listener = tokio::net::TcpListener::bind(format!("{}:{}", args.host, args.port))
             .await
             .unwrap();
-        loop {
-            let (stream, addr) = listener.accept().await.unwrap();
-
-            tracing::info!("got connect from {:?}", addr);
-            let (mut read, mut write) = ServerBuilder::async_accept(
-                stream,
-                default_handshake_handler,
-                // AsyncWsStringCodec::factory,
-                AsyncStringCodec::factory,
-            )
-            .await
-            .unwrap()
-            .split();
-
-            loop {
-                match read.receive().await {
-                    Ok(msg) => write.send(msg).await.unwrap(),
-                    Err(e) => {
-                        dbg!(e);
-                        break;
+        ServerBuilder::serve(
+            listener,
+            default_handshake_handler,
+            AsyncStringCodec::factory,
+            |codec: AsyncStringCodec<TcpStream>| async move {
+                let (mut read, mut write) = codec.split();
+                loop {
+                    match read.receive().await {
+                        Ok(msg) => write.send(msg).await.unwrap(),
+                        Err(e) => {
+                            dbg!(e);
+                            break;
+                        }
                     }
                 }
-            }
-        }
+            },
+            async {
+                tokio::signal::ctrl_c().await.ok();
+            },
+        )
+        .await
+        .unwrap();
+        Ok(())
     }
 }
@@ -30,6 +30,7 @@ fn main() {
         client_no_context_takeover: ClientConfig::default().context_take_over,
         server_max_window_bits: WindowBit::Fifteen,
         client_max_window_bits: WindowBit::Fifteen,
+        ..Default::default()
     };
     let mut stream = ClientBuilder::new()
         .extension(pmd_config.ext_string())
@@ -0,0 +1,36 @@
+//! minimal browser client, ws-tool codec runs over a byte stream tunneled by
+//! browser websocket, e.g. a websockify bridge in front of a websocket server
+//!
+//! build with `wasm-pack build --target web examples/wasm_client`, then call
+//! `await echo("ws://127.0.0.1:9001", "ws://127.0.0.1:9000", "hello")` in page
+use wasm_bindgen::prelude::*;
+use ws_stream_wasm::WsMeta;
+use ws_tool::{codec::AsyncStringCodec, errors::WsError, stream::FuturesIoStream, ClientBuilder};
+
+/// connect to `bridge`, perform websocket handshake with `uri` over it, send
+/// `text` and return first message received
+#[wasm_bindgen]
+pub async fn echo(bridge: String, uri: String, text: String) -> Result<String, JsValue> {
+    run(&bridge, &uri, &text)
+        .await
+        .map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+async fn run(bridge: &str, uri: &str, text: &str) -> Result<String, WsError> {
+    let (_meta, ws) = WsMeta::connect(bridge, None)
+        .await
+        .map_err(|e| WsError::ConnectionFailed(e.to_string()))?;
+    let uri: http::Uri = uri
+        .parse()
+        .map_err(|e: http::uri::InvalidUri| WsError::InvalidUri(e.to_string()))?;
+    let mut client = ClientBuilder::new()
+        .async_with_stream(
+            uri,
+            FuturesIoStream(ws.into_io()),
+            AsyncStringCodec::check_fn,
+        )
+        .await?;
+    client.send(text).await?;
+    let msg = client.receive().await?;
+    Ok(msg.data.to_string())
+}
@@ -205,6 +205,7 @@ fn connect(url: &str, config: Option<ClientConfig>) -> PyResult<Client> {
         client_no_context_takeover: config.context_take_over,
         server_max_window_bits: WindowBit::try_from(w).unwrap_or(WindowBit::Fifteen),
         client_max_window_bits: WindowBit::try_from(w).unwrap_or(WindowBit::Fifteen),
+        ..Default::default()
     });
     let mut builder = ClientBuilder::new();
     if let Some(conf) = pmd_config {
@@ -52,3 +52,7 @@ cargo build -q --example load_test -F deflate
 echo "building tls_proxy_deflate_client ..."
 cargo build -q --example tls_proxy_deflate_client 
 
+
+echo "building wasm_client ..."
+rustup target add wasm32-unknown-unknown
+cargo build -q -p wasm_client --target wasm32-unknown-unknown
@@ -1,29 +1,42 @@
 use http;
 use crate::{
     codec::{
-        FrameCodec, FrameConfig, FrameReadState, FrameRecv, FrameSend, FrameWriteState, Split,
+        until_close, CloseState, FrameCodec, FrameConfig, FrameReadState, FrameRecv, FrameSend,
+        FrameWriteState, Split,
     },
     errors::WsError,
-    frame::OpCode,
+    frame::{OpCode, SimplifiedHeader},
     protocol::standard_handshake_resp_check,
-    Message,
+    Event, Message,
 };
-use bytes::Buf;
+use bytes::{Buf, Bytes, BytesMut};
 use std::borrow::Cow;
 use std::io::{Read, Write};
 
 macro_rules! impl_recv {
-    () => {
+    ($receive:ident) => {
+        /// number of bytes read from stream but not parsed yet
+        pub fn buffered_len(&self) -> usize {
+            self.frame_codec.buffered_len()
+        }
+
+        /// max number of unparsed bytes ever buffered
+        pub fn buffered_high_water_mark(&self) -> usize {
+            self.frame_codec.buffered_high_water_mark()
+        }
+
+        /// histogram of received message payload size
+        #[cfg(feature = "metrics")]
+        pub fn size_histogram(&self) -> &hdrhistogram::Histogram<u64> {
+            self.frame_codec.size_histogram()
+        }
+
         /// receive a message
         pub fn receive(&mut self) -> Result<Message<Cow<[u8]>>, WsError> {
-            let (header, mut data) = self.frame_codec.receive()?;
-            let close_code = if header.code == OpCode::Close {
-                let code = if data.len() >= 2 {
-                    data.get_u16()
-                } else {
-                    1000
-                };
-                Some(code)
+            let (header, mut data) = self.frame_codec.$receive()?;
+            // close frame without status code has no close code
+            let close_code = if header.code == OpCode::Close && data.len() >= 2 {
+                Some(data.get_u16())
             } else {
                 None
             };
@@ -33,6 +46,30 @@ macro_rules! impl_recv {
                 close_code,
             })
         }
+
+        /// iterate received messages, payload is copied, see `receive`
+        ///
+        /// iterator stops after yielding close message or first error
+        pub fn messages(&mut self) -> impl Iterator<Item = Result<Message<Vec<u8>>, WsError>> + '_ {
+            until_close(
+                || {
+                    let msg = self.receive()?;
+                    Ok(Message {
+                        code: msg.code,
+                        data: msg.data.into_owned(),
+                        close_code: msg.close_code,
+                    })
+                },
+                |msg| msg.code == OpCode::Close,
+            )
+        }
+
+        /// receive a single frame without merging fragmented frames
+        ///
+        /// returned header keeps frame fin bit, use it to reassemble message manually
+        pub fn receive_frame(&mut self) -> Result<(SimplifiedHeader, &[u8]), WsError> {
+            self.frame_codec.receive_frame()
+        }
     };
 }
 
@@ -50,7 +87,7 @@ macro_rules! impl_send {
 
         /// helper method to send close message
         pub fn close<'a>(&mut self, code: u16, msg: &'a [u8]) -> Result<(), WsError> {
-            self.send((code, msg))
+            self.send(Message::close(code, msg))
         }
 
         /// send a message
@@ -58,9 +95,9 @@ macro_rules! impl_send {
             let msg: Message<Cow<'a, [u8]>> = msg.into();
             if let Some(close_code) = msg.close_code {
                 if msg.code == OpCode::Close {
-                    let mut data = close_code.to_be_bytes().to_vec();
-                    data.extend_from_slice(msg.data.as_ref());
-                    self.frame_codec.send(msg.code, &data)
+                    let code = close_code.to_be_bytes();
+                    self.frame_codec
+                        .send_vectored(msg.code, &[&code, msg.data.as_ref()])
                 } else {
                     self.frame_codec.send(msg.code, msg.data.as_ref())
                 }
@@ -69,6 +106,28 @@ macro_rules! impl_send {
             }
         }
 
+        /// send a frame encoded once by `frame::encode_once` to this connection,
+        /// encoding is not repeated for every connection of a broadcast
+        ///
+        /// **NOTE** frame bytes bypass masking of this connection, only use it on
+        /// server side
+        pub fn send_encoded(&mut self, frame: &Bytes) -> Result<(), WsError> {
+            self.frame_codec.send_encoded(frame)
+        }
+
+        /// send a single frame with explicit fin bit, **this method do not fragment**
+        ///
+        /// use `OpCode::Continue` for frames following the first one of a fragmented message
+        pub fn send_frame(&mut self, code: OpCode, data: &[u8], fin: bool) -> Result<(), WsError> {
+            self.frame_codec.send_frame(code, data, fin)
+        }
+
+        /// send close frame for protocol error with masking matching role, do
+        /// nothing for other errors
+        pub fn close_with_error(&mut self, error: &WsError) -> Result<(), WsError> {
+            self.frame_codec.close_with_error(error)
+        }
+
         /// flush underlying stream
         pub fn flush(&mut self) -> Result<(), WsError> {
             self.frame_codec.flush()
@@ -89,7 +148,7 @@ impl<S: Read> BytesRecv<S> {
         }
     }
 
-    impl_recv! {}
+    impl_recv! {receive}
 }
 
 /// send part of bytes message
@@ -111,6 +170,7 @@ impl<S: Write> BytesSend<S> {
 /// recv/send bytes message
 pub struct BytesCodec<S: Read + Write> {
     frame_codec: FrameCodec<S>,
+    handshake_response: Option<http::Response<()>>,
 }
 
 impl<S: Read + Write> BytesCodec<S> {
@@ -118,6 +178,7 @@ impl<S: Read + Write> BytesCodec<S> {
     pub fn new(stream: S) -> Self {
         Self {
             frame_codec: FrameCodec::new(stream),
+            handshake_response: None,
         }
     }
 
@@ -125,22 +186,33 @@ impl<S: Read + Write> BytesCodec<S> {
     pub fn new_with(stream: S, config: FrameConfig) -> Self {
         Self {
             frame_codec: FrameCodec::new_with(stream, config),
+            handshake_response: None,
         }
     }
 
     /// used for server side to construct a new server
-    pub fn factory(_req: http::Request<()>, stream: S) -> Result<Self, WsError> {
+    pub fn factory(req: http::Request<()>, stream: S) -> Result<Self, WsError> {
         let config = FrameConfig {
             mask_send_frame: false,
+            expect_mask: Some(true),
             ..Default::default()
         };
-        Ok(Self::new_with(stream, config))
+        let mut codec = Self::new_with(stream, config);
+        codec.frame_codec.read_state.feed_remain(req.extensions());
+        Ok(codec)
     }
 
     /// used to client side to construct a new client
     pub fn check_fn(key: String, resp: http::Response<()>, stream: S) -> Result<Self, WsError> {
         standard_handshake_resp_check(key.as_bytes(), &resp)?;
-        Ok(Self::new_with(stream, FrameConfig::default()))
+        let config = FrameConfig {
+            expect_mask: Some(false),
+            ..Default::default()
+        };
+        let mut codec = Self::new_with(stream, config);
+        codec.frame_codec.read_state.feed_remain(resp.extensions());
+        codec.handshake_response = Some(resp);
+        Ok(codec)
     }
 
     /// get mutable underlying stream
@@ -148,7 +220,70 @@ impl<S: Read + Write> BytesCodec<S> {
         self.frame_codec.stream_mut()
     }
 
-    impl_recv! {}
+    /// handshake response of server, `None` if codec is not constructed by `check_fn`
+    ///
+    /// use it to read headers selected by server, such as `Sec-WebSocket-Protocol`
+    pub fn handshake_response(&self) -> Option<&http::Response<()>> {
+        self.handshake_response.as_ref()
+    }
+
+    /// current closing handshake state
+    pub fn close_state(&self) -> CloseState {
+        self.frame_codec.close_state()
+    }
+
+    /// set masking key generator of outgoing frames, see `FrameWriteState::set_mask_fn`
+    pub fn set_mask_fn<F: FnMut() -> [u8; 4] + Send + 'static>(&mut self, f: F) {
+        self.frame_codec.set_mask_fn(f);
+    }
+
+    /// receive all complete messages already buffered, at most `max` messages
+    ///
+    /// read from stream only if no message is buffered, ping is answered with pong
+    /// automatically and pong is dropped, batch ends after a close message
+    pub fn receive_available(&mut self, max: usize) -> Result<Vec<Message<BytesMut>>, WsError> {
+        let mut msgs = vec![];
+        let mut polled = false;
+        while msgs.len() < max && (!polled || self.frame_codec.read_state.is_message_ready()) {
+            polled = true;
+            let (header, data) = self.frame_codec.receive_reassembled()?;
+            match header.code {
+                OpCode::Ping => {
+                    let payload = data.to_vec();
+                    self.frame_codec.send(OpCode::Pong, &payload)?;
+                }
+                OpCode::Pong => {}
+                OpCode::Close => {
+                    let mut data = BytesMut::from(data);
+                    let close_code = if data.len() >= 2 {
+                        Some(data.get_u16())
+                    } else {
+                        None
+                    };
+                    msgs.push(Message {
+                        code: OpCode::Close,
+                        data,
+                        close_code,
+                    });
+                    break;
+                }
+                code => msgs.push(Message {
+                    code,
+                    data: BytesMut::from(data),
+                    close_code: None,
+                }),
+            }
+        }
+        Ok(msgs)
+    }
+
+    /// receive next data message, control frames are handled internally,
+    /// see `FrameCodec::receive_message`
+    pub fn receive_message(&mut self) -> Result<Event<&[u8]>, WsError> {
+        self.frame_codec.receive_message()
+    }
+
+    impl_recv! {receive_reassembled}
 
     impl_send! {}
 }
@@ -173,3 +308,111 @@ where
         )
     }
 }
+
+#[test]
+fn test_short_close_payload() {
+    let mut codec = BytesCodec::new(std::io::Cursor::new(vec![0x88, 0x00]));
+    let msg = codec.receive().unwrap();
+    assert_eq!(msg.code, OpCode::Close);
+    assert_eq!(msg.close_code, None);
+    assert!(msg.data.is_empty());
+
+    let mut codec = BytesCodec::new(std::io::Cursor::new(vec![0x88, 0x01, 0x03]));
+    assert!(matches!(
+        codec.receive(),
+        Err(WsError::ProtocolError {
+            close_code: 1002,
+            error: crate::errors::ProtocolError::InvalidCloseFramePayload
+        })
+    ));
+}
+
+#[test]
+fn test_lossy_close_reason() {
+    let data = vec![0x88, 0x04, 0x03, 0xe8, 0xff, 0xfe];
+    let mut codec = BytesCodec::new(std::io::Cursor::new(data.clone()));
+    assert!(matches!(
+        codec.receive(),
+        Err(WsError::ProtocolError {
+            close_code: 1007,
+            error: crate::errors::ProtocolError::InvalidUtf8 { valid_up_to: 0 }
+        })
+    ));
+
+    let config = FrameConfig::builder()
+        .lossy_close_reason(true)
+        .build()
+        .unwrap();
+    let mut codec = BytesCodec::new_with(std::io::Cursor::new(data), config);
+    let msg = codec.receive().unwrap();
+    assert_eq!(msg.code, OpCode::Close);
+    assert_eq!(msg.close_code, Some(1000));
+    assert_eq!(msg.data.as_ref(), &[0xff, 0xfe]);
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_size_histogram() {
+    let mut data = vec![];
+    for size in [1u8, 10, 100] {
+        data.extend_from_slice(&[0x82, size]);
+        data.resize(data.len() + size as usize, 0);
+    }
+    let mut codec = BytesCodec::new(std::io::Cursor::new(data));
+    for _ in 0..3 {
+        codec.receive().unwrap();
+    }
+    let hist = codec.size_histogram();
+    assert_eq!(hist.len(), 3);
+    assert_eq!(hist.min(), 1);
+    assert_eq!(hist.value_at_quantile(0.5), 10);
+    assert_eq!(hist.max(), 100);
+}
+
+#[test]
+fn test_factory_expect_mask() {
+    use crate::{errors::ProtocolError, frame::OwnedFrame, protocol::cal_accept_key};
+
+    fn frame_bytes(frame: OwnedFrame) -> Vec<u8> {
+        let mut data = frame.header().as_bytes().to_vec();
+        data.extend_from_slice(frame.payload());
+        data
+    }
+
+    let unmasked = frame_bytes(OwnedFrame::binary_frame(None, b"hi"));
+    let masked = frame_bytes(OwnedFrame::binary_frame([1, 2, 3, 4], b"hi"));
+
+    let req = http::Request::new(());
+    let mut server =
+        BytesCodec::factory(req.clone(), std::io::Cursor::new(masked.clone())).unwrap();
+    assert_eq!(server.receive().unwrap().data.as_ref(), b"hi");
+    let mut server = BytesCodec::factory(req, std::io::Cursor::new(unmasked.clone())).unwrap();
+    assert!(matches!(
+        server.receive(),
+        Err(WsError::ProtocolError {
+            close_code: 1002,
+            error: ProtocolError::UnexpectedMask(false)
+        })
+    ));
+
+    let key = crate::protocol::gen_key();
+    let resp = || {
+        http::Response::builder()
+            .status(http::StatusCode::SWITCHING_PROTOCOLS)
+            .header("sec-websocket-accept", cal_accept_key(key.as_bytes()))
+            .body(())
+            .unwrap()
+    };
+    let mut client =
+        BytesCodec::check_fn(key.clone(), resp(), std::io::Cursor::new(unmasked)).unwrap();
+    assert_eq!(client.receive().unwrap().data.as_ref(), b"hi");
+    let mut client =
+        BytesCodec::check_fn(key.clone(), resp(), std::io::Cursor::new(masked)).unwrap();
+    assert!(matches!(
+        client.receive(),
+        Err(WsError::ProtocolError {
+            close_code: 1002,
+            error: ProtocolError::UnexpectedMask(true)
+        })
+    ));
+}
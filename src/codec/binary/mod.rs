@@ -4,8 +4,8 @@ mod blocking;
 #[cfg(feature = "sync")]
 pub use blocking::*;
 
-#[cfg(feature = "async")]
+#[cfg(feature = "async_codec")]
 mod non_blocking;
 
-#[cfg(feature = "async")]
+#[cfg(feature = "async_codec")]
 pub use non_blocking::*;
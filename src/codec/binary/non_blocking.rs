@@ -1,30 +1,47 @@
 use http;
 use crate::{
     codec::{
-        AsyncFrameCodec, AsyncFrameRecv, AsyncFrameSend, FrameConfig, FrameReadState,
-        FrameWriteState, Split,
+        AsyncFrameCodec, AsyncFrameRecv, AsyncFrameSend, CloseState, FrameConfig, FrameReadState,
+        FrameWriteState, Keepalive, KeepaliveConfig, Split,
     },
     errors::WsError,
-    frame::OpCode,
+    frame::{OpCode, SimplifiedHeader},
     protocol::standard_handshake_resp_check,
-    Message,
+    Event, Message,
 };
-use bytes::Buf;
+use bytes::{Buf, Bytes, BytesMut};
 use std::borrow::Cow;
+#[cfg(feature = "futures")]
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
 use tokio::io::{AsyncRead, AsyncWrite};
 
 macro_rules! impl_recv {
-    () => {
+    ($receive:ident $(, $keepalive:ident)?) => {
+        /// number of bytes read from stream but not parsed yet
+        pub fn buffered_len(&self) -> usize {
+            self.frame_codec.buffered_len()
+        }
+
+        /// max number of unparsed bytes ever buffered
+        pub fn buffered_high_water_mark(&self) -> usize {
+            self.frame_codec.buffered_high_water_mark()
+        }
+
+        /// histogram of received message payload size
+        #[cfg(feature = "metrics")]
+        pub fn size_histogram(&self) -> &hdrhistogram::Histogram<u64> {
+            self.frame_codec.size_histogram()
+        }
+
         /// receive a message
         pub async fn receive(&mut self) -> Result<Message<Cow<[u8]>>, WsError> {
-            let (header, mut data) = self.frame_codec.receive().await?;
-            let close_code = if header.code == OpCode::Close {
-                let code = if data.len() >= 2 {
-                    data.get_u16()
-                } else {
-                    1000
-                };
-                Some(code)
+            let (header, mut data) = self.frame_codec.$receive($(&mut self.$keepalive)?).await?;
+            // close frame without status code has no close code
+            let close_code = if header.code == OpCode::Close && data.len() >= 2 {
+                Some(data.get_u16())
             } else {
                 None
             };
@@ -34,6 +51,13 @@ macro_rules! impl_recv {
                 close_code,
             })
         }
+
+        /// receive a single frame without merging fragmented frames
+        ///
+        /// returned header keeps frame fin bit, use it to reassemble message manually
+        pub async fn receive_frame(&mut self) -> Result<(SimplifiedHeader, &[u8]), WsError> {
+            self.frame_codec.receive_frame().await
+        }
     };
 }
 
@@ -51,7 +75,7 @@ macro_rules! impl_send {
 
         /// helper method to send close message
         pub async fn close<'a>(&mut self, code: u16, msg: &'a [u8]) -> Result<(), WsError> {
-            self.send((code, msg)).await
+            self.send(Message::close(code, msg)).await
         }
 
         /// send a message
@@ -62,9 +86,10 @@ macro_rules! impl_send {
             let msg: Message<Cow<'a, [u8]>> = msg.into();
             if let Some(close_code) = msg.close_code {
                 if msg.code == OpCode::Close {
-                    let mut data = close_code.to_be_bytes().to_vec();
-                    data.extend_from_slice(msg.data.as_ref());
-                    self.frame_codec.send(msg.code, &data).await
+                    let code = close_code.to_be_bytes();
+                    self.frame_codec
+                        .send_vectored(msg.code, &[&code, msg.data.as_ref()])
+                        .await
                 } else {
                     self.frame_codec.send(msg.code, msg.data.as_ref()).await
                 }
@@ -73,6 +98,41 @@ macro_rules! impl_send {
             }
         }
 
+        /// send shared payload, such as one message fanned out to many connections
+        ///
+        /// payload is never mutated, it's masked in internal buffer if masking is
+        /// required, or written directly otherwise
+        pub async fn send_bytes(&mut self, code: OpCode, data: Bytes) -> Result<(), WsError> {
+            self.frame_codec.send(code, &data).await
+        }
+
+        /// send a frame encoded once by `frame::encode_once` to this connection,
+        /// encoding is not repeated for every connection of a broadcast
+        ///
+        /// **NOTE** frame bytes bypass masking of this connection, only use it on
+        /// server side
+        pub async fn send_encoded(&mut self, frame: &Bytes) -> Result<(), WsError> {
+            self.frame_codec.send_encoded(frame).await
+        }
+
+        /// send a single frame with explicit fin bit, **this method do not fragment**
+        ///
+        /// use `OpCode::Continue` for frames following the first one of a fragmented message
+        pub async fn send_frame(
+            &mut self,
+            code: OpCode,
+            data: &[u8],
+            fin: bool,
+        ) -> Result<(), WsError> {
+            self.frame_codec.send_frame(code, data, fin).await
+        }
+
+        /// send close frame for protocol error with masking matching role, do
+        /// nothing for other errors
+        pub async fn close_with_error(&mut self, error: &WsError) -> Result<(), WsError> {
+            self.frame_codec.close_with_error(error).await
+        }
+
         /// flush underlying stream
         pub async fn flush(&mut self) -> Result<(), WsError> {
             self.frame_codec.flush().await
@@ -93,7 +153,7 @@ impl<S: AsyncRead + Unpin> AsyncBytesRecv<S> {
         }
     }
 
-    impl_recv! {}
+    impl_recv! {receive}
 }
 
 /// send part of bytes message
@@ -115,6 +175,10 @@ impl<S: AsyncWrite + Unpin> AsyncBytesSend<S> {
 /// recv/send bytes message
 pub struct AsyncBytesCodec<S: AsyncRead + AsyncWrite> {
     frame_codec: AsyncFrameCodec<S>,
+    handshake_response: Option<http::Response<()>>,
+    keepalive: Option<Keepalive>,
+    #[cfg(feature = "futures")]
+    sink_buf: BytesMut,
 }
 
 impl<S: AsyncRead + AsyncWrite + Unpin> AsyncBytesCodec<S> {
@@ -122,6 +186,10 @@ impl<S: AsyncRead + AsyncWrite + Unpin> AsyncBytesCodec<S> {
     pub fn new(stream: S) -> Self {
         Self {
             frame_codec: AsyncFrameCodec::new(stream),
+            handshake_response: None,
+            keepalive: None,
+            #[cfg(feature = "futures")]
+            sink_buf: BytesMut::new(),
         }
     }
 
@@ -129,22 +197,36 @@ impl<S: AsyncRead + AsyncWrite + Unpin> AsyncBytesCodec<S> {
     pub fn new_with(stream: S, config: FrameConfig) -> Self {
         Self {
             frame_codec: AsyncFrameCodec::new_with(stream, config),
+            handshake_response: None,
+            keepalive: None,
+            #[cfg(feature = "futures")]
+            sink_buf: BytesMut::new(),
         }
     }
 
     /// used for server side to construct a new server
-    pub fn factory(_req: http::Request<()>, stream: S) -> Result<Self, WsError> {
+    pub fn factory(req: http::Request<()>, stream: S) -> Result<Self, WsError> {
         let config = FrameConfig {
             mask_send_frame: false,
+            expect_mask: Some(true),
             ..Default::default()
         };
-        Ok(Self::new_with(stream, config))
+        let mut codec = Self::new_with(stream, config);
+        codec.frame_codec.read_state.feed_remain(req.extensions());
+        Ok(codec)
     }
 
     /// used for client side to construct a new client
     pub fn check_fn(key: String, resp: http::Response<()>, stream: S) -> Result<Self, WsError> {
         standard_handshake_resp_check(key.as_bytes(), &resp)?;
-        Ok(Self::new_with(stream, FrameConfig::default()))
+        let config = FrameConfig {
+            expect_mask: Some(false),
+            ..Default::default()
+        };
+        let mut codec = Self::new_with(stream, config);
+        codec.frame_codec.read_state.feed_remain(resp.extensions());
+        codec.handshake_response = Some(resp);
+        Ok(codec)
     }
 
     /// get mutable underlying stream
@@ -152,7 +234,84 @@ impl<S: AsyncRead + AsyncWrite + Unpin> AsyncBytesCodec<S> {
         self.frame_codec.stream_mut()
     }
 
-    impl_recv! {}
+    /// handshake response of server, `None` if codec is not constructed by `check_fn`
+    ///
+    /// use it to read headers selected by server, such as `Sec-WebSocket-Protocol`
+    pub fn handshake_response(&self) -> Option<&http::Response<()>> {
+        self.handshake_response.as_ref()
+    }
+
+    /// current closing handshake state
+    pub fn close_state(&self) -> CloseState {
+        self.frame_codec.close_state()
+    }
+
+    /// set masking key generator of outgoing frames, see `FrameWriteState::set_mask_fn`
+    pub fn set_mask_fn<F: FnMut() -> [u8; 4] + Send + 'static>(&mut self, f: F) {
+        self.frame_codec.set_mask_fn(f);
+    }
+
+    /// enable or disable keepalive, disabled by default
+    ///
+    /// `receive` sends ping if connection is idle for keepalive interval and
+    /// answers ping with pong
+    ///
+    /// **NOTE** keepalive is dropped by `split`, recv half can not send ping,
+    /// spawn a task which owns send half to send ping periodically instead
+    pub fn set_keepalive(&mut self, config: Option<KeepaliveConfig>) {
+        self.keepalive = config.map(Keepalive::from);
+    }
+
+    /// receive all complete messages already buffered, at most `max` messages
+    ///
+    /// read from stream only if no message is buffered, ping is answered with pong
+    /// automatically and pong is dropped, batch ends after a close message
+    pub async fn receive_available(
+        &mut self,
+        max: usize,
+    ) -> Result<Vec<Message<BytesMut>>, WsError> {
+        let mut msgs = vec![];
+        let mut polled = false;
+        while msgs.len() < max && (!polled || self.frame_codec.read_state.is_message_ready()) {
+            polled = true;
+            let (header, data) = self.frame_codec.receive_reassembled().await?;
+            match header.code {
+                OpCode::Ping => {
+                    let payload = data.to_vec();
+                    self.frame_codec.send(OpCode::Pong, &payload).await?;
+                }
+                OpCode::Pong => {}
+                OpCode::Close => {
+                    let mut data = BytesMut::from(data);
+                    let close_code = if data.len() >= 2 {
+                        Some(data.get_u16())
+                    } else {
+                        None
+                    };
+                    msgs.push(Message {
+                        code: OpCode::Close,
+                        data,
+                        close_code,
+                    });
+                    break;
+                }
+                code => msgs.push(Message {
+                    code,
+                    data: BytesMut::from(data),
+                    close_code: None,
+                }),
+            }
+        }
+        Ok(msgs)
+    }
+
+    /// receive next data message, control frames are handled internally,
+    /// see `AsyncFrameCodec::receive_message`
+    pub async fn receive_message(&mut self) -> Result<Event<&[u8]>, WsError> {
+        self.frame_codec.receive_message().await
+    }
+
+    impl_recv! {receive_keepalive, keepalive}
 
     impl_send! {}
 }
@@ -177,3 +336,492 @@ where
         )
     }
 }
+
+impl<S: AsyncRead + AsyncWrite> AsyncBytesCodec<S> {
+    /// split codec to recv and send parts with `tokio::io::split`, works for
+    /// any stream, even if it does not implement `Split`
+    pub fn into_split(
+        self,
+    ) -> (
+        AsyncBytesRecv<tokio::io::ReadHalf<S>>,
+        AsyncBytesSend<tokio::io::WriteHalf<S>>,
+    ) {
+        let AsyncFrameCodec {
+            stream,
+            read_state,
+            write_state,
+        } = self.frame_codec;
+        let (read, write) = tokio::io::split(stream);
+        (
+            AsyncBytesRecv::new(read, read_state),
+            AsyncBytesSend::new(write, write_state),
+        )
+    }
+}
+
+/// yield received messages until peer close frame, ping and pong are yielded
+/// as is and not answered automatically
+///
+/// close echo is buffered and sent by next `poll_flush` or `poll_close`,
+/// keepalive is not applied
+#[cfg(feature = "futures")]
+impl<S: AsyncRead + AsyncWrite + Unpin> futures_core::Stream for AsyncBytesCodec<S> {
+    type Item = Result<Message<BytesMut>, WsError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        this.frame_codec.poll_next_message(&mut this.sink_buf, cx)
+    }
+}
+
+/// messages are buffered by `start_send` and written by `poll_flush`,
+/// `poll_close` sends normal close frame if close frame has not been sent
+#[cfg(feature = "futures")]
+impl<T: AsRef<[u8]>, S: AsyncRead + AsyncWrite + Unpin> futures_sink::Sink<Message<T>>
+    for AsyncBytesCodec<S>
+{
+    type Error = WsError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), WsError>> {
+        let this = self.get_mut();
+        this.frame_codec.poll_ready_buf(&mut this.sink_buf, cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, msg: Message<T>) -> Result<(), WsError> {
+        let this = self.get_mut();
+        this.frame_codec.encode_message(
+            &mut this.sink_buf,
+            msg.code,
+            msg.close_code,
+            msg.data.as_ref(),
+        )
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), WsError>> {
+        let this = self.get_mut();
+        this.frame_codec.poll_write_buf(&mut this.sink_buf, cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), WsError>> {
+        let this = self.get_mut();
+        this.frame_codec.poll_close_buf(&mut this.sink_buf, cx)
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_manual_fragmentation() {
+    let (client, server) = tokio::io::duplex(1024);
+    let mut client = AsyncBytesCodec::new(client);
+    let mut server = AsyncBytesCodec::new_with(
+        server,
+        FrameConfig {
+            mask_send_frame: false,
+            ..Default::default()
+        },
+    );
+
+    client
+        .send_frame(OpCode::Binary, b"hello ", false)
+        .await
+        .unwrap();
+    client.ping(b"ping").await.unwrap();
+    client
+        .send_frame(OpCode::Continue, b"world", true)
+        .await
+        .unwrap();
+    // interleaved ping is answered by codec
+    let msg = server.receive().await.unwrap();
+    assert_eq!(msg.code, OpCode::Binary);
+    assert_eq!(msg.data.as_ref(), b"hello world");
+    let (header, data) = client.receive_frame().await.unwrap();
+    assert_eq!(header.code, OpCode::Pong);
+    assert_eq!(data, b"ping");
+
+    server
+        .send_frame(OpCode::Binary, b"hello ", false)
+        .await
+        .unwrap();
+    server
+        .send_frame(OpCode::Continue, b"world", true)
+        .await
+        .unwrap();
+    let (header, data) = client.receive_frame().await.unwrap();
+    assert!(!header.fin);
+    assert_eq!(header.code, OpCode::Binary);
+    assert_eq!(data, b"hello ");
+    let (header, data) = client.receive_frame().await.unwrap();
+    assert!(header.fin);
+    assert_eq!(header.code, OpCode::Continue);
+    assert_eq!(data, b"world");
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_simultaneous_close() {
+    use crate::codec::ConnectionState;
+
+    let (client, server) = tokio::io::duplex(1024);
+    let mut client = AsyncBytesCodec::new(client);
+    let mut server = AsyncBytesCodec::new_with(
+        server,
+        FrameConfig {
+            mask_send_frame: false,
+            ..Default::default()
+        },
+    );
+
+    // both peers start closing before seeing each other's close frame
+    client.close(1000, b"client").await.unwrap();
+    server.close(1001, b"server").await.unwrap();
+    assert_eq!(client.close_state(), CloseState::LocalClosing);
+    assert_eq!(server.close_state(), CloseState::LocalClosing);
+
+    let msg = client.receive().await.unwrap();
+    assert_eq!(msg.code, OpCode::Close);
+    assert_eq!(msg.close_code, Some(1001));
+    assert_eq!(client.close_state(), CloseState::Closed);
+    let msg = server.receive().await.unwrap();
+    assert_eq!(msg.code, OpCode::Close);
+    assert_eq!(msg.close_code, Some(1000));
+    assert_eq!(server.close_state(), CloseState::Closed);
+
+    // echo is skipped since close frame has already been sent
+    client.close(1001, b"").await.unwrap();
+    drop(client);
+    // nothing can be received after peer close frame
+    assert!(matches!(
+        server.receive().await,
+        Err(WsError::InvalidConnState(ConnectionState::Closed))
+    ));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_receive_available() {
+    let (client, server) = tokio::io::duplex(1024);
+    let mut client = AsyncBytesCodec::new(client);
+    let mut server = AsyncBytesCodec::new_with(
+        server,
+        FrameConfig {
+            mask_send_frame: false,
+            ..Default::default()
+        },
+    );
+
+    client.send((OpCode::Text, &b"first"[..])).await.unwrap();
+    client
+        .send_frame(OpCode::Binary, b"second ", false)
+        .await
+        .unwrap();
+    client.ping(b"ping").await.unwrap();
+    client
+        .send_frame(OpCode::Continue, b"part", true)
+        .await
+        .unwrap();
+    client.send((OpCode::Binary, &b"third"[..])).await.unwrap();
+    client.send((OpCode::Binary, &b"fourth"[..])).await.unwrap();
+
+    let msgs = server.receive_available(3).await.unwrap();
+    assert_eq!(msgs.len(), 3);
+    assert_eq!(msgs[0].code, OpCode::Text);
+    assert_eq!(&msgs[0].data[..], b"first");
+    assert_eq!(msgs[1].code, OpCode::Binary);
+    assert_eq!(&msgs[1].data[..], b"second part");
+    assert_eq!(&msgs[2].data[..], b"third");
+
+    let msgs = server.receive_available(10).await.unwrap();
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(&msgs[0].data[..], b"fourth");
+
+    let msg = client.receive().await.unwrap();
+    assert_eq!(msg.code, OpCode::Pong);
+    assert_eq!(msg.data.as_ref(), b"ping");
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_auto_close_reply() {
+    let (client, server) = tokio::io::duplex(1024);
+    let mut client = AsyncBytesCodec::new(client);
+    let mut server = AsyncBytesCodec::new_with(
+        server,
+        FrameConfig::builder()
+            .mask_send_frame(false)
+            .auto_close_reply(true)
+            .build()
+            .unwrap(),
+    );
+
+    client.close(1001, b"bye").await.unwrap();
+    let msg = server.receive().await.unwrap();
+    assert_eq!(msg.code, OpCode::Close);
+    assert_eq!(msg.close_code, Some(1001));
+    assert_eq!(server.close_state(), CloseState::Closed);
+
+    let msg = client.receive().await.unwrap();
+    assert_eq!(msg.code, OpCode::Close);
+    assert_eq!(msg.close_code, Some(1001));
+    assert!(msg.data.is_empty());
+
+    // manual echo after auto reply is skipped
+    server.close(1001, b"").await.unwrap();
+    drop(server);
+    assert!(client.receive().await.is_err());
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_close_as_error() {
+    use crate::CloseEvent;
+
+    let (client, server) = tokio::io::duplex(1024);
+    let mut client = AsyncBytesCodec::new(client);
+    let mut server = AsyncBytesCodec::new_with(
+        server,
+        FrameConfig::builder()
+            .mask_send_frame(false)
+            .auto_close_reply(true)
+            .close_as_error(true)
+            .build()
+            .unwrap(),
+    );
+
+    client.send(b"hello".as_slice()).await.unwrap();
+    client.close(1001, "bye".as_bytes()).await.unwrap();
+    let mut received = vec![];
+    let err = loop {
+        match server.receive().await {
+            Ok(msg) => received.push(msg.data.into_owned()),
+            Err(e) => break e,
+        }
+    };
+    assert_eq!(received, vec![b"hello".to_vec()]);
+    assert!(matches!(
+        &err,
+        WsError::Closed { code: 1001, reason } if reason == "bye"
+    ));
+    assert_eq!(err.close_event(), Some(CloseEvent::Status(1001)));
+    // close frame is still replied
+    let msg = client.receive().await.unwrap();
+    assert_eq!(msg.code, OpCode::Close);
+    assert_eq!(msg.close_code, Some(1001));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_keepalive() {
+    use std::time::Duration;
+
+    let (client, server) = tokio::io::duplex(1024);
+    let mut client = AsyncBytesCodec::new(client);
+    client.set_keepalive(Some(KeepaliveConfig::new(Duration::from_millis(50))));
+    let config = FrameConfig {
+        mask_send_frame: false,
+        ..Default::default()
+    };
+    let mut server = AsyncFrameCodec::new_with(server, config);
+
+    let peer = tokio::spawn(async move {
+        // idle client sends ping
+        let (header, _) = server.receive().await.unwrap();
+        assert_eq!(header.code, OpCode::Ping);
+        server.send(OpCode::Pong, b"").await.unwrap();
+        server.send(OpCode::Ping, b"server").await.unwrap();
+        let (header, data) = server.receive().await.unwrap();
+        assert_eq!(header.code, OpCode::Pong);
+        assert_eq!(data, b"server");
+        server.send(OpCode::Binary, b"data").await.unwrap();
+        // stop answering ping
+        let (header, _) = server.receive().await.unwrap();
+        assert_eq!(header.code, OpCode::Ping);
+        server
+    });
+
+    let msg = client.receive().await.unwrap();
+    assert_eq!(msg.code, OpCode::Binary);
+    assert_eq!(msg.data.as_ref(), b"data");
+    match client.receive().await {
+        Err(WsError::ConnectionLost(e)) => assert_eq!(e.kind(), std::io::ErrorKind::TimedOut),
+        other => panic!("expect connection lost, got {:?}", other.map(|m| m.code)),
+    }
+    let _server = peer.await.unwrap();
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_keepalive_split_frame() {
+    use crate::frame::OwnedFrame;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (client, mut server) = tokio::io::duplex(1024);
+    let mut client = AsyncBytesCodec::new(client);
+    client.set_keepalive(Some(KeepaliveConfig::new(Duration::from_millis(50))));
+
+    let frame = OwnedFrame::binary_frame(None, b"hello world");
+    let mut data = frame.header().as_bytes().to_vec();
+    data.extend_from_slice(frame.payload());
+    let peer = tokio::spawn(async move {
+        // frame takes several intervals, but keeps making progress
+        for chunk in data.chunks(3) {
+            server.write_all(chunk).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(30)).await;
+        }
+        let mut sent = vec![];
+        server.read_to_end(&mut sent).await.unwrap();
+        sent
+    });
+
+    let msg = client.receive().await.unwrap();
+    assert_eq!(msg.code, OpCode::Binary);
+    assert_eq!(msg.data.as_ref(), b"hello world");
+    drop(client);
+    // slow frame is not mistaken for idle connection, no ping is sent
+    assert!(peer.await.unwrap().is_empty());
+}
+
+#[cfg(all(test, feature = "futures"))]
+#[tokio::test]
+async fn test_stream_sink() {
+    use futures_core::Stream;
+    use futures_sink::Sink;
+    use std::future::poll_fn;
+    type Item<'a> = Message<Cow<'a, [u8]>>;
+
+    let (client, server) = tokio::io::duplex(1024);
+    let mut client = AsyncBytesCodec::new(client);
+    let mut server = AsyncBytesCodec::new_with(
+        server,
+        FrameConfig {
+            mask_send_frame: false,
+            auto_fragment_size: 4,
+            auto_close_reply: true,
+            ..Default::default()
+        },
+    );
+
+    let mut client_pin = Pin::new(&mut client);
+    poll_fn(|cx| Sink::<Item>::poll_ready(client_pin.as_mut(), cx))
+        .await
+        .unwrap();
+    client_pin
+        .as_mut()
+        .start_send(Item::from((OpCode::Text, &b"hello"[..])))
+        .unwrap();
+    client_pin
+        .as_mut()
+        .start_send(Message {
+            code: OpCode::Binary,
+            data: vec![1u8, 2, 3],
+            close_code: None,
+        })
+        .unwrap();
+    poll_fn(|cx| Sink::<Item>::poll_flush(client_pin.as_mut(), cx))
+        .await
+        .unwrap();
+
+    let mut server_pin = Pin::new(&mut server);
+    let msg = poll_fn(|cx| server_pin.as_mut().poll_next(cx))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(msg.code, OpCode::Text);
+    assert_eq!(msg.data.as_ref(), b"hello");
+    let msg = poll_fn(|cx| server_pin.as_mut().poll_next(cx))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(msg.code, OpCode::Binary);
+    assert_eq!(msg.data.as_ref(), &[1, 2, 3]);
+
+    // server forwards fragmented message back
+    server_pin
+        .as_mut()
+        .start_send(Item::from(&b"fragmented"[..]))
+        .unwrap();
+    poll_fn(|cx| Sink::<Item>::poll_flush(server_pin.as_mut(), cx))
+        .await
+        .unwrap();
+    let msg = poll_fn(|cx| client_pin.as_mut().poll_next(cx))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(msg.data.as_ref(), b"fragmented");
+
+    // close frame is sent by poll_close and echoed by peer
+    poll_fn(|cx| Sink::<Item>::poll_close(client_pin.as_mut(), cx))
+        .await
+        .unwrap();
+    let msg = poll_fn(|cx| server_pin.as_mut().poll_next(cx))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(msg.code, OpCode::Close);
+    assert_eq!(msg.close_code, Some(1000));
+    assert!(poll_fn(|cx| server_pin.as_mut().poll_next(cx))
+        .await
+        .is_none());
+    poll_fn(|cx| Sink::<Item>::poll_flush(server_pin.as_mut(), cx))
+        .await
+        .unwrap();
+    let msg = poll_fn(|cx| client_pin.as_mut().poll_next(cx))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(msg.code, OpCode::Close);
+    assert_eq!(msg.close_code, Some(1000));
+    assert_eq!(client.close_state(), CloseState::Closed);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_send_bytes() {
+    let (client, server) = tokio::io::duplex(1024);
+    let mut client = AsyncBytesCodec::new(client);
+    let mut server = AsyncBytesCodec::new_with(
+        server,
+        FrameConfig {
+            mask_send_frame: false,
+            ..Default::default()
+        },
+    );
+
+    let data = Bytes::from_static(b"broadcast");
+    client
+        .send_bytes(OpCode::Binary, data.clone())
+        .await
+        .unwrap();
+    server
+        .send_bytes(OpCode::Binary, data.clone())
+        .await
+        .unwrap();
+    assert_eq!(data, &b"broadcast"[..]);
+    let msg = server.receive().await.unwrap();
+    assert_eq!(msg.code, OpCode::Binary);
+    assert_eq!(msg.data.as_ref(), b"broadcast");
+    let msg = client.receive().await.unwrap();
+    assert_eq!(msg.data.as_ref(), b"broadcast");
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_send_encoded() {
+    let config = FrameConfig {
+        mask_send_frame: false,
+        ..Default::default()
+    };
+    let frame = crate::frame::encode_once(OpCode::Text, b"broadcast");
+    let mut clients = vec![];
+    for _ in 0..3 {
+        let (client, server) = tokio::io::duplex(1024);
+        let (_, mut send) = AsyncBytesCodec::new_with(server, config.clone()).into_split();
+        send.send_encoded(&frame).await.unwrap();
+        clients.push(AsyncBytesCodec::new(client));
+    }
+    for client in clients.iter_mut() {
+        let msg = client.receive().await.unwrap();
+        assert_eq!(msg.code, OpCode::Text);
+        assert_eq!(msg.data.as_ref(), b"broadcast");
+    }
+}
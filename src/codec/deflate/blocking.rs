@@ -2,15 +2,22 @@ use std::io::{Read, Write};
 
 use http;
 use crate::{
-    codec::{apply_mask, FrameConfig, Split},
+    codec::{
+        apply_mask,
+        frame::{check_close, check_utf8},
+        Direction, FrameConfig, Split,
+    },
     errors::{ProtocolError, WsError},
     frame::{ctor_header, OpCode, OwnedFrame, SimplifiedHeader},
     protocol::standard_handshake_resp_check,
+    CloseEvent, Event,
 };
 use bytes::BytesMut;
-use rand::random;
 
-use super::{DeflateReadState, DeflateWriteState, PMDConfig};
+use super::{
+    apply_compression_params, CompressionStrategy, DeflateReadState, DeflateWriteState,
+    NegotiatedParams, PMDConfig, Utf8Stream,
+};
 
 impl DeflateWriteState {
     /// send a read frame, **this method will not check validation of frame and do not fragment**
@@ -23,7 +30,7 @@ impl DeflateWriteState {
             return self
                 .write_state
                 .send_owned_frame(stream, frame)
-                .map_err(WsError::IOError);
+                .map_err(WsError::from);
         }
         let prev_mask = frame.unmask();
         let header = frame.header();
@@ -38,15 +45,18 @@ impl DeflateWriteState {
                     .com
                     .compress(&[frame.payload()], &mut compressed)
                     .map_err(|code| WsError::CompressFailed(code.to_string()))?;
-                compressed.truncate(compressed.len() - 4);
+                // fragments of a message share one deflate stream, only
+                // first fragment carries rsv1
+                let fin = header.fin();
+                if fin {
+                    compressed.truncate(compressed.len() - 4);
+                }
                 let mut new = OwnedFrame::new(header.opcode(), prev_mask, &compressed);
-                let header = new.header_mut();
-                header.set_rsv1(true);
-                header.set_fin(header.fin());
+                let new_header = new.header_mut();
+                new_header.set_rsv1(header.opcode() != OpCode::Continue);
+                new_header.set_fin(fin);
 
-                if (self.is_server && handler.config.server_no_context_takeover)
-                    || (!self.is_server && handler.config.client_no_context_takeover)
-                {
+                if fin && handler.config.reset_compressor(self.is_server) {
                     handler
                         .com
                         .reset()
@@ -63,7 +73,22 @@ impl DeflateWriteState {
             });
         self.write_state
             .send_owned_frame(stream, frame?)
-            .map_err(WsError::IOError)
+            .map_err(WsError::from)
+    }
+
+    /// send payload without compression even if deflate is enabled, rsv1 is not set
+    /// and compressor state is untouched
+    ///
+    /// will auto fragment if auto_fragment_size > 0
+    pub fn send_uncompressed<S: Write>(
+        &mut self,
+        stream: &mut S,
+        code: OpCode,
+        payload: &[u8],
+    ) -> Result<(), WsError> {
+        self.write_state
+            .send(stream, code, payload)
+            .map_err(WsError::from)
     }
 
     /// send payload
@@ -75,16 +100,8 @@ impl DeflateWriteState {
         code: OpCode,
         payload: &[u8],
     ) -> Result<(), WsError> {
-        let mask_send = self.config.mask_send_frame;
-        let mask_fn = || {
-            if mask_send {
-                Some(random())
-            } else {
-                None
-            }
-        };
         if payload.is_empty() {
-            let mask = mask_fn();
+            let mask = self.write_state.mask_key();
             let frame = OwnedFrame::new(code, mask, &[]);
             return self.send_owned_frame(stream, frame);
         }
@@ -98,7 +115,8 @@ impl DeflateWriteState {
         let total = parts.len();
         for (idx, chunk) in parts.into_iter().enumerate() {
             let fin = idx + 1 == total;
-            let mask = mask_fn();
+            let frame_code = if idx == 0 { code } else { OpCode::Continue };
+            let mask = self.write_state.mask_key();
             match (self.com.as_mut(), code.is_data()) {
                 (Some(handler), true) => {
                     let mut output = vec![];
@@ -106,25 +124,28 @@ impl DeflateWriteState {
                         .com
                         .compress(&[chunk], &mut output)
                         .map_err(|code| WsError::CompressFailed(code.to_string()))?;
-                    output.truncate(output.len() - 4);
+                    // only last fragment drops trailing 0x00 0x00 0xff 0xff,
+                    // and only first fragment carries rsv1
+                    if fin {
+                        output.truncate(output.len() - 4);
+                    }
                     let header = ctor_header(
                         &mut self.header_buf,
                         fin,
-                        true,
+                        idx == 0,
                         false,
                         false,
                         mask,
-                        code,
+                        frame_code,
                         output.len() as u64,
                     );
+                    self.config.notify(Direction::Outbound, header);
                     stream.write_all(header)?;
                     if let Some(mask) = mask {
                         apply_mask(&mut output, mask)
                     };
                     stream.write_all(&output)?;
-                    if (self.is_server && handler.config.server_no_context_takeover)
-                        || (!self.is_server && handler.config.client_no_context_takeover)
-                    {
+                    if fin && handler.config.reset_compressor(self.is_server) {
                         handler
                             .com
                             .reset()
@@ -140,9 +161,10 @@ impl DeflateWriteState {
                         false,
                         false,
                         mask,
-                        code,
+                        frame_code,
                         chunk.len() as u64,
                     );
+                    self.config.notify(Direction::Outbound, header);
                     stream.write_all(header)?;
                     if let Some(mask) = mask {
                         let mut data = BytesMut::from_iter(chunk);
@@ -159,53 +181,39 @@ impl DeflateWriteState {
 }
 
 impl DeflateReadState {
+    /// receive a frame, return raw payload and whether it belongs to a compressed message
     fn receive_one<S: Read>(
         &mut self,
         stream: &mut S,
-    ) -> Result<(SimplifiedHeader, Vec<u8>), WsError> {
+    ) -> Result<(SimplifiedHeader, Vec<u8>, bool), WsError> {
         let (mut header, data) = self.read_state.receive(stream)?;
         let data = data.to_vec();
-        let compressed = header.rsv1;
-        let is_data_frame = header.code.is_data();
-        if compressed && !is_data_frame {
-            return Err(WsError::ProtocolError {
-                close_code: 1002,
-                error: ProtocolError::CompressedControlFrame,
-            });
-        }
-        if !is_data_frame || !compressed {
-            return Ok((header, data));
-        }
-        let frame = match self.de.as_mut() {
-            Some(handler) => {
-                let mut de_data = vec![];
-                handler
-                    .de
-                    .de_compress(&[&data, &[0, 0, 255, 255]], &mut de_data)
-                    .map_err(|code| WsError::DeCompressFailed(code.to_string()))?;
-                if (self.is_server && handler.config.server_no_context_takeover)
-                    || (!self.is_server && handler.config.client_no_context_takeover)
-                {
-                    handler
-                        .de
-                        .reset()
-                        .map_err(|code| WsError::DeCompressFailed(code.to_string()))?;
-                    tracing::trace!("reset decompressor state");
-                }
-                de_data
-            }
-            None => {
-                if header.rsv1 {
-                    return Err(WsError::DeCompressFailed(
-                        "extension not enabled but got compressed frame".into(),
-                    ));
-                } else {
-                    data
-                }
-            }
-        };
+        let compressed = self.is_compressed(&header)?;
         header.rsv1 = false;
-        Ok((header, frame))
+        Ok((header, data, compressed))
+    }
+
+    /// receive a message, payload is passed to `on_chunk` chunk by chunk with
+    /// message type instead of being buffered, compressed message is decompressed
+    /// in chunks of at most 16K, return header of completed message
+    ///
+    /// control frames interleaved in a fragmented message are passed as a whole,
+    /// text payload is not utf-8 validated, don't mix with `receive` in the
+    /// middle of a message
+    pub fn receive_stream<S: Read, F>(
+        &mut self,
+        stream: &mut S,
+        mut on_chunk: F,
+    ) -> Result<SimplifiedHeader, WsError>
+    where
+        F: FnMut(OpCode, &[u8]) -> Result<(), WsError>,
+    {
+        loop {
+            let (header, loc) = self.read_state.receive_loc(stream, None)?;
+            if let Some(header) = self.stream_frame(header, loc, &mut on_chunk)? {
+                return Ok(header);
+            }
+        }
     }
 
     /// receive a message
@@ -214,8 +222,11 @@ impl DeflateReadState {
         stream: &mut S,
     ) -> Result<(SimplifiedHeader, &[u8]), WsError> {
         loop {
-            let (mut header, mut data) = self.receive_one(stream)?;
+            let (mut header, mut data, compressed) = self.receive_one(stream)?;
             if !self.config.merge_frame {
+                if compressed {
+                    data = self.inflate(&data, header.fin)?;
+                }
                 self.fragmented_data.clear();
                 self.fragmented_data.append(&mut data);
                 break Ok((header, &self.fragmented_data));
@@ -229,10 +240,25 @@ impl DeflateReadState {
                         });
                     }
                     let fin = header.fin;
+                    if let Some(utf8) = self.utf8_stream.as_mut() {
+                        utf8.feed(&data, fin).map_err(WsError::invalid_utf8)?;
+                    }
+                    self.config
+                        .check_message_size(self.fragmented_data.len() + data.len())?;
                     self.fragmented_data.extend_from_slice(&data);
                     if fin {
                         self.fragmented = false;
+                        self.utf8_stream = None;
                         header.code = self.fragmented_type;
+                        if compressed {
+                            let payload = std::mem::take(&mut self.fragmented_data);
+                            self.fragmented_data = self.inflate(&payload, true)?;
+                            if header.code == OpCode::Text
+                                && self.config.validate_utf8.should_check()
+                            {
+                                check_utf8(&self.fragmented_data)?;
+                            }
+                        }
                         break Ok((header, &self.fragmented_data));
                     } else {
                         continue;
@@ -245,30 +271,36 @@ impl DeflateReadState {
                             error: ProtocolError::NotContinueFrameAfterFragmented,
                         });
                     }
+                    self.config.check_message_size(data.len())?;
+                    // compressed fragments are validated after whole message is decompressed
+                    if compressed && header.fin {
+                        data = self.inflate(&data, true)?;
+                    }
+                    let plain = !compressed || header.fin;
+                    if plain
+                        && header.code == OpCode::Text
+                        && self.config.validate_utf8.is_incremental()
+                    {
+                        let mut utf8 = Utf8Stream::default();
+                        utf8.feed(&data, header.fin)
+                            .map_err(WsError::invalid_utf8)?;
+                        self.utf8_stream = (!header.fin).then_some(utf8);
+                    }
                     if !header.fin {
                         self.fragmented = true;
                         self.fragmented_type = header.code;
-                        if header.code == OpCode::Text
+                        if plain
+                            && header.code == OpCode::Text
                             && self.config.validate_utf8.is_fast_fail()
-                            && simdutf8::basic::from_utf8(&data).is_err()
                         {
-                            return Err(WsError::ProtocolError {
-                                close_code: 1007,
-                                error: ProtocolError::InvalidUtf8,
-                            });
+                            check_utf8(&data)?;
                         }
                         self.fragmented_data.clear();
                         self.fragmented_data.extend_from_slice(&data);
                         continue;
                     } else {
-                        if header.code == OpCode::Text
-                            && self.config.validate_utf8.should_check()
-                            && simdutf8::basic::from_utf8(&data).is_err()
-                        {
-                            return Err(WsError::ProtocolError {
-                                close_code: 1007,
-                                error: ProtocolError::InvalidUtf8,
-                            });
+                        if header.code == OpCode::Text && self.config.validate_utf8.should_check() {
+                            check_utf8(&data)?;
                         }
                         self.fragmented_data.clear();
                         self.fragmented_data.extend_from_slice(&data);
@@ -290,24 +322,31 @@ pub struct DeflateCodec<S: Read + Write> {
     read_state: DeflateReadState,
     write_state: DeflateWriteState,
     stream: S,
+    negotiated: NegotiatedParams,
 }
 
 impl<S: Read + Write> DeflateCodec<S> {
-    /// construct method
+    /// construct method, fail with `WsError::InvalidConfig` if `pmd_config`
+    /// is invalid
     pub fn new(
         stream: S,
         frame_config: FrameConfig,
         pmd_config: Option<PMDConfig>,
         is_server: bool,
-    ) -> Self {
+    ) -> Result<Self, WsError> {
         let read_state =
-            DeflateReadState::with_config(frame_config.clone(), pmd_config.clone(), is_server);
-        let write_state = DeflateWriteState::with_config(frame_config, pmd_config, is_server);
-        Self {
+            DeflateReadState::with_config(frame_config.clone(), pmd_config.clone(), is_server)?;
+        let write_state =
+            DeflateWriteState::with_config(frame_config, pmd_config.clone(), is_server)?;
+        Ok(Self {
             read_state,
             write_state,
             stream,
-        }
+            negotiated: NegotiatedParams {
+                subprotocol: None,
+                deflate: pmd_config,
+            },
+        })
     }
 
     /// used for server side to construct a new server
@@ -326,18 +365,18 @@ impl<S: Read + Write> DeflateCodec<S> {
             }
         }
         let mut pmd_conf = pmd_confs.pop();
-        if let Some(conf) = pmd_conf.as_mut() {
-            let min = conf.client_max_window_bits.min(conf.server_max_window_bits);
-            conf.client_max_window_bits = min;
-            conf.server_max_window_bits = min;
-        }
+        apply_compression_params(&mut pmd_conf, req.extensions());
         tracing::debug!("use deflate config {:?}", pmd_conf);
 
         let frame_conf = FrameConfig {
             mask_send_frame: false,
+            expect_mask: Some(true),
             ..Default::default()
         };
-        let codec = DeflateCodec::new(stream, frame_conf, pmd_conf, true);
+        let negotiated = NegotiatedParams::from_req(&req, pmd_conf.clone());
+        let mut codec = DeflateCodec::new(stream, frame_conf, pmd_conf, true)?;
+        codec.negotiated = negotiated;
+        codec.read_state.read_state.feed_remain(req.extensions());
         Ok(codec)
     }
 
@@ -358,24 +397,131 @@ impl<S: Read + Write> DeflateCodec<S> {
             }
         }
         let mut pmd_conf = pmd_confs.pop();
-        if let Some(conf) = pmd_conf.as_mut() {
-            let min = conf.client_max_window_bits.min(conf.server_max_window_bits);
-            conf.client_max_window_bits = min;
-            conf.server_max_window_bits = min;
-        }
+        apply_compression_params(&mut pmd_conf, resp.extensions());
         tracing::debug!("use deflate config: {:?}", pmd_conf);
-        let codec = DeflateCodec::new(stream, Default::default(), pmd_conf, false);
+        let negotiated = NegotiatedParams::from_resp(&resp, pmd_conf.clone());
+        let frame_conf = FrameConfig {
+            expect_mask: Some(false),
+            ..Default::default()
+        };
+        let mut codec = DeflateCodec::new(stream, frame_conf, pmd_conf, false)?;
+        codec.negotiated = negotiated;
+        codec.read_state.read_state.feed_remain(resp.extensions());
         Ok(codec)
     }
 
+    /// parameters negotiated during handshake
+    pub fn negotiated(&self) -> &NegotiatedParams {
+        &self.negotiated
+    }
+
+    /// effective window bits of (decompressor, compressor) after negotiation,
+    /// `None` if deflate is not enabled
+    pub fn window_bits(&self) -> Option<(u8, u8)> {
+        let inflate = self.read_state.window_bits()?;
+        let deflate = self.write_state.window_bits()?;
+        Some((inflate as u8, deflate as u8))
+    }
+
     /// get mutable underlying stream
     pub fn stream_mut(&mut self) -> &mut S {
         &mut self.stream
     }
 
+    /// number of bytes read from stream but not parsed yet
+    pub fn buffered_len(&self) -> usize {
+        self.read_state.buffered_len()
+    }
+
+    /// max number of unparsed bytes ever buffered
+    pub fn buffered_high_water_mark(&self) -> usize {
+        self.read_state.buffered_high_water_mark()
+    }
+
+    /// set masking key generator of outgoing frames, see `FrameWriteState::set_mask_fn`
+    pub fn set_mask_fn<F: FnMut() -> [u8; 4] + Send + 'static>(&mut self, f: F) {
+        self.write_state.set_mask_fn(f);
+    }
+
+    /// change compression level(0-9) and strategy of compressor, see
+    /// `DeflateWriteState::set_compression`
+    pub fn set_compression(
+        &mut self,
+        level: u32,
+        strategy: CompressionStrategy,
+    ) -> Result<(), WsError> {
+        self.write_state.set_compression(level, strategy)
+    }
+
     /// receive a message
     pub fn receive(&mut self) -> Result<(SimplifiedHeader, &[u8]), WsError> {
-        self.read_state.receive(&mut self.stream)
+        let (header, data) = self.read_state.receive(&mut self.stream)?;
+        if let Some(echo) = self.write_state.write_state.close_reply(&header, data) {
+            self.write_state
+                .write_state
+                .send(&mut self.stream, OpCode::Close, echo)?;
+        }
+        check_close(self.write_state.config.close_as_error, &header, data)?;
+        Ok((header, data))
+    }
+
+    /// receive next data message, control frames are handled internally
+    ///
+    /// ping is answered with pong, pong is dropped, fragments are reassembled
+    /// and close frame is echoed unless close frame has been sent, then
+    /// reported as `Event::Closed`
+    pub fn receive_message(&mut self) -> Result<Event<&[u8]>, WsError> {
+        let header = loop {
+            let (header, data) = self.read_state.receive(&mut self.stream)?;
+            match header.code {
+                OpCode::Ping => {
+                    let payload = data.to_vec();
+                    self.write_state
+                        .write_state
+                        .send(&mut self.stream, OpCode::Pong, &payload)?;
+                }
+                OpCode::Pong => {}
+                _ => break header,
+            }
+        };
+        // payload is kept in read state, take it again to release borrow of loop
+        match header.code {
+            OpCode::Close => {
+                let data = &self.read_state.control_buf;
+                if !self.write_state.write_state.close_sent() {
+                    let echo = if data.len() >= 2 { &data[..2] } else { &[] };
+                    self.write_state
+                        .write_state
+                        .send(&mut self.stream, OpCode::Close, echo)?;
+                }
+                Ok(Event::Closed(CloseEvent::from_payload(data)))
+            }
+            OpCode::Text => Ok(Event::Text(&self.read_state.fragmented_data)),
+            OpCode::Binary => Ok(Event::Binary(&self.read_state.fragmented_data)),
+            code => Err(WsError::UnsupportedFrame(code)),
+        }
+    }
+
+    /// receive a message chunk by chunk, see `DeflateReadState::receive_stream`
+    pub fn receive_stream<F>(&mut self, mut on_chunk: F) -> Result<SimplifiedHeader, WsError>
+    where
+        F: FnMut(OpCode, &[u8]) -> Result<(), WsError>,
+    {
+        loop {
+            let (header, loc) = self
+                .read_state
+                .read_state
+                .receive_loc(&mut self.stream, None)?;
+            let data = self.read_state.read_state.payload(&loc);
+            if let Some(echo) = self.write_state.write_state.close_reply(&header, data) {
+                self.write_state
+                    .write_state
+                    .send(&mut self.stream, OpCode::Close, echo)?;
+            }
+            if let Some(header) = self.read_state.stream_frame(header, loc, &mut on_chunk)? {
+                return Ok(header);
+            }
+        }
     }
 
     /// send a read frame, **this method will not check validation of frame and do not fragment**
@@ -383,6 +529,15 @@ impl<S: Read + Write> DeflateCodec<S> {
         self.write_state.send_owned_frame(&mut self.stream, frame)
     }
 
+    /// send close frame for protocol error with masking matching role, do
+    /// nothing for other errors
+    pub fn close_with_error(&mut self, error: &WsError) -> Result<(), WsError> {
+        self.write_state
+            .write_state
+            .send_error_close(&mut self.stream, error)
+            .map_err(WsError::from)
+    }
+
     /// send payload
     ///
     /// will auto fragment **before compression** if auto_fragment_size > 0
@@ -390,6 +545,12 @@ impl<S: Read + Write> DeflateCodec<S> {
         self.write_state.send(&mut self.stream, code, payload)
     }
 
+    /// send payload without compression, useful for incompressible data
+    pub fn send_uncompressed(&mut self, code: OpCode, payload: &[u8]) -> Result<(), WsError> {
+        self.write_state
+            .send_uncompressed(&mut self.stream, code, payload)
+    }
+
     /// helper function to send text message
     pub fn text(&mut self, text: &str) -> Result<(), WsError> {
         self.write_state
@@ -420,7 +581,7 @@ impl<S: Read + Write> DeflateCodec<S> {
 
     /// flush stream to ensure all data are send
     pub fn flush(&mut self) -> Result<(), WsError> {
-        self.stream.flush().map_err(WsError::IOError)
+        self.stream.flush().map_err(WsError::from)
     }
 }
 
@@ -441,10 +602,28 @@ impl<S: Read> DeflateRecv<S> {
         &mut self.stream
     }
 
+    /// number of bytes read from stream but not parsed yet
+    pub fn buffered_len(&self) -> usize {
+        self.read_state.buffered_len()
+    }
+
+    /// max number of unparsed bytes ever buffered
+    pub fn buffered_high_water_mark(&self) -> usize {
+        self.read_state.buffered_high_water_mark()
+    }
+
     /// receive a frame
     pub fn receive(&mut self) -> Result<(SimplifiedHeader, &[u8]), WsError> {
         self.read_state.receive(&mut self.stream)
     }
+
+    /// receive a message chunk by chunk, see `DeflateReadState::receive_stream`
+    pub fn receive_stream<F>(&mut self, on_chunk: F) -> Result<SimplifiedHeader, WsError>
+    where
+        F: FnMut(OpCode, &[u8]) -> Result<(), WsError>,
+    {
+        self.read_state.receive_stream(&mut self.stream, on_chunk)
+    }
 }
 
 /// send part of deflate message
@@ -472,6 +651,15 @@ impl<S: Write> DeflateSend<S> {
         self.write_state.send_owned_frame(&mut self.stream, frame)
     }
 
+    /// send close frame for protocol error with masking matching role, do
+    /// nothing for other errors
+    pub fn close_with_error(&mut self, error: &WsError) -> Result<(), WsError> {
+        self.write_state
+            .write_state
+            .send_error_close(&mut self.stream, error)
+            .map_err(WsError::from)
+    }
+
     /// send payload
     ///
     /// will auto fragment **before compression** if auto_fragment_size > 0
@@ -479,6 +667,12 @@ impl<S: Write> DeflateSend<S> {
         self.write_state.send(&mut self.stream, code, payload)
     }
 
+    /// send payload without compression, useful for incompressible data
+    pub fn send_uncompressed(&mut self, code: OpCode, payload: &[u8]) -> Result<(), WsError> {
+        self.write_state
+            .send_uncompressed(&mut self.stream, code, payload)
+    }
+
     /// helper function to send text message
     pub fn text(&mut self, text: &str) -> Result<(), WsError> {
         self.write_state
@@ -509,7 +703,7 @@ impl<S: Write> DeflateSend<S> {
 
     /// flush stream to ensure all data are send
     pub fn flush(&mut self) -> Result<(), WsError> {
-        self.stream.flush().map_err(WsError::IOError)
+        self.stream.flush().map_err(WsError::from)
     }
 }
 
@@ -525,6 +719,7 @@ where
             stream,
             read_state,
             write_state,
+            ..
         } = self;
         let (read, write) = stream.split();
         (
@@ -533,3 +728,445 @@ where
         )
     }
 }
+
+/// `Sec-WebSocket-Key` used by handshake tests
+#[cfg(test)]
+const TEST_KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+/// 101 response to `TEST_KEY` with `extensions` as `Sec-WebSocket-Extensions`
+#[cfg(test)]
+fn handshake_resp(extensions: &str) -> http::response::Builder {
+    http::Response::builder()
+        .status(http::StatusCode::SWITCHING_PROTOCOLS)
+        .header("upgrade", "websocket")
+        .header("connection", "upgrade")
+        .header(
+            "sec-websocket-accept",
+            crate::protocol::cal_accept_key(TEST_KEY.as_bytes()),
+        )
+        .header("sec-websocket-extensions", extensions)
+}
+
+/// server side codec with default `PMDConfig`
+#[cfg(test)]
+fn server_codec<S: Read + Write>(stream: S, config: FrameConfig) -> DeflateCodec<S> {
+    DeflateCodec::new(stream, config, Some(Default::default()), true).unwrap()
+}
+
+#[test]
+fn test_decompress_garbage() {
+    // fin + rsv1 binary frame with payload which is not a deflate stream
+    let mut data = vec![0xc2, 0x08];
+    data.extend_from_slice(&[0xff; 8]);
+    let mut codec = server_codec(std::io::Cursor::new(data), Default::default());
+    let err = codec.receive().unwrap_err();
+    assert!(matches!(
+        err,
+        WsError::ProtocolError {
+            close_code: 1002,
+            error: ProtocolError::DecompressFailed(_)
+        }
+    ));
+    assert!(err.as_close_frame(None).is_some());
+}
+
+#[test]
+fn test_receive_stream_bounded() {
+    use super::{WindowBit, ZLibCompressStream};
+
+    // 100MB of zeros, compressed 1MB at a time to keep memory bounded
+    let zeros = vec![0u8; 1024 * 1024];
+    let mut com = ZLibCompressStream::new(WindowBit::Fifteen);
+    let mut compressed = vec![];
+    for _ in 0..100 {
+        let mut output = vec![];
+        com.compress(&[&zeros], &mut output).unwrap();
+        compressed.extend_from_slice(&output);
+    }
+    compressed.truncate(compressed.len() - 4);
+    let mut frame = OwnedFrame::new(OpCode::Binary, None, &compressed);
+    frame.header_mut().set_rsv1(true);
+    let mut data = frame.header().0.to_vec();
+    data.extend_from_slice(frame.payload());
+    data.extend_from_slice(&[0x89, 0x02, b'h', b'i']);
+
+    let mut codec = server_codec(std::io::Cursor::new(data.clone()), Default::default());
+    let mut total = 0;
+    let mut max_chunk = 0;
+    let header = codec
+        .receive_stream(|code, chunk| {
+            assert_eq!(code, OpCode::Binary);
+            assert!(chunk.iter().all(|b| *b == 0));
+            total += chunk.len();
+            max_chunk = max_chunk.max(chunk.len());
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(header.code, OpCode::Binary);
+    assert!(header.fin && !header.rsv1);
+    assert_eq!(total, 100 * 1024 * 1024);
+    assert!(max_chunk <= 16 * 1024);
+    let mut ping = vec![];
+    let header = codec
+        .receive_stream(|_, chunk| {
+            ping.extend_from_slice(chunk);
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(header.code, OpCode::Ping);
+    assert_eq!(ping, b"hi");
+
+    let config = FrameConfig {
+        max_decompressed_size: 10 * 1024 * 1024,
+        ..Default::default()
+    };
+    let mut codec = server_codec(std::io::Cursor::new(data.clone()), config.clone());
+    let mut total = 0;
+    let err = codec
+        .receive_stream(|_, chunk| {
+            total += chunk.len();
+            Ok(())
+        })
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        WsError::ProtocolError {
+            close_code: 1009,
+            error: ProtocolError::PayloadTooLarge(_)
+        }
+    ));
+    assert!(total <= 10 * 1024 * 1024);
+
+    // buffered receive stops inflating once limit is exceeded too
+    let mut codec = server_codec(std::io::Cursor::new(data.clone()), config);
+    assert!(matches!(
+        codec.receive(),
+        Err(WsError::ProtocolError {
+            close_code: 1009,
+            error: ProtocolError::PayloadTooLarge(_)
+        })
+    ));
+
+    // message size limit applies to decompressed message, not only compressed payload
+    let config = FrameConfig {
+        max_message_size: Some(1024 * 1024),
+        ..Default::default()
+    };
+    let mut codec = server_codec(std::io::Cursor::new(data.clone()), config.clone());
+    assert!(matches!(
+        codec.receive(),
+        Err(WsError::ProtocolError {
+            close_code: 1009,
+            error: ProtocolError::PayloadTooLarge(1048576)
+        })
+    ));
+    let mut codec = server_codec(std::io::Cursor::new(data), config);
+    let mut total = 0;
+    let err = codec
+        .receive_stream(|_, chunk| {
+            total += chunk.len();
+            Ok(())
+        })
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        WsError::ProtocolError {
+            close_code: 1009,
+            error: ProtocolError::PayloadTooLarge(1048576)
+        }
+    ));
+    assert!(total <= 1024 * 1024);
+}
+
+#[test]
+fn test_final_deflate_block() {
+    // stored block with bfinal set ends deflate stream of each message
+    let mut data = vec![];
+    for text in [&b"hello"[..], b"world"] {
+        let mut payload = vec![0x01, text.len() as u8, 0, !(text.len() as u8), 0xff];
+        payload.extend_from_slice(text);
+        let mut frame = OwnedFrame::new(OpCode::Text, None, &payload);
+        frame.header_mut().set_rsv1(true);
+        data.extend_from_slice(&frame.header().0);
+        data.extend_from_slice(frame.payload());
+    }
+    let mut codec = server_codec(std::io::Cursor::new(data), Default::default());
+    let (header, payload) = codec.receive().unwrap();
+    assert_eq!(header.code, OpCode::Text);
+    assert_eq!(payload, b"hello");
+    let mut received = vec![];
+    codec
+        .receive_stream(|_, chunk| {
+            received.extend_from_slice(chunk);
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(received, b"world");
+}
+
+#[test]
+fn test_window_bits() {
+    let req = http::Request::builder()
+        .header(
+            "sec-websocket-extensions",
+            "permessage-deflate; client_max_window_bits=10; server_max_window_bits=12",
+        )
+        .body(())
+        .unwrap();
+    let codec = DeflateCodec::factory(req, std::io::Cursor::new(vec![])).unwrap();
+    assert_eq!(codec.window_bits(), Some((10, 12)));
+
+    let resp =
+        handshake_resp("permessage-deflate; client_max_window_bits=10; server_max_window_bits=12")
+            .body(())
+            .unwrap();
+    let codec =
+        DeflateCodec::check_fn(TEST_KEY.into(), resp, std::io::Cursor::new(vec![])).unwrap();
+    assert_eq!(codec.window_bits(), Some((12, 10)));
+
+    let req = http::Request::builder().body(()).unwrap();
+    let codec = DeflateCodec::factory(req, std::io::Cursor::new(vec![])).unwrap();
+    assert_eq!(codec.window_bits(), None);
+}
+
+#[test]
+fn test_compression_params() {
+    use super::{deflate_handshake_handler_with, CompressionParams};
+
+    let params = CompressionParams {
+        level: 1,
+        mem_level: 8,
+        strategy: CompressionStrategy::Rle,
+    };
+    let req = http::Request::builder()
+        .header("connection", "upgrade")
+        .header("upgrade", "websocket")
+        .header("sec-websocket-version", "13")
+        .header("sec-websocket-key", TEST_KEY)
+        .header("sec-websocket-extensions", "permessage-deflate")
+        .body(())
+        .unwrap();
+    let (req, _) = deflate_handshake_handler_with(params)(req).unwrap();
+    let codec = DeflateCodec::factory(req, std::io::Cursor::new(vec![])).unwrap();
+    let conf = codec.negotiated().deflate.as_ref().unwrap();
+    assert_eq!(CompressionParams::from(conf), params);
+
+    let resp = |params: CompressionParams| {
+        let mut resp = handshake_resp("permessage-deflate").body(()).unwrap();
+        resp.extensions_mut().insert(params);
+        resp
+    };
+    let codec = DeflateCodec::check_fn(TEST_KEY.into(), resp(params), std::io::Cursor::new(vec![]))
+        .unwrap();
+    let conf = codec.negotiated().deflate.as_ref().unwrap();
+    assert_eq!(CompressionParams::from(conf), params);
+
+    let invalid = CompressionParams {
+        level: 10,
+        ..Default::default()
+    };
+    assert!(matches!(
+        DeflateCodec::check_fn(TEST_KEY.into(), resp(invalid), std::io::Cursor::new(vec![])),
+        Err(WsError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_empty_messages() {
+    let data = [
+        // lone empty text frame
+        &[0x81, 0x00][..],
+        // empty text fragment and empty continuations
+        &[0x01, 0x00, 0x00, 0x00, 0x80, 0x00],
+        // compressed empty message
+        &[0xc1, 0x01, 0x00],
+        // next message is not swallowed
+        &[0x81, 0x01, b'x'],
+    ]
+    .concat();
+    let mut codec = server_codec(std::io::Cursor::new(data), Default::default());
+    for expect in [&b""[..], b"", b"", b"x"] {
+        let (header, payload) = codec.receive().unwrap();
+        assert!(header.fin);
+        assert_eq!(header.code, OpCode::Text);
+        assert_eq!(payload, expect);
+    }
+}
+
+#[test]
+fn test_negotiated_params() {
+    use crate::protocol::SubProtocol;
+
+    let mut req = http::Request::builder()
+        .header(
+            "sec-websocket-extensions",
+            "permessage-deflate; client_max_window_bits=10; server_max_window_bits=12",
+        )
+        .header("sec-websocket-protocol", "chat, superchat")
+        .body(())
+        .unwrap();
+    req.extensions_mut().insert(SubProtocol("chat".into()));
+    let codec = DeflateCodec::factory(req, std::io::Cursor::new(vec![])).unwrap();
+    let negotiated = codec.negotiated();
+    assert_eq!(negotiated.subprotocol.as_deref(), Some("chat"));
+    let deflate = negotiated.deflate.as_ref().unwrap();
+    assert_eq!(deflate.client_max_window_bits, super::WindowBit::Ten);
+    assert_eq!(deflate.server_max_window_bits, super::WindowBit::Twelve);
+
+    let resp = handshake_resp("permessage-deflate")
+        .header("sec-websocket-protocol", "superchat")
+        .body(())
+        .unwrap();
+    let codec =
+        DeflateCodec::check_fn(TEST_KEY.into(), resp, std::io::Cursor::new(vec![])).unwrap();
+    let negotiated = codec.negotiated();
+    assert_eq!(negotiated.subprotocol.as_deref(), Some("superchat"));
+    assert!(negotiated.deflate.is_some());
+
+    let req = http::Request::builder().body(()).unwrap();
+    let codec = DeflateCodec::factory(req, std::io::Cursor::new(vec![])).unwrap();
+    assert!(codec.negotiated().subprotocol.is_none());
+    assert!(codec.negotiated().deflate.is_none());
+}
+
+#[test]
+fn test_fragmented_compressed_message() {
+    use super::{WindowBit, ZLibCompressStream};
+    use crate::frame::OwnedFrame;
+
+    let text = "Hello, fragmented compressed world! ".repeat(20);
+    let raw_frame = |code, fin, rsv1, payload: &[u8]| {
+        let mut frame = OwnedFrame::new(code, None, payload);
+        frame.header_mut().set_fin(fin);
+        frame.header_mut().set_rsv1(rsv1);
+        let mut data = frame.header().0.to_vec();
+        data.extend_from_slice(frame.payload());
+        data
+    };
+
+    // like autobahn case 13.x, one deflate stream split into 3 frames,
+    // only first frame carries rsv1
+    let mut com = ZLibCompressStream::new(WindowBit::Fifteen);
+    let mut compressed = vec![];
+    com.compress(&[text.as_bytes()], &mut compressed).unwrap();
+    compressed.truncate(compressed.len() - 4);
+    let (first, rest) = compressed.split_at(compressed.len() / 3);
+    let (second, third) = rest.split_at(rest.len() / 2);
+    let mut data = raw_frame(OpCode::Text, false, true, first);
+    data.extend(raw_frame(OpCode::Continue, false, false, second));
+    data.extend(raw_frame(OpCode::Ping, true, false, b"hi"));
+    data.extend(raw_frame(OpCode::Continue, true, false, third));
+    let mut codec = server_codec(std::io::Cursor::new(data), Default::default());
+    let (header, payload) = codec.receive().unwrap();
+    assert_eq!(header.code, OpCode::Ping);
+    assert_eq!(payload, b"hi");
+    let (header, payload) = codec.receive().unwrap();
+    assert_eq!(header.code, OpCode::Text);
+    assert!(header.fin && !header.rsv1);
+    assert_eq!(payload, text.as_bytes());
+
+    // rsv1 on continuation frame is a protocol error
+    let mut data = raw_frame(OpCode::Text, false, true, first);
+    data.extend(raw_frame(OpCode::Continue, true, true, rest));
+    let mut codec = server_codec(std::io::Cursor::new(data), Default::default());
+    assert!(matches!(
+        codec.receive().unwrap_err(),
+        WsError::ProtocolError {
+            close_code: 1002,
+            error: ProtocolError::CompressedContinueFrame
+        }
+    ));
+
+    // auto fragmented message sets rsv1 on first frame only
+    let config = FrameConfig {
+        auto_fragment_size: 100,
+        ..Default::default()
+    };
+    let mut client = DeflateCodec::new(
+        std::io::Cursor::new(vec![]),
+        config,
+        Some(Default::default()),
+        false,
+    )
+    .unwrap();
+    client.send(OpCode::Text, text.as_bytes()).unwrap();
+    client.send(OpCode::Binary, b"second").unwrap();
+    let sent = client.stream_mut().get_ref().clone();
+    let frame_config = FrameConfig {
+        check_rsv: false,
+        merge_frame: false,
+        validate_utf8: crate::codec::ValidateUtf8Policy::Off,
+        ..Default::default()
+    };
+    let mut frames =
+        crate::codec::FrameCodec::new_with(std::io::Cursor::new(sent.clone()), frame_config);
+    let mut first = true;
+    loop {
+        let (header, _) = frames.receive().unwrap();
+        assert_eq!(header.code == OpCode::Text, first);
+        assert_eq!(header.rsv1, first);
+        first = false;
+        if header.fin {
+            break;
+        }
+    }
+    let mut server = server_codec(std::io::Cursor::new(sent), Default::default());
+    let (header, payload) = server.receive().unwrap();
+    assert_eq!(header.code, OpCode::Text);
+    assert_eq!(payload, text.as_bytes());
+    let (header, payload) = server.receive().unwrap();
+    assert_eq!(header.code, OpCode::Binary);
+    assert_eq!(payload, b"second");
+}
+
+#[test]
+fn test_close_echo_once() {
+    struct Peer {
+        input: std::io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Peer {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Peer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let close_count = |data: &[u8]| {
+        let mut state = crate::codec::FrameReadState::default();
+        let mut stream = data;
+        let mut count = 0;
+        while !stream.is_empty() {
+            let (header, _) = state.receive(&mut stream).unwrap();
+            assert_eq!(header.code, OpCode::Close);
+            count += 1;
+        }
+        count
+    };
+
+    for close_first in [false, true] {
+        let peer = Peer {
+            input: std::io::Cursor::new(vec![0x88, 0x02, 0x03, 0xe8]),
+            output: vec![],
+        };
+        let mut codec = server_codec(peer, Default::default());
+        if close_first {
+            codec.close(1001, b"").unwrap();
+        }
+        assert!(matches!(
+            codec.receive_message().unwrap(),
+            Event::Closed(CloseEvent::Status(1000))
+        ));
+        assert_eq!(close_count(&codec.stream.output), 1);
+    }
+}
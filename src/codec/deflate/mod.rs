@@ -14,6 +14,8 @@ pub const CLIENT_NO_CONTEXT_TAKEOVER: &str = "client_no_context_takeover";
 pub const SERVER_MAX_WINDOW_BITS: &str = "server_max_window_bits";
 /// client_max_window_bits param
 pub const CLIENT_MAX_WINDOW_BITS: &str = "client_max_window_bits";
+/// legacy webkit deflate extension id, not supported
+pub const WEBKIT_DEFLATE_FRAME: &str = "x-webkit-deflate-frame";
 
 /// zlib version
 pub const ZLIB_VERSION: &str = "1.2.13\0";
@@ -22,19 +24,51 @@ pub const ZLIB_VERSION: &str = "1.2.13\0";
 mod blocking;
 #[cfg(feature = "sync")]
 pub use blocking::*;
-use libz_sys::{Z_BUF_ERROR, Z_NO_FLUSH, Z_OK, Z_SYNC_FLUSH};
+use libz_sys::{Z_BUF_ERROR, Z_NO_FLUSH, Z_OK, Z_STREAM_END, Z_SYNC_FLUSH};
 
-#[cfg(feature = "async")]
+#[cfg(feature = "async_codec")]
 mod non_blocking;
-#[cfg(feature = "async")]
+#[cfg(feature = "async_codec")]
 pub use non_blocking::*;
 
-use crate::{errors::WsError, frame::OpCode};
+use crate::{
+    errors::{ProtocolError, WsError},
+    frame::{OpCode, SimplifiedHeader},
+    protocol::SubProtocol,
+};
 
 use super::{
-    default_handshake_handler, FrameConfig, FrameReadState, FrameWriteState, ValidateUtf8Policy,
+    default_handshake_handler, frame::Utf8Stream, FrameConfig, FrameReadState, FrameWriteState,
+    PayloadLoc, ValidateUtf8Policy,
 };
 
+/// size of buf used by streaming decompression
+const STREAM_CHUNK_SIZE: usize = 16 * 1024;
+
+/// fail with 1009 protocol error if decompressed size exceeds `max_size`,
+/// 0 means no limit
+#[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+fn check_decompressed_size(max_size: usize, size: usize) -> Result<(), WsError> {
+    if max_size > 0 && size > max_size {
+        return Err(WsError::ProtocolError {
+            close_code: 1009,
+            error: ProtocolError::PayloadTooLarge(max_size),
+        });
+    }
+    Ok(())
+}
+
+#[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+fn map_decompress_err(e: DeCompressError<WsError>) -> WsError {
+    match e {
+        DeCompressError::ZLib(code) => WsError::ProtocolError {
+            close_code: 1002,
+            error: ProtocolError::DecompressFailed(code),
+        },
+        DeCompressError::Chunk(e) => e,
+    }
+}
+
 /// permessage-deflate window bit
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(i8)]
@@ -63,6 +97,40 @@ impl TryFrom<u8> for WindowBit {
     }
 }
 
+/// parameters negotiated during handshake
+#[derive(Debug, Clone, Default)]
+pub struct NegotiatedParams {
+    /// selected subprotocol, `None` if no subprotocol is selected
+    pub subprotocol: Option<String>,
+    /// effective permessage-deflate config, `None` if deflate is not enabled
+    pub deflate: Option<PMDConfig>,
+}
+
+impl NegotiatedParams {
+    /// read subprotocol from `SubProtocol` extension of handshake request
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    pub(crate) fn from_req(req: &http::Request<()>, deflate: Option<PMDConfig>) -> Self {
+        Self {
+            subprotocol: req.extensions().get::<SubProtocol>().map(|p| p.0.clone()),
+            deflate,
+        }
+    }
+
+    /// read subprotocol from `Sec-WebSocket-Protocol` header of handshake response
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    pub(crate) fn from_resp(resp: &http::Response<()>, deflate: Option<PMDConfig>) -> Self {
+        let subprotocol = resp
+            .headers()
+            .get(http::header::SEC_WEBSOCKET_PROTOCOL)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().to_string());
+        Self {
+            subprotocol,
+            deflate,
+        }
+    }
+}
+
 /// permessage-deflate req handler
 pub fn deflate_handshake_handler(
     req: http::Request<()>,
@@ -92,19 +160,41 @@ pub fn deflate_handshake_handler(
     if let Some(config) = configs.pop() {
         resp.headers_mut().insert(
             "sec-websocket-extensions",
-            http::HeaderValue::from_str(&config.ext_string()).unwrap(),
+            http::HeaderValue::from_str(&config.response_ext_string()).unwrap(),
         );
     }
     Ok((req, resp))
 }
 
+/// same as `deflate_handshake_handler`, and insert `params` into request
+/// extensions, compressor of codec constructed by `factory` uses them
+#[allow(clippy::type_complexity, clippy::result_large_err)]
+pub fn deflate_handshake_handler_with(
+    params: CompressionParams,
+) -> impl FnMut(
+    http::Request<()>,
+) -> Result<
+    (http::Request<()>, http::Response<String>),
+    (http::Response<String>, WsError),
+> + Clone {
+    move |req| {
+        let (mut req, resp) = deflate_handshake_handler(req)?;
+        req.extensions_mut().insert(params);
+        Ok((req, resp))
+    }
+}
+
 fn gen_low_level_config(conf: &FrameConfig) -> FrameConfig {
     FrameConfig {
         mask_send_frame: conf.mask_send_frame,
+        write_buf_capacity: conf.write_buf_capacity,
         check_rsv: false,
         auto_fragment_size: conf.auto_fragment_size,
+        max_frame_payload_size: conf.max_frame_payload_size,
         merge_frame: false,
         validate_utf8: ValidateUtf8Policy::Off,
+        strict_ordering: conf.strict_ordering,
+        on_frame: conf.on_frame.clone(),
         ..Default::default()
     }
 }
@@ -125,7 +215,38 @@ pub struct ReadStreamHandler {
     pub de: ZLibDeCompressStream,
 }
 
+/// zlib compression strategy of compressor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompressionStrategy {
+    /// normal data
+    #[default]
+    Default,
+    /// data produced by a filter, favor huffman coding over string matching
+    Filtered,
+    /// huffman coding only, no string matching
+    HuffmanOnly,
+    /// limit match distance to one, run-length encoding
+    Rle,
+    /// prevent use of dynamic huffman codes
+    Fixed,
+}
+
+impl CompressionStrategy {
+    fn as_raw(self) -> c_int {
+        match self {
+            CompressionStrategy::Default => libz_sys::Z_DEFAULT_STRATEGY,
+            CompressionStrategy::Filtered => libz_sys::Z_FILTERED,
+            CompressionStrategy::HuffmanOnly => libz_sys::Z_HUFFMAN_ONLY,
+            CompressionStrategy::Rle => libz_sys::Z_RLE,
+            CompressionStrategy::Fixed => libz_sys::Z_FIXED,
+        }
+    }
+}
+
 /// permessage-deflate
+///
+/// `compression_level`, `mem_level` and `strategy` only apply to local
+/// compressor, they are not negotiated with peer
 #[allow(missing_docs)]
 #[derive(Debug, Clone)]
 pub struct PMDConfig {
@@ -133,6 +254,12 @@ pub struct PMDConfig {
     pub client_no_context_takeover: bool,
     pub server_max_window_bits: WindowBit,
     pub client_max_window_bits: WindowBit,
+    /// compression level 0-9, 0 means store only, default 9
+    pub compression_level: u32,
+    /// memory used by compressor 1-9, default 9
+    pub mem_level: u32,
+    /// compression strategy, default `CompressionStrategy::Default`
+    pub strategy: CompressionStrategy,
 }
 
 impl Default for PMDConfig {
@@ -142,11 +269,76 @@ impl Default for PMDConfig {
             client_no_context_takeover: false,
             server_max_window_bits: WindowBit::Fifteen,
             client_max_window_bits: WindowBit::Fifteen,
+            compression_level: 9,
+            mem_level: 9,
+            strategy: CompressionStrategy::Default,
+        }
+    }
+}
+
+/// local compressor parameters, they are not negotiated with peer
+///
+/// `ClientBuilder::deflate` inserts it into handshake response extensions and
+/// `deflate_handshake_handler_with` into handshake request extensions,
+/// `check_fn`/`factory` of deflate codecs apply it to negotiated config
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionParams {
+    /// compression level 0-9, 0 means store only, default 9
+    pub level: u32,
+    /// memory used by compressor 1-9, default 9
+    pub mem_level: u32,
+    /// compression strategy, default `CompressionStrategy::Default`
+    pub strategy: CompressionStrategy,
+}
+
+impl Default for CompressionParams {
+    fn default() -> Self {
+        Self {
+            level: 9,
+            mem_level: 9,
+            strategy: CompressionStrategy::Default,
         }
     }
 }
 
+impl From<&PMDConfig> for CompressionParams {
+    fn from(config: &PMDConfig) -> Self {
+        Self {
+            level: config.compression_level,
+            mem_level: config.mem_level,
+            strategy: config.strategy,
+        }
+    }
+}
+
+/// apply `CompressionParams` in handshake extensions to negotiated config
+#[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+fn apply_compression_params(config: &mut Option<PMDConfig>, extensions: &http::Extensions) {
+    if let (Some(config), Some(params)) = (config.as_mut(), extensions.get::<CompressionParams>()) {
+        config.compression_level = params.level;
+        config.mem_level = params.mem_level;
+        config.strategy = params.strategy;
+    }
+}
+
 impl PMDConfig {
+    /// check compression level and memory level are in range
+    pub fn validate(&self) -> Result<(), WsError> {
+        if self.compression_level > 9 {
+            return Err(WsError::InvalidConfig(format!(
+                "compression level should be 0-9, got {}",
+                self.compression_level
+            )));
+        }
+        if !(1..=9).contains(&self.mem_level) {
+            return Err(WsError::InvalidConfig(format!(
+                "memory level should be 1-9, got {}",
+                self.mem_level
+            )));
+        }
+        Ok(())
+    }
+
     /// get extension string
     pub fn ext_string(&self) -> String {
         let mut s = format!("{EXT_ID};");
@@ -171,6 +363,54 @@ impl PMDConfig {
         s
     }
 
+    /// get extension string sent back by server to accept this offer
+    ///
+    /// window bits are omitted unless they are less than 15, since a client
+    /// which did not offer `client_max_window_bits` rejects a response
+    /// carrying it
+    pub fn response_ext_string(&self) -> String {
+        let mut params = vec![EXT_ID.to_string()];
+        if self.client_no_context_takeover {
+            params.push(CLIENT_NO_CONTEXT_TAKEOVER.to_string());
+        }
+        if self.server_no_context_takeover {
+            params.push(SERVER_NO_CONTEXT_TAKEOVER.to_string());
+        }
+        if self.client_max_window_bits != WindowBit::Fifteen {
+            params.push(format!(
+                "{CLIENT_MAX_WINDOW_BITS}={}",
+                self.client_max_window_bits as u8
+            ));
+        }
+        if self.server_max_window_bits != WindowBit::Fifteen {
+            params.push(format!(
+                "{SERVER_MAX_WINDOW_BITS}={}",
+                self.server_max_window_bits as u8
+            ));
+        }
+        params.join("; ")
+    }
+
+    /// whether local compressor must be reset after every message
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    pub(crate) fn reset_compressor(&self, is_server: bool) -> bool {
+        if is_server {
+            self.server_no_context_takeover
+        } else {
+            self.client_no_context_takeover
+        }
+    }
+
+    /// whether peer compressor is reset after every message, so is local decompressor
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    pub(crate) fn reset_decompressor(&self, is_server: bool) -> bool {
+        if is_server {
+            self.client_no_context_takeover
+        } else {
+            self.server_no_context_takeover
+        }
+    }
+
     /// helper function to build multi permessage deflate config header
     pub fn multi_ext_string(configs: &[PMDConfig]) -> String {
         configs
@@ -184,6 +424,8 @@ impl PMDConfig {
 ///
 pub struct ZLibDeCompressStream {
     stream: Box<libz_sys::z_stream>,
+    // final deflate block is reached, reset before next message
+    finished: bool,
 }
 
 unsafe impl Send for ZLibDeCompressStream {}
@@ -219,12 +461,16 @@ impl ZLibDeCompressStream {
         assert!(result == libz_sys::Z_OK, "Failed to initialize compresser.");
         Self {
             stream: unsafe { Box::from_raw(Box::into_raw(stream) as *mut libz_sys::z_stream) },
+            finished: false,
         }
     }
 
     /// construct with custom stream
     pub fn with(stream: Box<libz_sys::z_stream>) -> Self {
-        Self { stream }
+        Self {
+            stream,
+            finished: false,
+        }
     }
 
     /// decompress data
@@ -275,16 +521,76 @@ impl ZLibDeCompressStream {
         Ok(())
     }
 
+    /// decompress data into `buf` chunk by chunk, `on_chunk` is called every time
+    /// `buf` is filled or input is consumed, so output is never fully buffered
+    ///
+    /// set `flush` for last input of a message, return decompressed len
+    ///
+    /// input after a final deflate block is dropped, call `reset` before
+    /// next message, see `is_finished`
+    pub fn de_compress_chunked<E>(
+        &mut self,
+        input: &[u8],
+        flush: bool,
+        buf: &mut [u8],
+        mut on_chunk: impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<usize, DeCompressError<E>> {
+        let mode = if flush { Z_SYNC_FLUSH } else { Z_NO_FLUSH };
+        let mut total = 0;
+        self.stream.next_in = input.as_ptr() as *mut _;
+        self.stream.avail_in = input.len() as c_uint;
+        loop {
+            self.stream.next_out = buf.as_mut_ptr();
+            self.stream.avail_out = buf.len() as c_uint;
+            let code = unsafe { libz_sys::inflate(self.stream.as_mut(), mode) };
+            if !matches!(code, Z_OK | Z_BUF_ERROR | Z_STREAM_END) {
+                return Err(DeCompressError::ZLib(code));
+            }
+            let produced = buf.len() - self.stream.avail_out as usize;
+            if produced > 0 {
+                total += produced;
+                on_chunk(&buf[..produced]).map_err(DeCompressError::Chunk)?;
+            }
+            // inflate makes no progress after stream end
+            if code == Z_STREAM_END {
+                self.finished = true;
+                break;
+            }
+            // output buf not filled means no pending output
+            if (self.stream.avail_in == 0 && self.stream.avail_out > 0) || produced == 0 {
+                break;
+            }
+        }
+        Ok(total)
+    }
+
+    /// whether final deflate block has been decompressed
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
     /// reset stream state
     pub fn reset(&mut self) -> Result<(), c_int> {
         let code = unsafe { libz_sys::inflateReset(self.stream.as_mut()) };
         match code {
-            Z_OK => Ok(()),
+            Z_OK => {
+                self.finished = false;
+                Ok(())
+            }
             code => Err(code),
         }
     }
 }
 
+/// error of `ZLibDeCompressStream::de_compress_chunked`
+#[derive(Debug)]
+pub enum DeCompressError<E> {
+    /// zlib error code
+    ZLib(c_int),
+    /// error returned by chunk callback
+    Chunk(E),
+}
+
 /// zlib compress stream
 pub struct ZLibCompressStream {
     stream: Box<libz_sys::z_stream>,
@@ -311,15 +617,27 @@ impl Drop for ZLibCompressStream {
 impl ZLibCompressStream {
     /// construct with window bit
     pub fn new(window: WindowBit) -> Self {
+        Self::with_params(window, 9, 9, CompressionStrategy::Default)
+    }
+
+    /// construct with window bit, compression level, memory level and strategy
+    ///
+    /// **NOTE** panic if level or memory level is out of range, see `PMDConfig::validate`
+    pub fn with_params(
+        window: WindowBit,
+        level: u32,
+        mem_level: u32,
+        strategy: CompressionStrategy,
+    ) -> Self {
         let mut stream: Box<MaybeUninit<libz_sys::z_stream>> = Box::new(MaybeUninit::zeroed());
         let result = unsafe {
             libz_sys::deflateInit2_(
                 stream.as_mut_ptr(),
-                9,
+                level as c_int,
                 libz_sys::Z_DEFLATED,
                 -(window as i8) as c_int,
-                9,
-                libz_sys::Z_DEFAULT_STRATEGY,
+                mem_level as c_int,
+                strategy.as_raw(),
                 ZLIB_VERSION.as_ptr() as *const c_char,
                 mem::size_of::<libz_sys::z_stream>() as c_int,
             )
@@ -338,8 +656,9 @@ impl ZLibCompressStream {
     /// compress data
     pub fn compress(&mut self, inputs: &[&[u8]], output: &mut Vec<u8>) -> Result<(), c_int> {
         let total_input: usize = inputs.iter().map(|i| i.len()).sum();
-        if total_input > output.capacity() * 2 + 4 {
-            output.resize(total_input * 2 + 4, 0);
+        // store only level output is a bit larger than input
+        if total_input * 2 + 16 > output.capacity() {
+            output.resize(total_input * 2 + 16, 0);
         }
         let mut write_idx = 0;
         let mut total_remain = total_input;
@@ -393,6 +712,17 @@ impl ZLibCompressStream {
             code => Err(code),
         }
     }
+
+    /// change compression level and strategy, take effect from next `compress` call
+    pub fn set_params(&mut self, level: u32, strategy: CompressionStrategy) -> Result<(), c_int> {
+        let code = unsafe {
+            libz_sys::deflateParams(self.stream.as_mut(), level as c_int, strategy.as_raw())
+        };
+        match code {
+            Z_OK => Ok(()),
+            code => Err(code),
+        }
+    }
 }
 
 #[derive(Default)]
@@ -412,7 +742,12 @@ impl PMDConfig {
         }
         let mut configs = vec![];
         for part in source.split(',') {
-            if part.trim_start().to_lowercase().starts_with(EXT_ID) {
+            let ext_id = part.split(';').next().unwrap_or_default().trim();
+            if ext_id.eq_ignore_ascii_case(WEBKIT_DEFLATE_FRAME) {
+                tracing::debug!("ignore legacy extension {WEBKIT_DEFLATE_FRAME}");
+                continue;
+            }
+            if ext_id.eq_ignore_ascii_case(EXT_ID) {
                 let mut conf = Self::default();
                 let mut counter = PMDParamCounter::default();
                 for param in part.split(';').skip(1) {
@@ -511,32 +846,79 @@ pub struct DeflateWriteState {
 }
 
 impl DeflateWriteState {
-    /// construct with config
+    /// window bits compressor was initialized with, `None` if deflate is not enabled
+    pub fn window_bits(&self) -> Option<WindowBit> {
+        self.com.as_ref().map(|handler| {
+            if self.is_server {
+                handler.config.server_max_window_bits
+            } else {
+                handler.config.client_max_window_bits
+            }
+        })
+    }
+
+    /// construct with config, fail with `WsError::InvalidConfig` if
+    /// `pmd_config` is invalid
     pub fn with_config(
         frame_config: FrameConfig,
         pmd_config: Option<PMDConfig>,
         is_server: bool,
-    ) -> Self {
+    ) -> Result<Self, WsError> {
         let low_level_config = gen_low_level_config(&frame_config);
         let write_state = FrameWriteState::with_config(low_level_config);
         let com = if let Some(config) = pmd_config {
+            config.validate()?;
             let com_size = if is_server {
-                config.client_max_window_bits
-            } else {
                 config.server_max_window_bits
+            } else {
+                config.client_max_window_bits
             };
-            let com = ZLibCompressStream::new(com_size);
+            let com = ZLibCompressStream::with_params(
+                com_size,
+                config.compression_level,
+                config.mem_level,
+                config.strategy,
+            );
             Some(WriteStreamHandler { config, com })
         } else {
             None
         };
-        Self {
+        Ok(Self {
             write_state,
             com,
             config: frame_config,
             header_buf: [0; 14],
             is_server,
-        }
+        })
+    }
+
+    /// set masking key generator of outgoing frames, see `FrameWriteState::set_mask_fn`
+    pub fn set_mask_fn<F: FnMut() -> [u8; 4] + Send + 'static>(&mut self, f: F) {
+        self.write_state.set_mask_fn(f);
+    }
+
+    /// change compression level(0-9) and strategy of compressor, do nothing
+    /// if deflate is not enabled
+    pub fn set_compression(
+        &mut self,
+        level: u32,
+        strategy: CompressionStrategy,
+    ) -> Result<(), WsError> {
+        let Some(handler) = self.com.as_mut() else {
+            return Ok(());
+        };
+        let config = PMDConfig {
+            compression_level: level,
+            strategy,
+            ..handler.config.clone()
+        };
+        config.validate()?;
+        handler
+            .com
+            .set_params(level, strategy)
+            .map_err(|code| WsError::CompressFailed(format!("set params failed, code {code}")))?;
+        handler.config = config;
+        Ok(())
     }
 }
 
@@ -549,19 +931,48 @@ pub struct DeflateReadState {
     fragmented_data: Vec<u8>,
     control_buf: Vec<u8>,
     fragmented_type: OpCode,
+    fragmented_compressed: bool,
     is_server: bool,
+    utf8_stream: Option<Utf8Stream>,
+    stream_type: Option<OpCode>,
+    stream_compressed: bool,
+    stream_size: usize,
+    chunk_buf: Vec<u8>,
 }
 
 impl DeflateReadState {
-    /// construct with config
+    /// number of bytes read from stream but not parsed yet
+    pub fn buffered_len(&self) -> usize {
+        self.read_state.buffered_len()
+    }
+
+    /// max number of unparsed bytes ever buffered
+    pub fn buffered_high_water_mark(&self) -> usize {
+        self.read_state.buffered_high_water_mark()
+    }
+
+    /// window bits decompressor was initialized with, `None` if deflate is not enabled
+    pub fn window_bits(&self) -> Option<WindowBit> {
+        self.de.as_ref().map(|handler| {
+            if self.is_server {
+                handler.config.client_max_window_bits
+            } else {
+                handler.config.server_max_window_bits
+            }
+        })
+    }
+
+    /// construct with config, fail with `WsError::InvalidConfig` if
+    /// `pmd_config` is invalid
     pub fn with_config(
         frame_config: FrameConfig,
         pmd_config: Option<PMDConfig>,
         is_server: bool,
-    ) -> Self {
+    ) -> Result<Self, WsError> {
         let low_level_config = gen_low_level_config(&frame_config);
         let read_state = FrameReadState::with_config(low_level_config);
         let de = if let Some(config) = pmd_config {
+            config.validate()?;
             let de_size = if is_server {
                 config.client_max_window_bits
             } else {
@@ -572,7 +983,7 @@ impl DeflateReadState {
         } else {
             None
         };
-        Self {
+        Ok(Self {
             read_state,
             de,
             config: frame_config,
@@ -580,7 +991,299 @@ impl DeflateReadState {
             fragmented_data: vec![],
             control_buf: vec![],
             fragmented_type: OpCode::Binary,
+            fragmented_compressed: false,
             is_server,
+            utf8_stream: None,
+            stream_type: None,
+            stream_compressed: false,
+            stream_size: 0,
+            chunk_buf: vec![],
+        })
+    }
+
+    /// check rsv1 of a received frame, return whether payload belongs to a
+    /// compressed message, only first frame of a fragmented message carries rsv1
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    fn is_compressed(&mut self, header: &SimplifiedHeader) -> Result<bool, WsError> {
+        match header.code {
+            OpCode::Text | OpCode::Binary => {
+                if header.rsv1 && self.de.is_none() {
+                    return Err(WsError::DeCompressFailed(
+                        "extension not enabled but got compressed frame".into(),
+                    ));
+                }
+                self.fragmented_compressed = header.rsv1 && !header.fin;
+                Ok(header.rsv1)
+            }
+            OpCode::Continue => {
+                if header.rsv1 {
+                    return Err(WsError::ProtocolError {
+                        close_code: 1002,
+                        error: ProtocolError::CompressedContinueFrame,
+                    });
+                }
+                let compressed = self.fragmented_compressed;
+                if header.fin {
+                    self.fragmented_compressed = false;
+                }
+                Ok(compressed)
+            }
+            _ => {
+                if header.rsv1 {
+                    return Err(WsError::ProtocolError {
+                        close_code: 1002,
+                        error: ProtocolError::CompressedControlFrame,
+                    });
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    /// decompress payload of a compressed message, if `fin` is true, payload
+    /// is the end of message and trailing `0x00 0x00 0xff 0xff` is appended
+    ///
+    /// payload is decompressed in chunks, so it fails with 1009 protocol error
+    /// as soon as output exceeds `max_decompressed_size` or `max_message_size`
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    fn inflate(&mut self, data: &[u8], fin: bool) -> Result<Vec<u8>, WsError> {
+        let handler = self.de.as_mut().ok_or_else(|| {
+            WsError::DeCompressFailed("extension not enabled but got compressed frame".into())
+        })?;
+        let config = &self.config;
+        let mut de_data = vec![];
+        let mut emit = |chunk: &[u8]| {
+            let size = de_data.len() + chunk.len();
+            check_decompressed_size(config.max_decompressed_size, size)?;
+            config.check_message_size(size)?;
+            de_data.extend_from_slice(chunk);
+            Ok(())
+        };
+        self.chunk_buf.resize(STREAM_CHUNK_SIZE, 0);
+        // flush every frame of a message so its output is not withheld
+        handler
+            .de
+            .de_compress_chunked(data, !fin, &mut self.chunk_buf, &mut emit)
+            .map_err(map_decompress_err)?;
+        if fin {
+            handler
+                .de
+                .de_compress_chunked(&[0, 0, 255, 255], true, &mut self.chunk_buf, &mut emit)
+                .map_err(map_decompress_err)?;
+        }
+        if fin && (handler.config.reset_decompressor(self.is_server) || handler.de.is_finished()) {
+            handler
+                .de
+                .reset()
+                .map_err(|code| WsError::DeCompressFailed(code.to_string()))?;
+            tracing::trace!("reset decompressor state");
+        }
+        Ok(de_data)
+    }
+
+    /// pass payload of a raw frame to `on_chunk`, decompress it chunk by chunk
+    /// if message is compressed
+    ///
+    /// fail with 1009 protocol error as soon as message exceeds
+    /// `max_message_size`, or its decompressed output exceeds
+    /// `max_decompressed_size`, return header if a message is completed
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    fn stream_frame<F>(
+        &mut self,
+        mut header: SimplifiedHeader,
+        loc: PayloadLoc,
+        on_chunk: &mut F,
+    ) -> Result<Option<SimplifiedHeader>, WsError>
+    where
+        F: FnMut(OpCode, &[u8]) -> Result<(), WsError>,
+    {
+        let data = self.read_state.payload(&loc);
+        match header.code {
+            OpCode::Close | OpCode::Ping | OpCode::Pong => {
+                if header.rsv1 {
+                    return Err(WsError::ProtocolError {
+                        close_code: 1002,
+                        error: ProtocolError::CompressedControlFrame,
+                    });
+                }
+                on_chunk(header.code, data)?;
+                return Ok(Some(header));
+            }
+            OpCode::Text | OpCode::Binary => {
+                if self.stream_type.is_some() {
+                    return Err(WsError::ProtocolError {
+                        close_code: 1002,
+                        error: ProtocolError::NotContinueFrameAfterFragmented,
+                    });
+                }
+                self.stream_type = Some(header.code);
+                self.stream_compressed = header.rsv1;
+                self.stream_size = 0;
+            }
+            OpCode::Continue if header.rsv1 => {
+                return Err(WsError::ProtocolError {
+                    close_code: 1002,
+                    error: ProtocolError::CompressedContinueFrame,
+                })
+            }
+            OpCode::Continue => match self.stream_type {
+                Some(code) => header.code = code,
+                None => {
+                    return Err(WsError::ProtocolError {
+                        close_code: 1002,
+                        error: ProtocolError::MissInitialFragmentedFrame,
+                    })
+                }
+            },
+            _ => return Err(WsError::UnsupportedFrame(header.code)),
+        }
+        if header.fin {
+            self.stream_type = None;
+        }
+        let code = header.code;
+        if !self.stream_compressed {
+            self.stream_size += data.len();
+            self.config.check_message_size(self.stream_size)?;
+            on_chunk(code, data)?;
+            return Ok(header.fin.then_some(header));
+        }
+        let handler = self.de.as_mut().ok_or_else(|| {
+            WsError::DeCompressFailed("extension not enabled but got compressed frame".into())
+        })?;
+        let config = &self.config;
+        let stream_size = &mut self.stream_size;
+        let mut emit = |chunk: &[u8]| {
+            *stream_size += chunk.len();
+            check_decompressed_size(config.max_decompressed_size, *stream_size)?;
+            config.check_message_size(*stream_size)?;
+            on_chunk(code, chunk)
+        };
+        self.chunk_buf.resize(STREAM_CHUNK_SIZE, 0);
+        handler
+            .de
+            .de_compress_chunked(data, false, &mut self.chunk_buf, &mut emit)
+            .map_err(map_decompress_err)?;
+        if !header.fin {
+            return Ok(None);
+        }
+        handler
+            .de
+            .de_compress_chunked(&[0, 0, 255, 255], true, &mut self.chunk_buf, &mut emit)
+            .map_err(map_decompress_err)?;
+        if handler.config.reset_decompressor(self.is_server) || handler.de.is_finished() {
+            handler
+                .de
+                .reset()
+                .map_err(|code| WsError::DeCompressFailed(code.to_string()))?;
+            tracing::trace!("reset decompressor state");
         }
+        header.rsv1 = false;
+        Ok(Some(header))
     }
 }
+
+#[test]
+fn test_legacy_webkit_deflate_frame() {
+    assert!(PMDConfig::parse_str(WEBKIT_DEFLATE_FRAME)
+        .unwrap()
+        .is_empty());
+    assert!(PMDConfig::parse_str("permessage-deflate-frame")
+        .unwrap()
+        .is_empty());
+    let configs =
+        PMDConfig::parse_str("x-webkit-deflate-frame, permessage-deflate; client_max_window_bits")
+            .unwrap();
+    assert_eq!(configs.len(), 1);
+
+    let req = http::Request::builder()
+        .header("upgrade", "websocket")
+        .header("sec-websocket-key", crate::protocol::gen_key())
+        .header("sec-websocket-version", "13")
+        .header("sec-websocket-extensions", WEBKIT_DEFLATE_FRAME)
+        .body(())
+        .unwrap();
+    let (_, resp) = deflate_handshake_handler(req).unwrap();
+    assert!(resp.headers().get("sec-websocket-extensions").is_none());
+}
+
+#[test]
+fn test_response_ext_string() {
+    let handshake = |ext: &str| {
+        let req = http::Request::builder()
+            .header("upgrade", "websocket")
+            .header("sec-websocket-key", crate::protocol::gen_key())
+            .header("sec-websocket-version", "13")
+            .header("sec-websocket-extensions", ext)
+            .body(())
+            .unwrap();
+        let (_, resp) = deflate_handshake_handler(req).unwrap();
+        resp.headers()["sec-websocket-extensions"]
+            .to_str()
+            .unwrap()
+            .to_string()
+    };
+    assert_eq!(handshake("permessage-deflate"), "permessage-deflate");
+    assert_eq!(
+        handshake("permessage-deflate; client_max_window_bits"),
+        "permessage-deflate"
+    );
+    assert_eq!(
+        handshake(
+            "permessage-deflate; client_no_context_takeover; client_max_window_bits=10; server_max_window_bits=12"
+        ),
+        "permessage-deflate; client_no_context_takeover; client_max_window_bits=10; server_max_window_bits=12"
+    );
+}
+
+#[test]
+fn test_compression_level() {
+    let data = "hello world ".repeat(100);
+    let compress = |level, strategy| {
+        let mut com = ZLibCompressStream::with_params(WindowBit::Fifteen, level, 8, strategy);
+        let mut output = Vec::with_capacity(data.len());
+        com.compress(&[data.as_bytes()], &mut output).unwrap();
+        output
+    };
+    let stored = compress(0, CompressionStrategy::Default);
+    let best = compress(9, CompressionStrategy::Default);
+    assert!(stored.len() > data.len());
+    assert!(best.len() < data.len() / 10);
+    for output in [stored, best, compress(1, CompressionStrategy::HuffmanOnly)] {
+        let mut de = ZLibDeCompressStream::new(WindowBit::Fifteen);
+        let mut decompressed = Vec::with_capacity(data.len());
+        de.de_compress(&[&output], &mut decompressed).unwrap();
+        assert_eq!(decompressed, data.as_bytes());
+    }
+    let mut com = ZLibCompressStream::with_params(WindowBit::Fifteen, 0, 9, Default::default());
+    let mut output = Vec::with_capacity(1);
+    com.compress(&[b"a"], &mut output).unwrap();
+    let mut de = ZLibDeCompressStream::new(WindowBit::Fifteen);
+    let mut decompressed = vec![];
+    de.de_compress(&[&output], &mut decompressed).unwrap();
+    assert_eq!(decompressed, b"a");
+
+    let mut state =
+        DeflateWriteState::with_config(FrameConfig::default(), Some(PMDConfig::default()), false)
+            .unwrap();
+    state
+        .set_compression(0, CompressionStrategy::Default)
+        .unwrap();
+    assert!(matches!(
+        state.set_compression(10, CompressionStrategy::Default),
+        Err(WsError::InvalidConfig(_))
+    ));
+    let conf = PMDConfig {
+        mem_level: 0,
+        ..Default::default()
+    };
+    assert!(matches!(conf.validate(), Err(WsError::InvalidConfig(_))));
+    assert!(matches!(
+        DeflateWriteState::with_config(FrameConfig::default(), Some(conf.clone()), false),
+        Err(WsError::InvalidConfig(_))
+    ));
+    assert!(matches!(
+        DeflateReadState::with_config(FrameConfig::default(), Some(conf), false),
+        Err(WsError::InvalidConfig(_))
+    ));
+    assert!(PMDConfig::default().validate().is_ok());
+}
@@ -1,15 +1,22 @@
 use http;
 use crate::{
-    codec::{apply_mask, FrameConfig, Split},
+    codec::{
+        apply_mask,
+        frame::{check_close, check_utf8},
+        Direction, FrameConfig, Split,
+    },
     errors::{ProtocolError, WsError},
     frame::{ctor_header, OpCode, OwnedFrame, SimplifiedHeader},
     protocol::standard_handshake_resp_check,
+    CloseEvent, Event,
 };
 use bytes::BytesMut;
-use rand::random;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 
-use super::{DeflateReadState, DeflateWriteState, PMDConfig};
+use super::{
+    apply_compression_params, CompressionStrategy, DeflateReadState, DeflateWriteState,
+    NegotiatedParams, PMDConfig, Utf8Stream,
+};
 
 impl DeflateWriteState {
     /// send a read frame, **this method will not check validation of frame and do not fragment**
@@ -23,7 +30,7 @@ impl DeflateWriteState {
                 .write_state
                 .async_send_owned_frame(stream, frame)
                 .await
-                .map_err(WsError::IOError);
+                .map_err(WsError::from);
         }
         let prev_mask = frame.unmask();
         let header = frame.header();
@@ -38,15 +45,18 @@ impl DeflateWriteState {
                     .com
                     .compress(&[frame.payload()], &mut compressed)
                     .map_err(|code| WsError::CompressFailed(code.to_string()))?;
-                compressed.truncate(compressed.len() - 4);
+                // fragments of a message share one deflate stream, only
+                // first fragment carries rsv1
+                let fin = header.fin();
+                if fin {
+                    compressed.truncate(compressed.len() - 4);
+                }
                 let mut new = OwnedFrame::new(header.opcode(), prev_mask, &compressed);
-                let header = new.header_mut();
-                header.set_rsv1(true);
-                header.set_fin(header.fin());
+                let new_header = new.header_mut();
+                new_header.set_rsv1(header.opcode() != OpCode::Continue);
+                new_header.set_fin(fin);
 
-                if (self.is_server && handler.config.server_no_context_takeover)
-                    || (!self.is_server && handler.config.client_no_context_takeover)
-                {
+                if fin && handler.config.reset_compressor(self.is_server) {
                     handler
                         .com
                         .reset()
@@ -64,7 +74,23 @@ impl DeflateWriteState {
         self.write_state
             .async_send_owned_frame(stream, frame?)
             .await
-            .map_err(WsError::IOError)
+            .map_err(WsError::from)
+    }
+
+    /// send payload without compression even if deflate is enabled, rsv1 is not set
+    /// and compressor state is untouched
+    ///
+    /// will auto fragment if auto_fragment_size > 0
+    pub async fn async_send_uncompressed<S: AsyncWrite + Unpin>(
+        &mut self,
+        stream: &mut S,
+        code: OpCode,
+        payload: &[u8],
+    ) -> Result<(), WsError> {
+        self.write_state
+            .async_send(stream, code, payload)
+            .await
+            .map_err(WsError::from)
     }
 
     /// send payload
@@ -76,16 +102,8 @@ impl DeflateWriteState {
         code: OpCode,
         payload: &[u8],
     ) -> Result<(), WsError> {
-        let mask_send = self.config.mask_send_frame;
-        let mask_fn = || {
-            if mask_send {
-                Some(random())
-            } else {
-                None
-            }
-        };
         if payload.is_empty() {
-            let mask = mask_fn();
+            let mask = self.write_state.mask_key();
             let frame = OwnedFrame::new(code, mask, &[]);
             return self.async_send_owned_frame(stream, frame).await;
         }
@@ -99,7 +117,8 @@ impl DeflateWriteState {
         let total = parts.len();
         for (idx, chunk) in parts.into_iter().enumerate() {
             let fin = idx + 1 == total;
-            let mask = mask_fn();
+            let frame_code = if idx == 0 { code } else { OpCode::Continue };
+            let mask = self.write_state.mask_key();
             match (self.com.as_mut(), code.is_data()) {
                 (Some(handler), true) => {
                     let mut output = vec![];
@@ -107,25 +126,28 @@ impl DeflateWriteState {
                         .com
                         .compress(&[chunk], &mut output)
                         .map_err(|code| WsError::CompressFailed(code.to_string()))?;
-                    output.truncate(output.len() - 4);
+                    // only last fragment drops trailing 0x00 0x00 0xff 0xff,
+                    // and only first fragment carries rsv1
+                    if fin {
+                        output.truncate(output.len() - 4);
+                    }
                     let header = ctor_header(
                         &mut self.header_buf,
                         fin,
-                        true,
+                        idx == 0,
                         false,
                         false,
                         mask,
-                        code,
+                        frame_code,
                         output.len() as u64,
                     );
+                    self.config.notify(Direction::Outbound, header);
                     stream.write_all(header).await?;
                     if let Some(mask) = mask {
                         apply_mask(&mut output, mask)
                     };
                     stream.write_all(&output).await?;
-                    if (self.is_server && handler.config.server_no_context_takeover)
-                        || (!self.is_server && handler.config.client_no_context_takeover)
-                    {
+                    if fin && handler.config.reset_compressor(self.is_server) {
                         handler
                             .com
                             .reset()
@@ -141,9 +163,10 @@ impl DeflateWriteState {
                         false,
                         false,
                         mask,
-                        code,
+                        frame_code,
                         chunk.len() as u64,
                     );
+                    self.config.notify(Direction::Outbound, header);
                     stream.write_all(header).await?;
                     if let Some(mask) = mask {
                         let mut data = BytesMut::from_iter(chunk);
@@ -160,53 +183,39 @@ impl DeflateWriteState {
 }
 
 impl DeflateReadState {
+    /// receive a frame, return raw payload and whether it belongs to a compressed message
     async fn async_receive_one<S: AsyncRead + Unpin>(
         &mut self,
         stream: &mut S,
-    ) -> Result<(SimplifiedHeader, Vec<u8>), WsError> {
+    ) -> Result<(SimplifiedHeader, Vec<u8>, bool), WsError> {
         let (mut header, data) = self.read_state.async_receive(stream).await?;
         let data = data.to_vec();
-        let compressed = header.rsv1;
-        let is_data_frame = header.code.is_data();
-        if compressed && !is_data_frame {
-            return Err(WsError::ProtocolError {
-                close_code: 1002,
-                error: ProtocolError::CompressedControlFrame,
-            });
-        }
-        if !is_data_frame || !compressed {
-            return Ok((header, data.to_vec()));
-        }
-        let frame = match self.de.as_mut() {
-            Some(handler) => {
-                let mut de_data = vec![];
-                handler
-                    .de
-                    .de_compress(&[&data, &[0, 0, 255, 255]], &mut de_data)
-                    .map_err(|code| WsError::DeCompressFailed(code.to_string()))?;
-                if (self.is_server && handler.config.server_no_context_takeover)
-                    || (!self.is_server && handler.config.client_no_context_takeover)
-                {
-                    handler
-                        .de
-                        .reset()
-                        .map_err(|code| WsError::DeCompressFailed(code.to_string()))?;
-                    tracing::trace!("reset decompressor state");
-                }
-                de_data
-            }
-            None => {
-                if header.rsv1 {
-                    return Err(WsError::DeCompressFailed(
-                        "extension not enabled but got compressed frame".into(),
-                    ));
-                } else {
-                    data
-                }
-            }
-        };
+        let compressed = self.is_compressed(&header)?;
         header.rsv1 = false;
-        Ok((header, frame))
+        Ok((header, data, compressed))
+    }
+
+    /// receive a message, payload is passed to `on_chunk` chunk by chunk with
+    /// message type instead of being buffered, compressed message is decompressed
+    /// in chunks of at most 16K, return header of completed message
+    ///
+    /// control frames interleaved in a fragmented message are passed as a whole,
+    /// text payload is not utf-8 validated, don't mix with `async_receive` in the
+    /// middle of a message
+    pub async fn async_receive_stream<S: AsyncRead + Unpin, F>(
+        &mut self,
+        stream: &mut S,
+        mut on_chunk: F,
+    ) -> Result<SimplifiedHeader, WsError>
+    where
+        F: FnMut(OpCode, &[u8]) -> Result<(), WsError>,
+    {
+        loop {
+            let (header, loc) = self.read_state.async_receive_loc(stream, None).await?;
+            if let Some(header) = self.stream_frame(header, loc, &mut on_chunk)? {
+                return Ok(header);
+            }
+        }
     }
 
     /// receive a message
@@ -215,8 +224,11 @@ impl DeflateReadState {
         stream: &mut S,
     ) -> Result<(SimplifiedHeader, &[u8]), WsError> {
         loop {
-            let (mut header, mut data) = self.async_receive_one(stream).await?;
+            let (mut header, mut data, compressed) = self.async_receive_one(stream).await?;
             if !self.config.merge_frame {
+                if compressed {
+                    data = self.inflate(&data, header.fin)?;
+                }
                 self.fragmented_data.clear();
                 self.fragmented_data.append(&mut data);
                 break Ok((header, &self.fragmented_data));
@@ -230,10 +242,25 @@ impl DeflateReadState {
                         });
                     }
                     let fin = header.fin;
+                    if let Some(utf8) = self.utf8_stream.as_mut() {
+                        utf8.feed(&data, fin).map_err(WsError::invalid_utf8)?;
+                    }
+                    self.config
+                        .check_message_size(self.fragmented_data.len() + data.len())?;
                     self.fragmented_data.extend_from_slice(&data);
                     if fin {
                         self.fragmented = false;
+                        self.utf8_stream = None;
                         header.code = self.fragmented_type;
+                        if compressed {
+                            let payload = std::mem::take(&mut self.fragmented_data);
+                            self.fragmented_data = self.inflate(&payload, true)?;
+                            if header.code == OpCode::Text
+                                && self.config.validate_utf8.should_check()
+                            {
+                                check_utf8(&self.fragmented_data)?;
+                            }
+                        }
                         break Ok((header, &self.fragmented_data));
                     } else {
                         continue;
@@ -246,30 +273,36 @@ impl DeflateReadState {
                             error: ProtocolError::NotContinueFrameAfterFragmented,
                         });
                     }
+                    self.config.check_message_size(data.len())?;
+                    // compressed fragments are validated after whole message is decompressed
+                    if compressed && header.fin {
+                        data = self.inflate(&data, true)?;
+                    }
+                    let plain = !compressed || header.fin;
+                    if plain
+                        && header.code == OpCode::Text
+                        && self.config.validate_utf8.is_incremental()
+                    {
+                        let mut utf8 = Utf8Stream::default();
+                        utf8.feed(&data, header.fin)
+                            .map_err(WsError::invalid_utf8)?;
+                        self.utf8_stream = (!header.fin).then_some(utf8);
+                    }
                     if !header.fin {
                         self.fragmented = true;
                         self.fragmented_type = header.code;
-                        if header.code == OpCode::Text
+                        if plain
+                            && header.code == OpCode::Text
                             && self.config.validate_utf8.is_fast_fail()
-                            && simdutf8::basic::from_utf8(&data).is_err()
                         {
-                            return Err(WsError::ProtocolError {
-                                close_code: 1007,
-                                error: ProtocolError::InvalidUtf8,
-                            });
+                            check_utf8(&data)?;
                         }
                         self.fragmented_data.clear();
                         self.fragmented_data.extend_from_slice(&data);
                         continue;
                     } else {
-                        if header.code == OpCode::Text
-                            && self.config.validate_utf8.should_check()
-                            && simdutf8::basic::from_utf8(&data).is_err()
-                        {
-                            return Err(WsError::ProtocolError {
-                                close_code: 1007,
-                                error: ProtocolError::InvalidUtf8,
-                            });
+                        if header.code == OpCode::Text && self.config.validate_utf8.should_check() {
+                            check_utf8(&data)?;
                         }
                         self.fragmented_data.clear();
                         self.fragmented_data.extend_from_slice(&data);
@@ -291,24 +324,31 @@ pub struct AsyncDeflateCodec<S: AsyncRead + AsyncWrite> {
     read_state: DeflateReadState,
     write_state: DeflateWriteState,
     stream: S,
+    negotiated: NegotiatedParams,
 }
 
 impl<S: AsyncRead + AsyncWrite + Unpin> AsyncDeflateCodec<S> {
-    /// construct method
+    /// construct method, fail with `WsError::InvalidConfig` if `pmd_config`
+    /// is invalid
     pub fn new(
         stream: S,
         frame_config: FrameConfig,
         pmd_config: Option<PMDConfig>,
         is_server: bool,
-    ) -> Self {
+    ) -> Result<Self, WsError> {
         let read_state =
-            DeflateReadState::with_config(frame_config.clone(), pmd_config.clone(), is_server);
-        let write_state = DeflateWriteState::with_config(frame_config, pmd_config, is_server);
-        Self {
+            DeflateReadState::with_config(frame_config.clone(), pmd_config.clone(), is_server)?;
+        let write_state =
+            DeflateWriteState::with_config(frame_config, pmd_config.clone(), is_server)?;
+        Ok(Self {
             read_state,
             write_state,
             stream,
-        }
+            negotiated: NegotiatedParams {
+                subprotocol: None,
+                deflate: pmd_config,
+            },
+        })
     }
 
     /// used for server side to construct a new server
@@ -327,17 +367,17 @@ impl<S: AsyncRead + AsyncWrite + Unpin> AsyncDeflateCodec<S> {
             }
         }
         let mut pmd_config = pmd_configs.pop();
-        if let Some(conf) = pmd_config.as_mut() {
-            let min = conf.client_max_window_bits.min(conf.server_max_window_bits);
-            conf.client_max_window_bits = min;
-            conf.server_max_window_bits = min;
-        }
+        apply_compression_params(&mut pmd_config, req.extensions());
         tracing::debug!("use deflate config {:?}", pmd_config);
         let frame_conf = FrameConfig {
             mask_send_frame: false,
+            expect_mask: Some(true),
             ..Default::default()
         };
-        let codec = AsyncDeflateCodec::new(stream, frame_conf, pmd_config, true);
+        let negotiated = NegotiatedParams::from_req(&req, pmd_config.clone());
+        let mut codec = AsyncDeflateCodec::new(stream, frame_conf, pmd_config, true)?;
+        codec.negotiated = negotiated;
+        codec.read_state.read_state.feed_remain(req.extensions());
         Ok(codec)
     }
 
@@ -358,24 +398,136 @@ impl<S: AsyncRead + AsyncWrite + Unpin> AsyncDeflateCodec<S> {
             }
         }
         let mut pmd_conf = pmd_confs.pop();
-        if let Some(conf) = pmd_conf.as_mut() {
-            let min = conf.client_max_window_bits.min(conf.server_max_window_bits);
-            conf.client_max_window_bits = min;
-            conf.server_max_window_bits = min;
-        }
+        apply_compression_params(&mut pmd_conf, resp.extensions());
         tracing::debug!("use deflate config: {:?}", pmd_conf);
-        let codec = AsyncDeflateCodec::new(stream, Default::default(), pmd_conf, false);
+        let negotiated = NegotiatedParams::from_resp(&resp, pmd_conf.clone());
+        let frame_conf = FrameConfig {
+            expect_mask: Some(false),
+            ..Default::default()
+        };
+        let mut codec = AsyncDeflateCodec::new(stream, frame_conf, pmd_conf, false)?;
+        codec.negotiated = negotiated;
+        codec.read_state.read_state.feed_remain(resp.extensions());
         Ok(codec)
     }
 
+    /// parameters negotiated during handshake
+    pub fn negotiated(&self) -> &NegotiatedParams {
+        &self.negotiated
+    }
+
+    /// effective window bits of (decompressor, compressor) after negotiation,
+    /// `None` if deflate is not enabled
+    pub fn window_bits(&self) -> Option<(u8, u8)> {
+        let inflate = self.read_state.window_bits()?;
+        let deflate = self.write_state.window_bits()?;
+        Some((inflate as u8, deflate as u8))
+    }
+
     /// get mutable underlying stream
     pub fn stream_mut(&mut self) -> &mut S {
         &mut self.stream
     }
 
+    /// number of bytes read from stream but not parsed yet
+    pub fn buffered_len(&self) -> usize {
+        self.read_state.buffered_len()
+    }
+
+    /// max number of unparsed bytes ever buffered
+    pub fn buffered_high_water_mark(&self) -> usize {
+        self.read_state.buffered_high_water_mark()
+    }
+
+    /// set masking key generator of outgoing frames, see `FrameWriteState::set_mask_fn`
+    pub fn set_mask_fn<F: FnMut() -> [u8; 4] + Send + 'static>(&mut self, f: F) {
+        self.write_state.set_mask_fn(f);
+    }
+
+    /// change compression level(0-9) and strategy of compressor, see
+    /// `DeflateWriteState::set_compression`
+    pub fn set_compression(
+        &mut self,
+        level: u32,
+        strategy: CompressionStrategy,
+    ) -> Result<(), WsError> {
+        self.write_state.set_compression(level, strategy)
+    }
+
     /// receive a message
     pub async fn receive(&mut self) -> Result<(SimplifiedHeader, &[u8]), WsError> {
-        self.read_state.async_receive(&mut self.stream).await
+        let (header, data) = self.read_state.async_receive(&mut self.stream).await?;
+        if let Some(echo) = self.write_state.write_state.close_reply(&header, data) {
+            self.write_state
+                .write_state
+                .async_send(&mut self.stream, OpCode::Close, echo)
+                .await?;
+        }
+        check_close(self.write_state.config.close_as_error, &header, data)?;
+        Ok((header, data))
+    }
+
+    /// receive next data message, control frames are handled internally
+    ///
+    /// ping is answered with pong, pong is dropped, fragments are reassembled
+    /// and close frame is echoed unless close frame has been sent, then
+    /// reported as `Event::Closed`
+    pub async fn receive_message(&mut self) -> Result<Event<&[u8]>, WsError> {
+        let header = loop {
+            let (header, data) = self.read_state.async_receive(&mut self.stream).await?;
+            match header.code {
+                OpCode::Ping => {
+                    let payload = data.to_vec();
+                    self.write_state
+                        .write_state
+                        .async_send(&mut self.stream, OpCode::Pong, &payload)
+                        .await?;
+                }
+                OpCode::Pong => {}
+                _ => break header,
+            }
+        };
+        // payload is kept in read state, take it again to release borrow of loop
+        match header.code {
+            OpCode::Close => {
+                let data = &self.read_state.control_buf;
+                if !self.write_state.write_state.close_sent() {
+                    let echo = if data.len() >= 2 { &data[..2] } else { &[] };
+                    self.write_state
+                        .write_state
+                        .async_send(&mut self.stream, OpCode::Close, echo)
+                        .await?;
+                }
+                Ok(Event::Closed(CloseEvent::from_payload(data)))
+            }
+            OpCode::Text => Ok(Event::Text(&self.read_state.fragmented_data)),
+            OpCode::Binary => Ok(Event::Binary(&self.read_state.fragmented_data)),
+            code => Err(WsError::UnsupportedFrame(code)),
+        }
+    }
+
+    /// receive a message chunk by chunk, see `DeflateReadState::async_receive_stream`
+    pub async fn receive_stream<F>(&mut self, mut on_chunk: F) -> Result<SimplifiedHeader, WsError>
+    where
+        F: FnMut(OpCode, &[u8]) -> Result<(), WsError>,
+    {
+        loop {
+            let (header, loc) = self
+                .read_state
+                .read_state
+                .async_receive_loc(&mut self.stream, None)
+                .await?;
+            let data = self.read_state.read_state.payload(&loc);
+            if let Some(echo) = self.write_state.write_state.close_reply(&header, data) {
+                self.write_state
+                    .write_state
+                    .async_send(&mut self.stream, OpCode::Close, echo)
+                    .await?;
+            }
+            if let Some(header) = self.read_state.stream_frame(header, loc, &mut on_chunk)? {
+                return Ok(header);
+            }
+        }
     }
 
     /// send a read frame, **this method will not check validation of frame and do not fragment**
@@ -385,6 +537,16 @@ impl<S: AsyncRead + AsyncWrite + Unpin> AsyncDeflateCodec<S> {
             .await
     }
 
+    /// send close frame for protocol error with masking matching role, do
+    /// nothing for other errors
+    pub async fn close_with_error(&mut self, error: &WsError) -> Result<(), WsError> {
+        self.write_state
+            .write_state
+            .async_send_error_close(&mut self.stream, error)
+            .await
+            .map_err(WsError::from)
+    }
+
     /// send payload
     ///
     /// will auto fragment **before compression** if auto_fragment_size > 0
@@ -394,6 +556,13 @@ impl<S: AsyncRead + AsyncWrite + Unpin> AsyncDeflateCodec<S> {
             .await
     }
 
+    /// send payload without compression, useful for incompressible data
+    pub async fn send_uncompressed(&mut self, code: OpCode, payload: &[u8]) -> Result<(), WsError> {
+        self.write_state
+            .async_send_uncompressed(&mut self.stream, code, payload)
+            .await
+    }
+
     /// helper function to send text message
     pub async fn text(&mut self, text: &str) -> Result<(), WsError> {
         self.write_state
@@ -425,7 +594,7 @@ impl<S: AsyncRead + AsyncWrite + Unpin> AsyncDeflateCodec<S> {
 
     /// flush stream to ensure all data are send
     pub async fn flush(&mut self) -> Result<(), WsError> {
-        self.stream.flush().await.map_err(WsError::IOError)
+        self.stream.flush().await.map_err(WsError::from)
     }
 }
 
@@ -446,10 +615,30 @@ impl<S: AsyncRead + Unpin> AsyncDeflateRecv<S> {
         &mut self.stream
     }
 
+    /// number of bytes read from stream but not parsed yet
+    pub fn buffered_len(&self) -> usize {
+        self.read_state.buffered_len()
+    }
+
+    /// max number of unparsed bytes ever buffered
+    pub fn buffered_high_water_mark(&self) -> usize {
+        self.read_state.buffered_high_water_mark()
+    }
+
     /// receive a frame
     pub async fn receive(&mut self) -> Result<(SimplifiedHeader, &[u8]), WsError> {
         self.read_state.async_receive(&mut self.stream).await
     }
+
+    /// receive a message chunk by chunk, see `DeflateReadState::async_receive_stream`
+    pub async fn receive_stream<F>(&mut self, on_chunk: F) -> Result<SimplifiedHeader, WsError>
+    where
+        F: FnMut(OpCode, &[u8]) -> Result<(), WsError>,
+    {
+        self.read_state
+            .async_receive_stream(&mut self.stream, on_chunk)
+            .await
+    }
 }
 
 /// send part of deflate message
@@ -479,6 +668,16 @@ impl<S: AsyncWrite + Unpin> AsyncDeflateSend<S> {
             .await
     }
 
+    /// send close frame for protocol error with masking matching role, do
+    /// nothing for other errors
+    pub async fn close_with_error(&mut self, error: &WsError) -> Result<(), WsError> {
+        self.write_state
+            .write_state
+            .async_send_error_close(&mut self.stream, error)
+            .await
+            .map_err(WsError::from)
+    }
+
     /// send payload
     ///
     /// will auto fragment **before compression** if auto_fragment_size > 0
@@ -488,6 +687,13 @@ impl<S: AsyncWrite + Unpin> AsyncDeflateSend<S> {
             .await
     }
 
+    /// send payload without compression, useful for incompressible data
+    pub async fn send_uncompressed(&mut self, code: OpCode, payload: &[u8]) -> Result<(), WsError> {
+        self.write_state
+            .async_send_uncompressed(&mut self.stream, code, payload)
+            .await
+    }
+
     /// helper function to send text message
     pub async fn text(&mut self, text: &str) -> Result<(), WsError> {
         self.write_state
@@ -519,7 +725,7 @@ impl<S: AsyncWrite + Unpin> AsyncDeflateSend<S> {
 
     /// flush stream to ensure all data are send
     pub async fn flush(&mut self) -> Result<(), WsError> {
-        self.stream.flush().await.map_err(WsError::IOError)
+        self.stream.flush().await.map_err(WsError::from)
     }
 }
 
@@ -535,6 +741,7 @@ where
             stream,
             read_state,
             write_state,
+            ..
         } = self;
         let (read, write) = stream.split();
         (
@@ -543,3 +750,117 @@ where
         )
     }
 }
+
+impl<S: AsyncRead + AsyncWrite> AsyncDeflateCodec<S> {
+    /// split codec to recv and send parts with `tokio::io::split`, works for
+    /// any stream, even if it does not implement `Split`
+    pub fn into_split(
+        self,
+    ) -> (
+        AsyncDeflateRecv<tokio::io::ReadHalf<S>>,
+        AsyncDeflateSend<tokio::io::WriteHalf<S>>,
+    ) {
+        let AsyncDeflateCodec {
+            stream,
+            read_state,
+            write_state,
+            ..
+        } = self;
+        let (read, write) = tokio::io::split(stream);
+        (
+            AsyncDeflateRecv::new(read, read_state),
+            AsyncDeflateSend::new(write, write_state),
+        )
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_send_uncompressed() {
+    let (client, server) = tokio::io::duplex(4096);
+    let mut client =
+        AsyncDeflateCodec::new(client, Default::default(), Some(Default::default()), false)
+            .unwrap();
+    let mut server = AsyncDeflateCodec::new(
+        server,
+        FrameConfig {
+            mask_send_frame: false,
+            ..Default::default()
+        },
+        Some(Default::default()),
+        true,
+    )
+    .unwrap();
+
+    let messages: [&[u8]; 4] = [
+        b"hello hello hello",
+        b"\x00\xff\x10",
+        b"hello hello",
+        b"world",
+    ];
+    for (idx, msg) in messages.iter().enumerate() {
+        if idx % 2 == 0 {
+            client.send(OpCode::Binary, msg).await.unwrap();
+        } else {
+            client.send_uncompressed(OpCode::Binary, msg).await.unwrap();
+        }
+    }
+    for msg in messages {
+        let (header, data) = server.receive().await.unwrap();
+        assert_eq!(header.code, OpCode::Binary);
+        assert_eq!(data, msg);
+    }
+
+    server
+        .send_uncompressed(OpCode::Text, b"raw")
+        .await
+        .unwrap();
+    let (header, data) = client
+        .read_state
+        .read_state
+        .async_receive(&mut client.stream)
+        .await
+        .unwrap();
+    assert!(!header.rsv1);
+    assert_eq!(data, b"raw");
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_no_context_takeover() {
+    for (server_no_context_takeover, client_no_context_takeover) in
+        [(true, false), (false, true), (true, true), (false, false)]
+    {
+        let pmd = PMDConfig {
+            server_no_context_takeover,
+            client_no_context_takeover,
+            ..Default::default()
+        };
+        let (client, server) = tokio::io::duplex(4096);
+        let mut client =
+            AsyncDeflateCodec::new(client, Default::default(), Some(pmd.clone()), false).unwrap();
+        let mut server = AsyncDeflateCodec::new(
+            server,
+            FrameConfig {
+                mask_send_frame: false,
+                ..Default::default()
+            },
+            Some(pmd),
+            true,
+        )
+        .unwrap();
+
+        // second message refers to first one if context is taken over
+        let msg = b"hello permessage-deflate hello permessage-deflate";
+        for _ in 0..2 {
+            client.send(OpCode::Text, msg).await.unwrap();
+            server.send(OpCode::Text, msg).await.unwrap();
+        }
+        for _ in 0..2 {
+            let (_, data) = server.receive().await.unwrap();
+            assert_eq!(data, msg);
+            let (_, data) = client.receive().await.unwrap();
+            assert_eq!(data, msg);
+        }
+    }
+}
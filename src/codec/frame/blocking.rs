@@ -1,62 +1,142 @@
-use super::{FrameConfig, FrameReadState, FrameWriteState};
+use super::{
+    check_close, check_fragmented_opcode, to_owned_frame, CloseState, ConnectionState, Direction,
+    FrameConfig, FrameRead, FrameReadState, FrameWrite, FrameWriteState, PayloadLoc,
+    MAX_CONTROL_FRAME_LEN,
+};
 use http;
 use crate::{
     codec::{apply_mask, Split},
-    errors::WsError,
-    frame::{ctor_header, header_len, OpCode, OwnedFrame, SimplifiedHeader},
+    errors::{close_payload, WsError},
+    frame::{
+        ctor_header, header_len, parse_opcode, BorrowedFrame, OpCode, OwnedFrame,
+        PreparedMaskedFrame, SimplifiedHeader,
+    },
     protocol::standard_handshake_resp_check,
+    CloseEvent, Event,
 };
-use bytes::BytesMut;
+use bytes::Bytes;
 use std::{
     io::{IoSlice, Read, Write},
     ops::Range,
+    time::Instant,
 };
 
 type IOResult<T> = std::io::Result<T>;
 
 impl FrameReadState {
+    /// receive a message, control frame between fragments is returned as is,
+    /// partial message is kept and completed by following continuation frames
+    ///
     /// **NOTE** masked frame has already been unmasked
     pub fn receive<S: Read>(
         &mut self,
         stream: &mut S,
     ) -> Result<(SimplifiedHeader, &[u8]), WsError> {
+        let (header, loc) = self.receive_loc(stream, None)?;
+        self.record_size(&loc);
+        Ok((header, self.payload(&loc)))
+    }
+
+    /// receive a message, fail with `WsError::DeadlineExceeded` if message is
+    /// not complete before deadline
+    ///
+    /// deadline is checked before every read, a single blocking read is not
+    /// interrupted, set stream read timeout to bound it
+    pub fn receive_deadline<S: Read>(
+        &mut self,
+        stream: &mut S,
+        deadline: Instant,
+    ) -> Result<(SimplifiedHeader, &[u8]), WsError> {
+        let (header, loc) = self.receive_loc(stream, Some(deadline))?;
+        self.record_size(&loc);
+        Ok((header, self.payload(&loc)))
+    }
+
+    pub(crate) fn receive_loc<S: Read>(
+        &mut self,
+        stream: &mut S,
+        deadline: Option<Instant>,
+    ) -> Result<(SimplifiedHeader, PayloadLoc), WsError> {
         if self.config.merge_frame {
             loop {
-                let (mut header, range) = self.read_one_frame(stream)?;
+                let (mut header, range) = self.read_one_frame(stream, deadline)?;
                 if let Some(merged) = self
                     .check_frame(header, range.clone())
                     .and_then(|_| self.merge_frame(header, range.clone()))?
                 {
                     if merged {
                         header.code = self.fragmented_type;
-                        break Ok((header, &self.fragmented_data));
+                        break Ok((header, PayloadLoc::Merged));
                     } else {
-                        break Ok((header, &self.buf.buf[range]));
+                        break Ok((header, PayloadLoc::Buf(range)));
                     }
                 }
             }
         } else {
-            let (header, range) = self.read_one_frame(stream)?;
+            let (header, range) = self.read_one_frame(stream, deadline)?;
             self.check_frame(header, range.clone())?;
-            Ok((header, &self.buf.buf[range]))
+            Ok((header, PayloadLoc::Buf(range)))
         }
     }
 
+    /// receive a single frame without merging fragmented frames, frame header
+    /// keeps its original fin bit & opcode
+    ///
+    /// **NOTE** masked frame has already been unmasked
+    pub fn receive_frame<S: Read>(
+        &mut self,
+        stream: &mut S,
+    ) -> Result<(SimplifiedHeader, &[u8]), WsError> {
+        let (header, range) = self.read_one_frame(stream, None)?;
+        self.check_frame(header, range.clone())?;
+        Ok((header, &self.buf.buf[range]))
+    }
+
+    /// receive a single frame which borrows read buffer, fragmented frames
+    /// are not merged
+    ///
+    /// returned frame must be dropped before next receive, if `auto_unmask` is
+    /// enabled, payload has already been unmasked and header has no mask bit
+    /// and masking key
+    pub fn receive_borrowed<S: Read>(
+        &mut self,
+        stream: &mut S,
+    ) -> Result<BorrowedFrame<'_>, WsError> {
+        let (header_len, header, range) = self.read_raw_frame(stream, None)?;
+        self.check_frame(header, range.clone())?;
+        Ok(self.borrowed_frame(header_len, range))
+    }
+
     #[inline]
     fn read_one_frame<S: Read>(
         &mut self,
         stream: &mut S,
+        deadline: Option<Instant>,
     ) -> Result<(SimplifiedHeader, Range<usize>), WsError> {
+        let (_, header, range) = self.read_raw_frame(stream, deadline)?;
+        Ok((header, range))
+    }
+
+    /// read a frame, return header len, header and payload range
+    #[inline]
+    fn read_raw_frame<S: Read>(
+        &mut self,
+        stream: &mut S,
+        deadline: Option<Instant>,
+    ) -> Result<(usize, SimplifiedHeader, Range<usize>), WsError> {
         while !self.is_header_ok() {
-            self.poll(stream)?;
+            self.poll(stream, deadline)?;
         }
         let (header_len, payload_len, total_len) = self.parse_frame_header()?;
-        self.poll_one_frame(stream, total_len)?;
-        Ok(self.consume_frame(header_len, payload_len, total_len))
+        self.check_header(header_len, payload_len)?;
+        self.poll_one_frame(stream, total_len, deadline)?;
+        let (header, range) = self.consume_frame(header_len, payload_len, total_len);
+        Ok((header_len, header, range))
     }
 
     #[inline]
-    fn poll<S: Read>(&mut self, stream: &mut S) -> std::io::Result<usize> {
+    fn poll<S: Read>(&mut self, stream: &mut S, deadline: Option<Instant>) -> IOResult<usize> {
+        Self::check_deadline(deadline)?;
         let buf = self.buf.prepare(self.config.resize_size);
         let count = stream.read(buf)?;
         self.buf.produce(count);
@@ -70,7 +150,18 @@ impl FrameReadState {
     }
 
     #[inline]
-    fn poll_one_frame<S: Read>(&mut self, stream: &mut S, size: usize) -> std::io::Result<()> {
+    fn poll_one_frame<S: Read>(
+        &mut self,
+        stream: &mut S,
+        size: usize,
+        deadline: Option<Instant>,
+    ) -> IOResult<()> {
+        if deadline.is_some() {
+            while self.buf.ava_data().len() < size {
+                self.poll(stream, deadline)?;
+            }
+            return Ok(());
+        }
         let read_len = self.buf.ava_data().len();
         if read_len < size {
             let buf = self.buf.prepare(size - read_len);
@@ -94,12 +185,11 @@ impl FrameWriteState {
         opcode: OpCode,
         payload: &[u8],
     ) -> IOResult<()> {
+        if !self.mark_close(opcode) {
+            return Ok(());
+        }
         if payload.is_empty() {
-            let mask = if self.config.mask_send_frame {
-                Some(rand::random())
-            } else {
-                None
-            };
+            let mask = self.mask_key();
             let header = ctor_header(
                 &mut self.header_buf,
                 true,
@@ -110,9 +200,15 @@ impl FrameWriteState {
                 opcode,
                 0,
             );
+            self.config.notify(Direction::Outbound, header);
             stream.write_all(header)?;
             return Ok(());
         }
+        let mut control_buf = [0; MAX_CONTROL_FRAME_LEN];
+        if let Some(frame) = self.ctor_control_frame(&mut control_buf, opcode, payload) {
+            stream.write_all(frame)?;
+            return Ok(());
+        }
         if self.config.auto_fragment_size > 0 && self.config.auto_fragment_size < payload.len() {
             let chunk_size = self.config.auto_fragment_size;
             let parts: Vec<&[u8]> = payload.chunks(chunk_size).collect();
@@ -131,7 +227,7 @@ impl FrameWriteState {
                 parts.iter().enumerate().for_each(|(idx, chunk)| {
                     let fin = idx + 1 == total;
                     let s_idx = idx * single_header_len;
-                    let mask = rand::random();
+                    let mask = self.mask_gen.next();
                     let header_len = ctor_header(
                         &mut self.buf[s_idx..],
                         fin,
@@ -143,6 +239,8 @@ impl FrameWriteState {
                         chunk.len() as u64,
                     )
                     .len();
+                    self.config
+                        .notify(Direction::Outbound, &self.buf[s_idx..(s_idx + header_len)]);
                     let slice = &mut self.buf[(s_idx + header_len)..];
                     slice.copy_from_slice(chunk);
                     apply_mask(slice, mask);
@@ -156,7 +254,7 @@ impl FrameWriteState {
                 parts.iter().enumerate().for_each(|(idx, chunk)| {
                     let fin = idx + 1 == total;
                     let s_idx = idx * chunk_size;
-                    ctor_header(
+                    let header = ctor_header(
                         &mut self.buf[s_idx..],
                         fin,
                         false,
@@ -166,6 +264,7 @@ impl FrameWriteState {
                         opcode,
                         chunk.len() as u64,
                     );
+                    self.config.notify(Direction::Outbound, header);
                 });
                 parts.iter().enumerate().for_each(|(idx, chunk)| {
                     let fin = idx + 1 == total;
@@ -178,17 +277,10 @@ impl FrameWriteState {
                     }
                     slices.push(IoSlice::new(chunk));
                 });
-                let num = stream.write_vectored(&slices)?;
-                let remain = total_bytes - num;
-                if remain > 0 {
-                    if let Some(buf) = slices.last() {
-                        stream.write_all(&buf[(buf.len() - remain)..])?;
-                    }
-                }
+                write_all_vectored(stream, &slices)?;
             }
         } else if self.config.mask_send_frame {
-            let total_bytes = header_len(true, payload.len() as u64) + payload.len();
-            let mask: [u8; 4] = rand::random();
+            let mask = self.mask_gen.next();
             let header = ctor_header(
                 &mut self.header_buf,
                 true,
@@ -199,21 +291,20 @@ impl FrameWriteState {
                 opcode,
                 payload.len() as u64,
             );
+            self.config.notify(Direction::Outbound, header);
             if self.buf.len() < payload.len() {
                 self.buf.resize(payload.len(), 0)
             }
             self.buf[..(payload.len())].copy_from_slice(payload);
             apply_mask(&mut self.buf[..(payload.len())], mask);
-            let num = stream.write_vectored(&[
-                IoSlice::new(header),
-                IoSlice::new(&self.buf[..(payload.len())]),
-            ])?;
-            let remain = total_bytes - num;
-            if remain > 0 {
-                stream.write_all(&self.buf[(payload.len() - remain)..(payload.len())])?;
-            }
+            write_all_vectored(
+                stream,
+                &[
+                    IoSlice::new(header),
+                    IoSlice::new(&self.buf[..(payload.len())]),
+                ],
+            )?;
         } else {
-            let total_bytes = header_len(false, payload.len() as u64) + payload.len();
             let header = ctor_header(
                 &mut self.header_buf,
                 true,
@@ -224,39 +315,234 @@ impl FrameWriteState {
                 opcode,
                 payload.len() as u64,
             );
+            self.config.notify(Direction::Outbound, header);
             // if self.buf.len() < payload.len() {
             //     self.buf.resize(payload.len(), 0)
             // }
-            let num = stream.write_vectored(&[IoSlice::new(header), IoSlice::new(payload)])?;
-            let remain = total_bytes - num;
-            if remain > 0 {
-                stream.write_all(&payload[(payload.len() - remain)..])?;
-            }
+            write_all_vectored(stream, &[IoSlice::new(header), IoSlice::new(payload)])?;
         };
 
-        if self.config.renew_buf_on_write {
-            self.buf = BytesMut::new()
+        self.renew_buf();
+        Ok(())
+    }
+
+    /// send close frame for protocol error, do nothing for other errors
+    ///
+    /// close frame is masked according to `mask_send_frame`, so it always
+    /// matches role of this side
+    pub fn send_error_close<S: Write>(&mut self, stream: &mut S, error: &WsError) -> IOResult<()> {
+        match error.close_payload() {
+            Some(payload) => self.send(stream, OpCode::Close, &payload),
+            None => Ok(()),
+        }
+    }
+
+    /// send a single frame with explicit fin bit, **this method do not fragment**
+    ///
+    /// to send a fragmented message manually, send first frame with data opcode
+    /// and `fin = false`, then `OpCode::Continue` frames, the last one with `fin = true`
+    pub fn send_frame<S: Write>(
+        &mut self,
+        stream: &mut S,
+        fin: bool,
+        opcode: OpCode,
+        payload: &[u8],
+    ) -> IOResult<()> {
+        if !self.mark_close(opcode) {
+            return Ok(());
+        }
+        let mask = self.mask_key();
+        let header = ctor_header(
+            &mut self.header_buf,
+            fin,
+            false,
+            false,
+            false,
+            mask,
+            opcode,
+            payload.len() as u64,
+        );
+        self.config.notify(Direction::Outbound, header);
+        stream.write_all(header)?;
+        if let Some(mask) = mask {
+            if self.buf.len() < payload.len() {
+                self.buf.resize(payload.len(), 0)
+            }
+            self.buf[..(payload.len())].copy_from_slice(payload);
+            apply_mask(&mut self.buf[..(payload.len())], mask);
+            stream.write_all(&self.buf[..(payload.len())])?;
+        } else {
+            stream.write_all(payload)?;
         }
         Ok(())
     }
 
+    /// send a single frame whose payload is concatenation of `parts`
+    ///
+    /// header and parts are written with one vectored write, masked payload is
+    /// copied to inner buffer to apply mask, payload is concatenated and sent by
+    /// `send` if it should be fragmented
+    pub fn send_vectored<S: Write>(
+        &mut self,
+        stream: &mut S,
+        opcode: OpCode,
+        parts: &[&[u8]],
+    ) -> IOResult<()> {
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+        if self.config.auto_fragment_size > 0 && self.config.auto_fragment_size < len {
+            return self.send(stream, opcode, &parts.concat());
+        }
+        if !self.mark_close(opcode) {
+            return Ok(());
+        }
+        let mask = self.mask_key();
+        let header = ctor_header(
+            &mut self.header_buf,
+            true,
+            false,
+            false,
+            false,
+            mask,
+            opcode,
+            len as u64,
+        );
+        self.config.notify(Direction::Outbound, header);
+        if let Some(mask) = mask {
+            if self.buf.len() < len {
+                self.buf.resize(len, 0)
+            }
+            let mut offset = 0;
+            for part in parts {
+                self.buf[offset..(offset + part.len())].copy_from_slice(part);
+                offset += part.len();
+            }
+            apply_mask(&mut self.buf[..len], mask);
+            write_all_vectored(
+                stream,
+                &[IoSlice::new(header), IoSlice::new(&self.buf[..len])],
+            )?;
+        } else {
+            let mut slices = Vec::with_capacity(parts.len() + 1);
+            slices.push(IoSlice::new(header));
+            slices.extend(parts.iter().map(|part| IoSlice::new(part)));
+            write_all_vectored(stream, &slices)?;
+        }
+        self.renew_buf();
+        Ok(())
+    }
+
+    /// send chunks as a fragmented message, first chunk with `opcode`, following
+    /// chunks with `OpCode::Continue`, only the last one has fin bit set
+    ///
+    /// chunks are written as they are yielded, so payload is never buffered as
+    /// a whole, empty chunks yield a single empty frame, control frames can not
+    /// be fragmented
+    pub fn send_fragmented<'a, S: Write, I: IntoIterator<Item = &'a [u8]>>(
+        &mut self,
+        stream: &mut S,
+        opcode: OpCode,
+        chunks: I,
+    ) -> Result<(), WsError> {
+        check_fragmented_opcode(opcode)?;
+        let mut chunks = chunks.into_iter().peekable();
+        let mut code = opcode;
+        loop {
+            let chunk = chunks.next().unwrap_or_default();
+            let fin = chunks.peek().is_none();
+            self.send_frame(stream, fin, code, chunk)?;
+            if fin {
+                return Ok(());
+            }
+            code = OpCode::Continue;
+        }
+    }
+
     pub(crate) fn send_owned_frame<S: Write>(
         &mut self,
         stream: &mut S,
         frame: OwnedFrame,
     ) -> IOResult<()> {
-        let header = IoSlice::new(&frame.header().0);
-        let body = IoSlice::new(frame.payload());
-        let total = header.len() + body.len();
-        let num = stream.write_vectored(&[header, body])?;
-        let remain = total - num;
-        if remain > 0 {
-            stream.write_all(&body[(body.len() - remain)..])?
+        if !self.mark_close(frame.header().opcode()) {
+            return Ok(());
         }
-        Ok(())
+        self.config
+            .notify(Direction::Outbound, frame.header().as_bytes());
+        write_frame(stream, &frame)
+    }
+
+    /// send a prepared masked frame, payload is not copied or masked again
+    pub fn send_prepared<S: Write>(
+        &mut self,
+        stream: &mut S,
+        frame: &PreparedMaskedFrame,
+    ) -> IOResult<()> {
+        if !self.mark_close(frame.frame().header().opcode()) {
+            return Ok(());
+        }
+        self.config
+            .notify(Direction::Outbound, frame.frame().header().as_bytes());
+        write_frame(stream, frame.frame())
+    }
+
+    /// send a frame encoded by `frame::encode_once`, bytes are written as is
+    pub fn send_encoded<S: Write>(&mut self, stream: &mut S, frame: &[u8]) -> IOResult<()> {
+        let Some(&first) = frame.first() else {
+            return Ok(());
+        };
+        if !self.mark_close(parse_opcode(first)) {
+            return Ok(());
+        }
+        self.config.notify(Direction::Outbound, frame);
+        stream.write_all(frame)
     }
 }
 
+fn write_frame<S: Write>(stream: &mut S, frame: &OwnedFrame) -> IOResult<()> {
+    write_all_vectored(
+        stream,
+        &[
+            IoSlice::new(&frame.header().0),
+            IoSlice::new(frame.payload()),
+        ],
+    )
+}
+
+/// iterator calling `receive` until close message or first error is yielded
+pub(crate) fn until_close<T, F>(
+    mut receive: F,
+    is_close: fn(&T) -> bool,
+) -> impl Iterator<Item = Result<T, WsError>>
+where
+    F: FnMut() -> Result<T, WsError>,
+{
+    let mut done = false;
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let item = receive();
+        done = match &item {
+            Ok(item) => is_close(item),
+            Err(_) => true,
+        };
+        Some(item)
+    })
+}
+
+/// write all slices, continue from where a short vectored write stops
+fn write_all_vectored<S: Write>(stream: &mut S, slices: &[IoSlice<'_>]) -> IOResult<()> {
+    let mut num = stream.write_vectored(slices)?;
+    for slice in slices {
+        if num >= slice.len() {
+            num -= slice.len();
+        } else {
+            stream.write_all(&slice[num..])?;
+            num = 0;
+        }
+    }
+    Ok(())
+}
+
 /// recv part of websocket stream
 pub struct FrameRecv<S: Read> {
     stream: S,
@@ -271,7 +557,65 @@ impl<S: Read> FrameRecv<S> {
 
     /// receive a frame
     pub fn receive(&mut self) -> Result<(SimplifiedHeader, &[u8]), WsError> {
-        self.read_state.receive(&mut self.stream)
+        let close_as_error = self.read_state.config.close_as_error;
+        let (header, data) = self.read_state.receive(&mut self.stream)?;
+        check_close(close_as_error, &header, data)?;
+        Ok((header, data))
+    }
+
+    /// receive a frame, fail if it's not complete before deadline
+    pub fn receive_deadline(
+        &mut self,
+        deadline: Instant,
+    ) -> Result<(SimplifiedHeader, &[u8]), WsError> {
+        let close_as_error = self.read_state.config.close_as_error;
+        let (header, data) = self
+            .read_state
+            .receive_deadline(&mut self.stream, deadline)?;
+        check_close(close_as_error, &header, data)?;
+        Ok((header, data))
+    }
+
+    /// number of bytes read from stream but not parsed yet
+    pub fn buffered_len(&self) -> usize {
+        self.read_state.buffered_len()
+    }
+
+    /// max number of unparsed bytes ever buffered
+    pub fn buffered_high_water_mark(&self) -> usize {
+        self.read_state.buffered_high_water_mark()
+    }
+
+    /// histogram of received message payload size
+    #[cfg(feature = "metrics")]
+    pub fn size_histogram(&self) -> &hdrhistogram::Histogram<u64> {
+        self.read_state.size_histogram()
+    }
+
+    /// whether peer close frame has been received
+    pub fn close_received(&self) -> bool {
+        self.read_state.close_received()
+    }
+
+    /// receive a single frame, fragmented frames are not merged
+    pub fn receive_frame(&mut self) -> Result<(SimplifiedHeader, &[u8]), WsError> {
+        let close_as_error = self.read_state.config.close_as_error;
+        let (header, data) = self.read_state.receive_frame(&mut self.stream)?;
+        check_close(close_as_error, &header, data)?;
+        Ok((header, data))
+    }
+
+    /// iterate received frames, payload is copied, see `receive`
+    ///
+    /// iterator stops after yielding close frame or first error
+    pub fn messages(&mut self) -> impl Iterator<Item = Result<OwnedFrame, WsError>> + '_ {
+        until_close(
+            || {
+                let (header, data) = self.receive()?;
+                Ok(to_owned_frame(&header, data))
+            },
+            |frame| frame.header().opcode() == OpCode::Close,
+        )
     }
 }
 
@@ -290,25 +634,81 @@ impl<S: Write> FrameSend<S> {
         }
     }
 
+    /// whether close frame has been sent
+    pub fn close_sent(&self) -> bool {
+        self.write_state.close_sent()
+    }
+
     /// send payload
     ///
     /// will auto fragment if auto_fragment_size > 0
     pub fn send(&mut self, code: OpCode, payload: &[u8]) -> Result<(), WsError> {
         self.write_state
             .send(&mut self.stream, code, payload)
-            .map_err(WsError::IOError)
+            .map_err(WsError::from)
+    }
+
+    /// send a single frame with explicit fin bit, **this method do not fragment**
+    pub fn send_frame(&mut self, code: OpCode, payload: &[u8], fin: bool) -> Result<(), WsError> {
+        self.write_state
+            .send_frame(&mut self.stream, fin, code, payload)
+            .map_err(WsError::from)
+    }
+
+    /// send a single frame whose payload is concatenation of `parts`, see
+    /// `FrameWriteState::send_vectored`
+    pub fn send_vectored(&mut self, code: OpCode, parts: &[&[u8]]) -> Result<(), WsError> {
+        self.write_state
+            .send_vectored(&mut self.stream, code, parts)
+            .map_err(WsError::from)
+    }
+
+    /// send chunks as a fragmented message, see `FrameWriteState::send_fragmented`
+    pub fn send_fragmented<'a, I: IntoIterator<Item = &'a [u8]>>(
+        &mut self,
+        code: OpCode,
+        chunks: I,
+    ) -> Result<(), WsError> {
+        self.write_state
+            .send_fragmented(&mut self.stream, code, chunks)
     }
 
     /// send a read frame, **this method will not check validation of frame and do not fragment**
     pub fn send_owned_frame(&mut self, frame: OwnedFrame) -> Result<(), WsError> {
         self.write_state
             .send_owned_frame(&mut self.stream, frame)
-            .map_err(WsError::IOError)
+            .map_err(WsError::from)
+    }
+
+    /// send close frame for protocol error with masking matching role, do
+    /// nothing for other errors
+    pub fn close_with_error(&mut self, error: &WsError) -> Result<(), WsError> {
+        self.write_state
+            .send_error_close(&mut self.stream, error)
+            .map_err(WsError::from)
+    }
+
+    /// send a prepared masked frame
+    pub fn send_prepared(&mut self, frame: &PreparedMaskedFrame) -> Result<(), WsError> {
+        self.write_state
+            .send_prepared(&mut self.stream, frame)
+            .map_err(WsError::from)
+    }
+
+    /// send a frame encoded once by `frame::encode_once`, such as one message
+    /// fanned out to many connections
+    ///
+    /// **NOTE** frame bytes bypass masking of this connection, only use it on
+    /// server side
+    pub fn send_encoded(&mut self, frame: &Bytes) -> Result<(), WsError> {
+        self.write_state
+            .send_encoded(&mut self.stream, frame)
+            .map_err(WsError::from)
     }
 
     /// flush stream to ensure all data are send
     pub fn flush(&mut self) -> Result<(), WsError> {
-        self.stream.flush().map_err(WsError::IOError)
+        self.stream.flush().map_err(WsError::from)
     }
 }
 
@@ -347,42 +747,330 @@ impl<S: Read + Write> FrameCodec<S> {
     }
 
     /// used for server side to construct a new server
-    pub fn factory(_req: http::Request<()>, stream: S) -> Result<Self, WsError> {
+    pub fn factory(req: http::Request<()>, stream: S) -> Result<Self, WsError> {
         let config = FrameConfig {
             mask_send_frame: false,
+            expect_mask: Some(true),
             ..Default::default()
         };
-        Ok(Self::new_with(stream, config))
+        let mut codec = Self::new_with(stream, config);
+        codec.read_state.feed_remain(req.extensions());
+        Ok(codec)
     }
 
     /// used to client side to construct a new client
     pub fn check_fn(key: String, resp: http::Response<()>, stream: S) -> Result<Self, WsError> {
         standard_handshake_resp_check(key.as_bytes(), &resp)?;
-        Ok(Self::new_with(stream, Default::default()))
+        let config = FrameConfig {
+            expect_mask: Some(false),
+            ..Default::default()
+        };
+        let mut codec = Self::new_with(stream, config);
+        codec.read_state.feed_remain(resp.extensions());
+        Ok(codec)
     }
 
     /// receive a frame
     pub fn receive(&mut self) -> Result<(SimplifiedHeader, &[u8]), WsError> {
-        self.read_state.receive(&mut self.stream)
+        self.check_receive()?;
+        let (header, data) = self.read_state.receive(&mut self.stream)?;
+        if let Some(echo) = self.write_state.close_reply(&header, data) {
+            self.write_state
+                .send(&mut self.stream, OpCode::Close, echo)?;
+        }
+        check_close(self.write_state.config.close_as_error, &header, data)?;
+        Ok((header, data))
+    }
+
+    /// receive a frame, fail if it's not complete before deadline
+    ///
+    /// see `FrameReadState::receive_deadline`
+    pub fn receive_deadline(
+        &mut self,
+        deadline: Instant,
+    ) -> Result<(SimplifiedHeader, &[u8]), WsError> {
+        self.check_receive()?;
+        let (header, data) = self
+            .read_state
+            .receive_deadline(&mut self.stream, deadline)?;
+        if let Some(echo) = self.write_state.close_reply(&header, data) {
+            self.write_state
+                .send(&mut self.stream, OpCode::Close, echo)?;
+        }
+        check_close(self.write_state.config.close_as_error, &header, data)?;
+        Ok((header, data))
+    }
+
+    /// receive a complete message
+    ///
+    /// control frames interleaved between fragments are handled internally,
+    /// ping is answered with pong and pong is dropped, close is returned
+    pub fn receive_reassembled(&mut self) -> Result<(SimplifiedHeader, &[u8]), WsError> {
+        self.check_receive()?;
+        loop {
+            let (header, loc) = self.read_state.receive_loc(&mut self.stream, None)?;
+            if self.read_state.is_fragmented() {
+                match header.code {
+                    OpCode::Ping => {
+                        let payload = self.read_state.payload(&loc).to_vec();
+                        self.write_state
+                            .send(&mut self.stream, OpCode::Pong, &payload)?;
+                        continue;
+                    }
+                    OpCode::Pong => continue,
+                    _ => {}
+                }
+            }
+            self.read_state.record_size(&loc);
+            let data = self.read_state.payload(&loc);
+            if let Some(echo) = self.write_state.close_reply(&header, data) {
+                self.write_state
+                    .send(&mut self.stream, OpCode::Close, echo)?;
+            }
+            check_close(self.write_state.config.close_as_error, &header, data)?;
+            return Ok((header, data));
+        }
+    }
+
+    /// receive next data message, control frames are handled internally
+    ///
+    /// ping is answered with pong, pong is dropped, fragments are reassembled
+    /// and close frame is echoed then reported as `Event::Closed`, requires
+    /// `merge_frame` to be enabled
+    pub fn receive_message(&mut self) -> Result<Event<&[u8]>, WsError> {
+        self.check_receive()?;
+        loop {
+            let (header, loc) = self.read_state.receive_loc(&mut self.stream, None)?;
+            match header.code {
+                OpCode::Ping => {
+                    let payload = self.read_state.payload(&loc).to_vec();
+                    self.write_state
+                        .send(&mut self.stream, OpCode::Pong, &payload)?;
+                }
+                OpCode::Pong => {}
+                OpCode::Close => {
+                    let data = self.read_state.payload(&loc);
+                    let echo = if data.len() >= 2 { &data[..2] } else { &[] };
+                    self.write_state
+                        .send(&mut self.stream, OpCode::Close, echo)?;
+                    return Ok(Event::Closed(CloseEvent::from_payload(data)));
+                }
+                OpCode::Text | OpCode::Binary => {
+                    self.read_state.record_size(&loc);
+                    let data = self.read_state.payload(&loc);
+                    return Ok(if header.code == OpCode::Text {
+                        Event::Text(data)
+                    } else {
+                        Event::Binary(data)
+                    });
+                }
+                code => return Err(WsError::UnsupportedFrame(code)),
+            }
+        }
+    }
+
+    /// number of bytes read from stream but not parsed yet
+    pub fn buffered_len(&self) -> usize {
+        self.read_state.buffered_len()
+    }
+
+    /// max number of unparsed bytes ever buffered
+    pub fn buffered_high_water_mark(&self) -> usize {
+        self.read_state.buffered_high_water_mark()
+    }
+
+    /// histogram of received message payload size
+    #[cfg(feature = "metrics")]
+    pub fn size_histogram(&self) -> &hdrhistogram::Histogram<u64> {
+        self.read_state.size_histogram()
+    }
+
+    /// current closing handshake state
+    pub fn close_state(&self) -> CloseState {
+        CloseState::from_flags(
+            self.write_state.close_sent(),
+            self.read_state.close_received(),
+        )
+    }
+
+    fn check_receive(&self) -> Result<(), WsError> {
+        self.read_state.check_after_close()?;
+        self.close_state().check_receive()
+    }
+
+    /// set masking key generator of outgoing frames, see `FrameWriteState::set_mask_fn`
+    pub fn set_mask_fn<F: FnMut() -> [u8; 4] + Send + 'static>(&mut self, f: F) {
+        self.write_state.set_mask_fn(f);
+    }
+
+    /// current connection state, sending frames other than close is rejected
+    /// once it's closing, so is receiving after peer close frame
+    pub fn state(&self) -> ConnectionState {
+        if !self.write_state.frame_sent() && !self.read_state.frame_received() {
+            return ConnectionState::Created;
+        }
+        self.close_state().into()
+    }
+
+    /// reset codec for a fresh logical session over the same stream, close
+    /// state goes back to `Open`, fragmented message, stats and buffered bytes
+    /// are dropped
+    ///
+    /// **NOTE** advanced api, only valid if the underlying transport is really
+    /// reusable after closing handshake, such as some tunnels
+    pub fn reset(&mut self) {
+        self.read_state.reset();
+        self.write_state.reset();
+    }
+
+    /// receive a single frame, fragmented frames are not merged
+    pub fn receive_frame(&mut self) -> Result<(SimplifiedHeader, &[u8]), WsError> {
+        self.check_receive()?;
+        let (header, data) = self.read_state.receive_frame(&mut self.stream)?;
+        if let Some(echo) = self.write_state.close_reply(&header, data) {
+            self.write_state
+                .send(&mut self.stream, OpCode::Close, echo)?;
+        }
+        check_close(self.write_state.config.close_as_error, &header, data)?;
+        Ok((header, data))
+    }
+
+    /// iterate received frames, payload is copied, see `receive`
+    ///
+    /// iterator stops after yielding close frame or first error
+    pub fn messages(&mut self) -> impl Iterator<Item = Result<OwnedFrame, WsError>> + '_ {
+        until_close(
+            || {
+                let (header, data) = self.receive()?;
+                Ok(to_owned_frame(&header, data))
+            },
+            |frame| frame.header().opcode() == OpCode::Close,
+        )
+    }
+
+    /// receive a single frame which borrows codec read buffer without copying
+    /// payload, fragmented frames are not merged
+    ///
+    /// **NOTE** returned frame must be dropped before next receive, see
+    /// `FrameReadState::receive_borrowed`
+    pub fn receive_borrowed(&mut self) -> Result<BorrowedFrame<'_>, WsError> {
+        self.check_receive()?;
+        let frame = self.read_state.receive_borrowed(&mut self.stream)?;
+        let header: SimplifiedHeader = frame.header().into();
+        if let Some(echo) = self.write_state.close_reply(&header, frame.payload()) {
+            self.write_state
+                .send(&mut self.stream, OpCode::Close, echo)?;
+        }
+        check_close(
+            self.write_state.config.close_as_error,
+            &header,
+            frame.payload(),
+        )?;
+        Ok(frame)
     }
 
     /// send data, **will copy data if need mask**
     pub fn send(&mut self, code: OpCode, payload: &[u8]) -> Result<(), WsError> {
+        self.close_state().check_send(code)?;
         self.write_state
             .send(&mut self.stream, code, payload)
-            .map_err(WsError::IOError)
+            .map_err(WsError::from)
+    }
+
+    /// send a single frame with explicit fin bit, **this method do not fragment**
+    pub fn send_frame(&mut self, code: OpCode, payload: &[u8], fin: bool) -> Result<(), WsError> {
+        self.close_state().check_send(code)?;
+        self.write_state
+            .send_frame(&mut self.stream, fin, code, payload)
+            .map_err(WsError::from)
+    }
+
+    /// send a single frame whose payload is concatenation of `parts`, see
+    /// `FrameWriteState::send_vectored`
+    pub fn send_vectored(&mut self, code: OpCode, parts: &[&[u8]]) -> Result<(), WsError> {
+        self.close_state().check_send(code)?;
+        self.write_state
+            .send_vectored(&mut self.stream, code, parts)
+            .map_err(WsError::from)
+    }
+
+    /// send chunks as a fragmented message, see `FrameWriteState::send_fragmented`
+    pub fn send_fragmented<'a, I: IntoIterator<Item = &'a [u8]>>(
+        &mut self,
+        code: OpCode,
+        chunks: I,
+    ) -> Result<(), WsError> {
+        self.close_state().check_send(code)?;
+        self.write_state
+            .send_fragmented(&mut self.stream, code, chunks)
     }
 
     /// send a read frame, **this method will not check validation of frame and do not fragment**
     pub fn send_owned_frame(&mut self, frame: OwnedFrame) -> Result<(), WsError> {
+        self.close_state().check_send(frame.header().opcode())?;
         self.write_state
             .send_owned_frame(&mut self.stream, frame)
-            .map_err(WsError::IOError)
+            .map_err(WsError::from)
+    }
+
+    /// send close frame for protocol error with masking matching role, do
+    /// nothing for other errors
+    pub fn close_with_error(&mut self, error: &WsError) -> Result<(), WsError> {
+        self.write_state
+            .send_error_close(&mut self.stream, error)
+            .map_err(WsError::from)
+    }
+
+    /// perform closing handshake, send close frame, drop received frames until
+    /// peer close frame arrives, then flush stream
+    ///
+    /// wait at most `FrameConfig::close_timeout`, deadline is checked between
+    /// reads, set read timeout of underlying stream to avoid blocking forever,
+    /// reason is truncated to fit in a close frame
+    ///
+    /// after it, sending frames other than close returns `WsError::InvalidConnState`
+    pub fn close(&mut self, code: u16, reason: &str) -> Result<(), WsError> {
+        let payload = close_payload(code, reason);
+        self.write_state
+            .send(&mut self.stream, OpCode::Close, &payload)?;
+        self.stream.flush()?;
+        let deadline = self
+            .read_state
+            .config
+            .close_timeout
+            .map(|timeout| Instant::now() + timeout);
+        while !self.read_state.close_received() {
+            self.read_state.receive_loc(&mut self.stream, deadline)?;
+        }
+        Ok(())
+    }
+
+    /// send a prepared masked frame
+    pub fn send_prepared(&mut self, frame: &PreparedMaskedFrame) -> Result<(), WsError> {
+        self.close_state()
+            .check_send(frame.frame().header().opcode())?;
+        self.write_state
+            .send_prepared(&mut self.stream, frame)
+            .map_err(WsError::from)
+    }
+
+    /// send a frame encoded once by `frame::encode_once`, such as one message
+    /// fanned out to many connections
+    ///
+    /// **NOTE** frame bytes bypass masking of this connection, only use it on
+    /// server side
+    pub fn send_encoded(&mut self, frame: &Bytes) -> Result<(), WsError> {
+        if let Some(&first) = frame.first() {
+            self.close_state().check_send(parse_opcode(first))?;
+        }
+        self.write_state
+            .send_encoded(&mut self.stream, frame)
+            .map_err(WsError::from)
     }
 
     /// flush stream to ensure all data are send
     pub fn flush(&mut self) -> Result<(), WsError> {
-        self.stream.flush().map_err(WsError::IOError)
+        self.stream.flush().map_err(WsError::from)
     }
 }
 
@@ -406,3 +1094,823 @@ where
         )
     }
 }
+
+impl<S: Read + Write> FrameRead for FrameCodec<S> {
+    fn recv(&mut self) -> Result<OwnedFrame, WsError> {
+        let (header, data) = self.receive()?;
+        Ok(to_owned_frame(&header, data))
+    }
+}
+
+impl<S: Read + Write> FrameWrite for FrameCodec<S> {
+    fn send_owned_frame(&mut self, frame: OwnedFrame) -> Result<(), WsError> {
+        FrameCodec::send_owned_frame(self, frame)
+    }
+}
+
+impl<S: Read> FrameRead for FrameRecv<S> {
+    fn recv(&mut self) -> Result<OwnedFrame, WsError> {
+        let (header, data) = self.receive()?;
+        Ok(to_owned_frame(&header, data))
+    }
+}
+
+impl<S: Write> FrameWrite for FrameSend<S> {
+    fn send_owned_frame(&mut self, frame: OwnedFrame) -> Result<(), WsError> {
+        FrameSend::send_owned_frame(self, frame)
+    }
+}
+
+#[test]
+fn test_data_frame_after_fragmented() {
+    use crate::errors::ProtocolError;
+
+    let mut first = OwnedFrame::text_frame(None, "hello ");
+    first.header_mut().set_fin(false);
+    let second = OwnedFrame::binary_frame(None, b"world");
+    let mut data = vec![];
+    for frame in [first, second] {
+        data.extend_from_slice(frame.header().as_bytes());
+        data.extend_from_slice(frame.payload());
+    }
+
+    let mut state = FrameReadState::default();
+    match state.receive(&mut data.as_slice()) {
+        Err(WsError::ProtocolError {
+            close_code,
+            error: ProtocolError::NotContinueFrameAfterFragmented,
+        }) => assert_eq!(close_code, 1002),
+        other => panic!("expect protocol error, got {:?}", other.map(|(h, _)| h)),
+    }
+
+    let mut state = FrameReadState::default();
+    let mut stream = data.as_slice();
+    let (header, _) = state.receive_frame(&mut stream).unwrap();
+    assert!(!header.fin);
+    match state.receive_frame(&mut stream) {
+        Err(WsError::ProtocolError {
+            close_code,
+            error: ProtocolError::NotContinueFrameAfterFragmented,
+        }) => assert_eq!(close_code, 1002),
+        other => panic!("expect protocol error, got {:?}", other.map(|(h, _)| h)),
+    }
+}
+
+#[test]
+fn test_buffered_len() {
+    let first = OwnedFrame::text_frame(None, "hello");
+    let second = OwnedFrame::binary_frame(None, b"world!");
+    let mut data = vec![];
+    for frame in [&first, &second] {
+        data.extend_from_slice(frame.header().as_bytes());
+        data.extend_from_slice(frame.payload());
+    }
+    let second_len = second.header().as_bytes().len() + second.payload().len();
+
+    let mut codec = FrameCodec::new(std::io::Cursor::new(data));
+    assert_eq!(codec.buffered_len(), 0);
+    let (_, payload) = codec.receive().unwrap();
+    assert_eq!(payload, b"hello");
+    assert_eq!(codec.buffered_len(), second_len);
+    assert!(codec.buffered_high_water_mark() >= codec.buffered_len());
+    codec.receive().unwrap();
+    assert_eq!(codec.buffered_len(), 0);
+}
+
+#[test]
+fn test_connection_lost() {
+    struct ResetStream;
+
+    impl Read for ResetStream {
+        fn read(&mut self, _buf: &mut [u8]) -> IOResult<usize> {
+            Err(std::io::ErrorKind::ConnectionReset.into())
+        }
+    }
+
+    impl Write for ResetStream {
+        fn write(&mut self, _buf: &[u8]) -> IOResult<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> IOResult<()> {
+            Ok(())
+        }
+    }
+
+    let mut codec = FrameCodec::new(ResetStream);
+    match codec.receive() {
+        Err(WsError::ConnectionLost(e)) => {
+            assert_eq!(e.kind(), std::io::ErrorKind::ConnectionReset)
+        }
+        other => panic!("expect connection lost, got {:?}", other.map(|_| ())),
+    }
+    match codec.send(OpCode::Binary, b"hello") {
+        Err(WsError::ConnectionLost(e)) => assert_eq!(e.kind(), std::io::ErrorKind::BrokenPipe),
+        other => panic!("expect connection lost, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_send_prepared() {
+    let heartbeat = PreparedMaskedFrame::new(OpCode::Text, [1, 2, 3, 4], b"heartbeat");
+    let mut codec = FrameCodec::new(std::io::Cursor::new(vec![]));
+    for _ in 0..3 {
+        codec.send_prepared(&heartbeat).unwrap();
+    }
+    let data = codec.stream_mut().get_ref().clone();
+    let mut codec = FrameCodec::new(std::io::Cursor::new(data));
+    for _ in 0..3 {
+        let (header, payload) = codec.receive().unwrap();
+        assert_eq!(header.code, OpCode::Text);
+        assert_eq!(payload, b"heartbeat");
+    }
+    assert_eq!(
+        &heartbeat.frame().payload()[..4],
+        &[b'h' ^ 1, b'e' ^ 2, b'a' ^ 3, b'r' ^ 4]
+    );
+}
+
+#[test]
+fn test_reject_fragmented_control_header() {
+    // fin=false ping header claiming 5 bytes payload, payload never arrives
+    let mut stream = std::io::Cursor::new(vec![0x09, 0x05]);
+    let mut state = FrameReadState::default();
+    state.poll(&mut stream, None).unwrap();
+    assert!(matches!(
+        state.peek_header(),
+        Err(WsError::ProtocolError {
+            close_code: 1002,
+            error: crate::errors::ProtocolError::FragmentedControlFrame
+        })
+    ));
+
+    let mut codec = FrameCodec::new(std::io::Cursor::new(vec![0x09, 0x05]));
+    assert!(matches!(
+        codec.receive(),
+        Err(WsError::ProtocolError {
+            close_code: 1002,
+            error: crate::errors::ProtocolError::FragmentedControlFrame
+        })
+    ));
+
+    let mut stream = std::io::Cursor::new(vec![0x89, 0x05]);
+    let mut state = FrameReadState::default();
+    state.poll(&mut stream, None).unwrap();
+    let (header, payload_len) = state.peek_header().unwrap().unwrap();
+    assert_eq!(header.code, OpCode::Ping);
+    assert_eq!(payload_len, 5);
+    assert_eq!(state.buffered_len(), 2);
+}
+
+#[test]
+fn test_receive_deadline() {
+    use std::time::Duration;
+
+    /// yield one byte per read
+    struct Trickle(std::io::Cursor<Vec<u8>>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+            std::thread::sleep(Duration::from_millis(5));
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    let mut data = vec![0x82, 100];
+    data.extend_from_slice(&[0; 100]);
+    let mut recv = FrameRecv::new(Trickle(std::io::Cursor::new(data)), Default::default());
+    match recv.receive_deadline(Instant::now() + Duration::from_millis(50)) {
+        Err(e @ WsError::DeadlineExceeded) => assert!(e.close_event().is_none()),
+        other => panic!("expect timeout, got {:?}", other.map(|_| ())),
+    }
+
+    let mut recv = FrameRecv::new(
+        Trickle(std::io::Cursor::new(vec![0x82, 2, 1, 2])),
+        Default::default(),
+    );
+    let (header, data) = recv
+        .receive_deadline(Instant::now() + Duration::from_secs(5))
+        .unwrap();
+    assert_eq!(header.code, OpCode::Binary);
+    assert_eq!(data, &[1, 2]);
+}
+
+#[test]
+fn test_empty_fragments() {
+    let cases: [(&[u8], &[u8]); 3] = [
+        // data fragment then empty terminating continuation
+        (&[0x01, 0x03, b'a', b'b', b'c', 0x80, 0x00], b"abc"),
+        // empty initial fragment then data continuation
+        (&[0x01, 0x00, 0x80, 0x03, b'a', b'b', b'c'], b"abc"),
+        // all fragments empty
+        (&[0x01, 0x00, 0x00, 0x00, 0x80, 0x00], b""),
+    ];
+    for (data, expect) in cases {
+        let mut codec = FrameCodec::new(std::io::Cursor::new(data.to_vec()));
+        let (header, payload) = codec.receive().unwrap();
+        assert!(header.fin);
+        assert_eq!(header.code, OpCode::Text);
+        assert_eq!(payload, expect);
+        assert!(!codec.read_state.is_fragmented());
+    }
+}
+
+#[test]
+fn test_fragment_ordering() {
+    use crate::errors::ProtocolError;
+
+    fn frame(fin: bool, code: OpCode, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![(fin as u8) << 7 | code as u8, payload.len() as u8];
+        data.extend_from_slice(payload);
+        data
+    }
+    let (text, cont, ping, pong) = (OpCode::Text, OpCode::Continue, OpCode::Ping, OpCode::Pong);
+    type Case<'a> = (Vec<(bool, OpCode, &'a [u8])>, Vec<(OpCode, &'a [u8])>, bool);
+    // (frames sent, messages received, ends with 1002 protocol error)
+    let cases: Vec<Case> = vec![
+        // 5.1, 5.2 fragmented control frame
+        (vec![(false, ping, b"a"), (true, cont, b"b")], vec![], true),
+        (vec![(false, pong, b"a"), (true, cont, b"b")], vec![], true),
+        // 5.3 text in two fragments
+        (
+            vec![(false, text, b"frag1"), (true, cont, b"frag2")],
+            vec![(text, b"frag1frag2")],
+            false,
+        ),
+        // 5.6 text in two fragments with ping in between
+        (
+            vec![(false, text, b"a"), (true, ping, b"p"), (true, cont, b"b")],
+            vec![(ping, b"p"), (text, b"ab")],
+            false,
+        ),
+        // 5.9, 5.10 continuation without initial fragment
+        (vec![(true, cont, b"a"), (true, text, b"b")], vec![], true),
+        (vec![(false, cont, b"a"), (true, text, b"b")], vec![], true),
+        // 5.15 continuation after a complete fragmented message
+        (
+            vec![
+                (false, text, b"a"),
+                (true, cont, b"b"),
+                (false, cont, b"c"),
+                (true, text, b"d"),
+            ],
+            vec![(text, b"ab")],
+            true,
+        ),
+        // 5.18 new data frame before fragmented message completes
+        (vec![(false, text, b"a"), (true, text, b"b")], vec![], true),
+        // 5.19 fragments with several pings interleaved
+        (
+            vec![
+                (false, text, b"a"),
+                (false, cont, b"b"),
+                (true, ping, b"1"),
+                (false, cont, b"c"),
+                (true, ping, b"2"),
+                (true, cont, b"d"),
+            ],
+            vec![(ping, b"1"), (ping, b"2"), (text, b"abcd")],
+            false,
+        ),
+    ];
+    for (frames, expect, protocol_error) in cases {
+        let data: Vec<u8> = frames
+            .into_iter()
+            .flat_map(|(fin, code, payload)| frame(fin, code, payload))
+            .collect();
+        let mut stream = data.as_slice();
+        let mut state = FrameReadState::default();
+        for (code, payload) in expect {
+            let (header, data) = state.receive(&mut stream).unwrap();
+            assert_eq!((header.code, data), (code, payload));
+        }
+        if protocol_error {
+            match state.receive(&mut stream) {
+                Err(WsError::ProtocolError { close_code, .. }) => assert_eq!(close_code, 1002),
+                other => panic!("expect protocol error, got {:?}", other.map(|(h, _)| h)),
+            }
+        } else {
+            assert!(stream.is_empty());
+        }
+    }
+
+    let mut data = frame(true, OpCode::Close, &1000u16.to_be_bytes());
+    data.extend(frame(true, text, b"late"));
+    let mut state = FrameReadState::default();
+    let mut stream = data.as_slice();
+    state.receive(&mut stream).unwrap();
+    assert_eq!(state.receive(&mut stream).unwrap().1, b"late");
+
+    let config = FrameConfig::builder()
+        .strict_ordering(true)
+        .build()
+        .unwrap();
+    let mut state = FrameReadState::with_config(config.clone());
+    let mut stream = data.as_slice();
+    state.receive(&mut stream).unwrap();
+    match state.receive(&mut stream) {
+        Err(WsError::ProtocolError {
+            close_code,
+            error: ProtocolError::FrameAfterClose,
+        }) => assert_eq!(close_code, 1002),
+        other => panic!("expect protocol error, got {:?}", other.map(|(h, _)| h)),
+    }
+
+    // codec reports frame after close instead of invalid state
+    let mut codec = FrameCodec::new_with(std::io::Cursor::new(data.clone()), config);
+    assert_eq!(codec.receive().unwrap().0.code, OpCode::Close);
+    assert!(matches!(
+        codec.receive(),
+        Err(WsError::ProtocolError {
+            close_code: 1002,
+            error: ProtocolError::FrameAfterClose,
+        })
+    ));
+    let mut codec = FrameCodec::new(std::io::Cursor::new(data));
+    assert_eq!(codec.receive().unwrap().0.code, OpCode::Close);
+    assert!(matches!(
+        codec.receive(),
+        Err(WsError::InvalidConnState(ConnectionState::Closing))
+    ));
+}
+
+#[test]
+fn test_send_control_frame() {
+    let config = FrameConfig {
+        auto_fragment_size: 4,
+        renew_buf_on_write: true,
+        ..Default::default()
+    };
+    let mut state = FrameWriteState::with_config(config);
+    let mut out = vec![];
+    state.send(&mut out, OpCode::Ping, b"heartbeat").unwrap();
+    // control frame is sent as a single masked frame
+    assert_eq!(out.len(), 2 + 4 + 9);
+    assert_eq!(out[0], 0x89);
+    assert_eq!(out[1], 0x80 | 9);
+    assert!(state.buf.is_empty());
+
+    let mut read_state = FrameReadState::default();
+    let (header, data) = read_state.receive(&mut out.as_slice()).unwrap();
+    assert_eq!(header.code, OpCode::Ping);
+    assert_eq!(data, b"heartbeat");
+
+    let mut state = FrameWriteState::with_config(FrameConfig {
+        mask_send_frame: false,
+        ..Default::default()
+    });
+    let mut out = vec![];
+    state.send(&mut out, OpCode::Pong, b"pong").unwrap();
+    assert_eq!(out, [0x8a, 0x04, b'p', b'o', b'n', b'g']);
+}
+
+#[test]
+fn test_close_with_error() {
+    use crate::errors::ProtocolError;
+
+    let error = WsError::ProtocolError {
+        close_code: 1002,
+        error: ProtocolError::MissInitialFragmentedFrame,
+    };
+    let server_config = FrameConfig {
+        mask_send_frame: false,
+        ..Default::default()
+    };
+    let mut server = FrameCodec::new_with(std::io::Cursor::new(vec![]), server_config);
+    server.close_with_error(&error).unwrap();
+    // close frame sent only once
+    server.close_with_error(&error).unwrap();
+    let mut client = FrameCodec::new(std::io::Cursor::new(vec![]));
+    client.close_with_error(&error).unwrap();
+    client
+        .close_with_error(&WsError::InvalidUri("".into()))
+        .unwrap();
+
+    for (data, masked) in [
+        (server.stream.into_inner(), false),
+        (client.stream.into_inner(), true),
+    ] {
+        assert_eq!(data[0], 0x88);
+        assert_eq!(data[1] & 0x80 != 0, masked);
+        let mut state = FrameReadState::default();
+        let mut stream = data.as_slice();
+        let (header, payload) = state.receive(&mut stream).unwrap();
+        assert_eq!(header.code, OpCode::Close);
+        assert_eq!(&payload[..2], &1002u16.to_be_bytes());
+        assert_eq!(&payload[2..], b"missing init fragmented frame");
+        assert!(stream.is_empty());
+    }
+}
+
+#[test]
+fn test_max_payload_size() {
+    use crate::errors::ProtocolError;
+
+    // binary frame header claiming 2^62 bytes payload, without payload
+    let mut data = vec![0x82, 127];
+    data.extend_from_slice(&(1u64 << 62).to_be_bytes());
+    let config = FrameConfig::builder()
+        .max_frame_payload_size(1024)
+        .build()
+        .unwrap();
+    let mut codec = FrameCodec::new_with(std::io::Cursor::new(data), config);
+    match codec.receive() {
+        Err(WsError::ProtocolError {
+            close_code,
+            error: ProtocolError::PayloadTooLarge(1024),
+        }) => assert_eq!(close_code, 1009),
+        other => panic!("expect protocol error, got {:?}", other.map(|(h, _)| h)),
+    }
+
+    // most significant bit of 64 bit payload len is set
+    let mut data = vec![0x82, 127];
+    data.extend_from_slice(&u64::MAX.to_be_bytes());
+    let mut codec = FrameCodec::new(std::io::Cursor::new(data));
+    assert!(matches!(
+        codec.receive(),
+        Err(WsError::ProtocolError {
+            error: ProtocolError::InvalidLeadingLen(127),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_max_message_size() {
+    use crate::errors::ProtocolError;
+
+    fn frame(fin: bool, code: OpCode, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![(fin as u8) << 7 | code as u8, payload.len() as u8];
+        data.extend_from_slice(payload);
+        data
+    }
+    let config = FrameConfig::builder().max_message_size(10).build().unwrap();
+
+    // every fragment is small, but they sum past limit
+    let mut data = frame(false, OpCode::Text, b"abcd");
+    data.extend(frame(false, OpCode::Continue, b"efgh"));
+    data.extend(frame(true, OpCode::Continue, b"ijk"));
+    let mut state = FrameReadState::with_config(config.clone());
+    match state.receive(&mut data.as_slice()) {
+        Err(WsError::ProtocolError {
+            close_code,
+            error: ProtocolError::PayloadTooLarge(10),
+        }) => assert_eq!(close_code, 1009),
+        other => panic!("expect protocol error, got {:?}", other.map(|(h, _)| h)),
+    }
+
+    let mut data = frame(false, OpCode::Text, b"abcd");
+    data.extend(frame(true, OpCode::Continue, b"efghij"));
+    data.extend(frame(true, OpCode::Binary, b"0123456789a"));
+    let mut stream = data.as_slice();
+    let mut state = FrameReadState::with_config(config);
+    assert_eq!(state.receive(&mut stream).unwrap().1, b"abcdefghij");
+    assert!(matches!(
+        state.receive(&mut stream),
+        Err(WsError::ProtocolError {
+            close_code: 1009,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_set_mask_fn() {
+    let mut counter = 0u8;
+    let mut state = FrameWriteState::default();
+    state.set_mask_fn(move || {
+        counter += 1;
+        [counter; 4]
+    });
+    let mut out = vec![];
+    state.send(&mut out, OpCode::Text, b"abc").unwrap();
+    state.send(&mut out, OpCode::Ping, b"").unwrap();
+    state
+        .send_frame(&mut out, true, OpCode::Binary, b"d")
+        .unwrap();
+    state.reset();
+    state.send(&mut out, OpCode::Pong, b"").unwrap();
+    // key of each frame comes from mask fn, also after reset
+    assert_eq!(&out[2..6], &[1; 4]);
+    assert_eq!(&out[11..15], &[2; 4]);
+    assert_eq!(&out[17..21], &[3; 4]);
+    assert_eq!(&out[24..28], &[4; 4]);
+    assert_eq!(out.len(), 28);
+
+    let mut read_state = FrameReadState::default();
+    let mut stream = out.as_slice();
+    let (header, data) = read_state.receive(&mut stream).unwrap();
+    assert_eq!(header.code, OpCode::Text);
+    assert_eq!(data, b"abc");
+}
+
+#[test]
+fn test_on_frame_hook() {
+    use std::sync::{Arc, Mutex};
+
+    let frames = Arc::new(Mutex::new(vec![]));
+    let recorder = frames.clone();
+    let config = FrameConfig::builder()
+        .on_frame(move |direction, header| {
+            let mut frames = recorder.lock().unwrap();
+            frames.push((direction, header.opcode(), header.payload_len()));
+        })
+        .build()
+        .unwrap();
+    let mut state = FrameWriteState::with_config(config.clone());
+    let mut out = vec![];
+    state.send(&mut out, OpCode::Text, b"abc").unwrap();
+    state.send(&mut out, OpCode::Ping, b"").unwrap();
+    state.send(&mut out, OpCode::Pong, b"pong").unwrap();
+    state
+        .send_frame(&mut out, true, OpCode::Binary, b"d")
+        .unwrap();
+
+    let mut read_state = FrameReadState::with_config(config);
+    let mut stream = out.as_slice();
+    for _ in 0..4 {
+        read_state.receive(&mut stream).unwrap();
+    }
+    let expect = [
+        (OpCode::Text, 3),
+        (OpCode::Ping, 0),
+        (OpCode::Pong, 4),
+        (OpCode::Binary, 1),
+    ];
+    let frames = frames.lock().unwrap();
+    let outbound: Vec<_> = expect
+        .iter()
+        .map(|(code, len)| (Direction::Outbound, *code, *len))
+        .collect();
+    let inbound: Vec<_> = expect
+        .iter()
+        .map(|(code, len)| (Direction::Inbound, *code, *len))
+        .collect();
+    assert_eq!(frames[..4], outbound[..]);
+    assert_eq!(frames[4..], inbound[..]);
+
+    // panic of hook does not unwind through codec
+    let config = FrameConfig::builder()
+        .on_frame(|_, _| panic!("hook panicked"))
+        .build()
+        .unwrap();
+    let mut state = FrameWriteState::with_config(config);
+    let mut out = vec![];
+    state.send(&mut out, OpCode::Text, b"abc").unwrap();
+    assert_eq!(out.len(), 2 + 4 + 3);
+}
+
+#[test]
+fn test_send_fragmented() {
+    use crate::errors::ProtocolError;
+
+    let mut client = FrameCodec::new(std::io::Cursor::new(vec![]));
+    let chunks: Vec<&[u8]> = vec![b"hel", b"lo ", b"world"];
+    client.send_fragmented(OpCode::Text, chunks).unwrap();
+    client.send_fragmented(OpCode::Binary, []).unwrap();
+    assert!(matches!(
+        client.send_fragmented(OpCode::Ping, [&b"ping"[..]]),
+        Err(WsError::ProtocolError {
+            error: ProtocolError::FragmentedControlFrame,
+            ..
+        })
+    ));
+    assert!(matches!(
+        client.send_fragmented(OpCode::Continue, [&b"data"[..]]),
+        Err(WsError::ProtocolError {
+            error: ProtocolError::MissInitialFragmentedFrame,
+            ..
+        })
+    ));
+
+    let data = client.stream.into_inner();
+    let mut state = FrameReadState::with_config(FrameConfig {
+        merge_frame: false,
+        ..Default::default()
+    });
+    let mut stream = data.as_slice();
+    let mut frames = vec![];
+    for _ in 0..4 {
+        let (header, payload) = state.receive(&mut stream).unwrap();
+        frames.push((header.fin, header.code, payload.to_vec()));
+    }
+    assert_eq!(state.buffered_len(), 0);
+    assert_eq!(
+        frames,
+        vec![
+            (false, OpCode::Text, b"hel".to_vec()),
+            (false, OpCode::Continue, b"lo ".to_vec()),
+            (true, OpCode::Continue, b"world".to_vec()),
+            (true, OpCode::Binary, vec![]),
+        ]
+    );
+}
+
+#[test]
+fn test_receive_borrowed() {
+    let mask = [1, 2, 3, 4];
+    let mut first = OwnedFrame::text_frame(Some(mask), "hello");
+    first.header_mut().set_fin(false);
+    let second = OwnedFrame::new(OpCode::Continue, None, b" world");
+    let mut data = vec![];
+    for frame in [&first, &second] {
+        data.extend_from_slice(frame.header().as_bytes());
+        data.extend_from_slice(frame.payload());
+    }
+
+    let mut codec = FrameCodec::new(std::io::Cursor::new(data));
+    let frame = codec.receive_borrowed().unwrap();
+    assert_eq!(frame.header().opcode(), OpCode::Text);
+    assert!(!frame.header().fin());
+    assert_eq!(frame.header().masking_key(), None);
+    assert_eq!(frame.header().payload_len(), 5);
+    assert_eq!(frame.payload(), b"hello");
+    let mut owned = frame.to_owned();
+    assert_eq!(owned.unmask(), None);
+    assert_eq!(owned.payload().as_ref(), b"hello");
+    let frame = codec.receive_borrowed().unwrap();
+    assert_eq!(frame.header().opcode(), OpCode::Continue);
+    assert!(frame.header().fin());
+    assert_eq!(frame.payload(), b" world");
+}
+
+#[test]
+fn test_incremental_utf8() {
+    use super::ValidateUtf8Policy;
+    use crate::errors::ProtocolError;
+
+    let codec = |data: &[u8], policy| {
+        let config = FrameConfig {
+            validate_utf8: policy,
+            ..Default::default()
+        };
+        FrameCodec::new_with(std::io::Cursor::new(data.to_vec()), config)
+    };
+
+    // "é" split across fragments
+    let split: &[u8] = &[0x01, 0x01, 0xC3, 0x80, 0x01, 0xA9];
+    let mut incremental = codec(split, ValidateUtf8Policy::Incremental);
+    let (header, payload) = incremental.receive().unwrap();
+    assert_eq!(header.code, OpCode::Text);
+    assert_eq!(payload, "é".as_bytes());
+    assert!(codec(split, ValidateUtf8Policy::FastFail)
+        .receive()
+        .is_err());
+
+    // rest of message never arrives, fail on first fragment
+    let mut incremental = codec(&[0x01, 0x02, b'a', 0xFF], ValidateUtf8Policy::Incremental);
+    assert!(matches!(
+        incremental.receive(),
+        Err(WsError::ProtocolError {
+            close_code: 1007,
+            error: ProtocolError::InvalidUtf8 { valid_up_to: 1 }
+        })
+    ));
+    let mut incremental = codec(
+        &[0x01, 0x01, 0xC3, 0x80, 0x01, b'a'],
+        ValidateUtf8Policy::Incremental,
+    );
+    assert!(incremental.receive().is_err());
+}
+
+#[test]
+fn test_write_buf_capacity() {
+    let config = FrameConfig::builder()
+        .write_buf_capacity(64)
+        .build()
+        .unwrap();
+    let mut state = FrameWriteState::with_config(config);
+    assert_eq!(state.buf.len(), 64);
+    let ptr = state.buf.as_ptr();
+    let mut out = vec![];
+    for _ in 0..10 {
+        state.send(&mut out, OpCode::Binary, &[1; 32]).unwrap();
+    }
+    // buf is reused instead of reallocated
+    assert_eq!(state.buf.as_ptr(), ptr);
+    assert_eq!(out.len(), 10 * (2 + 4 + 32));
+
+    // only grow when payload exceeds it
+    state.send(&mut out, OpCode::Binary, &[1; 100]).unwrap();
+    assert_eq!(state.buf.len(), 100);
+
+    let config = FrameConfig::builder()
+        .write_buf_capacity(64)
+        .renew_buf_on_write(true)
+        .build()
+        .unwrap();
+    let mut state = FrameWriteState::with_config(config);
+    state.send(&mut out, OpCode::Binary, &[1; 100]).unwrap();
+    assert_eq!(state.buf.len(), 64);
+}
+
+#[test]
+fn test_frame_read_write_traits() {
+    fn forward<R: FrameRead, W: FrameWrite>(read: &mut R, write: &mut W) -> Result<(), WsError> {
+        let frame = read.recv()?;
+        write.send_owned_frame(frame)
+    }
+
+    let config = FrameConfig {
+        merge_frame: false,
+        mask_send_frame: false,
+        ..Default::default()
+    };
+    let data = vec![0x01, 0x03, b'a', b'b', b'c', 0x80, 0x01, b'd'];
+    let mut recv = FrameRecv::new(
+        std::io::Cursor::new(data.clone()),
+        FrameReadState::with_config(config.clone()),
+    );
+    let mut codec = FrameCodec::new_with(std::io::Cursor::new(vec![]), config);
+    forward(&mut recv, &mut codec).unwrap();
+    forward(&mut recv, &mut codec).unwrap();
+    assert_eq!(codec.stream_mut().get_ref(), &data);
+}
+
+#[test]
+fn test_send_vectored() {
+    /// count write calls
+    #[derive(Default)]
+    struct Counter {
+        data: Vec<u8>,
+        writes: usize,
+    }
+
+    impl Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.writes += 1;
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+            self.writes += 1;
+            bufs.iter().for_each(|buf| self.data.extend_from_slice(buf));
+            Ok(bufs.iter().map(|buf| buf.len()).sum())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let payload = vec![b'x'; 1024];
+    for mask_send_frame in [false, true] {
+        let config = FrameConfig {
+            mask_send_frame,
+            ..Default::default()
+        };
+        let mut expected = FrameWriteState::with_config(config.clone());
+        expected.set_mask_fn(|| [1, 2, 3, 4]);
+        let mut state = FrameWriteState::with_config(config);
+        state.set_mask_fn(|| [1, 2, 3, 4]);
+        let mut concat = b"\x03\xe8".to_vec();
+        concat.extend_from_slice(&payload);
+        let mut out = vec![];
+        expected.send(&mut out, OpCode::Binary, &concat).unwrap();
+        let mut counter = Counter::default();
+        state
+            .send_vectored(&mut counter, OpCode::Binary, &[b"\x03\xe8", &payload])
+            .unwrap();
+        assert_eq!(counter.data, out);
+        assert_eq!(counter.writes, 1);
+    }
+
+    let mut codec = crate::codec::BytesCodec::new(std::io::Cursor::new(vec![]));
+    codec.close(1000, b"bye").unwrap();
+    let data = codec.stream_mut().get_ref().clone();
+    let mut state = FrameReadState::default();
+    let (header, payload) = state.receive(&mut data.as_slice()).unwrap();
+    assert_eq!(header.code, OpCode::Close);
+    assert_eq!(payload, b"\x03\xe8bye");
+}
+
+#[test]
+fn test_messages() {
+    let data = vec![0x82, 0x01, b'a', 0x88, 0x00, 0x82, 0x01, b'b'];
+    let mut recv = FrameRecv::new(std::io::Cursor::new(data), Default::default());
+    let frames: Vec<_> = recv.messages().map(|frame| frame.unwrap()).collect();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(&frames[0].payload()[..], b"a");
+    assert_eq!(frames[1].header().opcode(), OpCode::Close);
+}
+
+#[test]
+fn test_connection_state_created() {
+    let mut codec = FrameCodec::new(std::io::Cursor::new(vec![]));
+    assert_eq!(codec.state(), ConnectionState::Created);
+    codec.send(OpCode::Text, b"hi").unwrap();
+    assert_eq!(codec.state(), ConnectionState::Running);
+
+    // receiving a frame starts connection too
+    let mut codec = FrameCodec::new(std::io::Cursor::new(vec![0x81, 0x02, b'h', b'i']));
+    assert_eq!(codec.state(), ConnectionState::Created);
+    codec.receive().unwrap();
+    assert_eq!(codec.state(), ConnectionState::Running);
+
+    // closing handshake can start right after creation
+    let mut codec = FrameCodec::new(std::io::Cursor::new(vec![]));
+    codec.send(OpCode::Close, b"").unwrap();
+    assert_eq!(codec.state(), ConnectionState::Closing);
+}
@@ -1,10 +1,18 @@
-use crate::errors::{ProtocolError, WsError};
-use crate::frame::{get_bit, HeaderView, OpCode, SimplifiedHeader};
+use crate::errors::{deadline_exceeded, ProtocolError, WsError};
+use crate::frame::{
+    ctor_header, get_bit, is_valid_close_code, BorrowedFrame, HeaderView, OpCode, OwnedFrame,
+    SimplifiedHeader,
+};
 use http;
-use crate::protocol::{cal_accept_key, standard_handshake_req_check};
+use crate::protocol::{
+    cal_accept_key, is_supported_version, standard_handshake_req_check, RemainBytes,
+};
 use bytes::BytesMut;
 use std::fmt::Debug;
+use std::future::Future;
 use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 #[cfg(feature = "sync")]
 mod blocking;
@@ -12,14 +20,19 @@ mod blocking;
 #[cfg(feature = "sync")]
 pub use blocking::*;
 
-#[cfg(feature = "async")]
+#[cfg(feature = "async_codec")]
 mod non_blocking;
 
-#[cfg(feature = "async")]
+#[cfg(feature = "async_codec")]
 pub use non_blocking::*;
 
+pub use crate::frame::apply_mask;
+
+/// max len of a control frame, 2 bytes header, 4 bytes mask key and 125 bytes payload
+const MAX_CONTROL_FRAME_LEN: usize = 131;
+
 /// text frame utf-8 checking policy
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ValidateUtf8Policy {
     /// no not validate utf
     Off,
@@ -27,6 +40,11 @@ pub enum ValidateUtf8Policy {
     FastFail,
     /// check utf8 after merged
     On,
+    /// check fragments of text message as they arrive, fail as soon as
+    /// received bytes can not be prefix of valid utf8
+    ///
+    /// unlike `FastFail`, a char split across fragments is accepted
+    Incremental,
 }
 
 #[allow(missing_docs)]
@@ -38,21 +56,214 @@ impl ValidateUtf8Policy {
     pub fn is_fast_fail(&self) -> bool {
         matches!(self, Self::FastFail)
     }
+
+    pub fn is_incremental(&self) -> bool {
+        matches!(self, Self::Incremental)
+    }
+}
+
+/// check payload is valid utf8, locate first invalid byte only if it's not
+#[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+pub(crate) fn check_utf8(payload: &[u8]) -> Result<(), WsError> {
+    if simdutf8::basic::from_utf8(payload).is_ok() {
+        return Ok(());
+    }
+    let valid_up_to = simdutf8::compat::from_utf8(payload)
+        .err()
+        .map_or(payload.len(), |e| e.valid_up_to());
+    Err(WsError::invalid_utf8(valid_up_to))
+}
+
+/// utf8 validator of fragmented text message, keeps incomplete trailing
+/// char of last fragment
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+pub(crate) struct Utf8Stream {
+    pending: [u8; 4],
+    pending_len: usize,
+    /// len of validated bytes of message, excluding pending bytes
+    valid_len: usize,
+}
+
+impl Utf8Stream {
+    /// feed next fragment, return offset of first invalid byte in message
+    /// if message can not be valid utf8
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    pub(crate) fn feed(&mut self, mut data: &[u8], fin: bool) -> Result<(), usize> {
+        if self.pending_len > 0 {
+            let width = match self.pending[0] {
+                0xF0.. => 4,
+                0xE0.. => 3,
+                _ => 2,
+            };
+            let take = (width - self.pending_len).min(data.len());
+            self.pending[self.pending_len..(self.pending_len + take)]
+                .copy_from_slice(&data[..take]);
+            self.pending_len += take;
+            data = &data[take..];
+            match simdutf8::compat::from_utf8(&self.pending[..self.pending_len]) {
+                Ok(_) => {
+                    self.valid_len += self.pending_len;
+                    self.pending_len = 0;
+                }
+                // still incomplete, all of data is taken
+                Err(e) if e.error_len().is_none() && !fin => return Ok(()),
+                Err(_) => return Err(self.valid_len),
+            }
+        }
+        match simdutf8::compat::from_utf8(data) {
+            Ok(_) => {
+                self.valid_len += data.len();
+                Ok(())
+            }
+            Err(e) if e.error_len().is_none() && !fin => {
+                let tail = &data[e.valid_up_to()..];
+                self.pending[..tail.len()].copy_from_slice(tail);
+                self.pending_len = tail.len();
+                self.valid_len += e.valid_up_to();
+                Ok(())
+            }
+            Err(e) => Err(self.valid_len + e.valid_up_to()),
+        }
+    }
+}
+
+/// `WsError::Closed` if received frame is close frame and `close_as_error` is enabled
+#[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+pub(crate) fn check_close(
+    close_as_error: bool,
+    header: &SimplifiedHeader,
+    payload: &[u8],
+) -> Result<(), WsError> {
+    if close_as_error && header.code == OpCode::Close {
+        return Err(WsError::closed(payload));
+    }
+    Ok(())
+}
+
+/// copy received frame into an `OwnedFrame`, fin bit is kept
+#[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+pub(crate) fn to_owned_frame(header: &SimplifiedHeader, payload: &[u8]) -> OwnedFrame {
+    let mut frame = OwnedFrame::new(header.code, None, payload);
+    frame.header_mut().set_fin(header.fin);
+    frame
+}
+
+/// receive frame of blocking codec, generic code can be written once for
+/// `FrameCodec` and `FrameRecv`
+///
+/// see `AsyncFrameRead` for async codec
+pub trait FrameRead {
+    /// receive a frame, payload is copied out of read buffer
+    fn recv(&mut self) -> Result<OwnedFrame, WsError>;
+}
+
+/// send frame of blocking codec, implemented by `FrameCodec` and `FrameSend`
+pub trait FrameWrite {
+    /// send a frame as is, it is not validated or fragmented
+    fn send_owned_frame(&mut self, frame: OwnedFrame) -> Result<(), WsError>;
+}
+
+/// async version of `FrameRead`, implemented by `AsyncFrameCodec` and `AsyncFrameRecv`
+pub trait AsyncFrameRead {
+    /// receive a frame, payload is copied out of read buffer
+    fn recv(&mut self) -> impl Future<Output = Result<OwnedFrame, WsError>> + Send;
+}
+
+/// async version of `FrameWrite`, implemented by `AsyncFrameCodec` and `AsyncFrameSend`
+pub trait AsyncFrameWrite {
+    /// send a frame as is, it is not validated or fragmented
+    fn send_owned_frame(
+        &mut self,
+        frame: OwnedFrame,
+    ) -> impl Future<Output = Result<(), WsError>> + Send;
+}
+
+/// direction of a frame passed to `FrameConfig::on_frame` hook
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// frame received from peer
+    Inbound,
+    /// frame sent to peer
+    Outbound,
+}
+
+type FrameHookFn = dyn Fn(Direction, HeaderView<'_>) + Send + Sync;
+
+/// per-frame callback, such as counting frames by opcode or recording payload size
+///
+/// cloned configs share the same callback, configs are equal only if they
+/// share the same callback
+#[derive(Clone)]
+pub struct FrameHook(Arc<FrameHookFn>);
+
+impl FrameHook {
+    /// wrap a callback
+    pub fn new<F: Fn(Direction, HeaderView<'_>) + Send + Sync + 'static>(f: F) -> Self {
+        Self(Arc::new(f))
+    }
+
+    /// panic of callback is caught and logged, it never unwinds through codec
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    fn call(&self, direction: Direction, header: &[u8]) {
+        let f = &self.0;
+        let ret = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            f(direction, HeaderView(header))
+        }));
+        if ret.is_err() {
+            tracing::error!("on_frame hook panicked on {:?} frame", direction);
+        }
+    }
 }
 
+impl Debug for FrameHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("FrameHook").finish()
+    }
+}
+
+impl PartialEq for FrameHook {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for FrameHook {}
+
 /// frame send/recv config
-#[derive(Debug, Clone)]
+///
+/// prefer `FrameConfig::builder()` to struct literal, it validates config
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FrameConfig {
     /// check rsv1 bits
     pub check_rsv: bool,
     /// auto mask send frame payload, for client, it must be true
+    ///
+    /// it's independent of role, set it to true on server side only to
+    /// generate protocol-violating frames for testing
     pub mask_send_frame: bool,
     /// allocate new buf for every frame
     pub renew_buf_on_write: bool,
+    /// preallocated size of write buf used to mask payload, default 0
+    ///
+    /// write buf is reused across sends and only grows when a payload exceeds
+    /// it, set it to usual payload size to avoid reallocation on first sends
+    pub write_buf_capacity: usize,
     /// auto unmask a masked frame payload
     pub auto_unmask: bool,
-    /// limit max payload size
+    /// limit max payload size, default 0, no limit
+    ///
+    /// frame exceeding it is rejected with 1009 protocol error as soon as its
+    /// header is parsed, before payload is buffered
     pub max_frame_payload_size: usize,
+    /// limit total payload size of a message merged from fragments, default
+    /// `None`, no limit
+    ///
+    /// unlike `max_frame_payload_size`, it bounds payload buffered across
+    /// frames, exceeding it is a 1009 protocol error
+    ///
+    /// deflate codec checks both compressed payload and decompressed message
+    pub max_message_size: Option<usize>,
     /// auto split size, if set 0, do not split frame
     pub auto_fragment_size: usize,
     /// auto merge fragmented frames into one frame
@@ -63,6 +274,46 @@ pub struct FrameConfig {
     pub resize_size: usize,
     /// if available len < resize, resize read buf, default 1K
     pub resize_thresh: usize,
+    /// reply close frame with same close code when receiving close frame if
+    /// close frame has not been sent, default false
+    pub auto_close_reply: bool,
+    /// skip utf-8 validation of close frame reason, default false
+    ///
+    /// only for debugging non-compliant peers, close reason is returned as raw bytes
+    pub lossy_close_reason: bool,
+    /// check mask bit of received frames, default `None`, do not check
+    ///
+    /// `Some(true)` rejects unmasked frames(server side), `Some(false)` rejects
+    /// masked frames(client side) with 1002 protocol error
+    ///
+    /// codecs built by `factory` set it to `Some(true)`, and by `check_fn` set
+    /// it to `Some(false)`, construct codec with `new_with` to relax it per connection
+    pub expect_mask: Option<bool>,
+    /// limit decompressed size of a message, default 0, no limit
+    ///
+    /// only used by deflate codec, exceeding it is a 1009 protocol error
+    pub max_decompressed_size: usize,
+    /// reject any frame received after a close frame with 1002 protocol
+    /// error, default false
+    ///
+    /// codecs check it before failing with `InvalidConnState` when receiving
+    /// after close, so frame sent by peer after its close frame is reported
+    ///
+    /// fragment ordering, such as a continue frame without initial fragment
+    /// or a data frame interrupting a fragmented message, is always checked
+    pub strict_ordering: bool,
+    /// max time `close` waits for peer close frame, default 5 seconds,
+    /// `None` means wait forever
+    pub close_timeout: Option<Duration>,
+    /// return `WsError::Closed` from `receive` instead of close frame, default false
+    ///
+    /// close frame is still replied if `auto_close_reply` is enabled
+    pub close_as_error: bool,
+    /// called with header of every frame after it's decoded and before it's
+    /// written, default `None`
+    ///
+    /// it runs on io path, keep it cheap, such as incrementing a counter
+    pub on_frame: Option<FrameHook>,
 }
 
 impl Default for FrameConfig {
@@ -71,53 +322,335 @@ impl Default for FrameConfig {
             check_rsv: true,
             mask_send_frame: true,
             renew_buf_on_write: false,
+            write_buf_capacity: 0,
             auto_unmask: true,
             max_frame_payload_size: 0,
+            max_message_size: None,
             auto_fragment_size: 0,
             merge_frame: true,
             validate_utf8: ValidateUtf8Policy::FastFail,
             resize_size: 4096,
             resize_thresh: 1024,
+            auto_close_reply: false,
+            lossy_close_reason: false,
+            expect_mask: None,
+            max_decompressed_size: 0,
+            strict_ordering: false,
+            close_timeout: Some(Duration::from_secs(5)),
+            close_as_error: false,
+            on_frame: None,
+        }
+    }
+}
+
+impl FrameConfig {
+    /// create a builder start with default config
+    pub fn builder() -> FrameConfigBuilder {
+        FrameConfigBuilder::default()
+    }
+
+    /// call `on_frame` hook with raw header bytes, trailing payload is ignored
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    #[inline]
+    pub(crate) fn notify(&self, direction: Direction, header: &[u8]) {
+        if let Some(hook) = self.on_frame.as_ref() {
+            hook.call(direction, header);
+        }
+    }
+
+    /// fail with 1009 protocol error if merged message exceeds `max_message_size`
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    pub(crate) fn check_message_size(&self, size: usize) -> Result<(), WsError> {
+        match self.max_message_size {
+            Some(max) if size > max => Err(WsError::ProtocolError {
+                close_code: 1009,
+                error: ProtocolError::PayloadTooLarge(max),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// validated `FrameConfig` builder
+#[derive(Debug, Clone, Default)]
+pub struct FrameConfigBuilder {
+    config: FrameConfig,
+    max_frame_payload_size: Option<usize>,
+}
+
+impl FrameConfigBuilder {
+    /// check rsv1 bits, default true
+    pub fn check_rsv(mut self, check_rsv: bool) -> Self {
+        self.config.check_rsv = check_rsv;
+        self
+    }
+
+    /// auto mask send frame payload, default true, for client, it must be true
+    pub fn mask_send_frame(mut self, mask_send_frame: bool) -> Self {
+        self.config.mask_send_frame = mask_send_frame;
+        self
+    }
+
+    /// allocate new buf for every frame, default false
+    pub fn renew_buf_on_write(mut self, renew_buf_on_write: bool) -> Self {
+        self.config.renew_buf_on_write = renew_buf_on_write;
+        self
+    }
+
+    /// preallocated size of write buf, default 0
+    pub fn write_buf_capacity(mut self, capacity: usize) -> Self {
+        self.config.write_buf_capacity = capacity;
+        self
+    }
+
+    /// auto unmask a masked frame payload, default true
+    pub fn auto_unmask(mut self, auto_unmask: bool) -> Self {
+        self.config.auto_unmask = auto_unmask;
+        self
+    }
+
+    /// limit max payload size, must be greater than 0, no limit if not set
+    pub fn max_frame_payload_size(mut self, size: usize) -> Self {
+        self.max_frame_payload_size = Some(size);
+        self
+    }
+
+    /// limit total payload size of a merged message, must be greater than 0,
+    /// no limit if not set
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.config.max_message_size = Some(size);
+        self
+    }
+
+    /// auto split size, default 0, do not split frame
+    pub fn auto_fragment_size(mut self, size: usize) -> Self {
+        self.config.auto_fragment_size = size;
+        self
+    }
+
+    /// auto merge fragmented frames into one frame, default true
+    pub fn merge_frame(mut self, merge_frame: bool) -> Self {
+        self.config.merge_frame = merge_frame;
+        self
+    }
+
+    /// utf8 check policy, default `FastFail`
+    pub fn validate_utf8(mut self, policy: ValidateUtf8Policy) -> Self {
+        self.config.validate_utf8 = policy;
+        self
+    }
+
+    /// resize size of read buf, default 4K, must be greater than 0
+    pub fn resize_size(mut self, size: usize) -> Self {
+        self.config.resize_size = size;
+        self
+    }
+
+    /// if available len < resize, resize read buf, default 1K
+    pub fn resize_thresh(mut self, thresh: usize) -> Self {
+        self.config.resize_thresh = thresh;
+        self
+    }
+
+    /// reply close frame automatically, default false
+    pub fn auto_close_reply(mut self, auto_close_reply: bool) -> Self {
+        self.config.auto_close_reply = auto_close_reply;
+        self
+    }
+
+    /// skip utf-8 validation of close frame reason, default false
+    pub fn lossy_close_reason(mut self, lossy_close_reason: bool) -> Self {
+        self.config.lossy_close_reason = lossy_close_reason;
+        self
+    }
+
+    /// check mask bit of received frames, default `None`, do not check
+    pub fn expect_mask(mut self, expect_mask: Option<bool>) -> Self {
+        self.config.expect_mask = expect_mask;
+        self
+    }
+
+    /// limit decompressed size of a message, default 0, no limit
+    pub fn max_decompressed_size(mut self, size: usize) -> Self {
+        self.config.max_decompressed_size = size;
+        self
+    }
+
+    /// reject any frame received after a close frame, default false
+    pub fn strict_ordering(mut self, strict_ordering: bool) -> Self {
+        self.config.strict_ordering = strict_ordering;
+        self
+    }
+
+    /// max time `close` waits for peer close frame, default 5 seconds
+    pub fn close_timeout(mut self, close_timeout: Option<Duration>) -> Self {
+        self.config.close_timeout = close_timeout;
+        self
+    }
+
+    /// return `WsError::Closed` instead of close frame, default false
+    pub fn close_as_error(mut self, close_as_error: bool) -> Self {
+        self.config.close_as_error = close_as_error;
+        self
+    }
+
+    /// set per-frame callback, default `None`
+    pub fn on_frame<F: Fn(Direction, HeaderView<'_>) + Send + Sync + 'static>(
+        mut self,
+        f: F,
+    ) -> Self {
+        self.config.on_frame = Some(FrameHook::new(f));
+        self
+    }
+
+    /// validate and build config
+    pub fn build(self) -> Result<FrameConfig, WsError> {
+        let mut config = self.config;
+        if let Some(size) = self.max_frame_payload_size {
+            if size == 0 {
+                return Err(WsError::InvalidConfig(
+                    "max_frame_payload_size must be greater than 0".to_string(),
+                ));
+            }
+            config.max_frame_payload_size = size;
+        }
+        if config.max_message_size == Some(0) {
+            return Err(WsError::InvalidConfig(
+                "max_message_size must be greater than 0".to_string(),
+            ));
         }
+        if config.resize_size == 0 {
+            return Err(WsError::InvalidConfig(
+                "resize_size must be greater than 0".to_string(),
+            ));
+        }
+        Ok(config)
     }
 }
 
-/// apply websocket mask to buf by given key
-#[inline]
-pub fn apply_mask(buf: &mut [u8], mask: [u8; 4]) {
-    apply_mask_array_chunk(buf, mask)
+/// only text/binary message can be sent as fragmented message
+#[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+fn check_fragmented_opcode(opcode: OpCode) -> Result<(), WsError> {
+    let error = match opcode {
+        OpCode::Text | OpCode::Binary => return Ok(()),
+        OpCode::Continue => ProtocolError::MissInitialFragmentedFrame,
+        _ => ProtocolError::FragmentedControlFrame,
+    };
+    Err(WsError::ProtocolError {
+        close_code: 1002,
+        error,
+    })
 }
 
-#[inline]
-fn apply_mask_array_chunk(buf: &mut [u8], mask: [u8; 4]) {
-    let mask32 = u32::from_ne_bytes(mask);
-    let mut iter = buf.chunks_exact_mut(4);
-    while let Some(chunk) = iter.next() {
-        let val: &mut u32 = unsafe { std::mem::transmute(chunk.as_mut_ptr().cast::<u32>()) };
-        *val ^= mask32;
+/// closing handshake state of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CloseState {
+    /// no close frame sent or received
+    #[default]
+    Open,
+    /// close frame sent, waiting for peer close frame
+    LocalClosing,
+    /// peer close frame received, close frame not sent yet
+    RemoteClosing,
+    /// close frame both sent and received
+    Closed,
+}
+
+impl CloseState {
+    /// combine close frame sent & received flags
+    pub fn from_flags(sent: bool, received: bool) -> Self {
+        match (sent, received) {
+            (false, false) => Self::Open,
+            (true, false) => Self::LocalClosing,
+            (false, true) => Self::RemoteClosing,
+            (true, true) => Self::Closed,
+        }
+    }
+
+    /// only close frame can be sent after close frame has been sent
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    pub(crate) fn check_send(self, opcode: OpCode) -> Result<(), WsError> {
+        match self {
+            Self::LocalClosing | Self::Closed if opcode != OpCode::Close => {
+                Err(WsError::InvalidConnState(self.into()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// nothing can be received after peer close frame
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    pub(crate) fn check_receive(self) -> Result<(), WsError> {
+        match self {
+            Self::RemoteClosing | Self::Closed => Err(WsError::InvalidConnState(self.into())),
+            _ => Ok(()),
+        }
     }
-    for (i, byte) in iter.into_remainder().iter_mut().enumerate() {
-        *byte ^= mask[i & 3];
+}
+
+/// connection state of a codec, see `CloseState` for which side started
+/// closing handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectionState {
+    /// codec is built, no frame sent or received yet
+    #[default]
+    Created,
+    /// frames can be sent and received
+    Running,
+    /// close frame sent or received, but not both
+    Closing,
+    /// close frame both sent and received
+    Closed,
+}
+
+impl From<CloseState> for ConnectionState {
+    fn from(state: CloseState) -> Self {
+        match state {
+            CloseState::Open => Self::Running,
+            CloseState::LocalClosing | CloseState::RemoteClosing => Self::Closing,
+            CloseState::Closed => Self::Closed,
+        }
     }
 }
 
+/// location of received payload in read state
+#[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+pub(crate) enum PayloadLoc {
+    /// payload of a single frame in read buf
+    Buf(Range<usize>),
+    /// merged payload of fragmented frames
+    Merged,
+}
+
 /// websocket frame reader
 pub struct FrameReadState {
     fragmented: bool,
+    frame_received: bool,
+    close_received: bool,
     config: FrameConfig,
     fragmented_data: Vec<u8>,
     fragmented_type: OpCode,
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    utf8_stream: Option<Utf8Stream>,
     buf: FrameBuffer,
+    #[cfg(feature = "metrics")]
+    size_histogram: hdrhistogram::Histogram<u64>,
 }
 
 impl Default for FrameReadState {
     fn default() -> Self {
         Self {
             fragmented: false,
+            frame_received: false,
+            close_received: false,
             config: Default::default(),
             fragmented_data: vec![],
             fragmented_type: OpCode::default(),
+            utf8_stream: None,
             buf: FrameBuffer::new(),
+            #[cfg(feature = "metrics")]
+            size_histogram: hdrhistogram::Histogram::new(3)
+                .expect("3 significant figures is valid"),
         }
     }
 }
@@ -131,6 +664,133 @@ impl FrameReadState {
         }
     }
 
+    /// number of bytes read from stream but not parsed yet
+    pub fn buffered_len(&self) -> usize {
+        self.buf.ava_data().len()
+    }
+
+    /// total number of bytes read from stream, wrapping, any change proves
+    /// a frame is making progress
+    #[cfg_attr(not(feature = "async_codec"), allow(dead_code))]
+    pub(crate) fn received_bytes(&self) -> usize {
+        self.buf.produced
+    }
+
+    /// reset to initial state with same config, fragmented message, close
+    /// state, stats and buffered bytes are dropped
+    pub fn reset(&mut self) {
+        *self = Self::with_config(self.config.clone());
+    }
+
+    /// change utf8 check policy of text frames, takes effect from next message
+    pub fn set_validate_utf8(&mut self, policy: ValidateUtf8Policy) {
+        self.config.validate_utf8 = policy;
+    }
+
+    /// append bytes already read from stream, they are parsed before reading
+    /// from stream again
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buf.prepare(data.len()).copy_from_slice(data);
+        self.buf.produce(data.len());
+    }
+
+    /// feed `RemainBytes` of handshake if any
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    pub(crate) fn feed_remain(&mut self, extensions: &http::Extensions) {
+        if let Some(remain) = extensions.get::<RemainBytes>() {
+            self.feed(&remain.0);
+        }
+    }
+
+    /// fail with 1002 protocol error if `strict_ordering` is enabled and
+    /// bytes arrived after peer close frame, they are never parsed as codec
+    /// refuses to receive after close
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    pub(crate) fn check_after_close(&self) -> Result<(), WsError> {
+        if self.config.strict_ordering && self.close_received && self.buffered_len() > 0 {
+            return Err(WsError::ProtocolError {
+                close_code: 1002,
+                error: ProtocolError::FrameAfterClose,
+            });
+        }
+        Ok(())
+    }
+
+    /// whether any frame has been received
+    pub fn frame_received(&self) -> bool {
+        self.frame_received
+    }
+
+    /// whether peer close frame has been received
+    pub fn close_received(&self) -> bool {
+        self.close_received
+    }
+
+    /// whether a fragmented message is being reassembled
+    pub fn is_fragmented(&self) -> bool {
+        self.fragmented
+    }
+
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    pub(crate) fn payload(&self, loc: &PayloadLoc) -> &[u8] {
+        match loc {
+            PayloadLoc::Buf(range) => &self.buf.buf[range.clone()],
+            PayloadLoc::Merged => &self.fragmented_data,
+        }
+    }
+
+    /// frame whose payload is at `range` of read buffer, header bytes are
+    /// right before payload
+    ///
+    /// if payload has been unmasked, mask bit and masking key are removed from
+    /// header, so header always matches payload
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    pub(crate) fn borrowed_frame(
+        &mut self,
+        mut header_len: usize,
+        range: Range<usize>,
+    ) -> BorrowedFrame<'_> {
+        let buf = &mut self.buf.buf;
+        let start = range.start - header_len;
+        if self.config.auto_unmask && buf[start + 1] & 0b1000_0000 != 0 {
+            buf.copy_within(start..(range.start - 4), start + 4);
+            buf[start + 5] &= 0b0111_1111;
+            header_len -= 4;
+        }
+        BorrowedFrame::with_raw(&buf[(range.start - header_len)..range.start], &buf[range])
+    }
+
+    /// max number of unparsed bytes ever buffered
+    pub fn buffered_high_water_mark(&self) -> usize {
+        self.buf.high_water_mark
+    }
+
+    /// histogram of received message payload size
+    ///
+    /// get percentiles by `value_at_quantile`, e.g. `size_histogram().value_at_quantile(0.99)`
+    #[cfg(feature = "metrics")]
+    pub fn size_histogram(&self) -> &hdrhistogram::Histogram<u64> {
+        &self.size_histogram
+    }
+
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    #[inline]
+    fn record_size(&mut self, _loc: &PayloadLoc) {
+        #[cfg(feature = "metrics")]
+        {
+            let size = self.payload(_loc).len() as u64;
+            self.size_histogram.saturating_record(size);
+        }
+    }
+
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    fn check_deadline(deadline: Option<Instant>) -> std::io::Result<()> {
+        match deadline {
+            Some(deadline) if Instant::now() >= deadline => Err(deadline_exceeded()),
+            _ => Ok(()),
+        }
+    }
+
     /// check if data in buffer is enough to parse frame header
     pub fn is_header_ok(&self) -> bool {
         let ava_data = self.buf.ava_data();
@@ -152,6 +812,38 @@ impl FrameReadState {
         }
     }
 
+    /// check if a complete message is in buffer without consuming it
+    ///
+    /// a control frame or a data frame sequence ending with fin frame is a complete message
+    pub fn is_message_ready(&self) -> bool {
+        let mut data = self.buf.ava_data();
+        loop {
+            if data.len() < 2 {
+                return false;
+            }
+            let mask_len: usize = if data[1] & 0b10000000 != 0 { 4 } else { 0 };
+            let (header_len, payload_len) = match data[1] & 0b01111111 {
+                126 if data.len() >= 4 => (4, u16::from_be_bytes([data[2], data[3]]) as usize),
+                127 if data.len() >= 10 => (
+                    10,
+                    u64::from_be_bytes(data[2..10].try_into().unwrap()) as usize,
+                ),
+                126 | 127 => return false,
+                len => (2, len as usize),
+            };
+            let total_len = (header_len + mask_len).saturating_add(payload_len);
+            if data.len() < total_len {
+                return false;
+            }
+            let fin = data[0] & 0b10000000 != 0;
+            let control = data[0] & 0b00001000 != 0;
+            if fin || control || !self.config.merge_frame {
+                return true;
+            }
+            data = &data[total_len..];
+        }
+    }
+
     /// return current frame header bits of buffer
     #[inline]
     pub fn get_leading_bits(&self) -> u8 {
@@ -182,6 +874,10 @@ impl FrameReadState {
                     if source.len() < 10 {
                         return Err(ProtocolError::InsufficientLen(source.len()));
                     }
+                    // most significant bit of 64 bit payload len must be 0
+                    if source[2] & 0x80 != 0 {
+                        return Err(ProtocolError::InvalidLeadingLen(source[1]));
+                    }
                     Ok((
                         1 + 8,
                         usize::from_be_bytes((&source[2..(8 + 2)]).try_into().unwrap()),
@@ -204,7 +900,7 @@ impl FrameReadState {
 
         if max_payload_size > 0 && payload_len > max_payload_size {
             return Err(WsError::ProtocolError {
-                close_code: 1008,
+                close_code: 1009,
                 error: ProtocolError::PayloadTooLarge(max_payload_size),
             });
         }
@@ -213,6 +909,51 @@ impl FrameReadState {
         Ok((header_len, payload_len, header_len + payload_len))
     }
 
+    /// inspect header of next buffered frame without consuming it, return (header, payload_len)
+    ///
+    /// return `Ok(None)` if buffered data is not enough to parse frame header,
+    /// control frame with fin=false or payload longer than 125 bytes is rejected
+    /// here, before its payload is buffered
+    pub fn peek_header(&mut self) -> Result<Option<(SimplifiedHeader, usize)>, WsError> {
+        if !self.is_header_ok() {
+            return Ok(None);
+        }
+        let (header_len, payload_len, _) = self.parse_frame_header()?;
+        self.check_header(header_len, payload_len).map(Some)
+    }
+
+    fn check_header(
+        &self,
+        header_len: usize,
+        payload_len: usize,
+    ) -> Result<(SimplifiedHeader, usize), WsError> {
+        let view = HeaderView(&self.buf.ava_data()[..header_len]);
+        if let Some(expect) = self.config.expect_mask {
+            if view.masked() != expect {
+                return Err(WsError::ProtocolError {
+                    close_code: 1002,
+                    error: ProtocolError::UnexpectedMask(view.masked()),
+                });
+            }
+        }
+        let header: SimplifiedHeader = view.into();
+        if matches!(header.code, OpCode::Close | OpCode::Ping | OpCode::Pong) {
+            if !header.fin {
+                return Err(WsError::ProtocolError {
+                    close_code: 1002,
+                    error: ProtocolError::FragmentedControlFrame,
+                });
+            }
+            if payload_len > 125 {
+                return Err(WsError::ProtocolError {
+                    close_code: 1002,
+                    error: ProtocolError::ControlFrameTooBig(payload_len),
+                });
+            }
+        }
+        Ok((header, payload_len))
+    }
+
     /// get a frame and reset state
     #[inline]
     pub fn consume_frame(
@@ -221,11 +962,13 @@ impl FrameReadState {
         payload_len: usize,
         total_len: usize,
     ) -> (SimplifiedHeader, Range<usize>) {
+        self.frame_received = true;
         let buf = &mut self.buf;
         let auto_unmask = self.config.auto_unmask;
 
         let ava_data = buf.ava_mut_data();
         let (header_data, remain) = ava_data.split_at_mut(header_len);
+        self.config.notify(Direction::Inbound, header_data);
         let header = HeaderView(header_data);
         let payload = remain.split_at_mut(payload_len).0;
         if auto_unmask {
@@ -245,8 +988,15 @@ impl FrameReadState {
         header: SimplifiedHeader,
         range: Range<usize>,
     ) -> Result<(), WsError> {
+        if self.config.strict_ordering && self.close_received {
+            return Err(WsError::ProtocolError {
+                close_code: 1002,
+                error: ProtocolError::FrameAfterClose,
+            });
+        }
         let fragmented = &mut self.fragmented;
         let utf8_policy = &self.config.validate_utf8;
+        let utf8_stream = &mut self.utf8_stream;
         let payload = &self.buf.buf[range];
         match header.code {
             OpCode::Continue => {
@@ -256,8 +1006,14 @@ impl FrameReadState {
                         error: ProtocolError::MissInitialFragmentedFrame,
                     });
                 }
+                if let Some(stream) = utf8_stream.as_mut() {
+                    stream
+                        .feed(payload, header.fin)
+                        .map_err(WsError::invalid_utf8)?;
+                }
                 if header.fin {
                     *fragmented = false;
+                    *utf8_stream = None;
                 }
                 Ok(())
             }
@@ -278,28 +1034,25 @@ impl FrameReadState {
                         error: ProtocolError::NotContinueFrameAfterFragmented,
                     });
                 }
+                if utf8_policy.is_incremental() {
+                    let mut stream = Utf8Stream::default();
+                    stream
+                        .feed(payload, header.fin)
+                        .map_err(WsError::invalid_utf8)?;
+                    *fragmented = !header.fin;
+                    *utf8_stream = fragmented.then_some(stream);
+                    return Ok(());
+                }
                 if !header.fin {
                     *fragmented = true;
-                    if header.code == OpCode::Text
-                        && utf8_policy.is_fast_fail()
-                        && simdutf8::basic::from_utf8(payload).is_err()
-                    {
-                        return Err(WsError::ProtocolError {
-                            close_code: 1007,
-                            error: ProtocolError::InvalidUtf8,
-                        });
+                    if header.code == OpCode::Text && utf8_policy.is_fast_fail() {
+                        check_utf8(payload)?;
                     }
 
                     Ok(())
                 } else {
-                    if header.code == OpCode::Text
-                        && utf8_policy.should_check()
-                        && simdutf8::basic::from_utf8(payload).is_err()
-                    {
-                        return Err(WsError::ProtocolError {
-                            close_code: 1007,
-                            error: ProtocolError::InvalidUtf8,
-                        });
+                    if header.code == OpCode::Text && utf8_policy.should_check() {
+                        check_utf8(payload)?;
                     }
                     Ok(())
                 }
@@ -332,11 +1085,7 @@ impl FrameReadState {
                         let mut code_byte = [0u8; 2];
                         code_byte.copy_from_slice(&payload[..2]);
                         let code = u16::from_be_bytes(code_byte);
-                        if code < 1000
-                            || (1004..=1006).contains(&code)
-                            || (1015..=2999).contains(&code)
-                            || code >= 5000
-                        {
+                        if !is_valid_close_code(code) {
                             let error = ProtocolError::InvalidCloseCode(code);
                             return Err(WsError::ProtocolError {
                                 close_code: 1002,
@@ -345,14 +1094,11 @@ impl FrameReadState {
                         }
 
                         // utf-8 validation
-                        if String::from_utf8(payload[2..].to_vec()).is_err() {
-                            let error = ProtocolError::InvalidUtf8;
-                            return Err(WsError::ProtocolError {
-                                close_code: 1007,
-                                error,
-                            });
+                        if !self.config.lossy_close_reason {
+                            std::str::from_utf8(&payload[2..])?;
                         }
                     }
+                    self.close_received = true;
                 }
                 Ok(())
             }
@@ -374,6 +1120,8 @@ impl FrameReadState {
         let payload = &self.buf.buf[range];
         match header.code {
             OpCode::Continue => {
+                self.config
+                    .check_message_size(fragmented_data.len() + payload.len())?;
                 fragmented_data.extend_from_slice(payload);
                 if header.fin {
                     *fragmented = false;
@@ -383,6 +1131,7 @@ impl FrameReadState {
                 }
             }
             OpCode::Text | OpCode::Binary => {
+                self.config.check_message_size(payload.len())?;
                 *fragmented_type = header.code;
                 if !header.fin {
                     *fragmented = true;
@@ -405,6 +1154,9 @@ pub(crate) struct FrameBuffer {
     tmp: Vec<u8>,
     produce_idx: usize,
     consume_idx: usize,
+    high_water_mark: usize,
+    // total bytes ever produced, wrapping
+    produced: usize,
 }
 
 impl FrameBuffer {
@@ -414,6 +1166,8 @@ impl FrameBuffer {
             tmp: vec![0; 8192],
             produce_idx: 0,
             consume_idx: 0,
+            high_water_mark: 0,
+            produced: 0,
         }
     }
 
@@ -454,6 +1208,10 @@ impl FrameBuffer {
 
     pub(crate) fn produce(&mut self, num: usize) {
         self.produce_idx += num;
+        self.produced = self.produced.wrapping_add(num);
+        self.high_water_mark = self
+            .high_water_mark
+            .max(self.produce_idx - self.consume_idx);
     }
 
     pub(crate) fn consume(&mut self, num: usize) {
@@ -461,6 +1219,34 @@ impl FrameBuffer {
     }
 }
 
+type MaskFn = dyn FnMut() -> [u8; 4] + Send;
+
+/// masking key source of outgoing frames, `rand::random` if not set
+///
+/// cloned write states share the same generator
+#[derive(Clone, Default)]
+pub(crate) struct MaskKeyGen(Option<Arc<Mutex<MaskFn>>>);
+
+impl Debug for MaskKeyGen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = if self.0.is_some() { "custom" } else { "random" };
+        f.debug_tuple("MaskKeyGen").field(&kind).finish()
+    }
+}
+
+impl MaskKeyGen {
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    pub(crate) fn next(&self) -> [u8; 4] {
+        match &self.0 {
+            Some(f) => {
+                let mut f = f.lock().unwrap_or_else(|e| e.into_inner());
+                f()
+            }
+            None => rand::random(),
+        }
+    }
+}
+
 /// websocket writing state
 #[allow(dead_code)]
 #[derive(Debug, Clone, Default)]
@@ -468,20 +1254,186 @@ pub struct FrameWriteState {
     config: FrameConfig,
     header_buf: [u8; 14],
     buf: BytesMut,
+    frame_sent: bool,
+    close_sent: bool,
+    mask_gen: MaskKeyGen,
 }
 
 impl FrameWriteState {
     /// construct with config
     pub fn with_config(config: FrameConfig) -> Self {
         Self {
+            buf: BytesMut::zeroed(config.write_buf_capacity),
             config,
             header_buf: [0; 14],
-            buf: BytesMut::new(),
+            frame_sent: false,
+            close_sent: false,
+            mask_gen: MaskKeyGen::default(),
+        }
+    }
+
+    /// set masking key generator of outgoing frames, every masked frame pulls
+    /// its key from `f` instead of `rand::random`
+    ///
+    /// useful for reproducible tests or routing masking through own CSPRNG,
+    /// it does not enable masking, see `FrameConfig::mask_send_frame`
+    pub fn set_mask_fn<F: FnMut() -> [u8; 4] + Send + 'static>(&mut self, f: F) {
+        self.mask_gen = MaskKeyGen(Some(Arc::new(Mutex::new(f))));
+    }
+
+    /// masking key of next outgoing frame, `None` if `mask_send_frame` is off
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    pub(crate) fn mask_key(&self) -> Option<[u8; 4]> {
+        self.config.mask_send_frame.then(|| self.mask_gen.next())
+    }
+
+    /// whether any frame has been sent
+    pub fn frame_sent(&self) -> bool {
+        self.frame_sent
+    }
+
+    /// whether close frame has been sent
+    pub fn close_sent(&self) -> bool {
+        self.close_sent
+    }
+
+    /// drop write buf after a send if `renew_buf_on_write` is enabled
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    pub(crate) fn renew_buf(&mut self) {
+        if self.config.renew_buf_on_write {
+            self.buf = BytesMut::zeroed(self.config.write_buf_capacity);
+        }
+    }
+
+    /// reset to initial state with same config and masking key generator
+    pub fn reset(&mut self) {
+        let mask_gen = self.mask_gen.clone();
+        *self = Self::with_config(self.config.clone());
+        self.mask_gen = mask_gen;
+    }
+
+    /// return close echo payload if received frame should be replied automatically
+    ///
+    /// echo only contains close code of received close frame
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    pub(crate) fn close_reply<'a>(
+        &self,
+        header: &SimplifiedHeader,
+        payload: &'a [u8],
+    ) -> Option<&'a [u8]> {
+        if self.config.auto_close_reply && header.code == OpCode::Close && !self.close_sent {
+            Some(if payload.len() >= 2 {
+                &payload[..2]
+            } else {
+                &[]
+            })
+        } else {
+            None
+        }
+    }
+
+    /// serialize a control frame into stack buf, avoid touching write buf
+    ///
+    /// return `None` if opcode is not ping/pong/close or payload is too big
+    /// for a control frame
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    fn ctor_control_frame<'a>(
+        &self,
+        buf: &'a mut [u8; MAX_CONTROL_FRAME_LEN],
+        opcode: OpCode,
+        payload: &[u8],
+    ) -> Option<&'a [u8]> {
+        if !matches!(opcode, OpCode::Close | OpCode::Ping | OpCode::Pong) || payload.len() > 125 {
+            return None;
+        }
+        let mask = self.mask_key();
+        let header_len = ctor_header(
+            &mut buf[..],
+            true,
+            false,
+            false,
+            false,
+            mask,
+            opcode,
+            payload.len() as u64,
+        )
+        .len();
+        let end = header_len + payload.len();
+        buf[header_len..end].copy_from_slice(payload);
+        if let Some(mask) = mask {
+            apply_mask(&mut buf[header_len..end], mask);
         }
+        self.config.notify(Direction::Outbound, &buf[..header_len]);
+        Some(&buf[..end])
+    }
+
+    /// encode payload as frames and append them to `out`, used by poll based
+    /// writers which can not hold a send future
+    ///
+    /// will auto fragment data frame if auto_fragment_size > 0
+    #[cfg_attr(not(feature = "futures"), allow(dead_code))]
+    pub(crate) fn encode(&mut self, out: &mut BytesMut, opcode: OpCode, payload: &[u8]) {
+        if !self.mark_close(opcode) {
+            return;
+        }
+        let chunk_size = match self.config.auto_fragment_size {
+            size if size > 0 && opcode.is_data() => size,
+            _ => payload.len().max(1),
+        };
+        let mut chunks = payload.chunks(chunk_size).peekable();
+        let mut code = opcode;
+        loop {
+            let chunk = chunks.next().unwrap_or_default();
+            let fin = chunks.peek().is_none();
+            let mask = self.mask_key();
+            let header = ctor_header(
+                &mut self.header_buf,
+                fin,
+                false,
+                false,
+                false,
+                mask,
+                code,
+                chunk.len() as u64,
+            );
+            self.config.notify(Direction::Outbound, header);
+            out.extend_from_slice(header);
+            let start = out.len();
+            out.extend_from_slice(chunk);
+            if let Some(mask) = mask {
+                apply_mask(&mut out[start..], mask);
+            }
+            if fin {
+                break;
+            }
+            code = OpCode::Continue;
+        }
+    }
+
+    /// mark frame as sent, return false if close frame has already been sent
+    ///
+    /// a close frame received while we are closing should not be echoed,
+    /// so second close frame is skipped
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    fn mark_close(&mut self, opcode: OpCode) -> bool {
+        if opcode != OpCode::Close {
+            self.frame_sent = true;
+            return true;
+        }
+        if self.close_sent {
+            tracing::debug!("close frame already sent, skip");
+            return false;
+        }
+        self.frame_sent = true;
+        self.close_sent = true;
+        true
     }
 }
 
 /// do standard handshake check and return response
+///
+/// non-`GET` request is rejected with 405, request without version 13 is
+/// rejected with 426, other invalid request is rejected with 400
 pub fn default_handshake_handler(
     req: http::Request<()>,
 ) -> Result<(http::Request<()>, http::Response<String>), (http::Response<String>, WsError)> {
@@ -499,13 +1451,120 @@ pub fn default_handshake_handler(
             Ok((req, resp))
         }
         Err(e) => {
-            let resp = http::Response::builder()
+            let builder = http::Response::builder()
                 .version(http::Version::HTTP_11)
-                .status(http::StatusCode::BAD_REQUEST)
-                .header("Content-Type", "text/html")
-                .body(e.to_string())
-                .unwrap();
+                .header("Content-Type", "text/html");
+            let builder = if req.method() != http::Method::GET {
+                builder
+                    .status(http::StatusCode::METHOD_NOT_ALLOWED)
+                    .header("Allow", "GET")
+            } else if !is_supported_version(&req) {
+                builder
+                    .status(http::StatusCode::UPGRADE_REQUIRED)
+                    .header("Sec-WebSocket-Version", "13")
+            } else {
+                builder.status(http::StatusCode::BAD_REQUEST)
+            };
+            let resp = builder.body(e.to_string()).unwrap();
             Err((resp, e))
         }
     }
 }
+
+#[test]
+fn test_frame_config_builder() {
+    assert_eq!(
+        FrameConfig::builder().build().unwrap(),
+        FrameConfig::default()
+    );
+    let server_config = FrameConfig {
+        mask_send_frame: false,
+        ..Default::default()
+    };
+    assert_eq!(
+        FrameConfig::builder()
+            .mask_send_frame(false)
+            .build()
+            .unwrap(),
+        server_config
+    );
+    let config = FrameConfig {
+        check_rsv: false,
+        max_frame_payload_size: 1024,
+        auto_fragment_size: 512,
+        merge_frame: false,
+        validate_utf8: ValidateUtf8Policy::On,
+        ..Default::default()
+    };
+    assert_eq!(
+        FrameConfig::builder()
+            .check_rsv(false)
+            .max_frame_payload_size(1024)
+            .auto_fragment_size(512)
+            .merge_frame(false)
+            .validate_utf8(ValidateUtf8Policy::On)
+            .build()
+            .unwrap(),
+        config
+    );
+
+    assert!(matches!(
+        FrameConfig::builder().max_frame_payload_size(0).build(),
+        Err(WsError::InvalidConfig(_))
+    ));
+    assert!(matches!(
+        FrameConfig::builder().resize_size(0).build(),
+        Err(WsError::InvalidConfig(_))
+    ));
+    assert!(matches!(
+        FrameConfig::builder().max_message_size(0).build(),
+        Err(WsError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_utf8_stream() {
+    let text = "héllo 世界 🦀".as_bytes();
+    for split in 0..text.len() {
+        let mut stream = Utf8Stream::default();
+        assert!(
+            stream.feed(&text[..split], false).is_ok(),
+            "split at {split}"
+        );
+        assert!(
+            stream.feed(&text[split..], true).is_ok(),
+            "split at {split}"
+        );
+    }
+    // every byte in its own fragment
+    let mut stream = Utf8Stream::default();
+    for (idx, byte) in text.iter().enumerate() {
+        assert!(stream.feed(&[*byte], idx == text.len() - 1).is_ok());
+    }
+
+    // invalid byte is rejected before message is complete
+    let mut stream = Utf8Stream::default();
+    assert!(stream.feed(b"ok", false).is_ok());
+    assert_eq!(stream.feed(b"\xff", false), Err(2));
+    // continuation does not complete pending char
+    let mut stream = Utf8Stream::default();
+    assert!(stream.feed(b"ok", false).is_ok());
+    assert!(stream.feed(&[0xE4, 0xB8], false).is_ok());
+    assert_eq!(stream.feed(b"a", false), Err(2));
+    // incomplete char at end of message
+    let mut stream = Utf8Stream::default();
+    assert!(stream.feed(&[0xE4], false).is_ok());
+    assert_eq!(stream.feed(&[0xB8], true), Err(0));
+}
+
+#[test]
+fn test_check_utf8() {
+    assert!(check_utf8("héllo".as_bytes()).is_ok());
+    assert!(matches!(
+        check_utf8(b"he\xffllo"),
+        Err(WsError::ProtocolError {
+            close_code: 1007,
+            error: ProtocolError::InvalidUtf8 { valid_up_to: 2 }
+        })
+    ));
+}
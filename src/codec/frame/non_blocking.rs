@@ -1,23 +1,52 @@
 use http;
+use bytes::Bytes;
+#[cfg(feature = "futures")]
 use bytes::BytesMut;
-use std::{io::IoSlice, ops::Range};
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::{
+    io::IoSlice,
+    ops::Range,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
 
-use super::{apply_mask, FrameConfig, FrameReadState, FrameWriteState};
+use super::{
+    apply_mask, check_close, check_fragmented_opcode, to_owned_frame, AsyncFrameRead,
+    AsyncFrameWrite, CloseState, ConnectionState, Direction, FrameConfig, FrameReadState,
+    FrameWriteState, PayloadLoc, MAX_CONTROL_FRAME_LEN,
+};
+#[cfg(feature = "futures")]
+use crate::Message;
 use crate::{
-    codec::Split,
-    errors::WsError,
-    frame::{ctor_header, header_len, OpCode, OwnedFrame, SimplifiedHeader},
+    codec::{Keepalive, PingTracker, Split},
+    errors::{close_payload, deadline_exceeded, WsError},
+    frame::{
+        ctor_header, header_len, parse_opcode, BorrowedFrame, OpCode, OwnedFrame,
+        PreparedMaskedFrame, SimplifiedHeader,
+    },
     protocol::standard_handshake_resp_check,
+    CloseEvent, Event,
 };
 
 type IOResult<T> = std::io::Result<T>;
 
 impl FrameReadState {
     #[inline]
-    async fn async_poll<S: AsyncRead + Unpin>(&mut self, stream: &mut S) -> IOResult<usize> {
+    async fn async_poll<S: AsyncRead + Unpin>(
+        &mut self,
+        stream: &mut S,
+        deadline: Option<Instant>,
+    ) -> IOResult<usize> {
+        Self::check_deadline(deadline)?;
         let buf = self.buf.prepare(self.config.resize_size);
-        let count = stream.read(buf).await?;
+        let count = match deadline {
+            // read is cancel safe, no data is lost if deadline is reached
+            Some(deadline) => tokio::time::timeout_at(deadline.into(), stream.read(buf))
+                .await
+                .map_err(|_| deadline_exceeded())??,
+            None => stream.read(buf).await?,
+        };
         self.buf.produce(count);
         if count == 0 {
             return Err(std::io::Error::new(
@@ -33,7 +62,14 @@ impl FrameReadState {
         &mut self,
         stream: &mut S,
         size: usize,
+        deadline: Option<Instant>,
     ) -> IOResult<()> {
+        if deadline.is_some() {
+            while self.buf.ava_data().len() < size {
+                self.async_poll(stream, deadline).await?;
+            }
+            return Ok(());
+        }
         let read_len = self.buf.ava_data().len();
         if read_len < size {
             let buf = self.buf.prepare(size - read_len);
@@ -47,41 +83,185 @@ impl FrameReadState {
     async fn async_read_one_frame<S: AsyncRead + Unpin>(
         &mut self,
         stream: &mut S,
+        deadline: Option<Instant>,
     ) -> Result<(SimplifiedHeader, Range<usize>), WsError> {
+        let (_, header, range) = self.async_read_raw_frame(stream, deadline).await?;
+        Ok((header, range))
+    }
+
+    /// read a frame, return header len, header and payload range
+    #[inline]
+    async fn async_read_raw_frame<S: AsyncRead + Unpin>(
+        &mut self,
+        stream: &mut S,
+        deadline: Option<Instant>,
+    ) -> Result<(usize, SimplifiedHeader, Range<usize>), WsError> {
         while !self.is_header_ok() {
-            self.async_poll(stream).await?;
+            self.async_poll(stream, deadline).await?;
         }
         let (header_len, payload_len, total_len) = self.parse_frame_header()?;
-        self.async_poll_one_frame(stream, total_len).await?;
-        Ok(self.consume_frame(header_len, payload_len, total_len))
+        self.check_header(header_len, payload_len)?;
+        self.async_poll_one_frame(stream, total_len, deadline)
+            .await?;
+        let (header, range) = self.consume_frame(header_len, payload_len, total_len);
+        Ok((header_len, header, range))
     }
 
+    /// receive a message, control frame between fragments is returned as is,
+    /// partial message is kept and completed by following continuation frames
+    ///
     /// **NOTE** masked frame has already been unmasked
     pub async fn async_receive<S: AsyncRead + Unpin>(
         &mut self,
         stream: &mut S,
     ) -> Result<(SimplifiedHeader, &[u8]), WsError> {
+        let (header, loc) = self.async_receive_loc(stream, None).await?;
+        self.record_size(&loc);
+        Ok((header, self.payload(&loc)))
+    }
+
+    /// receive a message, fail with `WsError::DeadlineExceeded` if message is
+    /// not complete before deadline
+    ///
+    /// unlike wrapping `async_receive` in a timeout, partial frame is kept in
+    /// buffer, so state is still valid after timeout
+    pub async fn async_receive_deadline<S: AsyncRead + Unpin>(
+        &mut self,
+        stream: &mut S,
+        deadline: Instant,
+    ) -> Result<(SimplifiedHeader, &[u8]), WsError> {
+        let (header, loc) = self.async_receive_loc(stream, Some(deadline)).await?;
+        self.record_size(&loc);
+        Ok((header, self.payload(&loc)))
+    }
+
+    pub(crate) async fn async_receive_loc<S: AsyncRead + Unpin>(
+        &mut self,
+        stream: &mut S,
+        deadline: Option<Instant>,
+    ) -> Result<(SimplifiedHeader, PayloadLoc), WsError> {
         if self.config.merge_frame {
             loop {
-                let (mut header, range) = self.async_read_one_frame(stream).await?;
+                let (mut header, range) = self.async_read_one_frame(stream, deadline).await?;
                 if let Some(merged) = self
                     .check_frame(header, range.clone())
                     .and_then(|_| self.merge_frame(header, range.clone()))?
                 {
                     if merged {
                         header.code = self.fragmented_type;
-                        break Ok((header, &self.fragmented_data));
+                        break Ok((header, PayloadLoc::Merged));
                     } else {
-                        break Ok((header, &self.buf.buf[range]));
+                        break Ok((header, PayloadLoc::Buf(range)));
                     }
                 }
             }
         } else {
-            let (header, range) = self.async_read_one_frame(stream).await?;
+            let (header, range) = self.async_read_one_frame(stream, deadline).await?;
+            self.check_frame(header, range.clone())?;
+            Ok((header, PayloadLoc::Buf(range)))
+        }
+    }
+
+    /// read once from stream into read buf, buf grows at least `size` bytes
+    fn poll_fill<S: AsyncRead + Unpin>(
+        &mut self,
+        stream: &mut S,
+        cx: &mut Context<'_>,
+        size: usize,
+    ) -> Poll<IOResult<()>> {
+        let buf = self.buf.prepare(size.max(self.config.resize_size));
+        let mut buf = ReadBuf::new(buf);
+        ready!(Pin::new(stream).poll_read(cx, &mut buf))?;
+        let count = buf.filled().len();
+        self.buf.produce(count);
+        if count == 0 {
+            return Poll::Ready(Err(std::io::Error::new(
+                std::io::ErrorKind::ConnectionAborted,
+                "read eof",
+            )));
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// poll version of `async_receive_loc`, partial frame is kept in read buf
+    /// so it's cancel safe
+    pub(crate) fn poll_receive_loc<S: AsyncRead + Unpin>(
+        &mut self,
+        stream: &mut S,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(SimplifiedHeader, PayloadLoc), WsError>> {
+        loop {
+            if !self.is_header_ok() {
+                ready!(self.poll_fill(stream, cx, 0))?;
+                continue;
+            }
+            let (header_len, payload_len, total_len) = self.parse_frame_header()?;
+            self.check_header(header_len, payload_len)?;
+            let read_len = self.buf.ava_data().len();
+            if read_len < total_len {
+                ready!(self.poll_fill(stream, cx, total_len - read_len))?;
+                continue;
+            }
+            let (mut header, range) = self.consume_frame(header_len, payload_len, total_len);
             self.check_frame(header, range.clone())?;
-            Ok((header, &self.buf.buf[range]))
+            if !self.config.merge_frame {
+                return Poll::Ready(Ok((header, PayloadLoc::Buf(range))));
+            }
+            if let Some(merged) = self.merge_frame(header, range.clone())? {
+                if merged {
+                    header.code = self.fragmented_type;
+                    return Poll::Ready(Ok((header, PayloadLoc::Merged)));
+                } else {
+                    return Poll::Ready(Ok((header, PayloadLoc::Buf(range))));
+                }
+            }
         }
     }
+
+    /// poll to receive a message as owned frame, see `AsyncFrameCodec::poll_receive`
+    pub fn poll_receive<S: AsyncRead + Unpin>(
+        &mut self,
+        stream: &mut S,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<OwnedFrame, WsError>> {
+        let (header, loc) = ready!(self.poll_receive_loc(stream, cx))?;
+        self.record_size(&loc);
+        let mut frame = OwnedFrame::new(header.code, None, self.payload(&loc));
+        let frame_header = frame.header_mut();
+        frame_header.set_fin(header.fin);
+        frame_header.set_rsv1(header.rsv1);
+        frame_header.set_rsv2(header.rsv2);
+        frame_header.set_rsv3(header.rsv3);
+        Poll::Ready(Ok(frame))
+    }
+
+    /// receive a single frame without merging fragmented frames, frame header
+    /// keeps its original fin bit & opcode
+    ///
+    /// **NOTE** masked frame has already been unmasked
+    pub async fn async_receive_frame<S: AsyncRead + Unpin>(
+        &mut self,
+        stream: &mut S,
+    ) -> Result<(SimplifiedHeader, &[u8]), WsError> {
+        let (header, range) = self.async_read_one_frame(stream, None).await?;
+        self.check_frame(header, range.clone())?;
+        Ok((header, &self.buf.buf[range]))
+    }
+
+    /// receive a single frame which borrows read buffer, fragmented frames
+    /// are not merged
+    ///
+    /// returned frame must be dropped before next receive, if `auto_unmask` is
+    /// enabled, payload has already been unmasked and header has no mask bit
+    /// and masking key
+    pub async fn async_receive_borrowed<S: AsyncRead + Unpin>(
+        &mut self,
+        stream: &mut S,
+    ) -> Result<BorrowedFrame<'_>, WsError> {
+        let (header_len, header, range) = self.async_read_raw_frame(stream, None).await?;
+        self.check_frame(header, range.clone())?;
+        Ok(self.borrowed_frame(header_len, range))
+    }
 }
 
 impl FrameWriteState {
@@ -96,12 +276,11 @@ impl FrameWriteState {
         opcode: OpCode,
         payload: &[u8],
     ) -> IOResult<()> {
+        if !self.mark_close(opcode) {
+            return Ok(());
+        }
         if payload.is_empty() {
-            let mask = if self.config.mask_send_frame {
-                Some(rand::random())
-            } else {
-                None
-            };
+            let mask = self.mask_key();
             let header = ctor_header(
                 &mut self.header_buf,
                 true,
@@ -112,9 +291,15 @@ impl FrameWriteState {
                 opcode,
                 0,
             );
+            self.config.notify(Direction::Outbound, header);
             stream.write_all(header).await?;
             return Ok(());
         }
+        let mut control_buf = [0; MAX_CONTROL_FRAME_LEN];
+        if let Some(frame) = self.ctor_control_frame(&mut control_buf, opcode, payload) {
+            stream.write_all(frame).await?;
+            return Ok(());
+        }
         if self.config.auto_fragment_size > 0 && self.config.auto_fragment_size < payload.len() {
             let chunk_size = self.config.auto_fragment_size;
             let parts: Vec<&[u8]> = payload.chunks(chunk_size).collect();
@@ -133,7 +318,7 @@ impl FrameWriteState {
                 parts.iter().enumerate().for_each(|(idx, chunk)| {
                     let fin = idx + 1 == total;
                     let s_idx = idx * single_header_len;
-                    let mask = rand::random();
+                    let mask = self.mask_gen.next();
                     let header_len = ctor_header(
                         &mut self.buf[s_idx..],
                         fin,
@@ -145,6 +330,8 @@ impl FrameWriteState {
                         chunk.len() as u64,
                     )
                     .len();
+                    self.config
+                        .notify(Direction::Outbound, &self.buf[s_idx..(s_idx + header_len)]);
                     let slice = &mut self.buf[(s_idx + header_len)..];
                     slice.copy_from_slice(chunk);
                     apply_mask(slice, mask);
@@ -158,7 +345,7 @@ impl FrameWriteState {
                 parts.iter().enumerate().for_each(|(idx, chunk)| {
                     let fin = idx + 1 == total;
                     let s_idx = idx * chunk_size;
-                    ctor_header(
+                    let header = ctor_header(
                         &mut self.buf[s_idx..],
                         fin,
                         false,
@@ -168,6 +355,7 @@ impl FrameWriteState {
                         opcode,
                         chunk.len() as u64,
                     );
+                    self.config.notify(Direction::Outbound, header);
                 });
                 parts.iter().enumerate().for_each(|(idx, chunk)| {
                     let fin = idx + 1 == total;
@@ -180,17 +368,10 @@ impl FrameWriteState {
                     }
                     slices.push(IoSlice::new(chunk));
                 });
-                let num = stream.write_vectored(&slices).await?;
-                let remain = total_bytes - num;
-                if remain > 0 {
-                    if let Some(buf) = slices.last() {
-                        stream.write_all(&buf[(buf.len() - remain)..]).await?;
-                    }
-                }
+                write_all_vectored(stream, &slices).await?;
             }
         } else if self.config.mask_send_frame {
-            let total_bytes = header_len(true, payload.len() as u64) + payload.len();
-            let mask: [u8; 4] = rand::random();
+            let mask = self.mask_gen.next();
             let header = ctor_header(
                 &mut self.header_buf,
                 true,
@@ -201,25 +382,21 @@ impl FrameWriteState {
                 opcode,
                 payload.len() as u64,
             );
+            self.config.notify(Direction::Outbound, header);
             if self.buf.len() < payload.len() {
                 self.buf.resize(payload.len(), 0)
             }
             self.buf[..(payload.len())].copy_from_slice(payload);
             apply_mask(&mut self.buf[..(payload.len())], mask);
-            let num = stream
-                .write_vectored(&[
+            write_all_vectored(
+                stream,
+                &[
                     IoSlice::new(header),
                     IoSlice::new(&self.buf[..(payload.len())]),
-                ])
-                .await?;
-            let remain = total_bytes - num;
-            if remain > 0 {
-                stream
-                    .write_all(&self.buf[(payload.len() - remain)..(payload.len())])
-                    .await?;
-            }
+                ],
+            )
+            .await?;
         } else {
-            let total_bytes = header_len(false, payload.len() as u64) + payload.len();
             let header = ctor_header(
                 &mut self.header_buf,
                 true,
@@ -230,34 +407,239 @@ impl FrameWriteState {
                 opcode,
                 payload.len() as u64,
             );
+            self.config.notify(Direction::Outbound, header);
             // if self.buf.len() < payload.len() {
             //     self.buf.resize(payload.len(), 0)
             // }
-            let num = stream
-                .write_vectored(&[IoSlice::new(header), IoSlice::new(payload)])
-                .await?;
-            let remain = total_bytes - num;
-            if remain > 0 {
-                stream
-                    .write_all(&payload[(payload.len() - remain)..])
-                    .await?;
-            }
+            write_all_vectored(stream, &[IoSlice::new(header), IoSlice::new(payload)]).await?;
         };
 
-        if self.config.renew_buf_on_write {
-            self.buf = BytesMut::new()
+        self.renew_buf();
+        Ok(())
+    }
+
+    /// send close frame for protocol error, do nothing for other errors
+    ///
+    /// close frame is masked according to `mask_send_frame`, so it always
+    /// matches role of this side
+    pub async fn async_send_error_close<S: AsyncWrite + Unpin>(
+        &mut self,
+        stream: &mut S,
+        error: &WsError,
+    ) -> IOResult<()> {
+        match error.close_payload() {
+            Some(payload) => self.async_send(stream, OpCode::Close, &payload).await,
+            None => Ok(()),
+        }
+    }
+
+    /// send a single frame with explicit fin bit, **this method do not fragment**
+    ///
+    /// to send a fragmented message manually, send first frame with data opcode
+    /// and `fin = false`, then `OpCode::Continue` frames, the last one with `fin = true`
+    pub async fn async_send_frame<S: AsyncWrite + Unpin>(
+        &mut self,
+        stream: &mut S,
+        fin: bool,
+        opcode: OpCode,
+        payload: &[u8],
+    ) -> IOResult<()> {
+        if !self.mark_close(opcode) {
+            return Ok(());
+        }
+        let mask = self.mask_key();
+        let header = ctor_header(
+            &mut self.header_buf,
+            fin,
+            false,
+            false,
+            false,
+            mask,
+            opcode,
+            payload.len() as u64,
+        );
+        self.config.notify(Direction::Outbound, header);
+        stream.write_all(header).await?;
+        if let Some(mask) = mask {
+            if self.buf.len() < payload.len() {
+                self.buf.resize(payload.len(), 0)
+            }
+            self.buf[..(payload.len())].copy_from_slice(payload);
+            apply_mask(&mut self.buf[..(payload.len())], mask);
+            stream.write_all(&self.buf[..(payload.len())]).await?;
+        } else {
+            stream.write_all(payload).await?;
         }
         Ok(())
     }
 
+    /// send a single frame whose payload is concatenation of `parts`
+    ///
+    /// header and parts are written with one vectored write, masked payload is
+    /// copied to inner buffer to apply mask, payload is concatenated and sent by
+    /// `async_send` if it should be fragmented
+    pub async fn async_send_vectored<S: AsyncWrite + Unpin>(
+        &mut self,
+        stream: &mut S,
+        opcode: OpCode,
+        parts: &[&[u8]],
+    ) -> IOResult<()> {
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+        if self.config.auto_fragment_size > 0 && self.config.auto_fragment_size < len {
+            return self.async_send(stream, opcode, &parts.concat()).await;
+        }
+        if !self.mark_close(opcode) {
+            return Ok(());
+        }
+        let mask = self.mask_key();
+        let header = ctor_header(
+            &mut self.header_buf,
+            true,
+            false,
+            false,
+            false,
+            mask,
+            opcode,
+            len as u64,
+        );
+        self.config.notify(Direction::Outbound, header);
+        if let Some(mask) = mask {
+            if self.buf.len() < len {
+                self.buf.resize(len, 0)
+            }
+            let mut offset = 0;
+            for part in parts {
+                self.buf[offset..(offset + part.len())].copy_from_slice(part);
+                offset += part.len();
+            }
+            apply_mask(&mut self.buf[..len], mask);
+            write_all_vectored(
+                stream,
+                &[IoSlice::new(header), IoSlice::new(&self.buf[..len])],
+            )
+            .await?;
+        } else {
+            let mut slices = Vec::with_capacity(parts.len() + 1);
+            slices.push(IoSlice::new(header));
+            slices.extend(parts.iter().map(|part| IoSlice::new(part)));
+            write_all_vectored(stream, &slices).await?;
+        }
+        self.renew_buf();
+        Ok(())
+    }
+
+    /// send chunks as a fragmented message, first chunk with `opcode`, following
+    /// chunks with `OpCode::Continue`, only the last one has fin bit set
+    ///
+    /// chunks are written as they are yielded, so payload is never buffered as
+    /// a whole, empty chunks yield a single empty frame, control frames can not
+    /// be fragmented
+    pub async fn async_send_fragmented<'a, S, I>(
+        &mut self,
+        stream: &mut S,
+        opcode: OpCode,
+        chunks: I,
+    ) -> Result<(), WsError>
+    where
+        S: AsyncWrite + Unpin,
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        check_fragmented_opcode(opcode)?;
+        let mut chunks = chunks.into_iter().peekable();
+        let mut code = opcode;
+        loop {
+            let chunk = chunks.next().unwrap_or_default();
+            let fin = chunks.peek().is_none();
+            self.async_send_frame(stream, fin, code, chunk).await?;
+            if fin {
+                return Ok(());
+            }
+            code = OpCode::Continue;
+        }
+    }
+
     pub(crate) async fn async_send_owned_frame<S: AsyncWrite + Unpin>(
         &mut self,
         stream: &mut S,
         frame: OwnedFrame,
     ) -> IOResult<()> {
-        stream.write_all(&frame.header().0).await?;
-        stream.write_all(frame.payload()).await
+        if !self.mark_close(frame.header().opcode()) {
+            return Ok(());
+        }
+        self.config
+            .notify(Direction::Outbound, frame.header().as_bytes());
+        write_all_vectored(
+            stream,
+            &[
+                IoSlice::new(&frame.header().0),
+                IoSlice::new(frame.payload()),
+            ],
+        )
+        .await
+    }
+
+    /// send a prepared masked frame, payload is not copied or masked again
+    pub async fn async_send_prepared<S: AsyncWrite + Unpin>(
+        &mut self,
+        stream: &mut S,
+        frame: &PreparedMaskedFrame,
+    ) -> IOResult<()> {
+        let frame = frame.frame();
+        if !self.mark_close(frame.header().opcode()) {
+            return Ok(());
+        }
+        self.config
+            .notify(Direction::Outbound, frame.header().as_bytes());
+        write_all_vectored(
+            stream,
+            &[
+                IoSlice::new(&frame.header().0),
+                IoSlice::new(frame.payload()),
+            ],
+        )
+        .await
     }
+
+    /// send a frame encoded by `frame::encode_once`, bytes are written as is
+    pub async fn async_send_encoded<S: AsyncWrite + Unpin>(
+        &mut self,
+        stream: &mut S,
+        frame: &[u8],
+    ) -> IOResult<()> {
+        let Some(&first) = frame.first() else {
+            return Ok(());
+        };
+        if !self.mark_close(parse_opcode(first)) {
+            return Ok(());
+        }
+        self.config.notify(Direction::Outbound, frame);
+        stream.write_all(frame).await
+    }
+}
+
+/// write all slices, continue from where a short vectored write stops
+///
+/// slices are written one by one if stream does not support vectored write
+async fn write_all_vectored<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    slices: &[IoSlice<'_>],
+) -> IOResult<()> {
+    if !stream.is_write_vectored() {
+        for slice in slices {
+            stream.write_all(slice).await?;
+        }
+        return Ok(());
+    }
+    let mut num = stream.write_vectored(slices).await?;
+    for slice in slices {
+        if num >= slice.len() {
+            num -= slice.len();
+        } else {
+            stream.write_all(&slice[num..]).await?;
+            num = 0;
+        }
+    }
+    Ok(())
 }
 
 /// recv part of websocket stream
@@ -274,7 +656,65 @@ impl<S: AsyncRead + Unpin> AsyncFrameRecv<S> {
 
     /// receive a frame
     pub async fn receive(&mut self) -> Result<(SimplifiedHeader, &[u8]), WsError> {
-        self.read_state.async_receive(&mut self.stream).await
+        let close_as_error = self.read_state.config.close_as_error;
+        let (header, data) = self.read_state.async_receive(&mut self.stream).await?;
+        check_close(close_as_error, &header, data)?;
+        Ok((header, data))
+    }
+
+    /// poll to receive a message, see `AsyncFrameCodec::poll_receive`
+    pub fn poll_receive(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<OwnedFrame, WsError>> {
+        let this = self.get_mut();
+        this.read_state.poll_receive(&mut this.stream, cx)
+    }
+
+    /// receive a frame, fail if it's not complete before deadline
+    pub async fn receive_deadline(
+        &mut self,
+        deadline: Instant,
+    ) -> Result<(SimplifiedHeader, &[u8]), WsError> {
+        let close_as_error = self.read_state.config.close_as_error;
+        let (header, data) = self
+            .read_state
+            .async_receive_deadline(&mut self.stream, deadline)
+            .await?;
+        check_close(close_as_error, &header, data)?;
+        Ok((header, data))
+    }
+
+    /// number of bytes read from stream but not parsed yet
+    pub fn buffered_len(&self) -> usize {
+        self.read_state.buffered_len()
+    }
+
+    /// max number of unparsed bytes ever buffered
+    pub fn buffered_high_water_mark(&self) -> usize {
+        self.read_state.buffered_high_water_mark()
+    }
+
+    /// histogram of received message payload size
+    #[cfg(feature = "metrics")]
+    pub fn size_histogram(&self) -> &hdrhistogram::Histogram<u64> {
+        self.read_state.size_histogram()
+    }
+
+    /// whether peer close frame has been received
+    pub fn close_received(&self) -> bool {
+        self.read_state.close_received()
+    }
+
+    /// receive a single frame, fragmented frames are not merged
+    pub async fn receive_frame(&mut self) -> Result<(SimplifiedHeader, &[u8]), WsError> {
+        let close_as_error = self.read_state.config.close_as_error;
+        let (header, data) = self
+            .read_state
+            .async_receive_frame(&mut self.stream)
+            .await?;
+        check_close(close_as_error, &header, data)?;
+        Ok((header, data))
     }
 }
 
@@ -293,6 +733,11 @@ impl<S: AsyncWrite + Unpin> AsyncFrameSend<S> {
         }
     }
 
+    /// whether close frame has been sent
+    pub fn close_sent(&self) -> bool {
+        self.write_state.close_sent()
+    }
+
     /// send immutable payload
     ///
     /// will auto fragment if auto_fragment_size > 0
@@ -300,7 +745,40 @@ impl<S: AsyncWrite + Unpin> AsyncFrameSend<S> {
         self.write_state
             .async_send(&mut self.stream, opcode, payload)
             .await
-            .map_err(WsError::IOError)
+            .map_err(WsError::from)
+    }
+
+    /// send a single frame with explicit fin bit, **this method do not fragment**
+    pub async fn send_frame(
+        &mut self,
+        code: OpCode,
+        payload: &[u8],
+        fin: bool,
+    ) -> Result<(), WsError> {
+        self.write_state
+            .async_send_frame(&mut self.stream, fin, code, payload)
+            .await
+            .map_err(WsError::from)
+    }
+
+    /// send a single frame whose payload is concatenation of `parts`, see
+    /// `FrameWriteState::async_send_vectored`
+    pub async fn send_vectored(&mut self, code: OpCode, parts: &[&[u8]]) -> Result<(), WsError> {
+        self.write_state
+            .async_send_vectored(&mut self.stream, code, parts)
+            .await
+            .map_err(WsError::from)
+    }
+
+    /// send chunks as a fragmented message, see `FrameWriteState::async_send_fragmented`
+    pub async fn send_fragmented<'a, I: IntoIterator<Item = &'a [u8]>>(
+        &mut self,
+        code: OpCode,
+        chunks: I,
+    ) -> Result<(), WsError> {
+        self.write_state
+            .async_send_fragmented(&mut self.stream, code, chunks)
+            .await
     }
 
     /// send a read frame, **this method will not check validation of frame and do not fragment**
@@ -308,12 +786,41 @@ impl<S: AsyncWrite + Unpin> AsyncFrameSend<S> {
         self.write_state
             .async_send_owned_frame(&mut self.stream, frame)
             .await
-            .map_err(WsError::IOError)
+            .map_err(WsError::from)
+    }
+
+    /// send close frame for protocol error with masking matching role, do
+    /// nothing for other errors
+    pub async fn close_with_error(&mut self, error: &WsError) -> Result<(), WsError> {
+        self.write_state
+            .async_send_error_close(&mut self.stream, error)
+            .await
+            .map_err(WsError::from)
+    }
+
+    /// send a prepared masked frame
+    pub async fn send_prepared(&mut self, frame: &PreparedMaskedFrame) -> Result<(), WsError> {
+        self.write_state
+            .async_send_prepared(&mut self.stream, frame)
+            .await
+            .map_err(WsError::from)
+    }
+
+    /// send a frame encoded once by `frame::encode_once`, such as one message
+    /// fanned out to many connections
+    ///
+    /// **NOTE** frame bytes bypass masking of this connection, only use it on
+    /// server side
+    pub async fn send_encoded(&mut self, frame: &Bytes) -> Result<(), WsError> {
+        self.write_state
+            .async_send_encoded(&mut self.stream, frame)
+            .await
+            .map_err(WsError::from)
     }
 
     /// flush to ensure all data are send
     pub async fn flush(&mut self) -> Result<(), WsError> {
-        self.stream.flush().await.map_err(WsError::IOError)
+        self.stream.flush().await.map_err(WsError::from)
     }
 }
 
@@ -352,46 +859,631 @@ impl<S: AsyncRead + AsyncWrite + Unpin> AsyncFrameCodec<S> {
     }
 
     /// used for server side to construct a new server
-    pub fn factory(_req: http::Request<()>, stream: S) -> Result<Self, WsError> {
+    pub fn factory(req: http::Request<()>, stream: S) -> Result<Self, WsError> {
         let config = FrameConfig {
             mask_send_frame: false,
+            expect_mask: Some(true),
             ..Default::default()
         };
-        Ok(Self::new_with(stream, config))
+        let mut codec = Self::new_with(stream, config);
+        codec.read_state.feed_remain(req.extensions());
+        Ok(codec)
     }
 
     /// used to client side to construct a new client
     pub fn check_fn(key: String, resp: http::Response<()>, stream: S) -> Result<Self, WsError> {
         standard_handshake_resp_check(key.as_bytes(), &resp)?;
-        Ok(Self::new_with(stream, FrameConfig::default()))
+        let config = FrameConfig {
+            expect_mask: Some(false),
+            ..Default::default()
+        };
+        let mut codec = Self::new_with(stream, config);
+        codec.read_state.feed_remain(resp.extensions());
+        Ok(codec)
     }
 
     /// receive a frame
     pub async fn receive(&mut self) -> Result<(SimplifiedHeader, &[u8]), WsError> {
-        self.read_state.async_receive(&mut self.stream).await
+        self.check_receive()?;
+        let (header, data) = self.read_state.async_receive(&mut self.stream).await?;
+        if let Some(echo) = self.write_state.close_reply(&header, data) {
+            self.write_state
+                .async_send(&mut self.stream, OpCode::Close, echo)
+                .await?;
+        }
+        check_close(self.write_state.config.close_as_error, &header, data)?;
+        Ok((header, data))
+    }
+
+    /// poll to receive a message, payload is copied into an owned frame
+    ///
+    /// for custom executor or manual `Future`/`Stream` impls, it's cancel safe,
+    /// partial frame is kept in read buf and next poll continues from it
+    ///
+    /// **NOTE** unlike `receive`, close frame is not replied automatically
+    pub fn poll_receive(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<OwnedFrame, WsError>> {
+        let this = self.get_mut();
+        this.close_state().check_receive()?;
+        this.read_state.poll_receive(&mut this.stream, cx)
+    }
+
+    /// receive a frame, fail if it's not complete before deadline
+    ///
+    /// see `FrameReadState::async_receive_deadline`
+    pub async fn receive_deadline(
+        &mut self,
+        deadline: Instant,
+    ) -> Result<(SimplifiedHeader, &[u8]), WsError> {
+        self.check_receive()?;
+        let (header, data) = self
+            .read_state
+            .async_receive_deadline(&mut self.stream, deadline)
+            .await?;
+        if let Some(echo) = self.write_state.close_reply(&header, data) {
+            self.write_state
+                .async_send(&mut self.stream, OpCode::Close, echo)
+                .await?;
+        }
+        check_close(self.write_state.config.close_as_error, &header, data)?;
+        Ok((header, data))
+    }
+
+    /// send ping with `payload` and wait for pong carrying the same payload,
+    /// return round trip time
+    ///
+    /// frames received meanwhile are passed to `on_frame`, ping is answered
+    /// with pong before being passed, close frame fails with `WsError::Closed`
+    /// after being replied if `auto_close_reply` is enabled, fail with
+    /// `ConnectionLost` if matching pong is not received within timeout
+    pub async fn ping_pong<F>(
+        &mut self,
+        payload: &[u8],
+        timeout: Duration,
+        mut on_frame: F,
+    ) -> Result<Duration, WsError>
+    where
+        F: FnMut(SimplifiedHeader, &[u8]) -> Result<(), WsError>,
+    {
+        self.check_receive()?;
+        let deadline = Instant::now() + timeout;
+        self.write_state
+            .async_send(&mut self.stream, OpCode::Ping, payload)
+            .await?;
+        let mut tracker = PingTracker::new(true);
+        tracker.ping_sent(payload);
+        loop {
+            let (header, loc) = match self
+                .read_state
+                .async_receive_loc(&mut self.stream, Some(deadline))
+                .await
+            {
+                Ok(ret) => ret,
+                Err(WsError::DeadlineExceeded) => {
+                    return Err(WsError::ConnectionLost(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "pong timeout",
+                    )));
+                }
+                Err(e) => return Err(e),
+            };
+            let data = self.read_state.payload(&loc);
+            match header.code {
+                OpCode::Pong => {
+                    if let Some(rtt) = tracker.pong_received(data) {
+                        return Ok(rtt);
+                    }
+                }
+                OpCode::Ping => {
+                    let data = data.to_vec();
+                    self.write_state
+                        .async_send(&mut self.stream, OpCode::Pong, &data)
+                        .await?;
+                }
+                OpCode::Close => {
+                    if let Some(echo) = self.write_state.close_reply(&header, data) {
+                        self.write_state
+                            .async_send(&mut self.stream, OpCode::Close, echo)
+                            .await?;
+                    }
+                    return Err(WsError::closed(data));
+                }
+                _ => {}
+            }
+            self.read_state.record_size(&loc);
+            on_frame(header, self.read_state.payload(&loc))?;
+        }
+    }
+
+    /// receive a complete message
+    ///
+    /// control frames interleaved between fragments are handled internally,
+    /// ping is answered with pong and pong is dropped, close is returned
+    pub async fn receive_reassembled(&mut self) -> Result<(SimplifiedHeader, &[u8]), WsError> {
+        self.check_receive()?;
+        loop {
+            let (header, loc) = self
+                .read_state
+                .async_receive_loc(&mut self.stream, None)
+                .await?;
+            if self.read_state.is_fragmented() {
+                match header.code {
+                    OpCode::Ping => {
+                        let payload = self.read_state.payload(&loc).to_vec();
+                        self.write_state
+                            .async_send(&mut self.stream, OpCode::Pong, &payload)
+                            .await?;
+                        continue;
+                    }
+                    OpCode::Pong => continue,
+                    _ => {}
+                }
+            }
+            self.read_state.record_size(&loc);
+            let data = self.read_state.payload(&loc);
+            if let Some(echo) = self.write_state.close_reply(&header, data) {
+                self.write_state
+                    .async_send(&mut self.stream, OpCode::Close, echo)
+                    .await?;
+            }
+            check_close(self.write_state.config.close_as_error, &header, data)?;
+            return Ok((header, data));
+        }
+    }
+
+    /// same as `receive_reassembled` if keepalive is not enabled, otherwise
+    /// send ping when nothing is read within keepalive interval, interval
+    /// restarts on every frame
+    ///
+    /// only pong, or bytes of a slow frame arriving, proves peer is alive,
+    /// return `ConnectionLost` if neither happens within another interval
+    /// after ping, ping is answered with pong, ping/pong are only returned if
+    /// `expose_control` is set
+    pub(crate) async fn receive_keepalive(
+        &mut self,
+        keepalive: &mut Option<Keepalive>,
+    ) -> Result<(SimplifiedHeader, &[u8]), WsError> {
+        self.check_receive()?;
+        let keepalive = match keepalive {
+            Some(keepalive) => keepalive,
+            None => return self.receive_reassembled().await,
+        };
+        loop {
+            // deadline read keeps partial frame in buffer, so a frame split
+            // across interval is resumed instead of being dropped
+            let deadline = Instant::now() + keepalive.config.interval;
+            let received = self.read_state.received_bytes();
+            let (header, loc) = match self
+                .read_state
+                .async_receive_loc(&mut self.stream, Some(deadline))
+                .await
+            {
+                Ok(ret) => ret,
+                Err(WsError::DeadlineExceeded) => {
+                    if self.read_state.received_bytes() != received {
+                        keepalive.tracker.pong_received(&[]);
+                        continue;
+                    }
+                    if keepalive.tracker.is_waiting() {
+                        return Err(WsError::ConnectionLost(std::io::Error::new(
+                            std::io::ErrorKind::TimedOut,
+                            "keepalive ping timeout",
+                        )));
+                    }
+                    self.write_state
+                        .async_send(&mut self.stream, OpCode::Ping, &[])
+                        .await?;
+                    keepalive.tracker.ping_sent(&[]);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            match header.code {
+                OpCode::Ping => {
+                    let payload = self.read_state.payload(&loc).to_vec();
+                    self.write_state
+                        .async_send(&mut self.stream, OpCode::Pong, &payload)
+                        .await?;
+                    if !keepalive.config.expose_control {
+                        continue;
+                    }
+                }
+                OpCode::Pong => {
+                    keepalive
+                        .tracker
+                        .pong_received(self.read_state.payload(&loc));
+                    if !keepalive.config.expose_control {
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            self.read_state.record_size(&loc);
+            let data = self.read_state.payload(&loc);
+            if let Some(echo) = self.write_state.close_reply(&header, data) {
+                self.write_state
+                    .async_send(&mut self.stream, OpCode::Close, echo)
+                    .await?;
+            }
+            check_close(self.write_state.config.close_as_error, &header, data)?;
+            return Ok((header, data));
+        }
+    }
+
+    /// receive next data message, control frames are handled internally
+    ///
+    /// ping is answered with pong, pong is dropped, fragments are reassembled
+    /// and close frame is echoed then reported as `Event::Closed`, requires
+    /// `merge_frame` to be enabled
+    pub async fn receive_message(&mut self) -> Result<Event<&[u8]>, WsError> {
+        self.check_receive()?;
+        loop {
+            let (header, loc) = self
+                .read_state
+                .async_receive_loc(&mut self.stream, None)
+                .await?;
+            match header.code {
+                OpCode::Ping => {
+                    let payload = self.read_state.payload(&loc).to_vec();
+                    self.write_state
+                        .async_send(&mut self.stream, OpCode::Pong, &payload)
+                        .await?;
+                }
+                OpCode::Pong => {}
+                OpCode::Close => {
+                    let data = self.read_state.payload(&loc);
+                    let echo = if data.len() >= 2 { &data[..2] } else { &[] };
+                    self.write_state
+                        .async_send(&mut self.stream, OpCode::Close, echo)
+                        .await?;
+                    return Ok(Event::Closed(CloseEvent::from_payload(data)));
+                }
+                OpCode::Text | OpCode::Binary => {
+                    self.read_state.record_size(&loc);
+                    let data = self.read_state.payload(&loc);
+                    return Ok(if header.code == OpCode::Text {
+                        Event::Text(data)
+                    } else {
+                        Event::Binary(data)
+                    });
+                }
+                code => return Err(WsError::UnsupportedFrame(code)),
+            }
+        }
+    }
+
+    /// number of bytes read from stream but not parsed yet
+    pub fn buffered_len(&self) -> usize {
+        self.read_state.buffered_len()
+    }
+
+    /// max number of unparsed bytes ever buffered
+    pub fn buffered_high_water_mark(&self) -> usize {
+        self.read_state.buffered_high_water_mark()
+    }
+
+    /// histogram of received message payload size
+    #[cfg(feature = "metrics")]
+    pub fn size_histogram(&self) -> &hdrhistogram::Histogram<u64> {
+        self.read_state.size_histogram()
+    }
+
+    /// current closing handshake state
+    pub fn close_state(&self) -> CloseState {
+        CloseState::from_flags(
+            self.write_state.close_sent(),
+            self.read_state.close_received(),
+        )
+    }
+
+    fn check_receive(&self) -> Result<(), WsError> {
+        self.read_state.check_after_close()?;
+        self.close_state().check_receive()
+    }
+
+    /// set masking key generator of outgoing frames, see `FrameWriteState::set_mask_fn`
+    pub fn set_mask_fn<F: FnMut() -> [u8; 4] + Send + 'static>(&mut self, f: F) {
+        self.write_state.set_mask_fn(f);
+    }
+
+    /// current connection state, sending frames other than close is rejected
+    /// once it's closing, so is receiving after peer close frame
+    pub fn state(&self) -> ConnectionState {
+        if !self.write_state.frame_sent() && !self.read_state.frame_received() {
+            return ConnectionState::Created;
+        }
+        self.close_state().into()
+    }
+
+    /// reset codec for a fresh logical session over the same stream, close
+    /// state goes back to `Open`, fragmented message, stats and buffered bytes
+    /// are dropped
+    ///
+    /// **NOTE** advanced api, only valid if the underlying transport is really
+    /// reusable after closing handshake, such as some tunnels
+    pub fn reset(&mut self) {
+        self.read_state.reset();
+        self.write_state.reset();
+    }
+
+    /// receive a single frame, fragmented frames are not merged
+    pub async fn receive_frame(&mut self) -> Result<(SimplifiedHeader, &[u8]), WsError> {
+        self.check_receive()?;
+        let (header, data) = self
+            .read_state
+            .async_receive_frame(&mut self.stream)
+            .await?;
+        if let Some(echo) = self.write_state.close_reply(&header, data) {
+            self.write_state
+                .async_send(&mut self.stream, OpCode::Close, echo)
+                .await?;
+        }
+        check_close(self.write_state.config.close_as_error, &header, data)?;
+        Ok((header, data))
+    }
+
+    /// receive a single frame which borrows codec read buffer without copying
+    /// payload, fragmented frames are not merged
+    ///
+    /// **NOTE** returned frame must be dropped before next receive, see
+    /// `FrameReadState::async_receive_borrowed`
+    pub async fn receive_borrowed(&mut self) -> Result<BorrowedFrame<'_>, WsError> {
+        self.check_receive()?;
+        let frame = self
+            .read_state
+            .async_receive_borrowed(&mut self.stream)
+            .await?;
+        let header: SimplifiedHeader = frame.header().into();
+        if let Some(echo) = self.write_state.close_reply(&header, frame.payload()) {
+            self.write_state
+                .async_send(&mut self.stream, OpCode::Close, echo)
+                .await?;
+        }
+        check_close(
+            self.write_state.config.close_as_error,
+            &header,
+            frame.payload(),
+        )?;
+        Ok(frame)
     }
 
     /// send payload
     ///
     /// will auto fragment if auto_fragment_size > 0
     pub async fn send(&mut self, opcode: OpCode, payload: &[u8]) -> Result<(), WsError> {
+        self.close_state().check_send(opcode)?;
         self.write_state
             .async_send(&mut self.stream, opcode, payload)
             .await
-            .map_err(WsError::IOError)
+            .map_err(WsError::from)
+    }
+
+    /// send a single frame with explicit fin bit, **this method do not fragment**
+    pub async fn send_frame(
+        &mut self,
+        code: OpCode,
+        payload: &[u8],
+        fin: bool,
+    ) -> Result<(), WsError> {
+        self.close_state().check_send(code)?;
+        self.write_state
+            .async_send_frame(&mut self.stream, fin, code, payload)
+            .await
+            .map_err(WsError::from)
+    }
+
+    /// send a single frame whose payload is concatenation of `parts`, see
+    /// `FrameWriteState::async_send_vectored`
+    pub async fn send_vectored(&mut self, code: OpCode, parts: &[&[u8]]) -> Result<(), WsError> {
+        self.close_state().check_send(code)?;
+        self.write_state
+            .async_send_vectored(&mut self.stream, code, parts)
+            .await
+            .map_err(WsError::from)
+    }
+
+    /// send chunks as a fragmented message, see `FrameWriteState::async_send_fragmented`
+    pub async fn send_fragmented<'a, I: IntoIterator<Item = &'a [u8]>>(
+        &mut self,
+        code: OpCode,
+        chunks: I,
+    ) -> Result<(), WsError> {
+        self.close_state().check_send(code)?;
+        self.write_state
+            .async_send_fragmented(&mut self.stream, code, chunks)
+            .await
     }
 
     /// send a read frame, **this method will not check validation of frame and do not fragment**
     pub async fn send_owned_frame(&mut self, frame: OwnedFrame) -> Result<(), WsError> {
+        self.close_state().check_send(frame.header().opcode())?;
         self.write_state
             .async_send_owned_frame(&mut self.stream, frame)
             .await
-            .map_err(WsError::IOError)
+            .map_err(WsError::from)
+    }
+
+    /// send close frame for protocol error with masking matching role, do
+    /// nothing for other errors
+    pub async fn close_with_error(&mut self, error: &WsError) -> Result<(), WsError> {
+        self.write_state
+            .async_send_error_close(&mut self.stream, error)
+            .await
+            .map_err(WsError::from)
+    }
+
+    /// perform closing handshake, send close frame, drop received frames until
+    /// peer close frame arrives, then shutdown stream
+    ///
+    /// wait at most `FrameConfig::close_timeout`, reason is truncated to fit
+    /// in a close frame
+    ///
+    /// after it, sending frames other than close returns `WsError::InvalidConnState`
+    pub async fn close(&mut self, code: u16, reason: &str) -> Result<(), WsError> {
+        let payload = close_payload(code, reason);
+        self.write_state
+            .async_send(&mut self.stream, OpCode::Close, &payload)
+            .await?;
+        self.stream.flush().await?;
+        let deadline = self
+            .read_state
+            .config
+            .close_timeout
+            .map(|timeout| Instant::now() + timeout);
+        while !self.read_state.close_received() {
+            self.read_state
+                .async_receive_loc(&mut self.stream, deadline)
+                .await?;
+        }
+        self.stream.shutdown().await?;
+        Ok(())
+    }
+
+    /// send a prepared masked frame
+    pub async fn send_prepared(&mut self, frame: &PreparedMaskedFrame) -> Result<(), WsError> {
+        self.close_state()
+            .check_send(frame.frame().header().opcode())?;
+        self.write_state
+            .async_send_prepared(&mut self.stream, frame)
+            .await
+            .map_err(WsError::from)
+    }
+
+    /// send a frame encoded once by `frame::encode_once`, such as one message
+    /// fanned out to many connections
+    ///
+    /// **NOTE** frame bytes bypass masking of this connection, only use it on
+    /// server side
+    pub async fn send_encoded(&mut self, frame: &Bytes) -> Result<(), WsError> {
+        if let Some(&first) = frame.first() {
+            self.close_state().check_send(parse_opcode(first))?;
+        }
+        self.write_state
+            .async_send_encoded(&mut self.stream, frame)
+            .await
+            .map_err(WsError::from)
     }
 
     /// flush to ensure all data are send
     pub async fn flush(&mut self) -> Result<(), WsError> {
-        self.stream.flush().await.map_err(WsError::IOError)
+        self.stream.flush().await.map_err(WsError::from)
+    }
+}
+
+/// `Sink` impls write buffered frames before accepting more once buffered
+/// bytes exceed this size
+#[cfg(feature = "futures")]
+const SINK_BUF_LIMIT: usize = 64 * 1024;
+
+#[cfg(feature = "futures")]
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncFrameCodec<S> {
+    /// poll next message for `Stream` impls, return `None` once peer close
+    /// frame has been received
+    ///
+    /// close echo is encoded into `out` instead of being written, it's sent
+    /// on next `poll_write_buf`
+    pub(crate) fn poll_next_message(
+        &mut self,
+        out: &mut BytesMut,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Message<BytesMut>, WsError>>> {
+        if self.read_state.close_received() {
+            return Poll::Ready(None);
+        }
+        let (header, loc) = match ready!(self.read_state.poll_receive_loc(&mut self.stream, cx)) {
+            Ok(ret) => ret,
+            Err(e) => return Poll::Ready(Some(Err(e))),
+        };
+        self.read_state.record_size(&loc);
+        let data = self.read_state.payload(&loc);
+        if let Some(echo) = self.write_state.close_reply(&header, data) {
+            self.write_state.encode(out, OpCode::Close, echo);
+        }
+        if let Err(e) = check_close(self.write_state.config.close_as_error, &header, data) {
+            return Poll::Ready(Some(Err(e)));
+        }
+        let mut data = BytesMut::from(data);
+        // close frame without status code has no close code
+        let close_code = if header.code == OpCode::Close && data.len() >= 2 {
+            Some(bytes::Buf::get_u16(&mut data))
+        } else {
+            None
+        };
+        Poll::Ready(Some(Ok(Message {
+            code: header.code,
+            data,
+            close_code,
+        })))
+    }
+
+    /// encode message into `buf`, close code is prepended to close payload
+    pub(crate) fn encode_message(
+        &mut self,
+        buf: &mut BytesMut,
+        code: OpCode,
+        close_code: Option<u16>,
+        data: &[u8],
+    ) -> Result<(), WsError> {
+        self.close_state().check_send(code)?;
+        match close_code {
+            Some(close_code) if code == OpCode::Close => {
+                let mut payload = close_code.to_be_bytes().to_vec();
+                payload.extend_from_slice(data);
+                self.write_state.encode(buf, code, &payload);
+            }
+            _ => self.write_state.encode(buf, code, data),
+        }
+        Ok(())
+    }
+
+    /// write buffered frames before accepting more if `buf` is too large
+    pub(crate) fn poll_ready_buf(
+        &mut self,
+        buf: &mut BytesMut,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), WsError>> {
+        if buf.len() >= SINK_BUF_LIMIT {
+            self.poll_write_buf(buf, cx)
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// send normal close frame if close frame has not been sent, then write
+    /// all buffered frames and shutdown stream
+    pub(crate) fn poll_close_buf(
+        &mut self,
+        buf: &mut BytesMut,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), WsError>> {
+        if !self.write_state.close_sent() {
+            self.encode_message(buf, OpCode::Close, Some(1000), &[])?;
+        }
+        ready!(self.poll_write_buf(buf, cx))?;
+        ready!(Pin::new(&mut self.stream).poll_shutdown(cx))?;
+        Poll::Ready(Ok(()))
+    }
+
+    /// write all encoded frames in `buf` and flush stream
+    pub(crate) fn poll_write_buf(
+        &mut self,
+        buf: &mut BytesMut,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), WsError>> {
+        while !buf.is_empty() {
+            let num = ready!(Pin::new(&mut self.stream).poll_write(cx, buf))?;
+            if num == 0 {
+                return Poll::Ready(Err(WsError::IOError(std::io::Error::new(
+                    std::io::ErrorKind::WriteZero,
+                    "failed to write frame",
+                ))));
+            }
+            bytes::Buf::advance(buf, num);
+        }
+        ready!(Pin::new(&mut self.stream).poll_flush(cx))?;
+        Poll::Ready(Ok(()))
     }
 }
 
@@ -415,3 +1507,412 @@ where
         )
     }
 }
+
+impl<S: AsyncRead + AsyncWrite> AsyncFrameCodec<S> {
+    /// split codec to recv and send parts with `tokio::io::split`, works for
+    /// any stream, even if it does not implement `Split`
+    ///
+    /// **NOTE** both halves share the stream behind a lock, every poll of either
+    /// half takes the lock, the overhead is small but prefer `split` if stream
+    /// implements `Split`, such as `TcpStream`
+    pub fn into_split(
+        self,
+    ) -> (
+        AsyncFrameRecv<tokio::io::ReadHalf<S>>,
+        AsyncFrameSend<tokio::io::WriteHalf<S>>,
+    ) {
+        let AsyncFrameCodec {
+            stream,
+            read_state,
+            write_state,
+        } = self;
+        let (read, write) = tokio::io::split(stream);
+        (
+            AsyncFrameRecv::new(read, read_state),
+            AsyncFrameSend::new(write, write_state),
+        )
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> AsyncFrameRead for AsyncFrameCodec<S> {
+    async fn recv(&mut self) -> Result<OwnedFrame, WsError> {
+        let (header, data) = self.receive().await?;
+        Ok(to_owned_frame(&header, data))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> AsyncFrameWrite for AsyncFrameCodec<S> {
+    async fn send_owned_frame(&mut self, frame: OwnedFrame) -> Result<(), WsError> {
+        AsyncFrameCodec::send_owned_frame(self, frame).await
+    }
+}
+
+impl<S: AsyncRead + Unpin + Send> AsyncFrameRead for AsyncFrameRecv<S> {
+    async fn recv(&mut self) -> Result<OwnedFrame, WsError> {
+        let (header, data) = self.receive().await?;
+        Ok(to_owned_frame(&header, data))
+    }
+}
+
+impl<S: AsyncWrite + Unpin + Send> AsyncFrameWrite for AsyncFrameSend<S> {
+    async fn send_owned_frame(&mut self, frame: OwnedFrame) -> Result<(), WsError> {
+        AsyncFrameSend::send_owned_frame(self, frame).await
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_frame_read_write_traits() {
+    /// echo data frames until close frame, written once for codec and split halves
+    async fn echo<R: AsyncFrameRead, W: AsyncFrameWrite>(
+        read: &mut R,
+        write: &mut W,
+    ) -> Result<usize, WsError> {
+        let mut count = 0;
+        loop {
+            let frame = read.recv().await?;
+            if frame.header().opcode() == OpCode::Close {
+                return Ok(count);
+            }
+            count += 1;
+            write.send_owned_frame(frame).await?;
+        }
+    }
+
+    let (client, server) = tokio::io::duplex(1024);
+    let mut client = AsyncFrameCodec::new(client);
+    let config = FrameConfig {
+        mask_send_frame: false,
+        ..Default::default()
+    };
+    let server = AsyncFrameCodec::new_with(server, config);
+    let peer = tokio::spawn(async move {
+        let (mut read, mut write) = server.into_split();
+        echo(&mut read, &mut write).await
+    });
+    client.send(OpCode::Text, b"hello").await.unwrap();
+    let frame = AsyncFrameRead::recv(&mut client).await.unwrap();
+    assert_eq!(frame.header().opcode(), OpCode::Text);
+    assert_eq!(&frame.payload()[..], b"hello");
+    client.send(OpCode::Binary, b"world").await.unwrap();
+    let frame = AsyncFrameRead::recv(&mut client).await.unwrap();
+    assert_eq!(frame.header().opcode(), OpCode::Binary);
+    assert_eq!(&frame.payload()[..], b"world");
+    client.send(OpCode::Close, b"\x03\xe8").await.unwrap();
+    assert_eq!(peer.await.unwrap().unwrap(), 2);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_receive_deadline() {
+    use std::time::Duration;
+
+    let (mut client, server) = tokio::io::duplex(1024);
+    let mut recv = AsyncFrameRecv::new(server, Default::default());
+    client.write_all(&[0x82, 4, 1, 2]).await.unwrap();
+    match recv
+        .receive_deadline(Instant::now() + Duration::from_millis(20))
+        .await
+    {
+        Err(WsError::DeadlineExceeded) => {}
+        other => panic!("expect timeout, got {:?}", other.map(|_| ())),
+    }
+
+    // partial frame is kept after timeout
+    client.write_all(&[3, 4]).await.unwrap();
+    let (header, data) = recv.receive().await.unwrap();
+    assert_eq!(header.code, OpCode::Binary);
+    assert_eq!(data, &[1, 2, 3, 4]);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_expect_mask() {
+    let (client, server) = tokio::io::duplex(1024);
+    let strict = FrameConfig::builder()
+        .expect_mask(Some(false))
+        .build()
+        .unwrap();
+    let mut client = AsyncFrameCodec::new_with(client, strict);
+    // server forced to mask its frames
+    let mut server = AsyncFrameCodec::new_with(server, FrameConfig::default());
+
+    server.send(OpCode::Text, b"masked").await.unwrap();
+    assert!(matches!(
+        client.receive().await,
+        Err(WsError::ProtocolError {
+            close_code: 1002,
+            error: crate::errors::ProtocolError::UnexpectedMask(true)
+        })
+    ));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_poll_receive() {
+    use std::future::poll_fn;
+
+    let (mut client, server) = tokio::io::duplex(1024);
+    let mut server = AsyncFrameCodec::new(server);
+
+    // masked "hello" split in two writes, first poll only sees half frame
+    let frame = OwnedFrame::text_frame([1, 2, 3, 4], "hello");
+    let mut data = frame.header().as_bytes().to_vec();
+    data.extend_from_slice(frame.payload());
+    client.write_all(&data[..4]).await.unwrap();
+    let pending = poll_fn(|cx| Poll::Ready(Pin::new(&mut server).poll_receive(cx).is_pending()));
+    assert!(pending.await);
+    client.write_all(&data[4..]).await.unwrap();
+    let frame = poll_fn(|cx| Pin::new(&mut server).poll_receive(cx))
+        .await
+        .unwrap();
+    assert_eq!(frame.header().opcode(), OpCode::Text);
+    assert!(frame.header().fin());
+    assert_eq!(&frame.payload()[..], b"hello");
+
+    let mut client = AsyncFrameCodec::new(client);
+    client
+        .send_frame(OpCode::Binary, b"ab", false)
+        .await
+        .unwrap();
+    client
+        .send_frame(OpCode::Continue, b"cd", true)
+        .await
+        .unwrap();
+    let frame = poll_fn(|cx| Pin::new(&mut server).poll_receive(cx))
+        .await
+        .unwrap();
+    assert_eq!(frame.header().opcode(), OpCode::Binary);
+    assert_eq!(&frame.payload()[..], b"abcd");
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_reset() {
+    let (client, server) = tokio::io::duplex(1024);
+    let mut client = AsyncFrameCodec::new(client);
+    let mut server = AsyncFrameCodec::new(server);
+
+    for msg in [&b"first"[..], b"second"] {
+        client.send(OpCode::Text, msg).await.unwrap();
+        let (_, data) = server.receive().await.unwrap();
+        assert_eq!(data, msg);
+        client
+            .send(OpCode::Close, &1000u16.to_be_bytes())
+            .await
+            .unwrap();
+        server.receive().await.unwrap();
+        server
+            .send(OpCode::Close, &1000u16.to_be_bytes())
+            .await
+            .unwrap();
+        client.receive().await.unwrap();
+        assert_eq!(client.close_state(), CloseState::Closed);
+        assert_eq!(server.close_state(), CloseState::Closed);
+
+        client.reset();
+        server.reset();
+        assert_eq!(client.close_state(), CloseState::Open);
+        assert_eq!(server.close_state(), CloseState::Open);
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_max_payload_size() {
+    use crate::errors::ProtocolError;
+
+    let (mut client, server) = tokio::io::duplex(1024);
+    let config = FrameConfig::builder()
+        .max_frame_payload_size(1024)
+        .build()
+        .unwrap();
+    let mut server = AsyncFrameCodec::new_with(server, config);
+    // only header is sent, frame must be rejected without waiting for payload
+    let mut data = vec![0x82, 127];
+    data.extend_from_slice(&(1u64 << 62).to_be_bytes());
+    client.write_all(&data).await.unwrap();
+    let ret = tokio::time::timeout(std::time::Duration::from_secs(5), server.receive())
+        .await
+        .unwrap();
+    match ret {
+        Err(WsError::ProtocolError {
+            close_code,
+            error: ProtocolError::PayloadTooLarge(1024),
+        }) => assert_eq!(close_code, 1009),
+        other => panic!("expect protocol error, got {:?}", other.map(|(h, _)| h)),
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_graceful_close() {
+    use std::time::Duration;
+
+    let (client, server) = tokio::io::duplex(1024);
+    let mut client = AsyncFrameCodec::new(client);
+    let config = FrameConfig {
+        mask_send_frame: false,
+        ..Default::default()
+    };
+    let mut server = AsyncFrameCodec::new_with(server, config);
+    let peer = tokio::spawn(async move {
+        // data frame sent before close echo is dropped by client
+        server.send(OpCode::Text, b"late").await.unwrap();
+        let (header, data) = server.receive().await.unwrap();
+        assert_eq!(header.code, OpCode::Close);
+        assert_eq!(data, b"\x03\xe8bye");
+        server.send(OpCode::Close, b"\x03\xe8").await.unwrap();
+    });
+    client.close(1000, "bye").await.unwrap();
+    peer.await.unwrap();
+    assert_eq!(client.close_state(), CloseState::Closed);
+    assert_eq!(client.state(), ConnectionState::Closed);
+    assert!(matches!(
+        client.send(OpCode::Text, b"after close").await,
+        Err(WsError::InvalidConnState(ConnectionState::Closed))
+    ));
+    assert!(matches!(
+        client.receive().await,
+        Err(WsError::InvalidConnState(ConnectionState::Closed))
+    ));
+    client.send(OpCode::Close, b"").await.unwrap();
+
+    // peer never answers
+    let (client, _server) = tokio::io::duplex(1024);
+    let config = FrameConfig::builder()
+        .close_timeout(Some(Duration::from_millis(50)))
+        .build()
+        .unwrap();
+    let mut client = AsyncFrameCodec::new_with(client, config);
+    match client.close(1000, "").await {
+        Err(WsError::DeadlineExceeded) => {}
+        other => panic!("expect timeout, got {:?}", other),
+    }
+    assert_eq!(client.close_state(), CloseState::LocalClosing);
+    assert_eq!(client.state(), ConnectionState::Closing);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_ping_pong() {
+    let (client, server) = tokio::io::duplex(1024);
+    let mut client = AsyncFrameCodec::new(client);
+    let mut server = AsyncFrameCodec::new_with(
+        server,
+        FrameConfig {
+            mask_send_frame: false,
+            ..Default::default()
+        },
+    );
+    let peer = tokio::spawn(async move {
+        server.send(OpCode::Text, b"before").await.unwrap();
+        let (header, data) = server.receive().await.unwrap();
+        assert_eq!(header.code, OpCode::Ping);
+        assert_eq!(data, b"probe");
+        server.send(OpCode::Pong, b"other").await.unwrap();
+        server.send(OpCode::Pong, b"probe").await.unwrap();
+        // never answer second ping
+        let (header, _) = server.receive().await.unwrap();
+        assert_eq!(header.code, OpCode::Ping);
+        let (header, _) = server.receive().await.unwrap();
+        assert_eq!(header.code, OpCode::Ping);
+        server
+            .send(OpCode::Close, &1000u16.to_be_bytes())
+            .await
+            .unwrap();
+    });
+
+    let mut received = vec![];
+    client
+        .ping_pong(b"probe", Duration::from_secs(5), |header, data| {
+            received.push((header.code, data.to_vec()));
+            Ok(())
+        })
+        .await
+        .unwrap();
+    // pong not matching ping is passed too
+    assert_eq!(
+        received,
+        [
+            (OpCode::Text, b"before".to_vec()),
+            (OpCode::Pong, b"other".to_vec())
+        ]
+    );
+
+    let ret = client
+        .ping_pong(b"lost", Duration::from_millis(20), |_, _| Ok(()))
+        .await;
+    assert!(
+        matches!(ret, Err(WsError::ConnectionLost(e)) if e.kind() == std::io::ErrorKind::TimedOut)
+    );
+
+    let ret = client
+        .ping_pong(b"closed", Duration::from_secs(5), |_, _| Ok(()))
+        .await;
+    assert!(matches!(ret, Err(WsError::Closed { code: 1000, .. })));
+    peer.await.unwrap();
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_interleaved_control_frame() {
+    use std::future::poll_fn;
+
+    fn frames() -> Vec<u8> {
+        let mut data = vec![];
+        for (fin, code, payload) in [
+            (false, OpCode::Text, &b"frag"[..]),
+            (true, OpCode::Ping, b"p"),
+            (true, OpCode::Continue, b"ment"),
+        ] {
+            data.extend([(fin as u8) << 7 | code as u8, payload.len() as u8]);
+            data.extend_from_slice(payload);
+        }
+        data
+    }
+    let config = FrameConfig {
+        mask_send_frame: false,
+        ..Default::default()
+    };
+
+    let (mut client, server) = tokio::io::duplex(1024);
+    let mut server = AsyncFrameCodec::new_with(server, config.clone());
+    client.write_all(&frames()).await.unwrap();
+    let (header, data) = server.receive().await.unwrap();
+    assert_eq!((header.code, data), (OpCode::Ping, &b"p"[..]));
+    assert!(server.read_state.is_fragmented());
+    let (header, data) = server.receive().await.unwrap();
+    assert_eq!((header.code, data), (OpCode::Text, &b"fragment"[..]));
+
+    // frames arrive byte by byte, pending polls must not drop partial message
+    let (mut client, server) = tokio::io::duplex(1024);
+    let mut server = AsyncFrameCodec::new_with(server, config.clone());
+    let writer = tokio::spawn(async move {
+        for byte in frames() {
+            client.write_all(&[byte]).await.unwrap();
+            tokio::task::yield_now().await;
+        }
+        client
+    });
+    let frame = poll_fn(|cx| Pin::new(&mut server).poll_receive(cx))
+        .await
+        .unwrap();
+    assert_eq!(frame.header().opcode(), OpCode::Ping);
+    let frame = poll_fn(|cx| Pin::new(&mut server).poll_receive(cx))
+        .await
+        .unwrap();
+    assert_eq!(frame.header().opcode(), OpCode::Text);
+    assert_eq!(&frame.payload()[..], b"fragment");
+    writer.await.unwrap();
+
+    // ping is answered internally by `receive_reassembled`
+    let (peer, server) = tokio::io::duplex(1024);
+    let mut server = AsyncFrameCodec::new_with(server, config);
+    let mut peer = AsyncFrameCodec::new(peer);
+    peer.stream_mut().write_all(&frames()).await.unwrap();
+    let (header, data) = server.receive_reassembled().await.unwrap();
+    assert_eq!((header.code, data), (OpCode::Text, &b"fragment"[..]));
+    let (header, data) = peer.receive().await.unwrap();
+    assert_eq!((header.code, data), (OpCode::Pong, &b"p"[..]));
+}
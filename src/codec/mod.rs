@@ -6,6 +6,7 @@ mod binary;
 ))]
 mod deflate;
 mod frame;
+mod ping;
 mod text;
 
 pub use binary::*;
@@ -16,6 +17,7 @@ pub use binary::*;
 ))]
 pub use deflate::*;
 pub use frame::*;
+pub use ping::*;
 pub use text::*;
 
 /// split something into two parts
@@ -82,13 +84,13 @@ mod blocking {
     }
 }
 
-#[cfg(feature = "async")]
+#[cfg(feature = "async_codec")]
 mod non_blocking {
-    use tokio::{
-        io::{AsyncRead, AsyncWrite, BufStream},
-        net::TcpStream,
-    };
+    use tokio::io::{AsyncRead, AsyncWrite, BufStream};
+    #[cfg(feature = "async")]
+    use tokio::net::TcpStream;
 
+    #[cfg(feature = "async")]
     impl crate::codec::Split for TcpStream {
         type R = tokio::io::ReadHalf<TcpStream>;
         type W = tokio::io::WriteHalf<TcpStream>;
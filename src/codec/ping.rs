@@ -0,0 +1,121 @@
+use std::time::{Duration, Instant};
+
+/// track outstanding ping and correlate it with received pong
+#[derive(Debug, Clone, Default)]
+pub struct PingTracker {
+    /// if true, pong with payload different from outstanding ping is ignored,
+    /// otherwise any pong satisfies outstanding ping, default false
+    pub match_pong_payload: bool,
+    outstanding: Option<(Vec<u8>, Instant)>,
+}
+
+impl PingTracker {
+    /// construct method
+    pub fn new(match_pong_payload: bool) -> Self {
+        Self {
+            match_pong_payload,
+            outstanding: None,
+        }
+    }
+
+    /// record a sent ping, replace previous outstanding ping
+    pub fn ping_sent(&mut self, payload: &[u8]) {
+        self.outstanding = Some((payload.to_vec(), Instant::now()));
+    }
+
+    /// whether there is a ping waiting for pong
+    pub fn is_waiting(&self) -> bool {
+        self.outstanding.is_some()
+    }
+
+    /// check received pong, return round trip time if it satisfies outstanding ping
+    pub fn pong_received(&mut self, payload: &[u8]) -> Option<Duration> {
+        match &self.outstanding {
+            Some((ping, _)) if self.match_pong_payload && ping != payload => {
+                tracing::debug!("pong payload does not match ping, ignore");
+                None
+            }
+            Some(_) => self
+                .outstanding
+                .take()
+                .map(|(_, sent_at)| sent_at.elapsed()),
+            None => None,
+        }
+    }
+}
+
+/// keepalive config of async bytes/string codec
+#[derive(Debug, Clone)]
+pub struct KeepaliveConfig {
+    /// send ping if nothing is received within interval, connection is
+    /// considered lost if neither pong nor bytes of a frame arrive within
+    /// another interval
+    pub interval: Duration,
+    /// return ping/pong frames to caller instead of dropping them, default false
+    ///
+    /// ping is answered with pong in either case
+    pub expose_control: bool,
+    /// only pong echoing payload of sent ping satisfies it, default false,
+    /// see `PingTracker::match_pong_payload`
+    pub match_pong_payload: bool,
+}
+
+impl KeepaliveConfig {
+    /// construct with ping interval, ping/pong frames are not returned to caller
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            expose_control: false,
+            match_pong_payload: false,
+        }
+    }
+}
+
+/// keepalive state held by codec
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "async_codec"), allow(dead_code))]
+pub(crate) struct Keepalive {
+    pub(crate) config: KeepaliveConfig,
+    pub(crate) tracker: PingTracker,
+}
+
+impl From<KeepaliveConfig> for Keepalive {
+    fn from(config: KeepaliveConfig) -> Self {
+        Self {
+            tracker: PingTracker::new(config.match_pong_payload),
+            config,
+        }
+    }
+}
+
+#[test]
+fn test_lenient_pong() {
+    let mut tracker = PingTracker::default();
+    assert!(tracker.pong_received(b"ping").is_none());
+    tracker.ping_sent(b"ping");
+    assert!(tracker.pong_received(b"other").is_some());
+    assert!(!tracker.is_waiting());
+    tracker.ping_sent(b"ping");
+    assert!(tracker.pong_received(b"ping").is_some());
+}
+
+#[test]
+fn test_keepalive_match_pong_payload() {
+    let keepalive = Keepalive::from(KeepaliveConfig::new(Duration::from_secs(1)));
+    assert!(!keepalive.tracker.match_pong_payload);
+    let keepalive = Keepalive::from(KeepaliveConfig {
+        match_pong_payload: true,
+        ..KeepaliveConfig::new(Duration::from_secs(1))
+    });
+    assert!(keepalive.tracker.match_pong_payload);
+}
+
+#[test]
+fn test_match_pong_payload() {
+    let mut tracker = PingTracker::new(true);
+    tracker.ping_sent(b"ping");
+    assert!(tracker.pong_received(b"other").is_none());
+    assert!(tracker.is_waiting());
+    assert!(tracker.pong_received(b"ping").is_some());
+    assert!(!tracker.is_waiting());
+}
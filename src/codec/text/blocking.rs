@@ -1,24 +1,41 @@
 use http;
 use crate::{
     codec::{
-        FrameCodec, FrameConfig, FrameReadState, FrameRecv, FrameSend, FrameWriteState, Split,
+        until_close, CloseState, FrameCodec, FrameConfig, FrameReadState, FrameRecv, FrameSend,
+        FrameWriteState, OnBinaryPolicy, Split, StringCodecConfig, ValidateUtf8Policy,
     },
-    errors::{ProtocolError, WsError},
+    errors::WsError,
     frame::OpCode,
     protocol::standard_handshake_resp_check,
-    Message,
+    Event, Message,
 };
 use bytes::Buf;
 use std::borrow::Cow;
 use std::io::{Read, Write};
 
 macro_rules! impl_recv {
-    () => {
+    ($receive:ident) => {
+        /// number of bytes read from stream but not parsed yet
+        pub fn buffered_len(&self) -> usize {
+            self.frame_codec.buffered_len()
+        }
+
+        /// max number of unparsed bytes ever buffered
+        pub fn buffered_high_water_mark(&self) -> usize {
+            self.frame_codec.buffered_high_water_mark()
+        }
+
+        /// histogram of received message payload size
+        #[cfg(feature = "metrics")]
+        pub fn size_histogram(&self) -> &hdrhistogram::Histogram<u64> {
+            self.frame_codec.size_histogram()
+        }
+
         /// in case of ping/pong/close contain non utf-8 string, use this api to receive raw message
         ///
         /// for close frame with body, first two bytes of string are close reason
         pub fn receive_raw(&mut self) -> Result<Message<Cow<[u8]>>, WsError> {
-            let (header, mut data) = self.frame_codec.receive()?;
+            let (header, mut data) = self.frame_codec.$receive()?;
             let close_code = if header.code == OpCode::Close && data.len() >= 2 {
                 let code = if data.len() >= 2 {
                     data.get_u16()
@@ -36,9 +53,26 @@ macro_rules! impl_recv {
             })
         }
 
+        /// iterate received messages, payload is copied, see `receive`
+        ///
+        /// iterator stops after yielding close message or first error
+        pub fn messages(&mut self) -> impl Iterator<Item = Result<Message<String>, WsError>> + '_ {
+            until_close(
+                || {
+                    let msg = self.receive()?;
+                    Ok(Message {
+                        code: msg.code,
+                        data: msg.data.into_owned(),
+                        close_code: msg.close_code,
+                    })
+                },
+                |msg| msg.code == OpCode::Close,
+            )
+        }
+
         /// for close frame with body, first two bytes of string are close reason
         pub fn receive(&mut self) -> Result<Message<Cow<str>>, WsError> {
-            let (header, mut data) = self.frame_codec.receive()?;
+            let (header, mut data) = self.frame_codec.$receive()?;
             let close_code = if header.code == OpCode::Close && data.len() >= 2 {
                 let code = if data.len() >= 2 {
                     data.get_u16()
@@ -49,20 +83,37 @@ macro_rules! impl_recv {
             } else {
                 None
             };
-            let data = if self.validate_utf8 && header.code == OpCode::Text {
-                std::str::from_utf8(data).map_err(|_| WsError::ProtocolError {
-                    close_code: 1001,
-                    error: ProtocolError::InvalidUtf8,
-                })?
-            } else {
-                unsafe { std::str::from_utf8_unchecked(data) }
+            let data = match header.code {
+                OpCode::Binary => match self.config.on_binary {
+                    OnBinaryPolicy::Error => return Err(WsError::UnsupportedFrame(header.code)),
+                    OnBinaryPolicy::Lossy => String::from_utf8_lossy(data),
+                    OnBinaryPolicy::Keep => Cow::Borrowed(std::str::from_utf8(data)?),
+                },
+                OpCode::Text if self.config.validate_utf8 => {
+                    Cow::Borrowed(std::str::from_utf8(data)?)
+                }
+                // close reason may be invalid utf-8 if `lossy_close_reason` is enabled
+                OpCode::Close => String::from_utf8_lossy(data),
+                _ => Cow::Borrowed(unsafe { std::str::from_utf8_unchecked(data) }),
             };
             Ok(Message {
-                data: Cow::Borrowed(data),
+                data,
                 close_code,
                 code: header.code,
             })
         }
+
+        /// receive a text message and deserialize it from json
+        ///
+        /// return `WsError::UnsupportedFrame` if message is not text
+        #[cfg(feature = "json")]
+        pub fn receive_json<T: serde::de::DeserializeOwned>(&mut self) -> Result<T, WsError> {
+            let msg = self.receive()?;
+            if msg.code != OpCode::Text {
+                return Err(WsError::UnsupportedFrame(msg.code));
+            }
+            serde_json::from_str(&msg.data).map_err(|e| WsError::Serde(e.to_string()))
+        }
     };
 }
 
@@ -88,9 +139,9 @@ macro_rules! impl_send {
             let msg: Message<Cow<'a, str>> = msg.into();
             if let Some(close_code) = msg.close_code {
                 if msg.code == OpCode::Close {
-                    let mut data = close_code.to_be_bytes().to_vec();
-                    data.extend_from_slice(msg.data.as_bytes());
-                    self.frame_codec.send(msg.code, &data)
+                    let code = close_code.to_be_bytes();
+                    self.frame_codec
+                        .send_vectored(msg.code, &[&code, msg.data.as_bytes()])
                 } else {
                     self.frame_codec.send(msg.code, msg.data.as_bytes())
                 }
@@ -99,6 +150,19 @@ macro_rules! impl_send {
             }
         }
 
+        /// serialize value to json and send it as text message
+        #[cfg(feature = "json")]
+        pub fn send_json<T: serde::Serialize>(&mut self, value: &T) -> Result<(), WsError> {
+            let data = serde_json::to_string(value).map_err(|e| WsError::Serde(e.to_string()))?;
+            self.frame_codec.send(OpCode::Text, data.as_bytes())
+        }
+
+        /// send close frame for protocol error with masking matching role, do
+        /// nothing for other errors
+        pub fn close_with_error(&mut self, error: &WsError) -> Result<(), WsError> {
+            self.frame_codec.close_with_error(error)
+        }
+
         /// flush underlying stream
         pub fn flush(&mut self) -> Result<(), WsError> {
             self.frame_codec.flush()
@@ -109,19 +173,28 @@ macro_rules! impl_send {
 /// recv part of text message
 pub struct StringRecv<S: Read> {
     frame_codec: FrameRecv<S>,
-    validate_utf8: bool,
+    config: StringCodecConfig,
 }
 
 impl<S: Read> StringRecv<S> {
     /// construct method
     pub fn new(stream: S, state: FrameReadState, validate_utf8: bool) -> Self {
+        let config = StringCodecConfig {
+            validate_utf8,
+            ..Default::default()
+        };
+        Self::new_with_config(stream, state, config)
+    }
+
+    /// construct with string codec config
+    pub fn new_with_config(stream: S, state: FrameReadState, config: StringCodecConfig) -> Self {
         Self {
             frame_codec: FrameRecv::new(stream, state),
-            validate_utf8,
+            config,
         }
     }
 
-    impl_recv! {}
+    impl_recv! {receive}
 }
 
 /// send part of text message
@@ -143,7 +216,8 @@ impl<S: Write> StringSend<S> {
 /// recv/send text message
 pub struct StringCodec<S: Read + Write> {
     frame_codec: FrameCodec<S>,
-    validate_utf8: bool,
+    config: StringCodecConfig,
+    handshake_response: Option<http::Response<()>>,
 }
 
 impl<S: Read + Write> StringCodec<S> {
@@ -151,15 +225,30 @@ impl<S: Read + Write> StringCodec<S> {
     pub fn new(stream: S) -> Self {
         Self {
             frame_codec: FrameCodec::new(stream),
-            validate_utf8: false,
+            config: Default::default(),
+            handshake_response: None,
         }
     }
 
     /// construct with config
     pub fn new_with(stream: S, config: FrameConfig, validate_utf8: bool) -> Self {
+        let codec_config = StringCodecConfig {
+            validate_utf8,
+            ..Default::default()
+        };
+        Self::new_with_config(stream, config, codec_config)
+    }
+
+    /// construct with frame config and string codec config
+    pub fn new_with_config(
+        stream: S,
+        config: FrameConfig,
+        codec_config: StringCodecConfig,
+    ) -> Self {
         Self {
             frame_codec: FrameCodec::new_with(stream, config),
-            validate_utf8,
+            config: codec_config,
+            handshake_response: None,
         }
     }
 
@@ -168,22 +257,64 @@ impl<S: Read + Write> StringCodec<S> {
         self.frame_codec.stream_mut()
     }
 
+    /// handshake response of server, `None` if codec is not constructed by `check_fn`
+    ///
+    /// use it to read headers selected by server, such as `Sec-WebSocket-Protocol`
+    pub fn handshake_response(&self) -> Option<&http::Response<()>> {
+        self.handshake_response.as_ref()
+    }
+
+    /// current closing handshake state
+    pub fn close_state(&self) -> CloseState {
+        self.frame_codec.close_state()
+    }
+
+    /// set masking key generator of outgoing frames, see `FrameWriteState::set_mask_fn`
+    pub fn set_mask_fn<F: FnMut() -> [u8; 4] + Send + 'static>(&mut self, f: F) {
+        self.frame_codec.set_mask_fn(f);
+    }
+
+    /// change utf8 validation of received text, `ValidateUtf8Policy::Off` skips
+    /// validation of both frames and reassembled message and assumes text is
+    /// valid utf8, only use it when peer is trusted
+    pub fn set_validate_utf8(&mut self, policy: ValidateUtf8Policy) {
+        self.config.validate_utf8 = policy.should_check();
+        self.frame_codec.read_state.set_validate_utf8(policy);
+    }
+
     /// used for server side to construct a new server
-    pub fn factory(_req: http::Request<()>, stream: S) -> Result<Self, WsError> {
+    pub fn factory(req: http::Request<()>, stream: S) -> Result<Self, WsError> {
         let config = FrameConfig {
             mask_send_frame: false,
+            expect_mask: Some(true),
             ..Default::default()
         };
-        Ok(Self::new_with(stream, config, true))
+        let mut codec = Self::new_with(stream, config, true);
+        codec.frame_codec.read_state.feed_remain(req.extensions());
+        Ok(codec)
     }
 
     /// used to client side to construct a new client
     pub fn check_fn(key: String, resp: http::Response<()>, stream: S) -> Result<Self, WsError> {
         standard_handshake_resp_check(key.as_bytes(), &resp)?;
-        Ok(Self::new_with(stream, FrameConfig::default(), true))
+        let config = FrameConfig {
+            expect_mask: Some(false),
+            ..Default::default()
+        };
+        let mut codec = Self::new_with(stream, config, true);
+        codec.frame_codec.read_state.feed_remain(resp.extensions());
+        codec.handshake_response = Some(resp);
+        Ok(codec)
     }
 
-    impl_recv! {}
+    /// receive next data message, control frames are handled internally,
+    /// see `FrameCodec::receive_message`
+    pub fn receive_message(&mut self) -> Result<Event<Cow<'_, str>>, WsError> {
+        let event = self.frame_codec.receive_message()?;
+        self.config.decode_event(event)
+    }
+
+    impl_recv! {receive_reassembled}
 
     impl_send! {}
 }
@@ -203,8 +334,190 @@ where
         } = self.frame_codec;
         let (read, write) = stream.split();
         (
-            StringRecv::new(read, read_state, self.validate_utf8),
+            StringRecv::new_with_config(read, read_state, self.config),
             StringSend::new(write, write_state),
         )
     }
 }
+
+#[test]
+fn test_on_binary_policy() {
+    fn codec(payload: &[u8], on_binary: OnBinaryPolicy) -> StringCodec<std::io::Cursor<Vec<u8>>> {
+        let mut data = vec![0x82, payload.len() as u8];
+        data.extend_from_slice(payload);
+        let config = StringCodecConfig {
+            validate_utf8: true,
+            on_binary,
+        };
+        StringCodec::new_with_config(std::io::Cursor::new(data), Default::default(), config)
+    }
+
+    let mut lossy = codec(b"\xffok", OnBinaryPolicy::Lossy);
+    let msg = lossy.receive().unwrap();
+    assert_eq!(msg.code, OpCode::Binary);
+    assert_eq!(msg.data, "\u{FFFD}ok");
+
+    let mut keep = codec(b"ok", OnBinaryPolicy::Keep);
+    let msg = keep.receive().unwrap();
+    assert_eq!(msg.code, OpCode::Binary);
+    assert_eq!(msg.data, "ok");
+    let mut keep = codec(b"\xffok", OnBinaryPolicy::Keep);
+    assert!(matches!(
+        keep.receive(),
+        Err(WsError::ProtocolError {
+            close_code: 1007,
+            error: crate::errors::ProtocolError::InvalidUtf8 { valid_up_to: 0 }
+        })
+    ));
+
+    let mut error = codec(b"ok", OnBinaryPolicy::Error);
+    assert!(matches!(
+        error.receive(),
+        Err(WsError::UnsupportedFrame(OpCode::Binary))
+    ));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_json_round_trip() {
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Trade {
+        symbol: String,
+        price: f64,
+    }
+
+    let trade = Trade {
+        symbol: "BTCUSDT".to_string(),
+        price: 42000.5,
+    };
+    let mut codec = StringCodec::new(std::io::Cursor::new(vec![]));
+    codec.send_json(&trade).unwrap();
+    codec.send((OpCode::Binary, "{}")).unwrap();
+    codec.send("not json").unwrap();
+    let data = codec.stream_mut().get_ref().clone();
+
+    let mut codec = StringCodec::new(std::io::Cursor::new(data));
+    assert_eq!(codec.receive_json::<Trade>().unwrap(), trade);
+    assert!(matches!(
+        codec.receive_json::<Trade>(),
+        Err(WsError::UnsupportedFrame(OpCode::Binary))
+    ));
+    assert!(matches!(
+        codec.receive_json::<Trade>(),
+        Err(WsError::Serde(_))
+    ));
+}
+
+#[test]
+fn test_empty_messages() {
+    let data = [
+        // lone empty text frame
+        &[0x81, 0x00][..],
+        // empty text fragment and empty continuations
+        &[0x01, 0x00, 0x00, 0x00, 0x80, 0x00],
+        // next message is not swallowed
+        &[0x81, 0x01, b'x'],
+    ]
+    .concat();
+    let mut codec = StringCodec::new(std::io::Cursor::new(data));
+    for expect in ["", "", "x"] {
+        let msg = codec.receive().unwrap();
+        assert_eq!(msg.code, OpCode::Text);
+        assert_eq!(msg.data, expect);
+    }
+}
+
+#[test]
+fn test_close_event() {
+    use crate::CloseEvent;
+
+    // close without payload
+    let mut codec = StringCodec::new(std::io::Cursor::new(vec![0x88, 0x00]));
+    let msg = codec.receive().unwrap();
+    assert_eq!(msg.close_code, None);
+    assert_eq!(msg.close_event(), Some(CloseEvent::NoStatus));
+    assert_eq!(msg.close_event().unwrap().code(), 1005);
+    // close with status code
+    let data = vec![0x88, 0x02, 0x03, 0xe8];
+    let mut codec = StringCodec::new(std::io::Cursor::new(data));
+    let msg = codec.receive().unwrap();
+    assert_eq!(msg.close_event(), Some(CloseEvent::Status(1000)));
+
+    // eof without close frame
+    let mut codec = StringCodec::new(std::io::Cursor::new(vec![0x81]));
+    let err = codec.receive().unwrap_err();
+    assert_eq!(err.close_event(), Some(CloseEvent::Abnormal));
+    assert_eq!(err.close_event().unwrap().code(), 1006);
+    assert!(WsError::InvalidUri("".into()).close_event().is_none());
+}
+
+#[test]
+#[allow(clippy::result_large_err)]
+fn test_handshake_response() {
+    use crate::protocol::cal_accept_key;
+
+    let key = "dGhlIHNhbXBsZSBub25jZQ==";
+    let resp = http::Response::builder()
+        .status(http::StatusCode::SWITCHING_PROTOCOLS)
+        .header("sec-websocket-accept", cal_accept_key(key.as_bytes()))
+        .header("sec-websocket-protocol", "graphql-ws")
+        .body(())
+        .unwrap();
+    let codec = StringCodec::check_fn(key.into(), resp, std::io::Cursor::new(vec![])).unwrap();
+    let protocol = codec
+        .handshake_response()
+        .and_then(|resp| resp.headers().get("sec-websocket-protocol"));
+    assert_eq!(protocol.unwrap(), "graphql-ws");
+
+    let codec = StringCodec::new(std::io::Cursor::new(vec![]));
+    assert!(codec.handshake_response().is_none());
+}
+
+#[test]
+fn test_set_validate_utf8() {
+    // "é" split across fragments
+    let data = vec![0x01, 0x01, 0xC3, 0x80, 0x01, 0xA9];
+    let mut codec = StringCodec::new(std::io::Cursor::new(data.clone()));
+    assert!(codec.receive().is_err());
+
+    let mut codec = StringCodec::new(std::io::Cursor::new(data));
+    codec.set_validate_utf8(ValidateUtf8Policy::Incremental);
+    assert_eq!(codec.receive().unwrap().data, "é");
+}
+
+#[test]
+fn test_invalid_text() {
+    let data = vec![0x81, 0x04, b'o', b'k', 0xff, b'!'];
+    let mut codec = StringCodec::new(std::io::Cursor::new(data));
+    assert!(matches!(
+        codec.receive(),
+        Err(WsError::ProtocolError {
+            close_code: 1007,
+            error: crate::errors::ProtocolError::InvalidUtf8 { valid_up_to: 2 }
+        })
+    ));
+}
+
+#[test]
+fn test_messages() {
+    let data = vec![
+        0x81, 0x01, b'a', 0x81, 0x01, b'b', 0x88, 0x05, 0x03, 0xe8, b'b', b'y', b'e', 0x81, 0x01,
+        b'c',
+    ];
+    let mut recv = StringRecv::new(std::io::Cursor::new(data), Default::default(), true);
+    let msgs: Vec<_> = recv.messages().map(|msg| msg.unwrap()).collect();
+    assert_eq!(msgs.len(), 3);
+    assert_eq!(msgs[0].data, "a");
+    assert_eq!(msgs[1].data, "b");
+    assert_eq!(msgs[2].code, OpCode::Close);
+    assert_eq!(msgs[2].close_code, Some(1000));
+    assert_eq!(msgs[2].data, "bye");
+
+    // stream ends without close frame
+    let data = vec![0x81, 0x01, b'a'];
+    let mut recv = StringRecv::new(std::io::Cursor::new(data), Default::default(), true);
+    let mut msgs = recv.messages();
+    assert_eq!(msgs.next().unwrap().unwrap().data, "a");
+    assert!(msgs.next().unwrap().is_err());
+    assert!(msgs.next().is_none());
+}
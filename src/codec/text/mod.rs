@@ -1,11 +1,89 @@
+use std::borrow::Cow;
+
+use crate::{
+    errors::WsError,
+    frame::OpCode,
+    Event,
+};
+
+#[cfg(feature = "futures")]
+use crate::Message;
+
 #[cfg(feature = "sync")]
 mod blocking;
 
 #[cfg(feature = "sync")]
 pub use blocking::*;
 
-#[cfg(feature = "async")]
+#[cfg(feature = "async_codec")]
 mod non_blocking;
 
-#[cfg(feature = "async")]
+#[cfg(feature = "async_codec")]
 pub use non_blocking::*;
+
+/// how string codec handles binary data frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnBinaryPolicy {
+    /// return `WsError::UnsupportedFrame` error
+    Error,
+    /// decode payload with `String::from_utf8_lossy`
+    #[default]
+    Lossy,
+    /// return payload unchanged with binary opcode, error if payload is not valid utf-8
+    ///
+    /// use `receive_raw` to get arbitrary binary payload
+    Keep,
+}
+
+/// string codec config
+#[derive(Debug, Clone, Default)]
+pub struct StringCodecConfig {
+    /// validate utf-8 of text frame payload
+    pub validate_utf8: bool,
+    /// binary frame handling policy
+    pub on_binary: OnBinaryPolicy,
+}
+
+impl StringCodecConfig {
+    /// decode payload of data event according to config
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    fn decode_event<'a>(&self, event: Event<&'a [u8]>) -> Result<Event<Cow<'a, str>>, WsError> {
+        match event {
+            Event::Text(data) if self.validate_utf8 => {
+                Ok(Event::Text(Cow::Borrowed(std::str::from_utf8(data)?)))
+            }
+            Event::Text(data) => Ok(Event::Text(String::from_utf8_lossy(data))),
+            Event::Binary(data) => match self.on_binary {
+                OnBinaryPolicy::Error => Err(WsError::UnsupportedFrame(OpCode::Binary)),
+                OnBinaryPolicy::Lossy => Ok(Event::Binary(String::from_utf8_lossy(data))),
+                OnBinaryPolicy::Keep => {
+                    Ok(Event::Binary(Cow::Borrowed(std::str::from_utf8(data)?)))
+                }
+            },
+            Event::Closed(close) => Ok(Event::Closed(close)),
+        }
+    }
+    /// decode owned message according to config, used by `Stream` impl
+    #[cfg(feature = "futures")]
+    fn decode_message(&self, msg: Message<bytes::BytesMut>) -> Result<Message<String>, WsError> {
+        let strict = match msg.code {
+            OpCode::Text => self.validate_utf8,
+            OpCode::Binary => match self.on_binary {
+                OnBinaryPolicy::Error => return Err(WsError::UnsupportedFrame(OpCode::Binary)),
+                OnBinaryPolicy::Lossy => false,
+                OnBinaryPolicy::Keep => true,
+            },
+            _ => false,
+        };
+        let data = if strict {
+            String::from_utf8(msg.data.to_vec())?
+        } else {
+            String::from_utf8_lossy(&msg.data).into_owned()
+        };
+        Ok(Message {
+            code: msg.code,
+            data,
+            close_code: msg.close_code,
+        })
+    }
+}
@@ -1,25 +1,47 @@
 use http;
 use crate::{
     codec::{
-        AsyncFrameCodec, AsyncFrameRecv, AsyncFrameSend, FrameConfig, FrameReadState,
-        FrameWriteState, Split,
+        AsyncFrameCodec, AsyncFrameRecv, AsyncFrameSend, CloseState, FrameConfig, FrameReadState,
+        FrameWriteState, Keepalive, KeepaliveConfig, OnBinaryPolicy, Split, StringCodecConfig,
+        ValidateUtf8Policy,
     },
-    errors::{ProtocolError, WsError},
+    errors::WsError,
     frame::OpCode,
     protocol::standard_handshake_resp_check,
-    Message,
+    Event, Message,
 };
 use bytes::Buf;
 use std::borrow::Cow;
+#[cfg(feature = "futures")]
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
 use tokio::io::{AsyncRead, AsyncWrite};
 
 macro_rules! impl_recv {
-    () => {
+    ($receive:ident $(, $keepalive:ident)?) => {
+        /// number of bytes read from stream but not parsed yet
+        pub fn buffered_len(&self) -> usize {
+            self.frame_codec.buffered_len()
+        }
+
+        /// max number of unparsed bytes ever buffered
+        pub fn buffered_high_water_mark(&self) -> usize {
+            self.frame_codec.buffered_high_water_mark()
+        }
+
+        /// histogram of received message payload size
+        #[cfg(feature = "metrics")]
+        pub fn size_histogram(&self) -> &hdrhistogram::Histogram<u64> {
+            self.frame_codec.size_histogram()
+        }
+
         /// in case of ping/pong/close contain non utf-8 string, use this api to receive raw message
         ///
         /// for close frame with body, first two bytes of string are close reason
         pub async fn receive_raw(&mut self) -> Result<Message<Cow<[u8]>>, WsError> {
-            let (header, mut data) = self.frame_codec.receive().await?;
+            let (header, mut data) = self.frame_codec.$receive($(&mut self.$keepalive)?).await?;
             let close_code = if header.code == OpCode::Close && data.len() >= 2 {
                 let code = if data.len() >= 2 {
                     data.get_u16()
@@ -39,7 +61,7 @@ macro_rules! impl_recv {
 
         /// for close frame with body, first two bytes of string are close reason
         pub async fn receive(&mut self) -> Result<Message<Cow<str>>, WsError> {
-            let (header, mut data) = self.frame_codec.receive().await?;
+            let (header, mut data) = self.frame_codec.$receive($(&mut self.$keepalive)?).await?;
             // TODO check protocol error
             let close_code = if header.code == OpCode::Close && data.len() >= 2 {
                 let code = if data.len() >= 2 {
@@ -51,20 +73,37 @@ macro_rules! impl_recv {
             } else {
                 None
             };
-            let data = if self.validate_utf8 && header.code == OpCode::Text {
-                std::str::from_utf8(data).map_err(|_| WsError::ProtocolError {
-                    close_code: 1001,
-                    error: ProtocolError::InvalidUtf8,
-                })?
-            } else {
-                unsafe { std::str::from_utf8_unchecked(data) }
+            let data = match header.code {
+                OpCode::Binary => match self.config.on_binary {
+                    OnBinaryPolicy::Error => return Err(WsError::UnsupportedFrame(header.code)),
+                    OnBinaryPolicy::Lossy => String::from_utf8_lossy(data),
+                    OnBinaryPolicy::Keep => Cow::Borrowed(std::str::from_utf8(data)?),
+                },
+                OpCode::Text if self.config.validate_utf8 => {
+                    Cow::Borrowed(std::str::from_utf8(data)?)
+                }
+                // close reason may be invalid utf-8 if `lossy_close_reason` is enabled
+                OpCode::Close => String::from_utf8_lossy(data),
+                _ => Cow::Borrowed(unsafe { std::str::from_utf8_unchecked(data) }),
             };
             Ok(Message {
-                data: Cow::Borrowed(data),
+                data,
                 close_code,
                 code: header.code,
             })
         }
+
+        /// receive a text message and deserialize it from json
+        ///
+        /// return `WsError::UnsupportedFrame` if message is not text
+        #[cfg(feature = "json")]
+        pub async fn receive_json<T: serde::de::DeserializeOwned>(&mut self) -> Result<T, WsError> {
+            let msg = self.receive().await?;
+            if msg.code != OpCode::Text {
+                return Err(WsError::UnsupportedFrame(msg.code));
+            }
+            serde_json::from_str(&msg.data).map_err(|e| WsError::Serde(e.to_string()))
+        }
     };
 }
 
@@ -93,9 +132,10 @@ macro_rules! impl_send {
             let msg: Message<Cow<'a, str>> = msg.into();
             if let Some(close_code) = msg.close_code {
                 if msg.code == OpCode::Close {
-                    let mut data = close_code.to_be_bytes().to_vec();
-                    data.extend_from_slice(msg.data.as_bytes());
-                    self.frame_codec.send(msg.code, &data).await
+                    let code = close_code.to_be_bytes();
+                    self.frame_codec
+                        .send_vectored(msg.code, &[&code, msg.data.as_bytes()])
+                        .await
                 } else {
                     self.frame_codec.send(msg.code, msg.data.as_bytes()).await
                 }
@@ -104,6 +144,19 @@ macro_rules! impl_send {
             }
         }
 
+        /// serialize value to json and send it as text message
+        #[cfg(feature = "json")]
+        pub async fn send_json<T: serde::Serialize>(&mut self, value: &T) -> Result<(), WsError> {
+            let data = serde_json::to_string(value).map_err(|e| WsError::Serde(e.to_string()))?;
+            self.frame_codec.send(OpCode::Text, data.as_bytes()).await
+        }
+
+        /// send close frame for protocol error with masking matching role, do
+        /// nothing for other errors
+        pub async fn close_with_error(&mut self, error: &WsError) -> Result<(), WsError> {
+            self.frame_codec.close_with_error(error).await
+        }
+
         /// flush underlying stream
         pub async fn flush(&mut self) -> Result<(), WsError> {
             self.frame_codec.flush().await
@@ -114,19 +167,28 @@ macro_rules! impl_send {
 /// send part of text message
 pub struct AsyncStringRecv<S: AsyncRead> {
     frame_codec: AsyncFrameRecv<S>,
-    validate_utf8: bool,
+    config: StringCodecConfig,
 }
 
 impl<S: AsyncRead + Unpin> AsyncStringRecv<S> {
     /// construct method
     pub fn new(stream: S, state: FrameReadState, validate_utf8: bool) -> Self {
+        let config = StringCodecConfig {
+            validate_utf8,
+            ..Default::default()
+        };
+        Self::new_with_config(stream, state, config)
+    }
+
+    /// construct with string codec config
+    pub fn new_with_config(stream: S, state: FrameReadState, config: StringCodecConfig) -> Self {
         Self {
             frame_codec: AsyncFrameRecv::new(stream, state),
-            validate_utf8,
+            config,
         }
     }
 
-    impl_recv! {}
+    impl_recv! {receive}
 }
 
 /// recv/send text message
@@ -148,7 +210,11 @@ impl<S: AsyncWrite + Unpin> AsyncStringSend<S> {
 /// recv/send text message
 pub struct AsyncStringCodec<S: AsyncRead + AsyncWrite> {
     frame_codec: AsyncFrameCodec<S>,
-    validate_utf8: bool,
+    config: StringCodecConfig,
+    handshake_response: Option<http::Response<()>>,
+    keepalive: Option<Keepalive>,
+    #[cfg(feature = "futures")]
+    sink_buf: bytes::BytesMut,
 }
 
 impl<S: AsyncRead + AsyncWrite + Unpin> AsyncStringCodec<S> {
@@ -156,15 +222,36 @@ impl<S: AsyncRead + AsyncWrite + Unpin> AsyncStringCodec<S> {
     pub fn new(stream: S) -> Self {
         Self {
             frame_codec: AsyncFrameCodec::new(stream),
-            validate_utf8: false,
+            config: Default::default(),
+            handshake_response: None,
+            keepalive: None,
+            #[cfg(feature = "futures")]
+            sink_buf: bytes::BytesMut::new(),
         }
     }
 
     /// construct with config
     pub fn new_with(stream: S, config: FrameConfig, validate_utf8: bool) -> Self {
+        let codec_config = StringCodecConfig {
+            validate_utf8,
+            ..Default::default()
+        };
+        Self::new_with_config(stream, config, codec_config)
+    }
+
+    /// construct with frame config and string codec config
+    pub fn new_with_config(
+        stream: S,
+        config: FrameConfig,
+        codec_config: StringCodecConfig,
+    ) -> Self {
         Self {
             frame_codec: AsyncFrameCodec::new_with(stream, config),
-            validate_utf8,
+            config: codec_config,
+            handshake_response: None,
+            keepalive: None,
+            #[cfg(feature = "futures")]
+            sink_buf: bytes::BytesMut::new(),
         }
     }
 
@@ -173,22 +260,75 @@ impl<S: AsyncRead + AsyncWrite + Unpin> AsyncStringCodec<S> {
         self.frame_codec.stream_mut()
     }
 
+    /// handshake response of server, `None` if codec is not constructed by `check_fn`
+    ///
+    /// use it to read headers selected by server, such as `Sec-WebSocket-Protocol`
+    pub fn handshake_response(&self) -> Option<&http::Response<()>> {
+        self.handshake_response.as_ref()
+    }
+
+    /// current closing handshake state
+    pub fn close_state(&self) -> CloseState {
+        self.frame_codec.close_state()
+    }
+
+    /// set masking key generator of outgoing frames, see `FrameWriteState::set_mask_fn`
+    pub fn set_mask_fn<F: FnMut() -> [u8; 4] + Send + 'static>(&mut self, f: F) {
+        self.frame_codec.set_mask_fn(f);
+    }
+
+    /// change utf8 validation of received text, `ValidateUtf8Policy::Off` skips
+    /// validation of both frames and reassembled message and assumes text is
+    /// valid utf8, only use it when peer is trusted
+    pub fn set_validate_utf8(&mut self, policy: ValidateUtf8Policy) {
+        self.config.validate_utf8 = policy.should_check();
+        self.frame_codec.read_state.set_validate_utf8(policy);
+    }
+
+    /// enable or disable keepalive, disabled by default
+    ///
+    /// `receive` sends ping if connection is idle for keepalive interval and
+    /// answers ping with pong
+    ///
+    /// **NOTE** keepalive is dropped by `split`, recv half can not send ping,
+    /// spawn a task which owns send half to send ping periodically instead
+    pub fn set_keepalive(&mut self, config: Option<KeepaliveConfig>) {
+        self.keepalive = config.map(Keepalive::from);
+    }
+
     /// used for server side to construct a new server
-    pub fn factory(_req: http::Request<()>, stream: S) -> Result<Self, WsError> {
+    pub fn factory(req: http::Request<()>, stream: S) -> Result<Self, WsError> {
         let config = FrameConfig {
             mask_send_frame: false,
+            expect_mask: Some(true),
             ..Default::default()
         };
-        Ok(Self::new_with(stream, config, true))
+        let mut codec = Self::new_with(stream, config, true);
+        codec.frame_codec.read_state.feed_remain(req.extensions());
+        Ok(codec)
     }
 
     /// used to client side to construct a new client
     pub fn check_fn(key: String, resp: http::Response<()>, stream: S) -> Result<Self, WsError> {
         standard_handshake_resp_check(key.as_bytes(), &resp)?;
-        Ok(Self::new_with(stream, FrameConfig::default(), true))
+        let config = FrameConfig {
+            expect_mask: Some(false),
+            ..Default::default()
+        };
+        let mut codec = Self::new_with(stream, config, true);
+        codec.frame_codec.read_state.feed_remain(resp.extensions());
+        codec.handshake_response = Some(resp);
+        Ok(codec)
     }
 
-    impl_recv! {}
+    /// receive next data message, control frames are handled internally,
+    /// see `AsyncFrameCodec::receive_message`
+    pub async fn receive_message(&mut self) -> Result<Event<Cow<'_, str>>, WsError> {
+        let event = self.frame_codec.receive_message().await?;
+        self.config.decode_event(event)
+    }
+
+    impl_recv! {receive_keepalive, keepalive}
     impl_send! {}
 }
 
@@ -207,8 +347,191 @@ where
         } = self.frame_codec;
         let (read, write) = stream.split();
         (
-            AsyncStringRecv::new(read, read_state, self.validate_utf8),
+            AsyncStringRecv::new_with_config(read, read_state, self.config),
+            AsyncStringSend::new(write, write_state),
+        )
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> AsyncStringCodec<S> {
+    /// split codec to recv and send parts with `tokio::io::split`, works for
+    /// any stream, even if it does not implement `Split`
+    pub fn into_split(
+        self,
+    ) -> (
+        AsyncStringRecv<tokio::io::ReadHalf<S>>,
+        AsyncStringSend<tokio::io::WriteHalf<S>>,
+    ) {
+        let AsyncFrameCodec {
+            stream,
+            read_state,
+            write_state,
+        } = self.frame_codec;
+        let (read, write) = tokio::io::split(stream);
+        (
+            AsyncStringRecv::new_with_config(read, read_state, self.config),
             AsyncStringSend::new(write, write_state),
         )
     }
 }
+
+/// yield received messages until peer close frame, payload is decoded
+/// according to string codec config, ping and pong are yielded as is and
+/// not answered automatically
+///
+/// close echo is buffered and sent by next `poll_flush` or `poll_close`,
+/// keepalive is not applied
+#[cfg(feature = "futures")]
+impl<S: AsyncRead + AsyncWrite + Unpin> futures_core::Stream for AsyncStringCodec<S> {
+    type Item = Result<Message<String>, WsError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let msg = ready!(this.frame_codec.poll_next_message(&mut this.sink_buf, cx));
+        Poll::Ready(msg.map(|msg| msg.and_then(|msg| this.config.decode_message(msg))))
+    }
+}
+
+/// messages are buffered by `start_send` and written by `poll_flush`,
+/// `poll_close` sends normal close frame if close frame has not been sent
+#[cfg(feature = "futures")]
+impl<T: AsRef<str>, S: AsyncRead + AsyncWrite + Unpin> futures_sink::Sink<Message<T>>
+    for AsyncStringCodec<S>
+{
+    type Error = WsError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), WsError>> {
+        let this = self.get_mut();
+        this.frame_codec.poll_ready_buf(&mut this.sink_buf, cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, msg: Message<T>) -> Result<(), WsError> {
+        let this = self.get_mut();
+        this.frame_codec.encode_message(
+            &mut this.sink_buf,
+            msg.code,
+            msg.close_code,
+            msg.data.as_ref().as_bytes(),
+        )
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), WsError>> {
+        let this = self.get_mut();
+        this.frame_codec.poll_write_buf(&mut this.sink_buf, cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), WsError>> {
+        let this = self.get_mut();
+        this.frame_codec.poll_close_buf(&mut this.sink_buf, cx)
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_interleaved_ping() {
+    let (client, server) = tokio::io::duplex(1024);
+    let mut client = AsyncFrameCodec::new(client);
+    let config = FrameConfig {
+        mask_send_frame: false,
+        ..Default::default()
+    };
+    let mut server = AsyncStringCodec::new_with(server, config, true);
+
+    client
+        .send_frame(OpCode::Text, b"hello ", false)
+        .await
+        .unwrap();
+    client.send(OpCode::Ping, b"ping").await.unwrap();
+    client.send(OpCode::Pong, b"pong").await.unwrap();
+    client
+        .send_frame(OpCode::Continue, b"world", true)
+        .await
+        .unwrap();
+    client.send(OpCode::Text, b"next").await.unwrap();
+
+    let msg = server.receive().await.unwrap();
+    assert_eq!(msg.code, OpCode::Text);
+    assert_eq!(msg.data, "hello world");
+    let msg = server.receive().await.unwrap();
+    assert_eq!(msg.data, "next");
+
+    let (header, data) = client.receive().await.unwrap();
+    assert_eq!(header.code, OpCode::Pong);
+    assert_eq!(data, b"ping");
+}
+
+#[cfg(all(test, feature = "json"))]
+#[tokio::test]
+async fn test_json_round_trip() {
+    let (client, server) = tokio::io::duplex(1024);
+    let mut client = AsyncStringCodec::new(client);
+    let mut server = AsyncStringCodec::new(server);
+
+    let value = serde_json::json!({"method": "SUBSCRIBE", "params": ["btcusdt@trade"], "id": 1});
+    client.send_json(&value).await.unwrap();
+    let received: serde_json::Value = server.receive_json().await.unwrap();
+    assert_eq!(received, value);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_empty_messages() {
+    let (client, server) = tokio::io::duplex(1024);
+    let mut client = AsyncFrameCodec::new(client);
+    let mut server = AsyncStringCodec::new(server);
+
+    client.send(OpCode::Text, b"").await.unwrap();
+    client.send_frame(OpCode::Text, b"", false).await.unwrap();
+    client
+        .send_frame(OpCode::Continue, b"", false)
+        .await
+        .unwrap();
+    client
+        .send_frame(OpCode::Continue, b"", true)
+        .await
+        .unwrap();
+    client.send(OpCode::Text, b"x").await.unwrap();
+    for expect in ["", "", "x"] {
+        let msg = server.receive().await.unwrap();
+        assert_eq!(msg.code, OpCode::Text);
+        assert_eq!(msg.data, expect);
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_into_split() {
+    let (client, server) = tokio::io::duplex(64);
+    let (mut client_recv, mut client_send) = AsyncStringCodec::new(client).into_split();
+    let config = FrameConfig {
+        mask_send_frame: false,
+        ..Default::default()
+    };
+    let (mut server_recv, mut server_send) =
+        AsyncStringCodec::new_with(server, config, true).into_split();
+
+    let echo = tokio::spawn(async move {
+        loop {
+            let msg = server_recv.receive().await.unwrap();
+            if msg.code == OpCode::Close {
+                break;
+            }
+            server_send.send(msg.data.as_ref()).await.unwrap();
+        }
+    });
+    let reader = tokio::spawn(async move {
+        for idx in 0..100 {
+            let msg = client_recv.receive().await.unwrap();
+            assert_eq!(msg.data, format!("message {idx}"));
+        }
+    });
+    for idx in 0..100 {
+        client_send
+            .send(format!("message {idx}").as_str())
+            .await
+            .unwrap();
+    }
+    reader.await.unwrap();
+    client_send.close(1000, "").await.unwrap();
+    echo.await.unwrap();
+}
@@ -1,6 +1,10 @@
 use thiserror::Error;
 
-use crate::frame::OpCode;
+use crate::{
+    codec::ConnectionState,
+    frame::{OpCode, OwnedFrame},
+    CloseEvent,
+};
 
 // TODO add custom error kind
 /// errors during handshake, read/write frame
@@ -18,12 +22,23 @@ pub enum WsError {
     #[error("connection failed `{0}`")]
     /// failed to connect websocket server
     ConnectionFailed(String),
+    #[error("proxy error `{0}`")]
+    /// proxy refused or failed to negotiate tunnel to websocket server
+    ProxyError(String),
     #[error("tls dns lookup failed `{0}`")]
     /// tls session DNS is broken
     TlsDnsFailed(String),
     #[error("io error {0:?}")]
     /// raised by underlying stream
     IOError(std::io::Error),
+    #[error("connection lost {0:?}")]
+    /// peer is unreachable, such as connection reset or keepalive timeout,
+    /// raised by underlying stream
+    ConnectionLost(std::io::Error),
+    #[error("receive deadline exceeded")]
+    /// message is not complete before deadline passed to `receive_deadline`
+    /// or `FrameConfig::close_timeout`, partial frame is kept in buffer
+    DeadlineExceeded,
     #[error("{0}")]
     /// invalid protocol handshake
     HandShakeFailed(String),
@@ -38,6 +53,26 @@ pub enum WsError {
     /// peer send a frame with unknown opcode
     #[error("unsupported frame {0:?}")]
     UnsupportedFrame(OpCode),
+    /// try to send frames other than close after close frame has been sent,
+    /// or receive after peer close frame
+    #[error("invalid connection state {0:?}")]
+    InvalidConnState(ConnectionState),
+    /// invalid codec config
+    #[error("invalid config `{0}`")]
+    InvalidConfig(String),
+    /// peer close frame, returned by `receive` if `FrameConfig::close_as_error`
+    /// is enabled
+    #[error("closed by peer {code} `{reason}`")]
+    Closed {
+        /// close code, 1005 if close frame has no status code
+        code: u16,
+        /// close reason
+        reason: String,
+    },
+    #[cfg(feature = "json")]
+    /// serialize or deserialize json failed
+    #[error("serde failed {0}")]
+    Serde(String),
 
     #[cfg(any(
         feature = "deflate",
@@ -57,9 +92,110 @@ pub enum WsError {
     DeCompressFailed(String),
 }
 
+impl WsError {
+    /// `Closed` error of received close frame payload
+    ///
+    /// reason has been validated when close frame is received unless
+    /// `lossy_close_reason` is enabled
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    pub(crate) fn closed(payload: &[u8]) -> Self {
+        let event = CloseEvent::from_payload(payload);
+        let reason = payload.get(2..).unwrap_or_default();
+        WsError::Closed {
+            code: event.code(),
+            reason: String::from_utf8_lossy(reason).into_owned(),
+        }
+    }
+
+    /// protocol error of invalid utf-8 text, closed with 1007
+    pub(crate) fn invalid_utf8(valid_up_to: usize) -> Self {
+        WsError::ProtocolError {
+            close_code: 1007,
+            error: ProtocolError::InvalidUtf8 { valid_up_to },
+        }
+    }
+
+    /// build close frame which should be sent to peer for protocol error
+    ///
+    /// return `None` if it's not a protocol error
+    pub fn as_close_frame(&self, mask: impl Into<Option<[u8; 4]>>) -> Option<OwnedFrame> {
+        match self {
+            WsError::ProtocolError { close_code, error } => {
+                Some(error.to_close_frame(*close_code, mask))
+            }
+            _ => None,
+        }
+    }
+
+    /// close frame payload(close code and truncated reason) which should be
+    /// sent to peer for protocol error
+    ///
+    /// return `None` if it's not a protocol error
+    pub fn close_payload(&self) -> Option<Vec<u8>> {
+        match self {
+            WsError::ProtocolError { close_code, error } => {
+                Some(close_payload(*close_code, &error.to_string()))
+            }
+            _ => None,
+        }
+    }
+
+    /// `CloseEvent::Abnormal` if connection is lost or reaches eof without
+    /// closing handshake, event of close frame for `Closed`, `None` for other errors
+    pub fn close_event(&self) -> Option<CloseEvent> {
+        use std::io::ErrorKind;
+        match self {
+            WsError::ConnectionLost(_) => Some(CloseEvent::Abnormal),
+            WsError::Closed { code: 1005, .. } => Some(CloseEvent::NoStatus),
+            WsError::Closed { code, .. } => Some(CloseEvent::Status(*code)),
+            WsError::IOError(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::UnexpectedEof | ErrorKind::ConnectionAborted
+                ) =>
+            {
+                Some(CloseEvent::Abnormal)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// marker of io error raised by read deadline, converted to
+/// `WsError::DeadlineExceeded`
+#[derive(Debug, Error)]
+#[error("receive deadline exceeded")]
+struct DeadlineElapsed;
+
+#[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+pub(crate) fn deadline_exceeded() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::TimedOut, DeadlineElapsed)
+}
+
 impl From<std::io::Error> for WsError {
     fn from(e: std::io::Error) -> Self {
-        WsError::IOError(e)
+        use std::io::ErrorKind;
+        if e.get_ref()
+            .is_some_and(|inner| inner.is::<DeadlineElapsed>())
+        {
+            return WsError::DeadlineExceeded;
+        }
+        match e.kind() {
+            ErrorKind::ConnectionReset | ErrorKind::BrokenPipe => WsError::ConnectionLost(e),
+            _ => WsError::IOError(e),
+        }
+    }
+}
+
+impl From<std::str::Utf8Error> for WsError {
+    fn from(e: std::str::Utf8Error) -> Self {
+        WsError::invalid_utf8(e.valid_up_to())
+    }
+}
+
+impl From<std::string::FromUtf8Error> for WsError {
+    fn from(e: std::string::FromUtf8Error) -> Self {
+        e.utf8_error().into()
     }
 }
 
@@ -93,6 +229,9 @@ pub enum ProtocolError {
     /// invalid data frame after first fragmented frame
     #[error("not continue frame after init fragmented frame")]
     NotContinueFrameAfterFragmented,
+    /// peer sent a frame after close frame, see `FrameConfig::strict_ordering`
+    #[error("frame after close frame")]
+    FrameAfterClose,
     /// control framed should not be fragmented
     #[error("fragmented control frame ")]
     FragmentedControlFrame,
@@ -102,15 +241,22 @@ pub enum ProtocolError {
     /// invalid payload content(need to be valid utf8) of a close frame
     #[error("invalid close frame payload len, expect 0, >= 2")]
     InvalidCloseFramePayload,
-    /// invalid utf8 payload of a text frame
-    #[error("invalid utf-8 text")]
-    InvalidUtf8,
+    /// invalid utf8 payload of a text frame, `valid_up_to` is offset of
+    /// first invalid byte
+    #[error("invalid utf-8 text, valid up to {valid_up_to}")]
+    InvalidUtf8 {
+        /// length of valid utf-8 prefix
+        valid_up_to: usize,
+    },
     /// invalid close code
     #[error("invalid close code {0}")]
     InvalidCloseCode(u16),
     /// payload exceed payload len limit
     #[error("payload too large, max payload size {0}")]
     PayloadTooLarge(usize),
+    /// mask bit of frame does not match `FrameConfig::expect_mask`
+    #[error("unexpected mask bit {0}")]
+    UnexpectedMask(bool),
 
     #[cfg(any(
         feature = "deflate",
@@ -120,4 +266,82 @@ pub enum ProtocolError {
     /// compressed control frame
     #[error("compressed control frame")]
     CompressedControlFrame,
+    #[cfg(any(
+        feature = "deflate",
+        feature = "deflate_ng",
+        feature = "deflate_static"
+    ))]
+    /// rsv1 is set on continuation frame, only first frame of a compressed
+    /// message carries it
+    #[error("compressed continuation frame")]
+    CompressedContinueFrame,
+    #[cfg(any(
+        feature = "deflate",
+        feature = "deflate_ng",
+        feature = "deflate_static"
+    ))]
+    /// compressed frame payload can not be decompressed
+    #[error("decompress failed, zlib code {0}")]
+    DecompressFailed(i32),
+}
+
+/// truncate reason to 123 bytes at char boundary to fit in a close frame
+fn truncate_close_reason(reason: &str) -> &str {
+    let mut len = reason.len().min(123);
+    while !reason.is_char_boundary(len) {
+        len -= 1;
+    }
+    &reason[..len]
+}
+
+/// close code followed by reason truncated to fit in a close frame
+pub(crate) fn close_payload(close_code: u16, reason: &str) -> Vec<u8> {
+    let mut payload = close_code.to_be_bytes().to_vec();
+    payload.extend_from_slice(truncate_close_reason(reason).as_bytes());
+    payload
+}
+
+impl ProtocolError {
+    /// build close frame with error message as close reason
+    ///
+    /// reason is truncated to 123 bytes to fit in a control frame
+    pub fn to_close_frame(&self, close_code: u16, mask: impl Into<Option<[u8; 4]>>) -> OwnedFrame {
+        let reason = self.to_string();
+        OwnedFrame::close_frame(mask, close_code, truncate_close_reason(&reason).as_bytes())
+    }
+}
+
+#[test]
+fn test_close_frame_reason() {
+    let error = WsError::ProtocolError {
+        close_code: 1002,
+        error: ProtocolError::InvalidCloseCode(1004),
+    };
+    let frame = error.as_close_frame(None).unwrap();
+    assert_eq!(&frame.payload()[..2], &1002u16.to_be_bytes());
+    assert_eq!(&frame.payload()[2..], b"invalid close code 1004");
+    assert!(WsError::InvalidUri("".into())
+        .as_close_frame(None)
+        .is_none());
+
+    let long = "x".repeat(200);
+    assert_eq!(truncate_close_reason(&long).len(), 123);
+    let long = format!("x{}", "错".repeat(50));
+    let truncated = truncate_close_reason(&long);
+    assert_eq!(truncated.len(), 121);
+    assert_eq!(truncated, format!("x{}", "错".repeat(40)));
+}
+
+#[test]
+fn test_from_utf8_error() {
+    let err: WsError = String::from_utf8(b"ok\xff".to_vec()).unwrap_err().into();
+    assert!(matches!(
+        &err,
+        WsError::ProtocolError {
+            close_code: 1007,
+            error: ProtocolError::InvalidUtf8 { valid_up_to: 2 }
+        }
+    ));
+    let frame = err.as_close_frame(None).unwrap();
+    assert_eq!(&frame.payload()[2..], b"invalid utf-8 text, valid up to 2");
 }
@@ -1,6 +1,5 @@
-use crate::codec::apply_mask;
-use bytes::{BufMut, BytesMut};
-use std::fmt::Debug;
+use bytes::{BufMut, Bytes, BytesMut};
+use core::fmt::Debug;
 
 /// Defines the interpretation of the "Payload data".  If an unknown
 /// opcode is received, the receiving endpoint MUST _Fail the
@@ -74,13 +73,33 @@ impl OpCode {
 
     /// check is reserved
     pub fn is_reserved(&self) -> bool {
-        matches!(self.as_u8(), 3..=5 | 11..=15)
+        matches!(self.as_u8(), 3..=7 | 11..=15)
     }
 }
 
+/// whether close code can be sent in close frame
+///
+/// codes below 1000, at and above 5000, unassigned 1004 and 1016-2999 and
+/// 1005, 1006, 1015 which must never be sent on the wire are invalid
+#[inline]
+pub fn is_valid_close_code(code: u16) -> bool {
+    !matches!(code, 0..=999 | 1004..=1006 | 1015..=2999 | 5000..)
+}
+
+/// close frame payload, code in network byte order followed by reason
+#[inline]
+fn close_payload(code: Option<u16>, reason: &[u8]) -> BytesMut {
+    let mut payload = BytesMut::with_capacity(2 + reason.len());
+    if let Some(code) = code {
+        payload.put_u16(code);
+        payload.extend_from_slice(reason);
+    }
+    payload
+}
+
 #[inline]
 pub(crate) fn parse_opcode(val: u8) -> OpCode {
-    unsafe { std::mem::transmute(val & 0b00001111) }
+    unsafe { core::mem::transmute(val & 0b00001111) }
 }
 
 #[inline]
@@ -216,6 +235,108 @@ macro_rules! impl_get {
     };
 }
 
+/// apply websocket mask to buf by given key
+///
+/// on x86_64, AVX2 or SSE2 is used for large buffers, AVX2 support is
+/// detected at runtime once
+#[inline]
+pub fn apply_mask(buf: &mut [u8], mask: [u8; 4]) {
+    #[cfg(target_arch = "x86_64")]
+    if buf.len() >= simd::MIN_LEN {
+        return simd::apply_mask(buf, mask);
+    }
+    apply_mask_fast32(buf, mask)
+}
+
+/// portable masking which xor buf by 4 bytes word
+#[inline]
+pub fn apply_mask_fast32(buf: &mut [u8], mask: [u8; 4]) {
+    let mask32 = u32::from_ne_bytes(mask);
+    let mut iter = buf.chunks_exact_mut(4);
+    while let Some(chunk) = iter.next() {
+        let val: &mut u32 = unsafe { core::mem::transmute(chunk.as_mut_ptr().cast::<u32>()) };
+        *val ^= mask32;
+    }
+    for (i, byte) in iter.into_remainder().iter_mut().enumerate() {
+        *byte ^= mask[i & 3];
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod simd {
+    use core::arch::x86_64::*;
+    use core::sync::atomic::{AtomicU8, Ordering};
+
+    /// buffers shorter than this are masked by `apply_mask_fast32`
+    pub(super) const MIN_LEN: usize = 32;
+
+    const UNKNOWN: u8 = 0;
+    const SSE2: u8 = 1;
+    const AVX2: u8 = 2;
+
+    static LEVEL: AtomicU8 = AtomicU8::new(UNKNOWN);
+
+    // `is_x86_feature_detected!` requires std, this module only depends on core
+    fn detect() -> u8 {
+        unsafe {
+            let leaf1 = __cpuid(1);
+            let osxsave = leaf1.ecx & (1 << 27) != 0;
+            let avx = leaf1.ecx & (1 << 28) != 0;
+            if osxsave && avx && __cpuid(0).eax >= 7 {
+                // os saves xmm and ymm registers on context switch
+                let ymm = _xgetbv(0) & 0b110 == 0b110;
+                let avx2 = __cpuid_count(7, 0).ebx & (1 << 5) != 0;
+                if ymm && avx2 {
+                    return AVX2;
+                }
+            }
+        }
+        SSE2
+    }
+
+    #[inline]
+    pub(super) fn apply_mask(buf: &mut [u8], mask: [u8; 4]) {
+        let level = match LEVEL.load(Ordering::Relaxed) {
+            UNKNOWN => {
+                let level = detect();
+                LEVEL.store(level, Ordering::Relaxed);
+                level
+            }
+            level => level,
+        };
+        unsafe {
+            if level == AVX2 {
+                apply_mask_avx2(buf, mask)
+            } else {
+                apply_mask_sse2(buf, mask)
+            }
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn apply_mask_avx2(buf: &mut [u8], mask: [u8; 4]) {
+        let key = _mm256_set1_epi32(i32::from_ne_bytes(mask));
+        let mut iter = buf.chunks_exact_mut(32);
+        for chunk in &mut iter {
+            let ptr = chunk.as_mut_ptr().cast::<__m256i>();
+            _mm256_storeu_si256(ptr, _mm256_xor_si256(_mm256_loadu_si256(ptr), key));
+        }
+        // chunk size is multiple of 4, so remainder starts at mask[0]
+        super::apply_mask_fast32(iter.into_remainder(), mask);
+    }
+
+    // sse2 is always available on x86_64
+    unsafe fn apply_mask_sse2(buf: &mut [u8], mask: [u8; 4]) {
+        let key = _mm_set1_epi32(i32::from_ne_bytes(mask));
+        let mut iter = buf.chunks_exact_mut(16);
+        for chunk in &mut iter {
+            let ptr = chunk.as_mut_ptr().cast::<__m128i>();
+            _mm_storeu_si128(ptr, _mm_xor_si128(_mm_loadu_si128(ptr), key));
+        }
+        super::apply_mask_fast32(iter.into_remainder(), mask);
+    }
+}
+
 /// get expected header len
 pub fn header_len(mask: bool, payload_len: u64) -> usize {
     let mut header_len = 1;
@@ -294,6 +415,43 @@ pub fn ctor_header<M: Into<Option<[u8; 4]>>>(
     &buf[..header_len]
 }
 
+#[test]
+fn test_opcode_round_trip() {
+    for val in 0..16u8 {
+        let code = parse_opcode(val);
+        assert_eq!(code.as_u8(), val);
+        assert_eq!(code.is_reserved(), matches!(val, 3..=7 | 11..=15));
+    }
+    // high bits of first byte are ignored
+    assert_eq!(parse_opcode(0b1000_0110), OpCode::RNC6);
+}
+
+#[test]
+fn test_apply_mask() {
+    fn scalar(buf: &mut [u8], mask: [u8; 4]) {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte ^= mask[i & 3];
+        }
+    }
+
+    let mut data = [0u8; 1100];
+    for _ in 0..1000 {
+        data.iter_mut().for_each(|b| *b = fastrand::u8(..));
+        let mask = fastrand::u32(..).to_ne_bytes();
+        // random offset to test unaligned buffer
+        let start = fastrand::usize(0..64);
+        let end = fastrand::usize(start..data.len());
+        let mut expect = data;
+        scalar(&mut expect[start..end], mask);
+        let mut actual = data;
+        apply_mask(&mut actual[start..end], mask);
+        assert_eq!(expect, actual, "start {start} end {end}");
+        let mut actual = data;
+        apply_mask_fast32(&mut actual[start..end], mask);
+        assert_eq!(expect, actual, "start {start} end {end}");
+    }
+}
+
 #[test]
 fn test_header() {
     fn rand_mask() -> Option<[u8; 4]> {
@@ -301,7 +459,7 @@ fn test_header() {
     }
 
     fn rand_code() -> OpCode {
-        unsafe { std::mem::transmute(fastrand::u8(0..16)) }
+        unsafe { core::mem::transmute(fastrand::u8(0..16)) }
     }
 
     let mut buf = [0u8; 14];
@@ -548,6 +706,10 @@ impl OwnedFrame {
     }
 
     /// helper function to construct a close frame
+    ///
+    /// panic if reason is longer than 123 bytes or reason is given without
+    /// code, code is not validated, use `checked_close_frame` for code given
+    /// by caller
     #[inline]
     pub fn close_frame(
         mask: impl Into<Option<[u8; 4]>>,
@@ -557,12 +719,26 @@ impl OwnedFrame {
         assert!(data.len() <= 123);
         let code = code.into();
         assert!(code.is_some() || data.is_empty());
-        let mut payload = BytesMut::with_capacity(2 + data.len());
-        if let Some(code) = code {
-            payload.put_u16(code);
-            payload.extend_from_slice(data);
+        Self::new(OpCode::Close, mask, &close_payload(code, data))
+    }
+
+    /// construct a close frame, return `None` instead of panic if reason is
+    /// longer than 123 bytes, reason is given without code or code is not valid
+    #[inline]
+    pub fn checked_close_frame(
+        mask: impl Into<Option<[u8; 4]>>,
+        code: impl Into<Option<u16>>,
+        data: &[u8],
+    ) -> Option<Self> {
+        let code = code.into();
+        let valid = match code {
+            Some(code) => is_valid_close_code(code) && data.len() <= 123,
+            None => data.is_empty(),
+        };
+        if !valid {
+            return None;
         }
-        Self::new(OpCode::Close, mask, &payload)
+        Some(Self::new(OpCode::Close, mask, &close_payload(code, data)))
     }
 
     /// unmask frame if masked
@@ -592,7 +768,20 @@ impl OwnedFrame {
     ///
     /// **NOTE** this function will unmask first, and then extend payload, mask with old
     /// mask key finally
+    ///
+    /// # Panics
+    ///
+    /// panics if payload of a control frame exceeds 125 bytes after extending
     pub fn extend_from_slice(&mut self, data: &[u8]) {
+        if matches!(
+            self.header.opcode(),
+            OpCode::Close | OpCode::Ping | OpCode::Pong
+        ) {
+            assert!(
+                self.payload.len() + data.len() <= 125,
+                "control frame payload must not exceed 125 bytes"
+            );
+        }
         if let Some(mask) = self.unmask() {
             self.payload.extend_from_slice(data);
             self.header.set_payload_len(self.payload.len() as u64);
@@ -626,4 +815,278 @@ impl OwnedFrame {
     pub fn parts(self) -> (Header, BytesMut) {
         (self.header, self.payload)
     }
+
+    /// borrow frame header and payload, masking state is kept
+    #[inline]
+    pub fn as_borrowed(&self) -> BorrowedFrame<'_> {
+        BorrowedFrame {
+            header: HeaderView(&self.header.0),
+            payload: &self.payload,
+        }
+    }
+}
+
+/// frame masked once with a fixed key, can be sent many times without masking again
+///
+/// **NOTE** every sent copy reuses the same mask key, masking is meant to stop
+/// cache poisoning of intermediaries and a predictable key weakens it, only use it
+/// for high frequency messages such as heartbeat when the trade-off is acceptable
+#[derive(Debug, Clone)]
+pub struct PreparedMaskedFrame {
+    frame: OwnedFrame,
+}
+
+impl PreparedMaskedFrame {
+    /// construct a masked frame with fixed mask key
+    pub fn new(code: OpCode, mask: [u8; 4], data: &[u8]) -> Self {
+        Self {
+            frame: OwnedFrame::new(code, mask, data),
+        }
+    }
+
+    /// get prepared frame
+    #[inline]
+    pub fn frame(&self) -> &OwnedFrame {
+        &self.frame
+    }
+}
+
+/// encode a complete unmasked frame with fin bit set, such as one message fanned
+/// out to many connections by `send_encoded` of server codecs
+///
+/// **NOTE** frame is not masked, it's only valid for server to client direction
+pub fn encode_once(code: OpCode, data: &[u8]) -> Bytes {
+    let mut header = [0u8; 14];
+    let header = ctor_header(
+        &mut header,
+        true,
+        false,
+        false,
+        false,
+        None,
+        code,
+        data.len() as u64,
+    );
+    let mut buf = BytesMut::with_capacity(header.len() + data.len());
+    buf.extend_from_slice(header);
+    buf.extend_from_slice(data);
+    buf.freeze()
+}
+
+/// borrowed frame
+#[derive(Debug, Clone, Copy)]
+pub struct BorrowedFrame<'a> {
+    pub(crate) header: HeaderView<'a>,
+    pub(crate) payload: &'a [u8],
+}
+
+impl<'a> BorrowedFrame<'a> {
+    /// use raw header bytes and payload
+    ///
+    /// **NOTE**: this will not check header and payload
+    #[inline]
+    pub fn with_raw(header: &'a [u8], payload: &'a [u8]) -> Self {
+        Self {
+            header: HeaderView(header),
+            payload,
+        }
+    }
+
+    /// parse a frame from start of buf, return frame and consumed len,
+    /// `None` if buf does not contain a complete frame yet
+    ///
+    /// **NOTE**: this will not check header and payload
+    pub fn parse(buf: &'a [u8]) -> Option<(Self, usize)> {
+        let second = *buf.get(1)?;
+        let (len_bytes, payload_len) = match second & 0b0111_1111 {
+            126 => (
+                2,
+                u16::from_be_bytes(buf.get(2..4)?.try_into().ok()?) as u64,
+            ),
+            127 => (8, u64::from_be_bytes(buf.get(2..10)?.try_into().ok()?)),
+            len => (0, len as u64),
+        };
+        let mask_bytes = if second & 0b1000_0000 != 0 { 4 } else { 0 };
+        let header_len: usize = 2 + len_bytes + mask_bytes;
+        let total = header_len.checked_add(usize::try_from(payload_len).ok()?)?;
+        let payload = buf.get(header_len..total)?;
+        Some((Self::with_raw(&buf[..header_len], payload), total))
+    }
+
+    /// get frame header
+    #[inline]
+    pub fn header(&self) -> HeaderView<'a> {
+        self.header
+    }
+
+    /// get payload
+    #[inline]
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    /// copy header and payload to owned frame, masking state is kept
+    #[inline]
+    pub fn to_owned(&self) -> OwnedFrame {
+        OwnedFrame {
+            header: Header(BytesMut::from(self.header.0)),
+            payload: BytesMut::from(self.payload),
+        }
+    }
+}
+
+/// trailing bytes of buffer do not form a complete frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TruncatedFrame {
+    /// offset of incomplete frame in buffer
+    pub offset: usize,
+    /// number of trailing bytes
+    pub len: usize,
+}
+
+impl core::fmt::Display for TruncatedFrame {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "truncated frame at offset {}, {} bytes left",
+            self.offset, self.len
+        )
+    }
+}
+
+/// iterate frames concatenated in a buffer without copying
+///
+/// an `Err` is yielded and iteration stops if trailing bytes do not form a
+/// complete frame, frames are not checked
+#[derive(Debug, Clone)]
+pub struct FrameIter<'a> {
+    buf: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> FrameIter<'a> {
+    /// iterate frames from start of buf
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, offset: 0 }
+    }
+
+    /// number of bytes consumed by yielded frames
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<'a> Iterator for FrameIter<'a> {
+    type Item = Result<BorrowedFrame<'a>, TruncatedFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.buf[self.offset..];
+        if rest.is_empty() {
+            return None;
+        }
+        match BorrowedFrame::parse(rest) {
+            Some((frame, len)) => {
+                self.offset += len;
+                Some(Ok(frame))
+            }
+            None => {
+                let err = TruncatedFrame {
+                    offset: self.offset,
+                    len: rest.len(),
+                };
+                self.buf = &self.buf[..self.offset];
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+#[test]
+fn test_owned_borrowed_round_trip() {
+    for mask in [None, Some([1, 2, 3, 4])] {
+        let frame = OwnedFrame::binary_frame(mask, b"hello");
+        let borrowed = frame.as_borrowed();
+        assert_eq!(borrowed.header().masking_key(), mask);
+        assert_eq!(borrowed.header().opcode(), OpCode::Binary);
+        assert_eq!(borrowed.payload(), &frame.payload()[..]);
+
+        let mut owned = borrowed.to_owned();
+        assert_eq!(owned.header().as_bytes(), frame.header().as_bytes());
+        assert_eq!(owned.payload(), frame.payload());
+        assert_eq!(owned.unmask(), mask);
+        assert_eq!(&owned.payload()[..], b"hello");
+    }
+}
+
+#[test]
+fn test_frame_iter() {
+    let frames = [
+        OwnedFrame::text_frame(None, "hello"),
+        OwnedFrame::binary_frame([1, 2, 3, 4], &[0; 300]),
+        OwnedFrame::ping_frame(None, b""),
+    ];
+    let mut buf = BytesMut::new();
+    for frame in frames.iter() {
+        buf.extend_from_slice(frame.header().as_bytes());
+        buf.extend_from_slice(frame.payload());
+    }
+    assert_eq!(FrameIter::new(&buf).count(), 3);
+    for (frame, borrowed) in frames.iter().zip(FrameIter::new(&buf)) {
+        let borrowed = borrowed.unwrap();
+        assert_eq!(borrowed.header().0, frame.header().as_bytes());
+        assert_eq!(borrowed.payload(), &frame.payload()[..]);
+    }
+
+    let mut iter = FrameIter::new(&buf[..buf.len() - 1]);
+    assert!(iter.next().unwrap().is_ok());
+    assert!(iter.next().unwrap().is_ok());
+    let offset = iter.offset();
+    assert_eq!(
+        iter.next().unwrap().unwrap_err(),
+        TruncatedFrame { offset, len: 1 }
+    );
+    assert!(iter.next().is_none());
+    assert!(FrameIter::new(&[]).next().is_none());
+}
+
+#[test]
+#[should_panic(expected = "control frame payload must not exceed 125 bytes")]
+fn test_extend_control_frame() {
+    let mut frame = OwnedFrame::ping_frame([1, 2, 3, 4], &[0; 120]);
+    frame.extend_from_slice(&[0; 5]);
+    assert_eq!(frame.header().payload_len(), 125);
+    frame.extend_from_slice(&[0]);
+}
+
+#[test]
+fn test_encode_once() {
+    for len in [0, 125, 126, 65536] {
+        let data = BytesMut::zeroed(len);
+        let encoded = encode_once(OpCode::Binary, &data);
+        let frame = OwnedFrame::binary_frame(None, &data);
+        assert_eq!(&encoded[..frame.header().0.len()], &frame.header().0[..]);
+        let (borrowed, consumed) = BorrowedFrame::parse(&encoded).unwrap();
+        assert_eq!(consumed, encoded.len());
+        assert!(borrowed.header().fin());
+        assert_eq!(borrowed.payload(), &data[..]);
+    }
+}
+
+#[test]
+fn test_close_code() {
+    for code in [0, 999, 1004, 1005, 1006, 1015, 2999, 5000] {
+        assert!(!is_valid_close_code(code));
+        assert!(OwnedFrame::checked_close_frame(None, code, b"").is_none());
+    }
+    for code in [1000, 1003, 1007, 1011, 1014, 3000, 4999] {
+        assert!(is_valid_close_code(code));
+    }
+    let frame = OwnedFrame::checked_close_frame(None, 1000, b"bye").unwrap();
+    assert_eq!(&frame.payload()[..], b"\x03\xe8bye");
+    assert!(OwnedFrame::checked_close_frame(None, None, b"").is_some());
+    assert!(OwnedFrame::checked_close_frame(None, None, b"bye").is_none());
+    assert!(OwnedFrame::checked_close_frame(None, 1000, &[b'a'; 124]).is_none());
+    // unchecked constructor keeps accepting any code
+    let frame = OwnedFrame::close_frame(None, 1005, b"");
+    assert_eq!(&frame.payload()[..], b"\x03\xed");
 }
@@ -2,23 +2,45 @@
 
 #![warn(missing_docs)]
 #![cfg_attr(docrs, feature(doc_auto_cfg))]
+#![cfg_attr(not(feature = "std"), no_std)]
 
-use std::collections::HashMap;
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "std")]
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
 /// websocket error definitions
+#[cfg(feature = "std")]
 pub mod errors;
 /// websocket transport unit
+///
+/// only depends on `core`, `alloc` and `bytes`, no std io is involved, so frame
+/// encoding/decoding can be reused on embedded/wasm targets, it's the only
+/// module built with `alloc` feature and without `std` feature
+#[cfg(feature = "alloc")]
 pub mod frame;
 /// build connection & read/write frame utils
+#[cfg(feature = "std")]
 pub mod protocol;
 
 /// frame codec impl
+#[cfg(feature = "std")]
 pub mod codec;
 /// connection helper function
+#[cfg(feature = "std")]
 pub mod connector;
 
 /// helper message definition
+#[cfg(feature = "std")]
 mod message;
+#[cfg(feature = "std")]
 pub use message::*;
 #[cfg(feature = "simple")]
 /// simple api to create websocket connection
@@ -27,21 +49,62 @@ pub mod simple;
 pub use simple::ClientConfig;
 
 /// helper stream definition
+#[cfg(feature = "std")]
 pub mod stream;
 
 /// some helper extension
+#[cfg(feature = "std")]
 pub mod extension;
 
+/// client wrapper which reconnects with backoff when connection is lost
+#[cfg(feature = "async")]
+pub mod reconnect;
+
+/// stream wrappers for testing codec robustness
+#[cfg(feature = "test-util")]
+pub mod test_util;
+
 /// helper builder to construct websocket client
+#[cfg(feature = "std")]
 #[derive(Debug, Clone)]
 pub struct ClientBuilder {
     protocols: Vec<String>,
     extensions: Vec<String>,
-    #[cfg_attr(not(any(feature = "sync", feature = "async")), allow(dead_code))]
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
     version: u8,
     headers: HashMap<String, String>,
+    explicit_content_length: bool,
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    omit_version: bool,
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    request_template: protocol::RequestTemplate,
+    #[cfg_attr(not(any(feature = "sync", feature = "async")), allow(dead_code))]
+    proxy: Option<connector::ProxyConfig>,
+    #[cfg_attr(
+        not(any(
+            feature = "sync_tls_rustls",
+            feature = "sync_tls_native",
+            feature = "async_tls_rustls",
+            feature = "async_tls_native"
+        )),
+        allow(dead_code)
+    )]
+    certs: Vec<std::path::PathBuf>,
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    handshake_timeout: Option<std::time::Duration>,
+    #[cfg_attr(not(any(feature = "sync", feature = "async")), allow(dead_code))]
+    max_redirects: u8,
+    #[cfg(feature = "async_tls_rustls")]
+    tls_connector: Option<connector::CustomTlsConnector>,
+    #[cfg(any(
+        feature = "deflate",
+        feature = "deflate_ng",
+        feature = "deflate_static"
+    ))]
+    compression: Option<codec::CompressionParams>,
 }
 
+#[cfg(feature = "std")]
 impl Default for ClientBuilder {
     fn default() -> Self {
         Self {
@@ -49,10 +112,26 @@ impl Default for ClientBuilder {
             extensions: vec![],
             headers: HashMap::new(),
             version: 13,
+            explicit_content_length: false,
+            omit_version: false,
+            request_template: Default::default(),
+            proxy: None,
+            certs: vec![],
+            handshake_timeout: None,
+            max_redirects: 0,
+            #[cfg(feature = "async_tls_rustls")]
+            tls_connector: None,
+            #[cfg(any(
+                feature = "deflate",
+                feature = "deflate_ng",
+                feature = "deflate_static"
+            ))]
+            compression: None,
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl ClientBuilder {
     /// create builder with websocket url
     pub fn new() -> Self {
@@ -85,6 +164,22 @@ impl ClientBuilder {
         Self { extensions, ..self }
     }
 
+    /// add permessage-deflate extension of `config`
+    ///
+    /// compression level, memory level and strategy of `config` are passed to
+    /// `check_fn` of deflate codecs as `codec::CompressionParams` in response
+    /// extensions, invalid config fails in `check_fn` with `WsError::InvalidConfig`
+    #[cfg(any(
+        feature = "deflate",
+        feature = "deflate_ng",
+        feature = "deflate_static"
+    ))]
+    pub fn deflate(mut self, config: codec::PMDConfig) -> Self {
+        self.extensions.push(config.ext_string());
+        self.compression = Some(codec::CompressionParams::from(&config));
+        self
+    }
+
     /// set websocket version, default 13
     pub fn version(self, version: u8) -> Self {
         Self { version, ..self }
@@ -102,25 +197,319 @@ impl ClientBuilder {
     pub fn headers(self, headers: HashMap<String, String>) -> Self {
         Self { headers, ..self }
     }
+
+    /// add `Content-Length: 0` to handshake request, default false
+    ///
+    /// some strict proxies wait for request body without this header
+    pub fn explicit_content_length(self, explicit_content_length: bool) -> Self {
+        Self {
+            explicit_content_length,
+            ..self
+        }
+    }
+
+    /// do not send `Sec-WebSocket-Version` header, default false
+    ///
+    /// only for testing how server handles upgrade request without version
+    pub fn omit_version(self, omit_version: bool) -> Self {
+        Self {
+            omit_version,
+            ..self
+        }
+    }
+
+    /// set handshake request template for full control of request line and headers
+    ///
+    /// method, version and uri(if not `/`) of template are used for request line,
+    /// headers of template are sent as is and take precedence, websocket headers(host,
+    /// upgrade, key, version, protocol, extensions) and headers set by `header` method
+    /// are only added if template does not carry them
+    pub fn request_template(
+        self,
+        template: http::request::Builder,
+    ) -> Result<Self, errors::WsError> {
+        Ok(Self {
+            request_template: template.try_into()?,
+            ..self
+        })
+    }
+
+    /// use fixed `Sec-WebSocket-Key` instead of a random one, for deterministic tests
+    ///
+    /// key is set as header of request template, so it's kept by later
+    /// `request_template` call only if the new template carries no key
+    pub fn key(mut self, key: &str) -> Result<Self, errors::WsError> {
+        let value = http::HeaderValue::from_str(key)
+            .map_err(|e| errors::WsError::InvalidConfig(e.to_string()))?;
+        self.request_template
+            .headers
+            .insert(http::header::SEC_WEBSOCKET_KEY, value);
+        Ok(self)
+    }
+
+    /// connect to websocket server via socks5 proxy
+    ///
+    /// **NOTE** it will replace proxy set by `http_proxy` method
+    pub fn socks5_proxy(self, config: connector::Socks5Config) -> Self {
+        Self {
+            proxy: Some(connector::ProxyConfig::Socks5(config)),
+            ..self
+        }
+    }
+
+    /// connect to websocket server via http CONNECT proxy
+    ///
+    /// **NOTE** it will replace proxy set by `socks5_proxy` method
+    pub fn http_proxy(self, config: connector::HttpProxyConfig) -> Self {
+        Self {
+            proxy: Some(connector::ProxyConfig::Http(config)),
+            ..self
+        }
+    }
+
+    /// add extra root cert file trusted by tls connections, besides
+    /// default roots of tls backend
+    ///
+    /// rustls backend expects PEM file, native tls backend accepts PEM or DER file
+    pub fn cert<P: Into<std::path::PathBuf>>(mut self, path: P) -> Self {
+        self.certs.push(path.into());
+        self
+    }
+
+    /// set extra root cert files trusted by tls connections
+    ///
+    /// **NOTE** it will clear certs set by `cert` method
+    pub fn certs(self, certs: Vec<std::path::PathBuf>) -> Self {
+        Self { certs, ..self }
+    }
+
+    #[cfg(feature = "async_tls_rustls")]
+    /// use `connector` to start tls session in `async_rustls_connect`, instead of
+    /// building client config from webpki roots and certs added by `cert`
+    ///
+    /// it allows custom verifier, alpn protocols, session cache and client auth certs
+    pub fn tls_connector(self, connector: tokio_rustls::TlsConnector) -> Self {
+        Self {
+            tls_connector: Some(connector::CustomTlsConnector(connector)),
+            ..self
+        }
+    }
+
+    /// max time of tls and websocket handshake, default `None`, wait forever
+    ///
+    /// handshake failed with `WsError::HandShakeFailed("timeout")` on expiry,
+    /// blocking client sets read timeout of tcp stream during handshake and
+    /// restores it afterward, it's not applied to blocking `with_stream`
+    pub fn handshake_timeout(self, handshake_timeout: Option<std::time::Duration>) -> Self {
+        Self {
+            handshake_timeout,
+            ..self
+        }
+    }
+
+    /// follow at most `max` 3xx responses with `Location` header during handshake,
+    /// default 0, 3xx response is passed to check fn
+    ///
+    /// only connect methods opening their own connection follow redirects,
+    /// `with_stream`, `handshake` and their async versions use the given stream
+    /// as is, `simple::ClientConfig` doesn't follow redirects either
+    ///
+    /// redirect changing scheme between ws and wss is followed by `auto_connect`
+    /// and `async_auto_connect`, which open a raw or tls stream per scheme,
+    /// other connect methods fail on it
+    pub fn follow_redirects(self, max: u8) -> Self {
+        Self {
+            max_redirects: max,
+            ..self
+        }
+    }
+
+    /// get target of a redirect handshake response, `None` if response is not
+    /// a redirect or redirects are not followed
+    ///
+    /// relative location is resolved against `uri`, `visited` records uris
+    /// already redirected from to detect redirect loop, target with scheme
+    /// other than `uri` fails unless `allow_scheme_change` is set
+    #[cfg_attr(not(any(feature = "sync", feature = "async")), allow(dead_code))]
+    fn redirect_target(
+        &self,
+        uri: &http::Uri,
+        resp: &http::Response<()>,
+        visited: &mut Vec<http::Uri>,
+        allow_scheme_change: bool,
+    ) -> Result<Option<http::Uri>, errors::WsError> {
+        use errors::WsError;
+
+        if self.max_redirects == 0 || !resp.status().is_redirection() {
+            return Ok(None);
+        }
+        let Some(location) = resp.headers().get(http::header::LOCATION) else {
+            return Ok(None);
+        };
+        if visited.len() >= self.max_redirects as usize {
+            return Err(WsError::HandShakeFailed(format!(
+                "too many redirects, max {}",
+                self.max_redirects
+            )));
+        }
+        let location: http::Uri = location
+            .to_str()
+            .map_err(|e| WsError::HandShakeFailed(e.to_string()))?
+            .parse()
+            .map_err(|e: http::uri::InvalidUri| WsError::HandShakeFailed(e.to_string()))?;
+        let mut parts = location.into_parts();
+        if parts.authority.is_none() {
+            parts.authority = uri.authority().cloned();
+        }
+        parts.scheme = match parts.scheme.as_ref().map(|s| s.as_str()) {
+            None => uri.scheme().cloned(),
+            Some("http") | Some("ws") => Some("ws".parse().unwrap()),
+            Some("https") | Some("wss") => Some("wss".parse().unwrap()),
+            Some(scheme) => {
+                return Err(WsError::HandShakeFailed(format!(
+                    "unsupported redirect scheme {scheme}"
+                )))
+            }
+        };
+        let scheme = parts
+            .scheme
+            .as_ref()
+            .map(|s| s.as_str())
+            .unwrap_or_default();
+        if !allow_scheme_change && uri.scheme_str() != Some(scheme) {
+            return Err(WsError::HandShakeFailed(format!(
+                "redirect from {uri} changes scheme to {scheme}, use auto_connect to follow it"
+            )));
+        }
+        if parts.path_and_query.is_none() {
+            parts.path_and_query = Some("/".parse().unwrap());
+        }
+        let target =
+            http::Uri::from_parts(parts).map_err(|e| WsError::HandShakeFailed(e.to_string()))?;
+        visited.push(uri.clone());
+        if visited.contains(&target) {
+            return Err(WsError::HandShakeFailed(format!(
+                "redirect loop detected at {target}"
+            )));
+        }
+        tracing::debug!("redirect from {} to {}", uri, target);
+        Ok(Some(target))
+    }
+
+    /// insert compression params set by `deflate` into handshake response
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    #[cfg_attr(
+        not(any(
+            feature = "deflate",
+            feature = "deflate_ng",
+            feature = "deflate_static"
+        )),
+        allow(unused_variables)
+    )]
+    fn insert_compression_params(&self, resp: &mut http::Response<()>) {
+        #[cfg(any(
+            feature = "deflate",
+            feature = "deflate_ng",
+            feature = "deflate_static"
+        ))]
+        if let Some(params) = self.compression {
+            resp.extensions_mut().insert(params);
+        }
+    }
+
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    fn request_headers(&self) -> HashMap<String, String> {
+        let mut headers = self.headers.clone();
+        if self.explicit_content_length {
+            headers.insert("Content-Length".to_string(), "0".to_string());
+        }
+        headers
+    }
 }
 
 #[cfg(feature = "sync")]
 mod blocking {
     use std::{
-        io::{Read, Write},
+        cell::Cell,
+        io::{ErrorKind, Read, Write},
         net::TcpStream,
+        time::Duration,
     };
 
+    use bytes::BytesMut;
+
     use crate::{
-        connector::{get_scheme, tcp_connect},
+        connector::{get_scheme, proxy_connect, tcp_connect},
         errors::WsError,
-        protocol::{handle_handshake, req_handshake},
-        ClientBuilder, ServerBuilder,
+        protocol::{
+            handle_handshake, req_handshake_with_template, standard_handshake_resp_check, Mode,
+            RemainBytes,
+        },
+        stream::SyncStream,
+        ClientBuilder, ConnectionGuard, ConnectionLimiter, ServerBuilder,
     };
 
+    /// read timeout of tcp stream before handshake, restored after handshake
+    #[derive(Clone, Copy, Default)]
+    struct PrevTimeout(Option<Option<Duration>>);
+
+    impl PrevTimeout {
+        fn restore(&self, stream: &TcpStream) -> Result<(), WsError> {
+            if let Some(timeout) = self.0 {
+                stream.set_read_timeout(timeout)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// underlying tcp stream of a `SyncStream`
+    fn tcp_stream(stream: &SyncStream) -> &TcpStream {
+        match stream {
+            SyncStream::Raw(s) => s,
+            #[cfg(feature = "sync_tls_rustls")]
+            SyncStream::Rustls(s) => &s.sock,
+            #[cfg(feature = "sync_tls_native")]
+            SyncStream::NativeTls(s) => s.get_ref(),
+        }
+    }
+
+    /// blocking read reaching read timeout fails with `WouldBlock` or `TimedOut`
+    fn map_timeout(e: WsError) -> WsError {
+        match e {
+            WsError::IOError(ref io) | WsError::ConnectionLost(ref io)
+                if matches!(io.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+            {
+                WsError::HandShakeFailed("timeout".to_string())
+            }
+            e => e,
+        }
+    }
     impl ClientBuilder {
+        /// create tcp connection to server, via proxy if it's set
+        pub(crate) fn tcp_connect(&self, uri: &http::Uri) -> Result<TcpStream, WsError> {
+            match self.proxy.as_ref() {
+                Some(proxy) => proxy_connect(proxy, uri),
+                None => tcp_connect(uri),
+            }
+        }
+
+        /// set handshake timeout as read timeout of tcp stream
+        fn set_handshake_timeout(&self, stream: &TcpStream) -> Result<PrevTimeout, WsError> {
+            match self.handshake_timeout {
+                Some(timeout) => {
+                    let prev = stream.read_timeout()?;
+                    stream.set_read_timeout(Some(timeout))?;
+                    Ok(PrevTimeout(Some(prev)))
+                }
+                None => Ok(PrevTimeout(None)),
+            }
+        }
+
         /// perform protocol handshake & check server response
-        pub fn connect<C, F>(&self, uri: http::Uri, check_fn: F) -> Result<C, WsError>
+        ///
+        /// frames read along with response are left in [`RemainBytes`](crate::protocol::RemainBytes)
+        /// of response extensions, custom `check_fn` should feed them to codec
+        pub fn connect<C, F>(&self, uri: http::Uri, mut check_fn: F) -> Result<C, WsError>
         where
             F: FnMut(String, http::Response<()>, TcpStream) -> Result<C, WsError>,
         {
@@ -128,13 +517,26 @@ mod blocking {
             if matches!(mode, crate::protocol::Mode::WSS) {
                 panic!("can not perform ssl connection, use `rustls_connect` or `native_tls_connect` instead");
             }
-            let stream = tcp_connect(&uri)?;
-            self.with_stream(uri, stream, check_fn)
+            let prev = Cell::new(PrevTimeout::default());
+            self.connect_redirects(
+                uri,
+                false,
+                |uri| {
+                    let stream = self.tcp_connect(uri)?;
+                    prev.set(self.set_handshake_timeout(&stream)?);
+                    Ok(stream)
+                },
+                |key, resp, stream| {
+                    prev.get().restore(&stream)?;
+                    check_fn(key, resp, stream)
+                },
+            )
+            .map_err(map_timeout)
         }
 
         #[cfg(feature = "sync_tls_rustls")]
-        /// perform protocol handshake via ssl with default certs & check server response
-        pub fn rustls_connect<C, F>(&self, uri: http::Uri, check_fn: F) -> Result<C, WsError>
+        /// perform protocol handshake via rustls with webpki roots and certs added by `cert` & check server response
+        pub fn rustls_connect<C, F>(&self, uri: http::Uri, mut check_fn: F) -> Result<C, WsError>
         where
             F: FnMut(
                 String,
@@ -147,17 +549,33 @@ mod blocking {
         {
             use crate::connector::{get_host, wrap_rustls};
             let mode = get_scheme(&uri)?;
-            if matches!(mode, crate::protocol::Mode::WSS) {
+            if !matches!(mode, crate::protocol::Mode::WSS) {
                 panic!("can not perform not ssl connection, use `connect` instead");
             }
-            let stream = tcp_connect(&uri)?;
-            let stream = wrap_rustls(stream, get_host(&uri)?, vec![])?;
-            self.with_stream(uri, stream, check_fn)
+            let prev = Cell::new(PrevTimeout::default());
+            self.connect_redirects(
+                uri,
+                false,
+                |uri| {
+                    let stream = self.tcp_connect(uri)?;
+                    prev.set(self.set_handshake_timeout(&stream)?);
+                    wrap_rustls(stream, get_host(uri)?, self.certs.clone())
+                },
+                |key, resp, stream| {
+                    prev.get().restore(&stream.sock)?;
+                    check_fn(key, resp, stream)
+                },
+            )
+            .map_err(map_timeout)
         }
 
         #[cfg(feature = "sync_tls_native")]
-        /// perform protocol handshake via ssl with default certs & check server response
-        pub fn native_tls_connect<C, F>(&self, uri: http::Uri, check_fn: F) -> Result<C, WsError>
+        /// perform protocol handshake via native tls with system roots and certs added by `cert` & check server response
+        pub fn native_tls_connect<C, F>(
+            &self,
+            uri: http::Uri,
+            mut check_fn: F,
+        ) -> Result<C, WsError>
         where
             F: FnMut(
                 String,
@@ -167,12 +585,76 @@ mod blocking {
         {
             use crate::connector::{get_host, wrap_native_tls};
             let mode = get_scheme(&uri)?;
-            if matches!(mode, crate::protocol::Mode::WSS) {
+            if !matches!(mode, crate::protocol::Mode::WSS) {
                 panic!("can not perform not ssl connection, use `connect` instead");
             }
-            let stream = tcp_connect(&uri)?;
-            let stream = wrap_native_tls(stream, get_host(&uri)?, vec![])?;
-            self.with_stream(uri, stream, check_fn)
+            let prev = Cell::new(PrevTimeout::default());
+            self.connect_redirects(
+                uri,
+                false,
+                |uri| {
+                    let stream = self.tcp_connect(uri)?;
+                    prev.set(self.set_handshake_timeout(&stream)?);
+                    wrap_native_tls(stream, get_host(uri)?, self.certs.clone())
+                },
+                |key, resp, stream| {
+                    prev.get().restore(stream.get_ref())?;
+                    check_fn(key, resp, stream)
+                },
+            )
+            .map_err(map_timeout)
+        }
+
+        /// perform protocol handshake via raw tcp or tls chosen by uri scheme
+        /// & check server response
+        ///
+        /// tls session is started by rustls if `sync_tls_rustls` is enabled,
+        /// otherwise by native tls, redirect changing scheme between ws and wss
+        /// reopens connection with matching stream
+        pub fn auto_connect<C, F>(&self, uri: http::Uri, mut check_fn: F) -> Result<C, WsError>
+        where
+            F: FnMut(String, http::Response<()>, SyncStream) -> Result<C, WsError>,
+        {
+            let prev = Cell::new(PrevTimeout::default());
+            self.connect_redirects(
+                uri,
+                true,
+                |uri| {
+                    let stream = self.tcp_connect(uri)?;
+                    prev.set(self.set_handshake_timeout(&stream)?);
+                    match get_scheme(uri)? {
+                        Mode::WS => Ok(SyncStream::Raw(stream)),
+                        Mode::WSS => self.wrap_tls(uri, stream),
+                    }
+                },
+                |key, resp, stream| {
+                    prev.get().restore(tcp_stream(&stream))?;
+                    check_fn(key, resp, stream)
+                },
+            )
+            .map_err(map_timeout)
+        }
+
+        /// start tls session for `auto_connect`
+        #[cfg(feature = "sync_tls_rustls")]
+        fn wrap_tls(&self, uri: &http::Uri, stream: TcpStream) -> Result<SyncStream, WsError> {
+            use crate::connector::{get_host, wrap_rustls};
+            wrap_rustls(stream, get_host(uri)?, self.certs.clone()).map(SyncStream::Rustls)
+        }
+
+        /// start tls session for `auto_connect`
+        #[cfg(all(feature = "sync_tls_native", not(feature = "sync_tls_rustls")))]
+        fn wrap_tls(&self, uri: &http::Uri, stream: TcpStream) -> Result<SyncStream, WsError> {
+            use crate::connector::{get_host, wrap_native_tls};
+            wrap_native_tls(stream, get_host(uri)?, self.certs.clone()).map(SyncStream::NativeTls)
+        }
+
+        /// start tls session for `auto_connect`
+        #[cfg(not(any(feature = "sync_tls_rustls", feature = "sync_tls_native")))]
+        fn wrap_tls(&self, _uri: &http::Uri, _stream: TcpStream) -> Result<SyncStream, WsError> {
+            Err(WsError::InvalidConfig(
+                "wss requires sync_tls_rustls or sync_tls_native feature".to_string(),
+            ))
         }
 
         /// ## Low level api
@@ -187,22 +669,88 @@ mod blocking {
             S: Read + Write,
             F: FnMut(String, http::Response<()>, S) -> Result<C, WsError>,
         {
-            get_scheme(&uri)?;
-            let (key, resp) = req_handshake(
-                &mut stream,
-                &uri,
+            let (key, resp) = self.send_handshake(&uri, &mut stream)?;
+            check_fn(key, resp, stream)
+        }
+
+        /// ## Low level api
+        /// perform protocol handshake & check server response without
+        /// constructing codec, return response, stream and bytes read after
+        /// response
+        ///
+        /// remaining bytes are frames sent by server right after handshake,
+        /// feed them to codec built on returned stream, e.g. `FrameReadState::feed`
+        pub fn handshake<S: Read + Write>(
+            &self,
+            uri: http::Uri,
+            mut stream: S,
+        ) -> Result<(http::Response<()>, S, BytesMut), WsError> {
+            let (key, mut resp) = self.send_handshake(&uri, &mut stream)?;
+            standard_handshake_resp_check(key.as_bytes(), &resp)?;
+            let remain = resp
+                .extensions_mut()
+                .remove::<RemainBytes>()
+                .map(|remain| remain.0)
+                .unwrap_or_default();
+            Ok((resp, stream, remain))
+        }
+
+        /// send handshake request and read response
+        fn send_handshake<S: Read + Write>(
+            &self,
+            uri: &http::Uri,
+            stream: &mut S,
+        ) -> Result<(String, http::Response<()>), WsError> {
+            get_scheme(uri)?;
+            let (key, mut resp) = req_handshake_with_template(
+                stream,
+                &self.request_template,
+                uri,
                 &self.protocols,
                 &self.extensions,
-                self.version,
-                self.headers.clone(),
+                (!self.omit_version).then_some(self.version),
+                self.request_headers(),
             )?;
-            check_fn(key, resp, stream)
+            self.insert_compression_params(&mut resp);
+            Ok((key, resp))
+        }
+
+        /// open a stream with `open` and perform handshake, if redirected, open
+        /// another stream to redirect target and retry
+        fn connect_redirects<C, S, O, F>(
+            &self,
+            mut uri: http::Uri,
+            allow_scheme_change: bool,
+            mut open: O,
+            mut check_fn: F,
+        ) -> Result<C, WsError>
+        where
+            S: Read + Write,
+            O: FnMut(&http::Uri) -> Result<S, WsError>,
+            F: FnMut(String, http::Response<()>, S) -> Result<C, WsError>,
+        {
+            let mut visited = vec![];
+            loop {
+                let mut stream = open(&uri)?;
+                let (key, resp) = self.send_handshake(&uri, &mut stream)?;
+                match self.redirect_target(&uri, &resp, &mut visited, allow_scheme_change)? {
+                    Some(target) => uri = target,
+                    None => return check_fn(key, resp, stream),
+                }
+            }
         }
     }
 
     impl ServerBuilder {
         /// wait for protocol handshake from client
         /// checking handshake & construct server
+        ///
+        /// request passed to `handshake_handler` keeps all client headers,
+        /// including raw `Sec-WebSocket-Key`, raw request head is available
+        /// in extensions as `protocol::RawRequestHead`
+        ///
+        /// frames read along with request are left in [`RemainBytes`](crate::protocol::RemainBytes)
+        /// of request extensions, custom `factory` should feed them to codec
         pub fn accept<F1, F2, T, C, S>(
             mut stream: S,
             mut handshake_handler: F1,
@@ -231,6 +779,40 @@ mod blocking {
                 }
             }
         }
+
+        /// same as `accept`, but reply `503 Service Unavailable` if there
+        /// is no available slot in limiter
+        ///
+        /// **NOTE** keep returned guard alive until connection is closed
+        pub fn accept_limited<F1, F2, T, C, S>(
+            mut stream: S,
+            limiter: &ConnectionLimiter,
+            handshake_handler: F1,
+            codec_factory: F2,
+        ) -> Result<(C, ConnectionGuard), WsError>
+        where
+            S: Read + Write,
+            F1: FnMut(
+                http::Request<()>,
+            ) -> Result<
+                (http::Request<()>, http::Response<T>),
+                (http::Response<T>, WsError),
+            >,
+            F2: FnMut(http::Request<()>, S) -> Result<C, WsError>,
+            T: ToString + std::fmt::Debug,
+        {
+            match limiter.try_acquire() {
+                Some(guard) => {
+                    let codec = Self::accept(stream, handshake_handler, codec_factory)?;
+                    Ok((codec, guard))
+                }
+                None => {
+                    handle_handshake(&mut stream)?;
+                    write_resp(limiter.reject_resp(), &mut stream)?;
+                    Err(WsError::HandShakeFailed("too many connections".to_string()))
+                }
+            }
+        }
     }
 
     fn write_resp<S, T>(resp: http::Response<T>, stream: &mut S) -> Result<(), WsError>
@@ -251,37 +833,80 @@ mod blocking {
     }
 }
 
-#[cfg(feature = "async")]
+#[cfg(feature = "async_codec")]
 mod non_blocking {
     use http;
     use std::fmt::Debug;
+    #[cfg(feature = "async")]
+    use std::{future::Future, task::Poll};
 
-    use tokio::{
-        io::{AsyncRead, AsyncWrite, AsyncWriteExt},
-        net::TcpStream,
-    };
+    use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+    #[cfg(feature = "async")]
+    use tokio::net::{TcpListener, TcpStream};
 
+    use bytes::BytesMut;
+
+    #[cfg(feature = "async")]
+    use crate::{
+        connector::{async_proxy_connect, async_tcp_connect},
+        protocol::Mode,
+        stream::AsyncStream,
+    };
     use crate::{
-        connector::async_tcp_connect,
         errors::WsError,
-        protocol::{async_handle_handshake, async_req_handshake},
-        ServerBuilder,
+        protocol::{
+            async_handle_handshake, async_req_handshake_with_template,
+            standard_handshake_resp_check, RemainBytes,
+        },
+        ConnectionGuard, ConnectionLimiter, ServerBuilder,
     };
 
     use super::ClientBuilder;
 
     impl ClientBuilder {
+        #[cfg(feature = "async")]
+        /// create tcp connection to server, via proxy if it's set
+        pub(crate) async fn async_tcp_connect(
+            &self,
+            uri: &http::Uri,
+        ) -> Result<TcpStream, WsError> {
+            match self.proxy.as_ref() {
+                Some(proxy) => async_proxy_connect(proxy, uri).await,
+                None => async_tcp_connect(uri).await,
+            }
+        }
+
+        /// run tls or websocket handshake with `handshake_timeout`
+        async fn with_handshake_timeout<T, Fut>(&self, fut: Fut) -> Result<T, WsError>
+        where
+            Fut: std::future::Future<Output = Result<T, WsError>>,
+        {
+            match self.handshake_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, fut)
+                    .await
+                    .map_err(|_| WsError::HandShakeFailed("timeout".to_string()))?,
+                None => fut.await,
+            }
+        }
+
+        #[cfg(feature = "async")]
         /// perform protocol handshake & check server response
+        ///
+        /// frames read along with response are left in [`RemainBytes`](crate::protocol::RemainBytes)
+        /// of response extensions, custom `check_fn` should feed them to codec
         pub async fn async_connect<C, F>(&self, uri: http::Uri, check_fn: F) -> Result<C, WsError>
         where
             F: FnMut(String, http::Response<()>, TcpStream) -> Result<C, WsError>,
         {
-            let stream = async_tcp_connect(&uri).await?;
-            self.async_with_stream(uri, stream, check_fn).await
+            let open = |uri: http::Uri| async move { self.async_tcp_connect(&uri).await };
+            self.async_connect_redirects(uri, false, open, check_fn)
+                .await
         }
 
         #[cfg(feature = "async_tls_rustls")]
-        /// perform protocol handshake via ssl with default certs & check server response
+        /// perform protocol handshake via rustls with webpki roots and certs added by `cert` & check server response
+        ///
+        /// if connector is set by `tls_connector`, it's used as is
         pub async fn async_rustls_connect<C, F>(
             &self,
             uri: http::Uri,
@@ -294,18 +919,42 @@ mod non_blocking {
                 tokio_rustls::client::TlsStream<tokio::net::TcpStream>,
             ) -> Result<C, WsError>,
         {
-            use crate::connector::{async_wrap_rustls, get_host};
             let mode = crate::connector::get_scheme(&uri)?;
-            if matches!(mode, crate::protocol::Mode::WSS) {
+            if !matches!(mode, crate::protocol::Mode::WSS) {
                 panic!("can not perform not ssl connection, use `connect` instead");
             }
-            let stream = async_tcp_connect(&uri).await?;
-            let stream = async_wrap_rustls(stream, get_host(&uri)?, vec![]).await?;
-            self.async_with_stream(uri, stream, check_fn).await
+            let open = |uri: http::Uri| async move {
+                let stream = self.async_tcp_connect(&uri).await?;
+                self.async_wrap_rustls(&uri, stream).await
+            };
+            self.async_connect_redirects(uri, false, open, check_fn)
+                .await
+        }
+
+        /// start rustls session with connector set by `tls_connector` or
+        /// certs added by `cert`
+        #[cfg(feature = "async_tls_rustls")]
+        async fn async_wrap_rustls(
+            &self,
+            uri: &http::Uri,
+            stream: TcpStream,
+        ) -> Result<tokio_rustls::client::TlsStream<TcpStream>, WsError> {
+            use crate::connector::{async_wrap_rustls, async_wrap_rustls_with, get_host};
+            let host = get_host(uri)?;
+            match self.tls_connector.as_ref() {
+                Some(connector) => {
+                    self.with_handshake_timeout(async_wrap_rustls_with(&connector.0, stream, host))
+                        .await
+                }
+                None => {
+                    self.with_handshake_timeout(async_wrap_rustls(stream, host, self.certs.clone()))
+                        .await
+                }
+            }
         }
 
         #[cfg(feature = "async_tls_native")]
-        /// perform protocol handshake via ssl with default certs & check server response
+        /// perform protocol handshake via native tls with system roots and certs added by `cert` & check server response
         pub async fn async_native_tls_connect<C, F>(
             &self,
             uri: http::Uri,
@@ -318,14 +967,99 @@ mod non_blocking {
                 tokio_native_tls::TlsStream<TcpStream>,
             ) -> Result<C, WsError>,
         {
-            use crate::connector::{async_wrap_native_tls, get_host};
             let mode = crate::connector::get_scheme(&uri)?;
-            if matches!(mode, crate::protocol::Mode::WSS) {
+            if !matches!(mode, crate::protocol::Mode::WSS) {
                 panic!("can not perform not ssl connection, use `connect` instead");
             }
-            let stream = async_tcp_connect(&uri).await?;
-            let stream = async_wrap_native_tls(stream, get_host(&uri)?, vec![]).await?;
-            self.async_with_stream(uri, stream, check_fn).await
+            let open = |uri: http::Uri| async move {
+                let stream = self.async_tcp_connect(&uri).await?;
+                self.async_wrap_native_tls(&uri, stream).await
+            };
+            self.async_connect_redirects(uri, false, open, check_fn)
+                .await
+        }
+
+        /// start native tls session with certs added by `cert`
+        #[cfg(feature = "async_tls_native")]
+        async fn async_wrap_native_tls(
+            &self,
+            uri: &http::Uri,
+            stream: TcpStream,
+        ) -> Result<tokio_native_tls::TlsStream<TcpStream>, WsError> {
+            use crate::connector::{async_wrap_native_tls, get_host};
+            self.with_handshake_timeout(async_wrap_native_tls(
+                stream,
+                get_host(uri)?,
+                self.certs.clone(),
+            ))
+            .await
+        }
+
+        #[cfg(feature = "async")]
+        /// async version of `auto_connect`
+        ///
+        /// perform protocol handshake via raw tcp or tls chosen by uri scheme
+        /// & check server response
+        ///
+        /// tls session is started by rustls if `async_tls_rustls` is enabled,
+        /// otherwise by native tls, redirect changing scheme between ws and wss
+        /// reopens connection with matching stream
+        pub async fn async_auto_connect<C, F>(
+            &self,
+            uri: http::Uri,
+            check_fn: F,
+        ) -> Result<C, WsError>
+        where
+            F: FnMut(String, http::Response<()>, AsyncStream) -> Result<C, WsError>,
+        {
+            let open = |uri: http::Uri| async move {
+                let stream = self.async_tcp_connect(&uri).await?;
+                match crate::connector::get_scheme(&uri)? {
+                    Mode::WS => Ok(AsyncStream::Raw(stream)),
+                    Mode::WSS => self.async_wrap_tls(&uri, stream).await,
+                }
+            };
+            self.async_connect_redirects(uri, true, open, check_fn)
+                .await
+        }
+
+        /// start tls session for `async_auto_connect`
+        #[cfg(feature = "async_tls_rustls")]
+        async fn async_wrap_tls(
+            &self,
+            uri: &http::Uri,
+            stream: TcpStream,
+        ) -> Result<AsyncStream, WsError> {
+            self.async_wrap_rustls(uri, stream)
+                .await
+                .map(AsyncStream::from_rustls)
+        }
+
+        /// start tls session for `async_auto_connect`
+        #[cfg(all(feature = "async_tls_native", not(feature = "async_tls_rustls")))]
+        async fn async_wrap_tls(
+            &self,
+            uri: &http::Uri,
+            stream: TcpStream,
+        ) -> Result<AsyncStream, WsError> {
+            self.async_wrap_native_tls(uri, stream)
+                .await
+                .map(AsyncStream::NativeTls)
+        }
+
+        /// start tls session for `async_auto_connect`
+        #[cfg(all(
+            feature = "async",
+            not(any(feature = "async_tls_rustls", feature = "async_tls_native"))
+        ))]
+        async fn async_wrap_tls(
+            &self,
+            _uri: &http::Uri,
+            _stream: TcpStream,
+        ) -> Result<AsyncStream, WsError> {
+            Err(WsError::InvalidConfig(
+                "wss requires async_tls_rustls or async_tls_native feature".to_string(),
+            ))
         }
 
         /// async version of connect
@@ -341,17 +1075,77 @@ mod non_blocking {
             S: AsyncRead + AsyncWrite + Unpin,
             F: FnMut(String, http::Response<()>, S) -> Result<C, WsError>,
         {
-            let (key, resp) = async_req_handshake(
-                &mut stream,
-                &uri,
-                &self.protocols,
-                &self.extensions,
-                self.version,
-                self.headers.clone(),
-            )
-            .await?;
+            let (key, resp) = self.async_send_handshake(&uri, &mut stream).await?;
             check_fn(key, resp, stream)
         }
+
+        /// async version of `handshake`
+        ///
+        /// perform protocol handshake & check server response without
+        /// constructing codec, return response, stream and bytes read after
+        /// response
+        pub async fn async_handshake<S: AsyncRead + AsyncWrite + Unpin>(
+            &self,
+            uri: http::Uri,
+            mut stream: S,
+        ) -> Result<(http::Response<()>, S, BytesMut), WsError> {
+            let (key, mut resp) = self.async_send_handshake(&uri, &mut stream).await?;
+            standard_handshake_resp_check(key.as_bytes(), &resp)?;
+            let remain = resp
+                .extensions_mut()
+                .remove::<RemainBytes>()
+                .map(|remain| remain.0)
+                .unwrap_or_default();
+            Ok((resp, stream, remain))
+        }
+
+        /// send handshake request and read response with `handshake_timeout`
+        async fn async_send_handshake<S: AsyncRead + AsyncWrite + Unpin>(
+            &self,
+            uri: &http::Uri,
+            stream: &mut S,
+        ) -> Result<(String, http::Response<()>), WsError> {
+            let (key, mut resp) = self
+                .with_handshake_timeout(async_req_handshake_with_template(
+                    stream,
+                    &self.request_template,
+                    uri,
+                    &self.protocols,
+                    &self.extensions,
+                    (!self.omit_version).then_some(self.version),
+                    self.request_headers(),
+                ))
+                .await?;
+            self.insert_compression_params(&mut resp);
+            Ok((key, resp))
+        }
+
+        #[cfg(feature = "async")]
+        /// open a stream with `open` and perform handshake, if redirected, open
+        /// another stream to redirect target and retry
+        async fn async_connect_redirects<C, S, O, Fut, F>(
+            &self,
+            mut uri: http::Uri,
+            allow_scheme_change: bool,
+            mut open: O,
+            mut check_fn: F,
+        ) -> Result<C, WsError>
+        where
+            S: AsyncRead + AsyncWrite + Unpin,
+            O: FnMut(http::Uri) -> Fut,
+            Fut: Future<Output = Result<S, WsError>>,
+            F: FnMut(String, http::Response<()>, S) -> Result<C, WsError>,
+        {
+            let mut visited = vec![];
+            loop {
+                let mut stream = open(uri.clone()).await?;
+                let (key, resp) = self.async_send_handshake(&uri, &mut stream).await?;
+                match self.redirect_target(&uri, &resp, &mut visited, allow_scheme_change)? {
+                    Some(target) => uri = target,
+                    None => return check_fn(key, resp, stream),
+                }
+            }
+        }
     }
 
     impl ServerBuilder {
@@ -359,6 +1153,13 @@ mod non_blocking {
         ///
         /// wait for protocol handshake from client
         /// checking handshake & construct server
+        ///
+        /// request passed to `handshake_handler` keeps all client headers,
+        /// including raw `Sec-WebSocket-Key`, raw request head is available
+        /// in extensions as `protocol::RawRequestHead`
+        ///
+        /// frames read along with request are left in [`RemainBytes`](crate::protocol::RemainBytes)
+        /// of request extensions, custom `factory` should feed them to codec
         pub async fn async_accept<F1, F2, T, C, S>(
             mut stream: S,
             mut handshake_handler: F1,
@@ -387,6 +1188,101 @@ mod non_blocking {
                 }
             }
         }
+
+        /// async version of `accept_limited`
+        ///
+        /// reply `503 Service Unavailable` if there is no available slot in limiter
+        ///
+        /// **NOTE** keep returned guard alive until connection is closed
+        pub async fn async_accept_limited<F1, F2, T, C, S>(
+            mut stream: S,
+            limiter: &ConnectionLimiter,
+            handshake_handler: F1,
+            codec_factory: F2,
+        ) -> Result<(C, ConnectionGuard), WsError>
+        where
+            S: AsyncRead + AsyncWrite + Unpin,
+            F1: FnMut(
+                http::Request<()>,
+            ) -> Result<
+                (http::Request<()>, http::Response<T>),
+                (http::Response<T>, WsError),
+            >,
+            F2: FnMut(http::Request<()>, S) -> Result<C, WsError>,
+            T: ToString + Debug,
+        {
+            match limiter.try_acquire() {
+                Some(guard) => {
+                    let codec =
+                        Self::async_accept(stream, handshake_handler, codec_factory).await?;
+                    Ok((codec, guard))
+                }
+                None => {
+                    async_handle_handshake(&mut stream).await?;
+                    async_write_resp(limiter.reject_resp(), &mut stream).await?;
+                    Err(WsError::HandShakeFailed("too many connections".to_string()))
+                }
+            }
+        }
+
+        #[cfg(feature = "async")]
+        /// accept connections from listener until `shutdown` completes
+        ///
+        /// every connection runs `async_accept` and then `handler` in a spawned task,
+        /// handshake errors are logged. connections already accepted keep running
+        /// after shutdown
+        pub async fn serve<F1, F2, H, Fut, T, C>(
+            listener: TcpListener,
+            handshake_handler: F1,
+            codec_factory: F2,
+            handler: H,
+            shutdown: impl Future<Output = ()>,
+        ) -> Result<(), WsError>
+        where
+            F1: FnMut(
+                    http::Request<()>,
+                ) -> Result<
+                    (http::Request<()>, http::Response<T>),
+                    (http::Response<T>, WsError),
+                > + Clone
+                + Send
+                + 'static,
+            F2: FnMut(http::Request<()>, TcpStream) -> Result<C, WsError> + Clone + Send + 'static,
+            H: Fn(C) -> Fut + Clone + Send + 'static,
+            Fut: Future<Output = ()> + Send + 'static,
+            T: ToString + Debug + Send + 'static,
+            C: Send + 'static,
+        {
+            let mut shutdown = std::pin::pin!(shutdown);
+            loop {
+                let accepted = std::future::poll_fn(|cx| {
+                    if shutdown.as_mut().poll(cx).is_ready() {
+                        return Poll::Ready(None);
+                    }
+                    listener.poll_accept(cx).map(Some)
+                })
+                .await;
+                let (stream, addr) = match accepted {
+                    None => break,
+                    Some(Ok(accepted)) => accepted,
+                    Some(Err(e)) => {
+                        tracing::error!("failed to accept connection {e}");
+                        continue;
+                    }
+                };
+                let handshake_handler = handshake_handler.clone();
+                let codec_factory = codec_factory.clone();
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    match Self::async_accept(stream, handshake_handler, codec_factory).await {
+                        Ok(codec) => handler(codec).await,
+                        Err(e) => tracing::error!("{addr} handshake failed {e}"),
+                    }
+                });
+            }
+            tracing::debug!("server shutdown");
+            Ok(())
+        }
     }
 
     async fn async_write_resp<S, T>(resp: http::Response<T>, stream: &mut S) -> Result<(), WsError>
@@ -408,4 +1304,1007 @@ mod non_blocking {
 }
 
 /// helper struct to config & construct websocket server
+#[cfg(feature = "std")]
 pub struct ServerBuilder {}
+
+#[cfg(feature = "std")]
+impl ServerBuilder {
+    /// handshake handler which replies `403 Forbidden` if `Origin` header is
+    /// absent or not in `origins`, otherwise it's same as `default_handshake_handler`
+    ///
+    /// origin is compared case-insensitively with allowlist entries, such as
+    /// `https://example.com`
+    #[allow(clippy::type_complexity, clippy::result_large_err)]
+    pub fn with_origin_allowlist(
+        origins: Vec<String>,
+    ) -> impl FnMut(
+        http::Request<()>,
+    ) -> Result<
+        (http::Request<()>, http::Response<String>),
+        (http::Response<String>, errors::WsError),
+    > + Clone {
+        move |req| {
+            let allowed = req
+                .headers()
+                .get(http::header::ORIGIN)
+                .and_then(|origin| origin.to_str().ok())
+                .is_some_and(|origin| origins.iter().any(|o| o.eq_ignore_ascii_case(origin)));
+            if !allowed {
+                let e = errors::WsError::HandShakeFailed("origin is not allowed".to_string());
+                let resp = http::Response::builder()
+                    .version(http::Version::HTTP_11)
+                    .status(http::StatusCode::FORBIDDEN)
+                    .header("Content-Type", "text/html")
+                    .body(e.to_string())
+                    .unwrap();
+                return Err((resp, e));
+            }
+            codec::default_handshake_handler(req)
+        }
+    }
+
+    /// pick first protocol in client `offered` list which is also `supported`
+    pub fn select_protocol(offered: &[String], supported: &[String]) -> Option<String> {
+        offered
+            .iter()
+            .find(|offered| supported.contains(offered))
+            .cloned()
+    }
+
+    /// handshake handler which selects a subprotocol from client
+    /// `Sec-WebSocket-Protocol` headers by `select_protocol`, otherwise it's
+    /// same as `default_handshake_handler`
+    ///
+    /// selected subprotocol is echoed in response and inserted into request
+    /// extensions as `protocol::SubProtocol`, response has no subprotocol
+    /// header if nothing matches
+    #[allow(clippy::type_complexity, clippy::result_large_err)]
+    pub fn with_protocols(
+        supported: Vec<String>,
+    ) -> impl FnMut(
+        http::Request<()>,
+    ) -> Result<
+        (http::Request<()>, http::Response<String>),
+        (http::Response<String>, errors::WsError),
+    > + Clone {
+        move |req| {
+            let offered: Vec<String> = req
+                .headers()
+                .get_all(http::header::SEC_WEBSOCKET_PROTOCOL)
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .flat_map(|v| v.split(','))
+                .map(|p| p.trim().to_string())
+                .filter(|p| !p.is_empty())
+                .collect();
+            let (mut req, mut resp) = codec::default_handshake_handler(req)?;
+            if let Some(selected) = Self::select_protocol(&offered, &supported) {
+                resp.headers_mut().insert(
+                    http::header::SEC_WEBSOCKET_PROTOCOL,
+                    http::HeaderValue::from_str(&selected).unwrap(),
+                );
+                req.extensions_mut().insert(protocol::SubProtocol(selected));
+            }
+            Ok((req, resp))
+        }
+    }
+}
+
+/// limit number of concurrent accepted websocket connections
+///
+/// cloned limiters share the same slots
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct ConnectionLimiter {
+    max: usize,
+    active: Arc<AtomicUsize>,
+}
+
+#[cfg(feature = "std")]
+impl ConnectionLimiter {
+    /// construct with max concurrent connections
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            active: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// max concurrent connections
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// number of connections holding a slot
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Acquire)
+    }
+
+    /// try to take a slot, return `None` if limit is reached
+    ///
+    /// slot is released when returned guard is dropped
+    pub fn try_acquire(&self) -> Option<ConnectionGuard> {
+        self.active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |active| {
+                (active < self.max).then_some(active + 1)
+            })
+            .ok()
+            .map(|_| ConnectionGuard {
+                active: self.active.clone(),
+            })
+    }
+
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    fn reject_resp(&self) -> http::Response<String> {
+        http::Response::builder()
+            .version(http::Version::HTTP_11)
+            .status(http::StatusCode::SERVICE_UNAVAILABLE)
+            .header("Content-Type", "text/html")
+            .body(format!("too many connections, max {}", self.max))
+            .unwrap()
+    }
+}
+
+/// slot taken from [`ConnectionLimiter`], release the slot when dropped
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    active: Arc<AtomicUsize>,
+}
+
+#[cfg(feature = "std")]
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn test_connection_limit() {
+    use crate::codec::{default_handshake_handler, BytesCodec};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let limiter = ConnectionLimiter::new(2);
+    let server_limiter = limiter.clone();
+    let server = std::thread::spawn(move || {
+        listener
+            .incoming()
+            .take(3)
+            .map(|stream| {
+                ServerBuilder::accept_limited(
+                    stream.unwrap(),
+                    &server_limiter,
+                    default_handshake_handler,
+                    BytesCodec::factory,
+                )
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let uri: http::Uri = format!("ws://{addr}").parse().unwrap();
+    let mut clients = vec![];
+    for _ in 0..2 {
+        let client = ClientBuilder::new()
+            .connect(uri.clone(), BytesCodec::check_fn)
+            .unwrap();
+        clients.push(client);
+    }
+    let rejected = ClientBuilder::new().connect(uri, BytesCodec::check_fn);
+    assert!(matches!(rejected, Err(errors::WsError::HandShakeFailed(_))));
+
+    let accepted = server.join().unwrap();
+    assert!(accepted[0].is_ok());
+    assert!(accepted[1].is_ok());
+    assert!(accepted[2].is_err());
+    assert_eq!(limiter.active(), 2);
+    drop(accepted);
+    assert_eq!(limiter.active(), 0);
+}
+
+#[cfg(feature = "sync")]
+#[test]
+#[allow(clippy::result_large_err)]
+fn test_explicit_content_length() {
+    use crate::codec::{default_handshake_handler, BytesCodec};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        listener
+            .incoming()
+            .take(2)
+            .map(|stream| {
+                let mut content_length = None;
+                ServerBuilder::accept(
+                    stream.unwrap(),
+                    |req: http::Request<()>| {
+                        content_length = req.headers().get("content-length").cloned();
+                        default_handshake_handler(req)
+                    },
+                    BytesCodec::factory,
+                )
+                .unwrap();
+                content_length
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let uri: http::Uri = format!("ws://{addr}").parse().unwrap();
+    for explicit in [true, false] {
+        ClientBuilder::new()
+            .explicit_content_length(explicit)
+            .connect(uri.clone(), BytesCodec::check_fn)
+            .unwrap();
+    }
+    let content_lengths = server.join().unwrap();
+    assert_eq!(content_lengths[0].as_ref().unwrap(), "0");
+    assert!(content_lengths[1].is_none());
+}
+
+#[cfg(feature = "sync")]
+#[test]
+#[allow(clippy::result_large_err)]
+fn test_origin_allowlist() {
+    use crate::codec::BytesCodec;
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let handler = ServerBuilder::with_origin_allowlist(vec!["https://example.com".into()]);
+        listener
+            .incoming()
+            .take(3)
+            .map(|stream| {
+                ServerBuilder::accept(stream.unwrap(), handler.clone(), BytesCodec::factory).is_ok()
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let uri: http::Uri = format!("ws://{addr}").parse().unwrap();
+    let origins = [Some("https://EXAMPLE.com"), Some("https://evil.com"), None];
+    for origin in origins {
+        let builder = match origin {
+            Some(origin) => ClientBuilder::new().header("Origin", origin),
+            None => ClientBuilder::new(),
+        };
+        let ret = builder.connect(uri.clone(), BytesCodec::check_fn);
+        assert_eq!(ret.is_ok(), origin == Some("https://EXAMPLE.com"));
+    }
+    assert_eq!(server.join().unwrap(), vec![true, false, false]);
+}
+
+#[cfg(feature = "sync")]
+#[test]
+#[allow(clippy::result_large_err)]
+fn test_select_protocol() {
+    use crate::codec::StringCodec;
+    use std::net::TcpListener;
+
+    let supported = vec!["chat".to_string(), "superchat".to_string()];
+    assert_eq!(
+        ServerBuilder::select_protocol(&["v2".into(), "superchat".into()], &supported),
+        Some("superchat".to_string())
+    );
+    assert_eq!(
+        ServerBuilder::select_protocol(&["v2".into()], &supported),
+        None
+    );
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let handler = ServerBuilder::with_protocols(supported);
+        listener
+            .incoming()
+            .take(2)
+            .map(|stream| {
+                let mut selected = None;
+                let mut handler = handler.clone();
+                ServerBuilder::accept(
+                    stream.unwrap(),
+                    |req| {
+                        let ret = handler(req);
+                        if let Ok((req, _)) = &ret {
+                            selected = req.extensions().get::<protocol::SubProtocol>().cloned();
+                        }
+                        ret
+                    },
+                    StringCodec::factory,
+                )
+                .unwrap();
+                selected.map(|p| p.0)
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let uri: http::Uri = format!("ws://{addr}").parse().unwrap();
+    let client = ClientBuilder::new()
+        .protocols(vec!["v2".into(), "superchat".into(), "chat".into()])
+        .connect(uri.clone(), StringCodec::check_fn)
+        .unwrap();
+    let agreed = client
+        .handshake_response()
+        .and_then(|resp| resp.headers().get("sec-websocket-protocol"));
+    assert_eq!(agreed.unwrap(), "superchat");
+    let client = ClientBuilder::new()
+        .protocol("v2".into())
+        .connect(uri, StringCodec::check_fn)
+        .unwrap();
+    assert!(client
+        .handshake_response()
+        .unwrap()
+        .headers()
+        .get("sec-websocket-protocol")
+        .is_none());
+    assert_eq!(
+        server.join().unwrap(),
+        vec![Some("superchat".to_string()), None]
+    );
+}
+
+#[cfg(feature = "sync")]
+#[test]
+#[allow(clippy::result_large_err)]
+fn test_omit_version() {
+    use crate::codec::{default_handshake_handler, BytesCodec};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        listener
+            .incoming()
+            .take(2)
+            .map(|stream| {
+                let mut version = None;
+                let accepted = ServerBuilder::accept(
+                    stream.unwrap(),
+                    |req: http::Request<()>| {
+                        version = req.headers().get("sec-websocket-version").cloned();
+                        default_handshake_handler(req)
+                    },
+                    BytesCodec::factory,
+                )
+                .is_ok();
+                (version, accepted)
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let uri: http::Uri = format!("ws://{addr}").parse().unwrap();
+    // default handler rejects request without version
+    let err = ClientBuilder::new()
+        .omit_version(true)
+        .connect(uri.clone(), BytesCodec::check_fn)
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("426"), "{err}");
+    ClientBuilder::new()
+        .connect(uri, BytesCodec::check_fn)
+        .unwrap();
+    let versions = server.join().unwrap();
+    assert_eq!(versions[0], (None, false));
+    assert_eq!(versions[1].0.as_ref().unwrap(), "13");
+    assert!(versions[1].1);
+}
+
+#[cfg(feature = "sync")]
+#[test]
+#[allow(clippy::result_large_err)]
+fn test_reject_version_and_method() {
+    use crate::codec::{default_handshake_handler, BytesCodec};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        for stream in listener.incoming().take(2) {
+            let ret = ServerBuilder::accept(
+                stream.unwrap(),
+                default_handshake_handler,
+                BytesCodec::factory,
+            );
+            assert!(ret.is_err());
+        }
+    });
+
+    let uri: http::Uri = format!("ws://{addr}").parse().unwrap();
+    let err = ClientBuilder::new()
+        .version(10)
+        .connect(uri, BytesCodec::check_fn)
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("426"), "{err}");
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "POST / HTTP/1.1\r\n\
+         Host: localhost\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: {}\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n",
+        protocol::gen_key()
+    )
+    .unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).unwrap();
+    assert!(resp.starts_with("HTTP/1.1 405"), "{resp}");
+    assert!(resp.contains("allow: GET"), "{resp}");
+    server.join().unwrap();
+}
+
+#[cfg(feature = "sync")]
+#[test]
+#[allow(clippy::result_large_err)]
+fn test_request_template() {
+    use crate::codec::{default_handshake_handler, BytesCodec};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut req_parts = None;
+        ServerBuilder::accept(
+            stream,
+            |req: http::Request<()>| {
+                req_parts = Some((req.uri().clone(), req.headers().clone()));
+                default_handshake_handler(req)
+            },
+            BytesCodec::factory,
+        )
+        .unwrap();
+        req_parts.unwrap()
+    });
+
+    let uri: http::Uri = format!("ws://{addr}/ws").parse().unwrap();
+    let template = http::Request::builder()
+        .uri("/custom?token=1")
+        .header("X-Custom", "custom")
+        .header("User-Agent", "template")
+        .header("Host", "example.com");
+    ClientBuilder::new()
+        .header("User-Agent", "builder")
+        .request_template(template)
+        .unwrap()
+        .connect(uri, BytesCodec::check_fn)
+        .unwrap();
+    let (req_uri, headers) = server.join().unwrap();
+    assert_eq!(req_uri, "/custom?token=1");
+    assert_eq!(headers["x-custom"], "custom");
+    assert_eq!(headers["host"], "example.com");
+    assert_eq!(headers.get_all("user-agent").iter().count(), 1);
+    assert_eq!(headers["user-agent"], "template");
+    assert_eq!(headers["upgrade"], "websocket");
+    assert!(headers.contains_key("sec-websocket-key"));
+}
+
+#[cfg(feature = "sync")]
+#[test]
+#[allow(clippy::result_large_err)]
+fn test_fixed_key() {
+    use crate::codec::BytesCodec;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let mut keys = vec![];
+        for with_accept in [false, true] {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                match line.split_once(':') {
+                    Some((name, key)) if name.eq_ignore_ascii_case("sec-websocket-key") => {
+                        keys.push(key.trim().to_string())
+                    }
+                    _ => {}
+                }
+                line.clear();
+            }
+            let mut resp = "HTTP/1.1 101 Switching Protocols\r\n\
+                            Upgrade: websocket\r\n\
+                            Connection: Upgrade\r\n"
+                .to_string();
+            if with_accept {
+                let accept = protocol::cal_accept_key(KEY.as_bytes());
+                resp.push_str(&format!("Sec-WebSocket-Accept: {accept}\r\n"));
+            }
+            resp.push_str("\r\n");
+            stream.write_all(resp.as_bytes()).unwrap();
+        }
+        keys
+    });
+
+    let uri: http::Uri = format!("ws://{addr}").parse().unwrap();
+    let builder = ClientBuilder::new().key(KEY).unwrap();
+    assert!(matches!(
+        builder.connect(uri.clone(), BytesCodec::check_fn),
+        Err(errors::WsError::HandShakeFailed(msg)) if msg.contains("sec-websocket-accept")
+    ));
+    builder.connect(uri, BytesCodec::check_fn).unwrap();
+    assert_eq!(server.join().unwrap(), [KEY, KEY]);
+    assert!(ClientBuilder::new().key("bad\nkey").is_err());
+}
+
+#[cfg(feature = "sync")]
+#[test]
+#[allow(clippy::result_large_err)]
+fn test_follow_redirects() {
+    use crate::codec::{default_handshake_handler, BytesCodec};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    // redirect every request except `/chat` to `location`
+    fn serve(listener: TcpListener, location: &'static str, conns: usize) {
+        for _ in 0..conns {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut req = vec![];
+            let mut buf = [0u8];
+            while !req.ends_with(b"\r\n\r\n") {
+                stream.read_exact(&mut buf).unwrap();
+                req.push(buf[0]);
+            }
+            if req.starts_with(b"GET /chat ") {
+                let stream = ReplayStream(req, stream);
+                let mut codec =
+                    ServerBuilder::accept(stream, default_handshake_handler, BytesCodec::factory)
+                        .unwrap();
+                codec.send(&b"hello"[..]).unwrap();
+                codec.flush().unwrap();
+            } else {
+                let resp = format!("HTTP/1.1 302 Found\r\nLocation: {location}\r\n\r\n");
+                stream.write_all(resp.as_bytes()).unwrap();
+            }
+        }
+    }
+
+    // request is read before handing stream to server builder
+    struct ReplayStream(Vec<u8>, TcpStream);
+
+    impl Read for ReplayStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() {
+                return self.1.read(buf);
+            }
+            let len = buf.len().min(self.0.len());
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0.drain(..len);
+            Ok(len)
+        }
+    }
+
+    impl Write for ReplayStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.1.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.1.flush()
+        }
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let uri: http::Uri = format!("ws://{}/old", listener.local_addr().unwrap())
+        .parse()
+        .unwrap();
+    let server = std::thread::spawn(move || serve(listener, "/chat", 3));
+    assert!(matches!(
+        ClientBuilder::new().connect(uri.clone(), BytesCodec::check_fn),
+        Err(errors::WsError::HandShakeFailed(msg)) if msg.contains("302")
+    ));
+    let mut client = ClientBuilder::new()
+        .follow_redirects(1)
+        .connect(uri, BytesCodec::check_fn)
+        .unwrap();
+    assert_eq!(client.receive().unwrap().data.as_ref(), b"hello");
+    server.join().unwrap();
+
+    // `/loop` redirects to itself
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let uri: http::Uri = format!("ws://{}/loop", listener.local_addr().unwrap())
+        .parse()
+        .unwrap();
+    let server = std::thread::spawn(move || serve(listener, "/loop", 1));
+    assert!(matches!(
+        ClientBuilder::new().follow_redirects(5).connect(uri, BytesCodec::check_fn),
+        Err(errors::WsError::HandShakeFailed(msg)) if msg.contains("loop")
+    ));
+    server.join().unwrap();
+
+    // redirect to another server changing scheme is rejected
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let uri: http::Uri = format!("ws://{}/", listener.local_addr().unwrap())
+        .parse()
+        .unwrap();
+    let server = std::thread::spawn(move || serve(listener, "https://example.com/chat", 1));
+    assert!(matches!(
+        ClientBuilder::new().follow_redirects(5).connect(uri, BytesCodec::check_fn),
+        Err(errors::WsError::HandShakeFailed(msg)) if msg.contains("scheme")
+    ));
+    server.join().unwrap();
+}
+
+#[cfg(all(test, feature = "sync_tls_rustls"))]
+#[test]
+#[allow(clippy::result_large_err)]
+fn test_auto_connect_redirect() {
+    use crate::codec::{default_handshake_handler, BytesCodec};
+    use rustls_connector::rustls;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert_path =
+        std::env::temp_dir().join(format!("ws-tool-redirect-{}.pem", std::process::id()));
+    std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+    let tls_config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![rustls::Certificate(cert.serialize_der().unwrap())],
+            rustls::PrivateKey(cert.serialize_private_key_der()),
+        )
+        .unwrap();
+
+    // wss server sending one message after handshake
+    let wss_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let wss_port = wss_listener.local_addr().unwrap().port();
+    let wss_server = std::thread::spawn(move || {
+        let (stream, _) = wss_listener.accept().unwrap();
+        let conn = rustls::ServerConnection::new(Arc::new(tls_config)).unwrap();
+        let stream = rustls::StreamOwned::new(conn, stream);
+        let mut codec =
+            ServerBuilder::accept(stream, default_handshake_handler, BytesCodec::factory).unwrap();
+        codec.send(&b"hello"[..]).unwrap();
+        codec.flush().unwrap();
+    });
+
+    // ws server redirecting to wss server
+    let ws_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let ws_port = ws_listener.local_addr().unwrap().port();
+    let ws_server = std::thread::spawn(move || {
+        for _ in 0..2 {
+            let (mut stream, _) = ws_listener.accept().unwrap();
+            let mut req = vec![];
+            let mut buf = [0u8];
+            while !req.ends_with(b"\r\n\r\n") {
+                stream.read_exact(&mut buf).unwrap();
+                req.push(buf[0]);
+            }
+            let resp = format!(
+                "HTTP/1.1 301 Moved Permanently\r\nLocation: wss://localhost:{wss_port}/chat\r\n\r\n"
+            );
+            stream.write_all(resp.as_bytes()).unwrap();
+        }
+    });
+
+    let uri: http::Uri = format!("ws://localhost:{ws_port}/").parse().unwrap();
+    let builder = ClientBuilder::new()
+        .cert(cert_path.clone())
+        .follow_redirects(1);
+    assert!(matches!(
+        builder.connect(uri.clone(), BytesCodec::check_fn),
+        Err(errors::WsError::HandShakeFailed(msg)) if msg.contains("scheme")
+    ));
+    let mut client = builder.auto_connect(uri, BytesCodec::check_fn).unwrap();
+    assert!(matches!(client.stream_mut(), stream::SyncStream::Rustls(_)));
+    assert_eq!(client.receive().unwrap().data.as_ref(), b"hello");
+    ws_server.join().unwrap();
+    wss_server.join().unwrap();
+    std::fs::remove_file(cert_path).ok();
+}
+
+#[cfg(feature = "sync")]
+#[test]
+#[allow(clippy::result_large_err)]
+fn test_server_get_websocket_key() {
+    use crate::codec::{default_handshake_handler, BytesCodec};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut handler_key = None;
+        let mut factory_key = None;
+        ServerBuilder::accept(
+            stream,
+            |req: http::Request<()>| {
+                handler_key = req.headers().get("sec-websocket-key").cloned();
+                default_handshake_handler(req)
+            },
+            |req: http::Request<()>, stream| {
+                factory_key = req.headers().get("sec-websocket-key").cloned();
+                BytesCodec::factory(req, stream)
+            },
+        )
+        .unwrap();
+        (handler_key.unwrap(), factory_key.unwrap())
+    });
+
+    let uri: http::Uri = format!("ws://{addr}").parse().unwrap();
+    let mut client_key = String::new();
+    ClientBuilder::new()
+        .connect(uri, |key, resp, stream| {
+            client_key = key.clone();
+            BytesCodec::check_fn(key, resp, stream)
+        })
+        .unwrap();
+    let (handler_key, factory_key) = server.join().unwrap();
+    assert_eq!(handler_key, client_key.as_str());
+    assert_eq!(factory_key, client_key.as_str());
+}
+
+#[cfg(feature = "sync")]
+#[test]
+#[allow(clippy::result_large_err)]
+fn test_socks5_proxy() {
+    use crate::{
+        codec::{default_handshake_handler, BytesCodec},
+        connector::Socks5Config,
+    };
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+    };
+
+    // socks5 proxy which accepts user/passwd auth and serves websocket on tunnel
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let proxy = std::thread::spawn(move || {
+        listener
+            .incoming()
+            .take(2)
+            .map(|stream| {
+                let mut stream = stream.unwrap();
+                let mut buf = [0u8; 4];
+                stream.read_exact(&mut buf).unwrap();
+                assert_eq!(buf, [5, 2, 0, 2]);
+                stream.write_all(&[5, 2]).unwrap();
+                let mut buf = [0u8; 13];
+                stream.read_exact(&mut buf).unwrap();
+                if &buf != b"\x01\x04user\x06passwd" {
+                    stream.write_all(&[1, 1]).unwrap();
+                    return None;
+                }
+                stream.write_all(&[1, 0]).unwrap();
+                let mut buf = [0u8; 5];
+                stream.read_exact(&mut buf).unwrap();
+                assert_eq!(buf[..4], [5, 1, 0, 3]);
+                let mut target = vec![0; buf[4] as usize + 2];
+                stream.read_exact(&mut target).unwrap();
+                stream.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0]).unwrap();
+                ServerBuilder::accept(stream, default_handshake_handler, BytesCodec::factory)
+                    .unwrap();
+                Some(target)
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let uri: http::Uri = "ws://example.com:8080".parse().unwrap();
+    let config = |passwd: &str| Socks5Config {
+        host: "127.0.0.1".into(),
+        port,
+        auth: Some(("user".into(), passwd.into())),
+    };
+    ClientBuilder::new()
+        .socks5_proxy(config("passwd"))
+        .connect(uri.clone(), BytesCodec::check_fn)
+        .unwrap();
+    let rejected = ClientBuilder::new()
+        .socks5_proxy(config("wrong!"))
+        .connect(uri, BytesCodec::check_fn);
+    assert!(matches!(rejected, Err(errors::WsError::ProxyError(_))));
+
+    let targets = proxy.join().unwrap();
+    let mut expect = b"example.com".to_vec();
+    expect.extend_from_slice(&8080u16.to_be_bytes());
+    assert_eq!(targets[0].as_ref().unwrap(), &expect);
+    assert!(targets[1].is_none());
+}
+
+#[cfg(all(test, feature = "async"))]
+#[tokio::test]
+async fn test_serve() {
+    use crate::codec::{default_handshake_handler, AsyncBytesCodec};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(ServerBuilder::serve(
+        listener,
+        default_handshake_handler,
+        AsyncBytesCodec::factory,
+        |mut codec: AsyncBytesCodec<tokio::net::TcpStream>| async move {
+            let msg = codec.receive().await.unwrap();
+            let (code, data) = (msg.code, msg.data.to_vec());
+            codec.send((code, &data[..])).await.unwrap();
+        },
+        async {
+            rx.await.ok();
+        },
+    ));
+
+    let uri: http::Uri = format!("ws://{addr}").parse().unwrap();
+    for _ in 0..2 {
+        let mut client = ClientBuilder::new()
+            .async_connect(uri.clone(), AsyncBytesCodec::check_fn)
+            .await
+            .unwrap();
+        client.send(&b"hello"[..]).await.unwrap();
+        let msg = client.receive().await.unwrap();
+        assert_eq!(msg.data.as_ref(), b"hello");
+    }
+    tx.send(()).unwrap();
+    server.await.unwrap().unwrap();
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn test_handshake_timeout() {
+    use crate::codec::BytesCodec;
+    use std::{net::TcpListener, time::Duration};
+
+    // server accepts tcp connection but never answers handshake
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let uri: http::Uri = format!("ws://{}", listener.local_addr().unwrap())
+        .parse()
+        .unwrap();
+    let result = ClientBuilder::new()
+        .handshake_timeout(Some(Duration::from_millis(50)))
+        .connect(uri, BytesCodec::check_fn);
+    match result {
+        Err(errors::WsError::HandShakeFailed(reason)) => assert_eq!(reason, "timeout"),
+        other => panic!("expect timeout, got {:?}", other.map(|_| ())),
+    }
+    drop(listener);
+}
+
+#[cfg(all(test, feature = "async"))]
+#[tokio::test]
+async fn test_async_handshake_timeout() {
+    use crate::codec::AsyncBytesCodec;
+    use std::time::Duration;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let uri: http::Uri = format!("ws://{}", listener.local_addr().unwrap())
+        .parse()
+        .unwrap();
+    let result = ClientBuilder::new()
+        .handshake_timeout(Some(Duration::from_millis(50)))
+        .async_connect(uri, AsyncBytesCodec::check_fn)
+        .await;
+    match result {
+        Err(errors::WsError::HandShakeFailed(reason)) => assert_eq!(reason, "timeout"),
+        other => panic!("expect timeout, got {:?}", other.map(|_| ())),
+    }
+    drop(listener);
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn test_handshake_only() {
+    use crate::{
+        codec::{FrameReadState, FrameRecv},
+        frame::{OpCode, OwnedFrame},
+        protocol::cal_accept_key,
+    };
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (mut server, _) = listener.accept().unwrap();
+        let mut req = vec![];
+        let mut buf = [0u8];
+        while !req.ends_with(b"\r\n\r\n") {
+            server.read_exact(&mut buf).unwrap();
+            req.push(buf[0]);
+        }
+        let req = String::from_utf8(req).unwrap();
+        let key = req
+            .lines()
+            .filter_map(|line| line.split_once(": "))
+            .find(|(name, _)| name.eq_ignore_ascii_case("sec-websocket-key"))
+            .unwrap()
+            .1;
+        // frame is sent in the same write as response, so it's over-read
+        let mut data = format!(
+            "HTTP/1.1 101 Switching Protocols\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            cal_accept_key(key.as_bytes())
+        )
+        .into_bytes();
+        let frame = OwnedFrame::binary_frame(None, b"hello");
+        data.extend_from_slice(frame.header().as_bytes());
+        data.extend_from_slice(frame.payload());
+        server.write_all(&data).unwrap();
+        server
+    });
+
+    let (resp, stream, remain) = ClientBuilder::new()
+        .handshake(
+            format!("ws://{addr}").parse().unwrap(),
+            TcpStream::connect(addr).unwrap(),
+        )
+        .unwrap();
+    assert_eq!(resp.status(), http::StatusCode::SWITCHING_PROTOCOLS);
+    assert!(!remain.is_empty());
+    let mut state = FrameReadState::default();
+    state.feed(&remain);
+    let mut recv = FrameRecv::new(stream, state);
+    let (header, data) = recv.receive_frame().unwrap();
+    assert_eq!((header.code, data), (OpCode::Binary, &b"hello"[..]));
+    let _server = server.join().unwrap();
+}
+
+#[cfg(all(test, feature = "async_codec"))]
+#[tokio::test]
+async fn test_async_handshake_only() {
+    use crate::{
+        codec::{AsyncFrameRecv, FrameReadState},
+        frame::{OpCode, OwnedFrame},
+        protocol::cal_accept_key,
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (client, mut server) = tokio::io::duplex(1024);
+    let server = tokio::spawn(async move {
+        let mut req = vec![];
+        let mut buf = [0u8];
+        while !req.ends_with(b"\r\n\r\n") {
+            server.read_exact(&mut buf).await.unwrap();
+            req.push(buf[0]);
+        }
+        let req = String::from_utf8(req).unwrap();
+        let key = req
+            .lines()
+            .filter_map(|line| line.split_once(": "))
+            .find(|(name, _)| name.eq_ignore_ascii_case("sec-websocket-key"))
+            .unwrap()
+            .1;
+        // frame is sent in the same write as response, so it's over-read
+        let mut data = format!(
+            "HTTP/1.1 101 Switching Protocols\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            cal_accept_key(key.as_bytes())
+        )
+        .into_bytes();
+        let frame = OwnedFrame::binary_frame(None, b"hello");
+        data.extend_from_slice(frame.header().as_bytes());
+        data.extend_from_slice(frame.payload());
+        server.write_all(&data).await.unwrap();
+        server
+    });
+
+    let (resp, stream, remain) = ClientBuilder::new()
+        .async_handshake("ws://localhost".parse().unwrap(), client)
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), http::StatusCode::SWITCHING_PROTOCOLS);
+    assert!(!remain.is_empty());
+    let mut state = FrameReadState::default();
+    state.feed(&remain);
+    let mut recv = AsyncFrameRecv::new(stream, state);
+    let (header, data) = recv.receive_frame().await.unwrap();
+    assert_eq!((header.code, data), (OpCode::Binary, &b"hello"[..]));
+    let _server = server.await.unwrap();
+}
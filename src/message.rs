@@ -19,10 +19,128 @@ pub struct Message<T> {
 }
 
 impl<T> Message<T> {
+    /// construct text message
+    ///
+    /// `Message<&str>` and `Message<String>` can be passed to `send` of string codecs,
+    /// `Message<&[u8]>` and `Message<Vec<u8>>` to `send` of bytes codecs
+    pub fn text(data: T) -> Self {
+        Self {
+            code: OpCode::Text,
+            data,
+            close_code: None,
+        }
+    }
+
+    /// construct binary message
+    pub fn binary(data: T) -> Self {
+        Self {
+            code: OpCode::Binary,
+            data,
+            close_code: None,
+        }
+    }
+
+    /// construct ping message, payload should not exceed 125 bytes
+    pub fn ping(data: T) -> Self {
+        Self {
+            code: OpCode::Ping,
+            data,
+            close_code: None,
+        }
+    }
+
+    /// construct pong message, payload should not exceed 125 bytes
+    pub fn pong(data: T) -> Self {
+        Self {
+            code: OpCode::Pong,
+            data,
+            close_code: None,
+        }
+    }
+
+    /// construct close message with status code and reason, reason should
+    /// not exceed 123 bytes
+    pub fn close(code: u16, reason: T) -> Self {
+        Self {
+            code: OpCode::Close,
+            data: reason,
+            close_code: Some(code),
+        }
+    }
+
     /// consume message and return payload
     pub fn into(self) -> T {
         self.data
     }
+
+    /// close event of a close message, `None` if it's not a close message
+    pub fn close_event(&self) -> Option<CloseEvent> {
+        if self.code != OpCode::Close {
+            return None;
+        }
+        Some(match self.close_code {
+            Some(code) => CloseEvent::Status(code),
+            None => CloseEvent::NoStatus,
+        })
+    }
+}
+
+/// how a connection is closed, used to report close to application
+///
+/// reserved code 1005 & 1006 only exist here, they are never sent on the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseEvent {
+    /// peer sent close frame with status code
+    Status(u16),
+    /// peer sent close frame without payload, reported as 1005
+    NoStatus,
+    /// connection lost without close frame, reported as 1006
+    Abnormal,
+}
+
+impl CloseEvent {
+    /// close event of received close frame payload
+    #[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+    pub(crate) fn from_payload(payload: &[u8]) -> Self {
+        if payload.len() >= 2 {
+            CloseEvent::Status(u16::from_be_bytes([payload[0], payload[1]]))
+        } else {
+            CloseEvent::NoStatus
+        }
+    }
+
+    /// status code for reporting
+    pub fn code(&self) -> u16 {
+        match self {
+            CloseEvent::Status(code) => *code,
+            CloseEvent::NoStatus => 1005,
+            CloseEvent::Abnormal => 1006,
+        }
+    }
+}
+
+/// data message or terminal close returned by `receive_message`
+///
+/// control frames are handled by codec, application only sees data and close
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event<T> {
+    /// text message
+    Text(T),
+    /// binary message
+    Binary(T),
+    /// peer closed connection, close frame has been echoed
+    Closed(CloseEvent),
+}
+
+impl<T> Event<T> {
+    /// map payload of data event, such as converting borrowed payload to owned
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Event<U> {
+        match self {
+            Event::Text(data) => Event::Text(f(data)),
+            Event::Binary(data) => Event::Binary(f(data)),
+            Event::Closed(close) => Event::Closed(close),
+        }
+    }
 }
 
 impl<'a> From<&'a str> for Message<Cow<'a, str>> {
@@ -55,6 +173,46 @@ impl<'a> From<Cow<'a, str>> for Message<Cow<'a, str>> {
     }
 }
 
+impl<'a> From<Message<&'a str>> for Message<Cow<'a, str>> {
+    fn from(msg: Message<&'a str>) -> Self {
+        Message {
+            code: msg.code,
+            data: Cow::Borrowed(msg.data),
+            close_code: msg.close_code,
+        }
+    }
+}
+
+impl<'a> From<Message<String>> for Message<Cow<'a, str>> {
+    fn from(msg: Message<String>) -> Self {
+        Message {
+            code: msg.code,
+            data: Cow::Owned(msg.data),
+            close_code: msg.close_code,
+        }
+    }
+}
+
+impl<'a> From<Message<&'a [u8]>> for Message<Cow<'a, [u8]>> {
+    fn from(msg: Message<&'a [u8]>) -> Self {
+        Message {
+            code: msg.code,
+            data: Cow::Borrowed(msg.data),
+            close_code: msg.close_code,
+        }
+    }
+}
+
+impl<'a> From<Message<Vec<u8>>> for Message<Cow<'a, [u8]>> {
+    fn from(msg: Message<Vec<u8>>) -> Self {
+        Message {
+            code: msg.code,
+            data: Cow::Owned(msg.data),
+            close_code: msg.close_code,
+        }
+    }
+}
+
 impl<'a> From<&'a [u8]> for Message<Cow<'a, [u8]>> {
     fn from(data: &'a [u8]) -> Self {
         Message {
@@ -114,3 +272,24 @@ impl<'a, T: Into<Cow<'a, [u8]>>> From<(OpCode, T)> for Message<Cow<'a, [u8]>> {
         }
     }
 }
+
+#[test]
+fn test_message_constructors() {
+    // mimic `send` of string and bytes codecs
+    fn text<'a>(msg: impl Into<Message<Cow<'a, str>>>) -> Message<Cow<'a, str>> {
+        msg.into()
+    }
+    fn bytes<'a>(msg: impl Into<Message<Cow<'a, [u8]>>>) -> Message<Cow<'a, [u8]>> {
+        msg.into()
+    }
+
+    let msg = text(Message::text("hello"));
+    assert_eq!((msg.code, msg.data.as_ref()), (OpCode::Text, "hello"));
+    let msg = bytes(Message::binary(vec![1, 2]));
+    assert_eq!((msg.code, msg.data.as_ref()), (OpCode::Binary, &[1, 2][..]));
+    assert_eq!(bytes(Message::ping(&b"ping"[..])).code, OpCode::Ping);
+    assert_eq!(bytes(Message::pong(&b"pong"[..])).code, OpCode::Pong);
+    let msg = text(Message::close(1000, "bye".to_string()));
+    assert_eq!((msg.code, msg.close_code), (OpCode::Close, Some(1000)));
+    assert_eq!(msg.close_event(), Some(CloseEvent::Status(1000)));
+}
@@ -1,17 +1,97 @@
 use http;
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use sha1::Digest;
 use std::collections::HashMap;
 use std::fmt::Debug;
+use std::time::Duration;
 
 use crate::errors::WsError;
 
 const GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
 
-/// helper struct for using close code
-pub struct StatusCode;
+/// close code of close frame
+///
+/// see [status codes](https://datatracker.ietf.org/doc/html/rfc6455#section-7.4)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StatusCode {
+    /// 1000, normal closure
+    Normal,
+    /// 1001, endpoint is going away
+    GoingAway,
+    /// 1002, protocol error
+    ProtocolError,
+    /// 1003, received data type can not be accepted
+    Terminate,
+    /// 1005, no status code is present, never sent on the wire
+    NoStatus,
+    /// 1006, connection closed abnormally, never sent on the wire
+    Abnormal,
+    /// 1007, message data is not consistent with message type
+    NonConsistent,
+    /// 1008, message violates policy
+    ViolatePolicy,
+    /// 1009, message is too big
+    TooBig,
+    /// 1010, expected extensions are not negotiated
+    RequireExt,
+    /// 1011, server encountered an unexpected condition
+    UnexpectedCondition,
+    /// 1015, tls handshake failed, never sent on the wire
+    PlatformFail,
+    /// unassigned code in 1000-2999, reserved for this protocol
+    Reserved(u16),
+    /// code in 3000-3999, registered by libraries, frameworks and applications
+    Library(u16),
+    /// code in 4000-4999, for private use
+    Private(u16),
+    /// code out of 1000-4999
+    Invalid(u16),
+}
 
 impl StatusCode {
+    /// classify raw close code
+    pub fn from_u16(code: u16) -> Self {
+        match code {
+            1000 => Self::Normal,
+            1001 => Self::GoingAway,
+            1002 => Self::ProtocolError,
+            1003 => Self::Terminate,
+            1005 => Self::NoStatus,
+            1006 => Self::Abnormal,
+            1007 => Self::NonConsistent,
+            1008 => Self::ViolatePolicy,
+            1009 => Self::TooBig,
+            1010 => Self::RequireExt,
+            1011 => Self::UnexpectedCondition,
+            1015 => Self::PlatformFail,
+            1004 | 1012..=1014 | 1016..=2999 => Self::Reserved(code),
+            3000..=3999 => Self::Library(code),
+            4000..=4999 => Self::Private(code),
+            _ => Self::Invalid(code),
+        }
+    }
+
+    /// raw close code, `None` if it's out of 1000-4999
+    pub fn code(&self) -> Option<u16> {
+        let code = match self {
+            Self::Normal => 1000,
+            Self::GoingAway => 1001,
+            Self::ProtocolError => 1002,
+            Self::Terminate => 1003,
+            Self::NoStatus => 1005,
+            Self::Abnormal => 1006,
+            Self::NonConsistent => 1007,
+            Self::ViolatePolicy => 1008,
+            Self::TooBig => 1009,
+            Self::RequireExt => 1010,
+            Self::UnexpectedCondition => 1011,
+            Self::PlatformFail => 1015,
+            Self::Reserved(code) | Self::Library(code) | Self::Private(code) => *code,
+            Self::Invalid(_) => return None,
+        };
+        Some(code)
+    }
+
     /// 1000 indicates a normal closure, meaning that the purpose for
     /// which the connection was established has been fulfilled.
     pub fn normal() -> u16 {
@@ -111,6 +191,30 @@ impl StatusCode {
     }
 }
 
+impl std::fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let desc = match self {
+            Self::Normal => "normal closure",
+            Self::GoingAway => "going away",
+            Self::ProtocolError => "protocol error",
+            Self::Terminate => "unsupported data",
+            Self::NoStatus => "no status received",
+            Self::Abnormal => "abnormal closure",
+            Self::NonConsistent => "invalid payload data",
+            Self::ViolatePolicy => "policy violation",
+            Self::TooBig => "message too big",
+            Self::RequireExt => "mandatory extension",
+            Self::UnexpectedCondition => "internal error",
+            Self::PlatformFail => "tls handshake failure",
+            Self::Reserved(code) => return write!(f, "{code} reserved"),
+            Self::Library(code) => return write!(f, "{code} library defined"),
+            Self::Private(code) => return write!(f, "{code} private use"),
+            Self::Invalid(code) => return write!(f, "{code} invalid"),
+        };
+        write!(f, "{} {}", self.code().unwrap_or_default(), desc)
+    }
+}
+
 /// websocket connection mode
 #[derive(Debug, PartialEq, Eq)]
 pub enum Mode {
@@ -130,21 +234,148 @@ impl Mode {
     }
 }
 
+/// client handshake request template, see `ClientBuilder::request_template`
+#[derive(Debug, Clone)]
+pub struct RequestTemplate {
+    /// request method, default `GET`
+    pub method: http::Method,
+    /// request path, use path of connecting uri if `None`
+    pub path: Option<String>,
+    /// http version, default `HTTP/1.1`
+    pub version: http::Version,
+    /// headers sent as is
+    pub headers: http::HeaderMap,
+}
+
+impl Default for RequestTemplate {
+    fn default() -> Self {
+        Self {
+            method: http::Method::GET,
+            path: None,
+            version: http::Version::HTTP_11,
+            headers: http::HeaderMap::new(),
+        }
+    }
+}
+
+impl TryFrom<http::request::Builder> for RequestTemplate {
+    type Error = WsError;
+
+    /// uri of builder is used as request path unless it's `/`(builder default)
+    fn try_from(builder: http::request::Builder) -> Result<Self, Self::Error> {
+        let (parts, _) = builder
+            .body(())
+            .map_err(|e| WsError::InvalidConfig(e.to_string()))?
+            .into_parts();
+        let path = parts
+            .uri
+            .path_and_query()
+            .map(|p| p.to_string())
+            .filter(|p| p != "/");
+        Ok(Self {
+            method: parts.method,
+            path,
+            version: parts.version,
+            headers: parts.headers,
+        })
+    }
+}
+
+/// time from sending handshake request to receiving server response
+///
+/// client side handshake response carries it in extensions, get it by
+/// `resp.extensions().get::<HandshakeDuration>()` in check function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandshakeDuration(pub Duration);
+
+/// reason phrase of server handshake response status line
+///
+/// client side handshake response carries it in extensions, get it by
+/// `resp.extensions().get::<ReasonPhrase>()` in check function
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReasonPhrase(pub String);
+
+/// subprotocol selected by server handshake handler
+///
+/// handler which selects a subprotocol inserts it into extensions of returned
+/// request, codecs constructed by `factory` record it as negotiated subprotocol
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubProtocol(pub String);
+
+/// bytes read after http header during handshake, such as frames sent by peer
+/// right after handshake
+///
+/// handshake reads stream in chunks, so bytes after http header may be read
+/// together with it, handshake request/response carries them in extensions
+/// if any. codecs constructed by built-in `factory`/`check_fn` consume them
+/// as buffered data, custom `factory`/`check_fn` passed to any connect or
+/// accept method of `ClientBuilder`/`ServerBuilder` should take them out of
+/// extensions and feed them to codec, e.g. `FrameReadState::feed`, otherwise
+/// these frames are lost
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemainBytes(pub BytesMut);
+
+/// raw http head of client handshake request as received on the wire,
+/// including request line and trailing `\r\n\r\n`
+///
+/// server side handshake request carries it in extensions, get it by
+/// `req.extensions().get::<RawRequestHead>()` in handshake handler or codec
+/// factory to inspect headers verbatim, such as exotic casing or duplicated
+/// headers inserted by proxy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawRequestHead(pub Bytes);
+
+/// size of each read when reading http header
+#[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+pub(crate) const HEADER_READ_SIZE: usize = 4096;
+
+/// max size of http header, including trailing `\r\n\r\n`
+pub const MAX_HEADER_SIZE: usize = 8 * 1024;
+
+/// split http header off `read_bytes` if `\r\n\r\n` is found, bytes after
+/// it are left in `read_bytes`
+///
+/// only bytes after `scanned - 3` are searched, terminator may cross previous read,
+/// fail if header exceeds `MAX_HEADER_SIZE`
+#[cfg_attr(not(any(feature = "sync", feature = "async_codec")), allow(dead_code))]
+fn split_http_header(
+    read_bytes: &mut BytesMut,
+    scanned: usize,
+) -> Result<Option<BytesMut>, WsError> {
+    let end = read_bytes.len().min(MAX_HEADER_SIZE);
+    let start = scanned.saturating_sub(3).min(end);
+    match read_bytes[start..end]
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+    {
+        Some(pos) => Ok(Some(read_bytes.split_to(start + pos + 4))),
+        None if read_bytes.len() >= MAX_HEADER_SIZE => Err(WsError::HandShakeFailed(format!(
+            "http header exceeds {MAX_HEADER_SIZE} bytes"
+        ))),
+        None => Ok(None),
+    }
+}
+
 #[cfg(feature = "sync")]
 mod blocking {
     use http;
     use std::{
         collections::HashMap,
         io::{Read, Write},
+        time::Instant,
     };
 
-    use bytes::{BufMut, BytesMut};
+    use bytes::BytesMut;
 
     use crate::errors::WsError;
 
-    use super::{handle_parse_handshake, perform_parse_req, prepare_handshake};
+    use super::{
+        handle_parse_handshake, perform_parse_req, prepare_handshake_with_template,
+        split_http_header, HandshakeDuration, RemainBytes, RequestTemplate, HEADER_READ_SIZE,
+    };
 
-    /// perform http upgrade
+    /// perform http upgrade, bytes read after response header are left in
+    /// `RemainBytes` of response extensions
     ///
     /// **NOTE**: low level api
     pub fn req_handshake<S: Read + Write>(
@@ -155,53 +386,103 @@ mod blocking {
         version: u8,
         extra_headers: HashMap<String, String>,
     ) -> Result<(String, http::Response<()>), WsError> {
-        let (key, req_str) = prepare_handshake(protocols, extensions, extra_headers, uri, version);
+        req_handshake_with_template(
+            stream,
+            &RequestTemplate::default(),
+            uri,
+            protocols,
+            extensions,
+            Some(version),
+            extra_headers,
+        )
+    }
+
+    /// perform http upgrade with request template, see `req_handshake`
+    ///
+    /// **NOTE**: low level api
+    pub fn req_handshake_with_template<S: Read + Write>(
+        stream: &mut S,
+        template: &RequestTemplate,
+        uri: &http::Uri,
+        protocols: &[String],
+        extensions: &[String],
+        version: Option<u8>,
+        extra_headers: HashMap<String, String>,
+    ) -> Result<(String, http::Response<()>), WsError> {
+        let (key, req_str) = prepare_handshake_with_template(
+            template,
+            protocols,
+            extensions,
+            extra_headers,
+            uri,
+            version,
+        );
+        let start = Instant::now();
         stream.write_all(req_str.as_bytes())?;
         stream.flush()?;
-        let mut read_bytes = BytesMut::with_capacity(1024);
-        let mut buf: [u8; 1] = [0; 1];
-        loop {
-            stream.read_exact(&mut buf)?;
-            read_bytes.put_u8(buf[0]);
-            let header_complete = read_bytes.ends_with(&[b'\r', b'\n', b'\r', b'\n']);
-            if header_complete {
-                break;
-            }
+        let (read_bytes, remain) = read_http_header(stream)?;
+        let (key, mut resp) = perform_parse_req(read_bytes, key)?;
+        resp.extensions_mut()
+            .insert(HandshakeDuration(start.elapsed()));
+        if !remain.is_empty() {
+            resp.extensions_mut().insert(RemainBytes(remain));
         }
-        perform_parse_req(read_bytes, key)
+        Ok((key, resp))
     }
 
-    /// handle protocol handshake
+    /// handle protocol handshake, bytes read after request header are left in
+    /// `RemainBytes` of request extensions
     pub fn handle_handshake<S: Read + Write>(stream: &mut S) -> Result<http::Request<()>, WsError> {
-        let mut req_bytes = BytesMut::with_capacity(1024);
-        let mut buf = [0u8];
+        let (req_bytes, remain) = read_http_header(stream)?;
+        let mut req = handle_parse_handshake(req_bytes)?;
+        if !remain.is_empty() {
+            req.extensions_mut().insert(RemainBytes(remain));
+        }
+        Ok(req)
+    }
+
+    /// read until end of http header, return header and bytes read after it
+    fn read_http_header<S: Read>(stream: &mut S) -> Result<(BytesMut, BytesMut), WsError> {
+        let mut read_bytes = BytesMut::with_capacity(HEADER_READ_SIZE);
+        let mut buf = [0u8; HEADER_READ_SIZE];
         loop {
-            stream.read_exact(&mut buf)?;
-            req_bytes.put_u8(buf[0]);
-            if req_bytes.ends_with(&[b'\r', b'\n', b'\r', b'\n']) {
-                break;
+            let n = match stream.read(&mut buf) {
+                Ok(0) => {
+                    return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+                }
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let scanned = read_bytes.len();
+            read_bytes.extend_from_slice(&buf[..n]);
+            if let Some(header) = split_http_header(&mut read_bytes, scanned)? {
+                return Ok((header, read_bytes));
             }
         }
-        handle_parse_handshake(req_bytes)
     }
 }
 
 #[cfg(feature = "sync")]
 pub use blocking::*;
 
-#[cfg(feature = "async")]
+#[cfg(feature = "async_codec")]
 mod non_blocking {
     use http;
-    use std::collections::HashMap;
+    use std::{collections::HashMap, time::Instant};
 
-    use bytes::{BufMut, BytesMut};
+    use bytes::BytesMut;
     use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
-    use crate::{errors::WsError, protocol::prepare_handshake};
+    use crate::errors::WsError;
 
-    use super::{handle_parse_handshake, perform_parse_req};
+    use super::{
+        handle_parse_handshake, perform_parse_req, prepare_handshake_with_template,
+        split_http_header, HandshakeDuration, RemainBytes, RequestTemplate, HEADER_READ_SIZE,
+    };
 
-    /// perform http upgrade
+    /// perform http upgrade, bytes read after response header are left in
+    /// `RemainBytes` of response extensions
     ///
     /// **NOTE**: low level api
     pub async fn async_req_handshake<S: AsyncRead + AsyncWrite + Unpin>(
@@ -212,39 +493,81 @@ mod non_blocking {
         version: u8,
         extra_headers: HashMap<String, String>,
     ) -> Result<(String, http::Response<()>), WsError> {
-        let (key, req_str) = prepare_handshake(protocols, extensions, extra_headers, uri, version);
+        async_req_handshake_with_template(
+            stream,
+            &RequestTemplate::default(),
+            uri,
+            protocols,
+            extensions,
+            Some(version),
+            extra_headers,
+        )
+        .await
+    }
+
+    /// perform http upgrade with request template, see `async_req_handshake`
+    ///
+    /// **NOTE**: low level api
+    pub async fn async_req_handshake_with_template<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut S,
+        template: &RequestTemplate,
+        uri: &http::Uri,
+        protocols: &[String],
+        extensions: &[String],
+        version: Option<u8>,
+        extra_headers: HashMap<String, String>,
+    ) -> Result<(String, http::Response<()>), WsError> {
+        let (key, req_str) = prepare_handshake_with_template(
+            template,
+            protocols,
+            extensions,
+            extra_headers,
+            uri,
+            version,
+        );
+        let start = Instant::now();
         stream.write_all(req_str.as_bytes()).await?;
-        let mut read_bytes = BytesMut::with_capacity(1024);
-        let mut buf = [0u8];
-        loop {
-            stream.read_exact(&mut buf).await?;
-            read_bytes.put_u8(buf[0]);
-            let header_complete = read_bytes.ends_with(&[b'\r', b'\n', b'\r', b'\n']);
-            if header_complete {
-                break;
-            }
+        let (read_bytes, remain) = read_http_header(stream).await?;
+        let (key, mut resp) = perform_parse_req(read_bytes, key)?;
+        resp.extensions_mut()
+            .insert(HandshakeDuration(start.elapsed()));
+        if !remain.is_empty() {
+            resp.extensions_mut().insert(RemainBytes(remain));
         }
-        perform_parse_req(read_bytes, key)
+        Ok((key, resp))
     }
 
-    /// async version of handling protocol handshake
+    /// async version of handling protocol handshake, bytes read after request
+    /// header are left in `RemainBytes` of request extensions
     pub async fn async_handle_handshake<S: AsyncRead + AsyncWrite + Unpin>(
         stream: &mut S,
     ) -> Result<http::Request<()>, WsError> {
-        let mut req_bytes = BytesMut::with_capacity(1024);
-        let mut buf = [0u8];
+        let (req_bytes, remain) = read_http_header(stream).await?;
+        let mut req = handle_parse_handshake(req_bytes)?;
+        if !remain.is_empty() {
+            req.extensions_mut().insert(RemainBytes(remain));
+        }
+        Ok(req)
+    }
+
+    /// read until end of http header, return header and bytes read after it
+    async fn read_http_header<S: AsyncRead + Unpin>(
+        stream: &mut S,
+    ) -> Result<(BytesMut, BytesMut), WsError> {
+        let mut read_bytes = BytesMut::with_capacity(HEADER_READ_SIZE);
         loop {
-            stream.read_exact(&mut buf).await?;
-            req_bytes.put_u8(buf[0]);
-            if req_bytes.ends_with(&[b'\r', b'\n', b'\r', b'\n']) {
-                break;
+            let scanned = read_bytes.len();
+            if stream.read_buf(&mut read_bytes).await? == 0 {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+            if let Some(header) = split_http_header(&mut read_bytes, scanned)? {
+                return Ok((header, read_bytes));
             }
         }
-        handle_parse_handshake(req_bytes)
     }
 }
 
-#[cfg(feature = "async")]
+#[cfg(feature = "async_codec")]
 pub use non_blocking::*;
 
 /// generate random key
@@ -286,8 +609,35 @@ pub fn standard_handshake_resp_check(key: &[u8], resp: &http::Response<()>) -> R
     Ok(())
 }
 
+/// check `sec-websocket-version` header of handshake request is 13,
+/// the only version defined by rfc6455
+pub fn is_supported_version(req: &http::Request<()>) -> bool {
+    req.headers()
+        .get("sec-websocket-version")
+        .and_then(|val| val.to_str().ok())
+        .map(|val| val.trim() == "13")
+        .unwrap_or_default()
+}
+
 /// perform rfc standard check
+///
+/// 1. check request method is `GET`
+/// 2. check `sec-websocket-version` header is 13
+/// 3. check `upgrade` & `sec-websocket-key` header
 pub fn standard_handshake_req_check(req: &http::Request<()>) -> Result<(), WsError> {
+    if req.method() != http::Method::GET {
+        return Err(WsError::HandShakeFailed(format!(
+            "expect `GET` method, got {}",
+            req.method()
+        )));
+    }
+    if !is_supported_version(req) {
+        return Err(WsError::HandShakeFailed(format!(
+            "expect `sec-websocket-version` 13, got {:?}",
+            req.headers().get("sec-websocket-version")
+        )));
+    }
+
     if let Some(val) = req.headers().get("upgrade") {
         if val != "websocket" {
             return Err(WsError::HandShakeFailed(format!(
@@ -314,7 +664,7 @@ pub fn standard_handshake_req_check(req: &http::Request<()>) -> Result<(), WsErr
     Ok(())
 }
 
-/// build protocol http reqeust
+/// build protocol http request
 ///
 /// return (key, request_str)
 pub fn prepare_handshake(
@@ -324,35 +674,82 @@ pub fn prepare_handshake(
     uri: &http::Uri,
     version: u8,
 ) -> (String, String) {
-    let key = gen_key();
-    let mut headers = vec![
+    prepare_handshake_with_template(
+        &RequestTemplate::default(),
+        protocols,
+        extensions,
+        extra_headers,
+        uri,
+        Some(version),
+    )
+}
+
+/// build protocol http request on top of a request template
+///
+/// headers in template take precedence, websocket headers(host, upgrade,
+/// key, version, protocol, extensions) and `extra_headers` are only
+/// added if template does not carry them, version header is omitted if
+/// `version` is `None`
+///
+/// return (key, request_str)
+pub fn prepare_handshake_with_template(
+    template: &RequestTemplate,
+    protocols: &[String],
+    extensions: &[String],
+    extra_headers: HashMap<String, String>,
+    uri: &http::Uri,
+    version: Option<u8>,
+) -> (String, String) {
+    let key = template
+        .headers
+        .get(http::header::SEC_WEBSOCKET_KEY)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+        .unwrap_or_else(gen_key);
+    let mut headers: Vec<String> = template
+        .headers
+        .iter()
+        .map(|(k, v)| format!("{k}: {}", String::from_utf8_lossy(v.as_bytes())))
+        .collect();
+    let mut push = |name: &str, value: String| {
+        if !template.headers.contains_key(name) {
+            headers.push(format!("{name}: {value}"));
+        }
+    };
+    push(
+        "Host",
         format!(
-            "Host: {}{}",
+            "{}{}",
             uri.host().unwrap_or_default(),
             uri.port_u16().map(|p| format!(":{p}")).unwrap_or_default()
         ),
-        "Upgrade: websocket".to_string(),
-        "Connection: Upgrade".to_string(),
-        format!("Sec-Websocket-Key: {key}"),
-        format!("Sec-WebSocket-Version: {version}"),
-    ];
+    );
+    push("Upgrade", "websocket".to_string());
+    push("Connection", "Upgrade".to_string());
+    push("Sec-Websocket-Key", key.clone());
+    if let Some(version) = version {
+        push("Sec-WebSocket-Version", version.to_string());
+    }
     for pro in protocols {
-        headers.push(format!("Sec-WebSocket-Protocol: {pro}"))
+        push("Sec-WebSocket-Protocol", pro.to_string());
     }
     for ext in extensions {
-        headers.push(format!("Sec-WebSocket-Extensions: {ext}"))
+        push("Sec-WebSocket-Extensions", ext.to_string());
     }
     for (k, v) in extra_headers.iter() {
-        headers.push(format!("{k}: {v}"));
+        push(k, v.to_string());
     }
-    let req_str = format!(
-        "{method} {path} {version:?}\r\n{headers}\r\n\r\n",
-        method = http::Method::GET,
-        path = uri
+    let path = match &template.path {
+        Some(path) => path.clone(),
+        None => uri
             .path_and_query()
             .map(|full_path| full_path.to_string())
             .unwrap_or_default(),
-        version = http::Version::HTTP_11,
+    };
+    let req_str = format!(
+        "{method} {path} {version:?}\r\n{headers}\r\n\r\n",
+        method = template.method,
+        version = template.version,
         headers = headers.join("\r\n")
     );
     tracing::debug!("handshake request\n{}", req_str);
@@ -382,8 +779,14 @@ pub fn perform_parse_req(
     for header in resp.headers.iter() {
         resp_builder = resp_builder.header(header.name, header.value);
     }
+    if let Some(reason) = resp.reason {
+        resp_builder = resp_builder.extension(ReasonPhrase(reason.to_string()));
+    }
+    let resp = resp_builder
+        .body(())
+        .map_err(|e| WsError::HandShakeFailed(format!("invalid response {e}")))?;
     tracing::debug!("protocol handshake complete");
-    Ok((key, resp_builder.body(()).unwrap()))
+    Ok((key, resp))
 }
 
 /// parse http request, used by server building
@@ -407,7 +810,245 @@ pub fn handle_parse_handshake(req_bytes: BytesMut) -> Result<http::Request<()>,
     for header in req.headers.iter() {
         req_builder = req_builder.header(header.name, header.value);
     }
-    req_builder
+    let mut req = req_builder
         .body(())
-        .map_err(|e| WsError::HandShakeFailed(e.to_string()))
+        .map_err(|e| WsError::HandShakeFailed(e.to_string()))?;
+    req.extensions_mut()
+        .insert(RawRequestHead(req_bytes.freeze()));
+    Ok(req)
+}
+
+#[test]
+fn test_raw_request_head() {
+    let head = b"GET /chat HTTP/1.1\r\n\
+        Host: localhost\r\n\
+        X-Custom-AUTH: Token\r\n\
+        x-custom-auth: again\r\n\r\n";
+    let req = handle_parse_handshake(BytesMut::from(&head[..])).unwrap();
+    assert_eq!(req.headers().get_all("x-custom-auth").iter().count(), 2);
+    let raw = req.extensions().get::<RawRequestHead>().unwrap();
+    assert_eq!(raw.0.as_ref(), &head[..]);
+}
+
+#[cfg(feature = "sync")]
+#[test]
+#[allow(clippy::result_large_err)]
+fn test_handshake_duration() {
+    use crate::codec::{default_handshake_handler, BytesCodec};
+    use crate::{ClientBuilder, ServerBuilder};
+    use std::net::TcpListener;
+
+    let delay = Duration::from_millis(100);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        ServerBuilder::accept(
+            stream,
+            |req: http::Request<()>| {
+                std::thread::sleep(delay);
+                default_handshake_handler(req)
+            },
+            BytesCodec::factory,
+        )
+        .unwrap()
+    });
+
+    let uri: http::Uri = format!("ws://{addr}").parse().unwrap();
+    let mut duration = None;
+    ClientBuilder::new()
+        .connect(uri, |key, resp, stream| {
+            duration = resp.extensions().get::<HandshakeDuration>().copied();
+            BytesCodec::check_fn(key, resp, stream)
+        })
+        .unwrap();
+    server.join().unwrap();
+    assert!(duration.unwrap().0 >= delay);
+}
+
+#[test]
+fn test_parse_response_headers() {
+    let raw = "HTTP/1.1 101 Switching Protocols For Test\r\n\
+               Upgrade: websocket\r\n\
+               Connection: Upgrade\r\n\
+               Set-Cookie: a=1\r\n\
+               Set-Cookie: b=2\r\n\r\n";
+    let (_, resp) = perform_parse_req(BytesMut::from(raw), String::new()).unwrap();
+    assert_eq!(resp.status(), http::StatusCode::SWITCHING_PROTOCOLS);
+    assert_eq!(resp.version(), http::Version::HTTP_11);
+    let cookies: Vec<_> = resp.headers().get_all("set-cookie").iter().collect();
+    assert_eq!(cookies, ["a=1", "b=2"]);
+    assert_eq!(
+        resp.extensions().get::<ReasonPhrase>().unwrap().0,
+        "Switching Protocols For Test"
+    );
+
+    // status code out of http range
+    let raw = "HTTP/1.1 099 Bad\r\n\r\n";
+    assert!(matches!(
+        perform_parse_req(BytesMut::from(raw), String::new()),
+        Err(WsError::HandShakeFailed(_))
+    ));
+}
+
+#[cfg(all(test, feature = "async_codec"))]
+#[tokio::test]
+async fn test_handshake_remain_bytes() {
+    use crate::codec::{default_handshake_handler, AsyncBytesCodec};
+    use crate::frame::OwnedFrame;
+    use crate::{ClientBuilder, ServerBuilder};
+    use tokio::io::AsyncWriteExt;
+
+    // client side, response and first frame arrive in one write
+    let (client, mut server) = tokio::io::duplex(4096);
+    let server = tokio::spawn(async move {
+        let req = async_handle_handshake(&mut server).await.unwrap();
+        let key = req.headers().get("sec-websocket-key").unwrap().as_bytes();
+        let mut data = format!(
+            "HTTP/1.1 101 Switching Protocols\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            cal_accept_key(key)
+        )
+        .into_bytes();
+        data.extend_from_slice(&[0x82, 0x02, b'h', b'i']);
+        server.write_all(&data).await.unwrap();
+        server
+    });
+    let mut client = ClientBuilder::new()
+        .async_with_stream(
+            "ws://localhost".parse().unwrap(),
+            client,
+            AsyncBytesCodec::check_fn,
+        )
+        .await
+        .unwrap();
+    assert_eq!(client.receive().await.unwrap().data.as_ref(), b"hi");
+    let _server = server.await.unwrap();
+
+    // server side, request and first frame arrive in one write
+    let (mut client, server) = tokio::io::duplex(4096);
+    let mut data = format!(
+        "GET / HTTP/1.1\r\n\
+         Host: localhost\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: {}\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n",
+        gen_key()
+    )
+    .into_bytes();
+    let frame = OwnedFrame::binary_frame([1, 2, 3, 4], b"hello");
+    data.extend_from_slice(frame.header().as_bytes());
+    data.extend_from_slice(frame.payload());
+    client.write_all(&data).await.unwrap();
+    let mut server =
+        ServerBuilder::async_accept(server, default_handshake_handler, AsyncBytesCodec::factory)
+            .await
+            .unwrap();
+    assert_eq!(server.receive().await.unwrap().data.as_ref(), b"hello");
+}
+
+#[cfg(all(test, feature = "async_codec"))]
+#[tokio::test]
+async fn test_async_max_header_size() {
+    use tokio::io::AsyncWriteExt;
+
+    let (mut client, mut server) = tokio::io::duplex(MAX_HEADER_SIZE * 2);
+    let mut data = b"GET / HTTP/1.1\r\n".to_vec();
+    while data.len() <= MAX_HEADER_SIZE {
+        data.extend_from_slice(b"X-Padding: aaaaaaaaaaaaaaaa\r\n");
+    }
+    data.extend_from_slice(b"\r\n");
+    client.write_all(&data).await.unwrap();
+    assert!(matches!(
+        async_handle_handshake(&mut server).await,
+        Err(WsError::HandShakeFailed(_))
+    ));
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn test_sync_handshake_remain_bytes() {
+    use crate::codec::BytesCodec;
+    use crate::frame::OwnedFrame;
+    use crate::ServerBuilder;
+
+    /// yield at most 3 bytes each read, so terminator crosses reads
+    struct Chunked(std::io::Cursor<Vec<u8>>);
+
+    impl std::io::Read for Chunked {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(3);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    impl std::io::Write for Chunked {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut data = format!(
+        "GET / HTTP/1.1\r\n\
+         Host: localhost\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: {}\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n",
+        gen_key()
+    )
+    .into_bytes();
+    let frame = OwnedFrame::binary_frame([1, 2, 3, 4], b"hello");
+    data.extend_from_slice(frame.header().as_bytes());
+    data.extend_from_slice(frame.payload());
+    let stream = Chunked(std::io::Cursor::new(data));
+    let mut server = ServerBuilder::accept(
+        stream,
+        crate::codec::default_handshake_handler,
+        BytesCodec::factory,
+    )
+    .unwrap();
+    assert_eq!(server.receive().unwrap().data.as_ref(), b"hello");
+}
+
+#[test]
+fn test_max_header_size() {
+    let mut read_bytes = BytesMut::from(&b"GET / HTTP/1.1\r\n\r\nrest"[..]);
+    let header = split_http_header(&mut read_bytes, 0).unwrap().unwrap();
+    assert_eq!(&header[..], b"GET / HTTP/1.1\r\n\r\n");
+    assert_eq!(&read_bytes[..], b"rest");
+
+    let mut read_bytes = BytesMut::from(&b"GET / HTTP/1.1\r\n"[..]);
+    assert!(split_http_header(&mut read_bytes, 0).unwrap().is_none());
+
+    let mut data = vec![b'a'; MAX_HEADER_SIZE - 2];
+    data.extend_from_slice(b"\r\n\r\n");
+    let mut read_bytes = BytesMut::from(&data[..]);
+    assert!(matches!(
+        split_http_header(&mut read_bytes, MAX_HEADER_SIZE - 4),
+        Err(WsError::HandShakeFailed(_))
+    ));
+}
+
+#[test]
+fn test_status_code() {
+    assert_eq!(StatusCode::from_u16(1000), StatusCode::Normal);
+    assert_eq!(StatusCode::from_u16(1004), StatusCode::Reserved(1004));
+    assert_eq!(StatusCode::from_u16(1015), StatusCode::PlatformFail);
+    assert_eq!(StatusCode::from_u16(3001), StatusCode::Library(3001));
+    assert_eq!(StatusCode::from_u16(4999), StatusCode::Private(4999));
+    assert_eq!(StatusCode::from_u16(999), StatusCode::Invalid(999));
+    assert_eq!(StatusCode::from_u16(5000).code(), None);
+    for code in 1000..5000 {
+        assert_eq!(StatusCode::from_u16(code).code(), Some(code));
+    }
+    assert_eq!(StatusCode::Normal.to_string(), "1000 normal closure");
+    assert_eq!(StatusCode::Private(4000).to_string(), "4000 private use");
 }
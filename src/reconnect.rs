@@ -0,0 +1,291 @@
+use std::{borrow::Cow, future::Future, pin::Pin, time::Duration};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+
+use crate::{
+    codec::{AsyncBytesCodec, AsyncStringCodec},
+    errors::WsError,
+    ClientBuilder, Message,
+};
+
+/// exponential backoff of reconnecting
+///
+/// delay of nth retry is `min(base * 2^n, max)` plus random jitter in `[0, jitter)`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backoff {
+    /// delay before first retry, default 100ms
+    pub base: Duration,
+    /// max delay between retries, default 30s
+    pub max: Duration,
+    /// max random delay added to every retry, default 100ms
+    pub jitter: Duration,
+    /// give up after this many failed retries, default `None`, retry forever
+    pub max_retries: Option<usize>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            base: Duration::from_millis(100),
+            max: Duration::from_secs(30),
+            jitter: Duration::from_millis(100),
+            max_retries: None,
+        }
+    }
+}
+
+impl Backoff {
+    /// delay before nth retry, start from 0
+    pub fn delay(&self, retry: usize) -> Duration {
+        let exp = self
+            .base
+            .checked_mul(1u32.checked_shl(retry as u32).unwrap_or(u32::MAX))
+            .unwrap_or(self.max)
+            .min(self.max);
+        let jitter = match self.jitter.as_millis() as u64 {
+            0 => Duration::ZERO,
+            ms => Duration::from_millis(rand::random::<u64>() % ms),
+        };
+        exp + jitter
+    }
+}
+
+/// future returned by reconnect callback
+pub type ReconnectFuture<'a> = Pin<Box<dyn Future<Output = Result<(), WsError>> + Send + 'a>>;
+
+/// future returned by connect callback, resolves to codec of new connection
+pub type ConnectFuture<'a, C> = Pin<Box<dyn Future<Output = Result<C, WsError>> + Send + 'a>>;
+
+type OnReconnect<C> = Box<dyn for<'a> FnMut(&'a mut C) -> ReconnectFuture<'a> + Send>;
+
+type Connect<C> =
+    Box<dyn for<'a> FnMut(&'a ClientBuilder, http::Uri) -> ConnectFuture<'a, C> + Send>;
+
+/// client which re-runs handshake with backoff when connection is lost
+/// during `receive`, and resumes receiving on the new connection
+///
+/// messages buffered by old connection are dropped
+pub struct ReconnectingClient<C> {
+    builder: ClientBuilder,
+    uri: http::Uri,
+    connect: Connect<C>,
+    backoff: Backoff,
+    codec: C,
+    reconnects: usize,
+    on_reconnect: Option<OnReconnect<C>>,
+}
+
+impl<C> ReconnectingClient<C> {
+    /// perform first handshake over plain tcp by `ClientBuilder::async_connect`,
+    /// it's not retried if failed
+    pub async fn connect(
+        builder: ClientBuilder,
+        uri: http::Uri,
+        check_fn: fn(String, http::Response<()>, TcpStream) -> Result<C, WsError>,
+    ) -> Result<Self, WsError>
+    where
+        C: Send + 'static,
+    {
+        Self::connect_with(builder, uri, move |builder, uri| {
+            Box::pin(builder.async_connect(uri, check_fn))
+        })
+        .await
+    }
+
+    /// perform first handshake by `connect`, which is called again on every
+    /// reconnect, it's not retried if failed
+    ///
+    /// use it for tls or proxy connection, such as
+    ///
+    /// ```ignore
+    /// ReconnectingClient::connect_with(builder, uri, |builder, uri| {
+    ///     Box::pin(builder.async_auto_connect(uri, AsyncStringCodec::check_fn))
+    /// })
+    /// ```
+    pub async fn connect_with<F>(
+        builder: ClientBuilder,
+        uri: http::Uri,
+        mut connect: F,
+    ) -> Result<Self, WsError>
+    where
+        F: for<'a> FnMut(&'a ClientBuilder, http::Uri) -> ConnectFuture<'a, C> + Send + 'static,
+    {
+        let codec = connect(&builder, uri.clone()).await?;
+        Ok(Self {
+            builder,
+            uri,
+            connect: Box::new(connect),
+            backoff: Backoff::default(),
+            codec,
+            reconnects: 0,
+            on_reconnect: None,
+        })
+    }
+
+    /// set backoff of reconnecting
+    pub fn backoff(self, backoff: Backoff) -> Self {
+        Self { backoff, ..self }
+    }
+
+    /// set callback called with new codec after every reconnect, such as
+    /// re-subscribing channels, error returned by it is returned by `receive`
+    ///
+    /// ```ignore
+    /// client.on_reconnect(|codec| Box::pin(async move { codec.send("sub").await }))
+    /// ```
+    pub fn on_reconnect<F>(mut self, f: F) -> Self
+    where
+        F: for<'a> FnMut(&'a mut C) -> ReconnectFuture<'a> + Send + 'static,
+    {
+        self.on_reconnect = Some(Box::new(f));
+        self
+    }
+
+    /// get mutable codec of current connection
+    pub fn codec_mut(&mut self) -> &mut C {
+        &mut self.codec
+    }
+
+    /// number of successful reconnects
+    pub fn reconnects(&self) -> usize {
+        self.reconnects
+    }
+
+    /// re-run handshake with backoff until success or `max_retries` is reached
+    pub async fn reconnect(&mut self) -> Result<(), WsError> {
+        let mut retry = 0;
+        loop {
+            tokio::time::sleep(self.backoff.delay(retry)).await;
+            match (self.connect)(&self.builder, self.uri.clone()).await {
+                Ok(codec) => {
+                    self.codec = codec;
+                    self.reconnects += 1;
+                    tracing::debug!("reconnected to {} after {} retries", self.uri, retry);
+                    if let Some(f) = self.on_reconnect.as_mut() {
+                        f(&mut self.codec).await?;
+                    }
+                    return Ok(());
+                }
+                Err(e) => {
+                    retry += 1;
+                    tracing::warn!("reconnect to {} failed {}", self.uri, e);
+                    if self.backoff.max_retries.is_some_and(|max| retry >= max) {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+macro_rules! impl_reconnect {
+    ($codec:ident, $data:ty) => {
+        impl<S: AsyncRead + AsyncWrite + Unpin> ReconnectingClient<$codec<S>> {
+            /// receive a message, reconnect and retry if connection is lost
+            ///
+            /// **NOTE** payload is copied, returned message does not borrow codec
+            pub async fn receive(&mut self) -> Result<Message<Cow<'static, $data>>, WsError> {
+                loop {
+                    match self.codec.receive().await {
+                        Ok(msg) => {
+                            return Ok(Message {
+                                code: msg.code,
+                                data: Cow::Owned(msg.data.into_owned()),
+                                close_code: msg.close_code,
+                            })
+                        }
+                        Err(WsError::IOError(e) | WsError::ConnectionLost(e)) => {
+                            tracing::warn!("connection to {} lost {}", self.uri, e);
+                            self.reconnect().await?;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+
+            /// send a message on current connection, it's not retried if failed,
+            /// next `receive` reconnects
+            pub async fn send<'a, T: Into<Message<Cow<'a, $data>>>>(
+                &mut self,
+                msg: T,
+            ) -> Result<(), WsError> {
+                self.codec.send(msg).await
+            }
+        }
+    };
+}
+
+impl_reconnect!(AsyncBytesCodec, [u8]);
+impl_reconnect!(AsyncStringCodec, str);
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_reconnect() {
+    use crate::codec::default_handshake_handler;
+    use crate::ServerBuilder;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        for idx in 0..2 {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut server = ServerBuilder::async_accept(
+                stream,
+                default_handshake_handler,
+                AsyncStringCodec::factory,
+            )
+            .await
+            .unwrap();
+            if idx == 1 {
+                let msg = server.receive().await.unwrap();
+                assert_eq!(msg.data, "sub");
+            }
+            server.send(format!("hello {idx}").as_str()).await.unwrap();
+            // drop connection without closing handshake
+        }
+    });
+
+    let uri: http::Uri = format!("ws://{addr}").parse().unwrap();
+    let called = Arc::new(AtomicUsize::new(0));
+    let counter = called.clone();
+    // any stream works, such as `AsyncStream` which may be wrapped by tls
+    let mut client = ReconnectingClient::connect_with(ClientBuilder::new(), uri, |builder, uri| {
+        Box::pin(builder.async_auto_connect(uri, AsyncStringCodec::check_fn))
+    })
+    .await
+    .unwrap()
+    .backoff(Backoff {
+        base: Duration::from_millis(1),
+        jitter: Duration::ZERO,
+        ..Default::default()
+    })
+    .on_reconnect(move |codec| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Box::pin(async move { codec.send("sub").await })
+    });
+    assert_eq!(client.receive().await.unwrap().data, "hello 0");
+    assert_eq!(client.receive().await.unwrap().data, "hello 1");
+    assert_eq!(client.reconnects(), 1);
+    assert_eq!(called.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_backoff_delay() {
+    let backoff = Backoff {
+        base: Duration::from_millis(100),
+        max: Duration::from_secs(1),
+        jitter: Duration::ZERO,
+        max_retries: None,
+    };
+    assert_eq!(backoff.delay(0), Duration::from_millis(100));
+    assert_eq!(backoff.delay(3), Duration::from_millis(800));
+    assert_eq!(backoff.delay(4), Duration::from_secs(1));
+    assert_eq!(backoff.delay(100), Duration::from_secs(1));
+}
@@ -363,15 +363,18 @@ mod blocking {
 #[cfg(feature = "sync")]
 pub use blocking::*;
 
-#[cfg(feature = "async")]
+#[cfg(feature = "async_codec")]
 mod non_blocking {
+    #[cfg(any(feature = "async", feature = "futures-io"))]
     use std::pin::Pin;
 
-    use tokio::{
-        io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf},
-        net::TcpStream,
-    };
+    use tokio::io::{AsyncRead, AsyncWrite};
+    #[cfg(any(feature = "async", feature = "futures-io"))]
+    use tokio::io::{ReadHalf, WriteHalf};
+    #[cfg(feature = "async")]
+    use tokio::net::TcpStream;
 
+    #[cfg(any(feature = "async", feature = "futures-io"))]
     use crate::codec::Split;
 
     #[allow(missing_docs)]
@@ -379,19 +382,45 @@ mod non_blocking {
 
     impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRW for S {}
 
+    #[cfg(feature = "async")]
     /// a wrapper of most common use raw/ssl tcp based stream
-    pub enum AsyncStream {
+    ///
+    /// underlying stream is `TcpStream` by default, other streams, such as a
+    /// socket already tls wrapped by a custom proxy, can be wrapped by
+    /// `from_plain`, `from_rustls` or `from_native_tls`
+    pub enum AsyncStream<S = TcpStream> {
         /// raw tcp stream
-        Raw(TcpStream),
+        Raw(S),
         /// rustls wrapped stream
         #[cfg(feature = "async_tls_rustls")]
-        Rustls(tokio_rustls::TlsStream<TcpStream>),
+        Rustls(tokio_rustls::TlsStream<S>),
         /// native tls wrapped stream
         #[cfg(feature = "async_tls_native")]
-        NativeTls(tokio_native_tls::TlsStream<TcpStream>),
+        NativeTls(tokio_native_tls::TlsStream<S>),
     }
 
-    impl Split for AsyncStream {
+    #[cfg(feature = "async")]
+    impl<S> AsyncStream<S> {
+        /// wrap a stream without tls
+        pub fn from_plain(stream: S) -> Self {
+            Self::Raw(stream)
+        }
+
+        /// wrap an established rustls client or server stream
+        #[cfg(feature = "async_tls_rustls")]
+        pub fn from_rustls<T: Into<tokio_rustls::TlsStream<S>>>(stream: T) -> Self {
+            Self::Rustls(stream.into())
+        }
+
+        /// wrap an established native tls stream
+        #[cfg(feature = "async_tls_native")]
+        pub fn from_native_tls(stream: tokio_native_tls::TlsStream<S>) -> Self {
+            Self::NativeTls(stream)
+        }
+    }
+
+    #[cfg(feature = "async")]
+    impl<S: AsyncRead + AsyncWrite + Unpin> Split for AsyncStream<S> {
         type R = ReadHalf<Self>;
 
         type W = WriteHalf<Self>;
@@ -401,7 +430,8 @@ mod non_blocking {
         }
     }
 
-    impl AsyncRead for AsyncStream {
+    #[cfg(feature = "async")]
+    impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for AsyncStream<S> {
         fn poll_read(
             self: std::pin::Pin<&mut Self>,
             cx: &mut std::task::Context<'_>,
@@ -417,7 +447,8 @@ mod non_blocking {
         }
     }
 
-    impl AsyncWrite for AsyncStream {
+    #[cfg(feature = "async")]
+    impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for AsyncStream<S> {
         fn poll_write(
             self: Pin<&mut Self>,
             cx: &mut std::task::Context<'_>,
@@ -458,7 +489,216 @@ mod non_blocking {
             }
         }
     }
+
+    /// adapter of a `futures-io` stream, such as a tcp stream of async-std/smol,
+    /// so it can be used by async codecs and async handshake
+    ///
+    /// only io traits of tokio are used on it, no tokio runtime is required unless
+    /// timer based features, such as `handshake_timeout`, are enabled
+    #[cfg(feature = "futures-io")]
+    pub struct FuturesIoStream<S>(pub S);
+
+    #[cfg(feature = "futures-io")]
+    impl<S: futures_io::AsyncRead + futures_io::AsyncWrite + Unpin> Split for FuturesIoStream<S> {
+        type R = ReadHalf<Self>;
+
+        type W = WriteHalf<Self>;
+
+        fn split(self) -> (Self::R, Self::W) {
+            tokio::io::split(self)
+        }
+    }
+
+    #[cfg(feature = "futures-io")]
+    impl<S: futures_io::AsyncRead + Unpin> AsyncRead for FuturesIoStream<S> {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            let n = std::task::ready!(
+                Pin::new(&mut self.get_mut().0).poll_read(cx, buf.initialize_unfilled())
+            )?;
+            buf.advance(n);
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[cfg(feature = "futures-io")]
+    impl<S: futures_io::AsyncWrite + Unpin> AsyncWrite for FuturesIoStream<S> {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<Result<usize, std::io::Error>> {
+            Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+        }
+
+        fn poll_flush(
+            self: Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), std::io::Error>> {
+            Pin::new(&mut self.get_mut().0).poll_flush(cx)
+        }
+
+        fn poll_shutdown(
+            self: Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), std::io::Error>> {
+            Pin::new(&mut self.get_mut().0).poll_close(cx)
+        }
+    }
 }
 
-#[cfg(feature = "async")]
+#[cfg(feature = "async_codec")]
 pub use non_blocking::*;
+
+#[cfg(all(test, feature = "async"))]
+#[tokio::test]
+async fn test_from_plain() {
+    use crate::codec::AsyncBytesCodec;
+
+    let (client, server) = tokio::io::duplex(1024);
+    let (mut recv, mut send) = AsyncBytesCodec::new(AsyncStream::from_plain(client)).split();
+    let mut server = AsyncBytesCodec::new(AsyncStream::from_plain(server));
+    send.send(&b"hello"[..]).await.unwrap();
+    assert_eq!(server.receive().await.unwrap().data.as_ref(), b"hello");
+    server.send(&b"world"[..]).await.unwrap();
+    assert_eq!(recv.receive().await.unwrap().data.as_ref(), b"world");
+}
+
+#[cfg(all(test, feature = "async_tls_rustls"))]
+#[tokio::test]
+async fn test_from_rustls() {
+    use crate::{
+        codec::{default_handshake_handler, AsyncStringCodec},
+        ClientBuilder, ServerBuilder,
+    };
+    use std::sync::Arc;
+    use tokio_rustls::rustls;
+
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert_der = rustls::Certificate(cert.serialize_der().unwrap());
+    let server_config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![cert_der.clone()],
+            rustls::PrivateKey(cert.serialize_private_key_der()),
+        )
+        .unwrap();
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(&cert_der).unwrap();
+    let client_config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    // tls is established outside of ws-tool, like a custom proxy does
+    let (client, server) = tokio::io::duplex(4096);
+    let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(server_config));
+    let server = tokio::spawn(async move {
+        let stream = AsyncStream::from_rustls(acceptor.accept(server).await.unwrap());
+        let mut codec = ServerBuilder::async_accept(
+            stream,
+            default_handshake_handler,
+            AsyncStringCodec::factory,
+        )
+        .await
+        .unwrap();
+        let msg = codec.receive().await.unwrap();
+        let data = msg.data.to_string();
+        codec.send(data.as_str()).await.unwrap();
+    });
+    let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config));
+    let domain = rustls::ServerName::try_from("localhost").unwrap();
+    let stream = AsyncStream::from_rustls(connector.connect(domain, client).await.unwrap());
+    let mut client = ClientBuilder::new()
+        .async_with_stream(
+            "wss://localhost".parse().unwrap(),
+            stream,
+            AsyncStringCodec::check_fn,
+        )
+        .await
+        .unwrap();
+    client.send("hello").await.unwrap();
+    assert_eq!(client.receive().await.unwrap().data, "hello");
+    server.await.unwrap();
+}
+
+#[cfg(all(test, feature = "futures-io"))]
+#[tokio::test]
+async fn test_futures_io_stream() {
+    use crate::codec::AsyncBytesCodec;
+    use std::{
+        pin::Pin,
+        task::{ready, Context, Poll},
+    };
+    use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+
+    /// futures-io view of a tokio stream, stands in for an async-std stream
+    struct FuturesIo(DuplexStream);
+
+    impl futures_io::AsyncRead for FuturesIo {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let mut buf = ReadBuf::new(buf);
+            ready!(Pin::new(&mut self.get_mut().0).poll_read(cx, &mut buf))?;
+            Poll::Ready(Ok(buf.filled().len()))
+        }
+    }
+
+    impl futures_io::AsyncWrite for FuturesIo {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.get_mut().0).poll_flush(cx)
+        }
+
+        fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.get_mut().0).poll_shutdown(cx)
+        }
+    }
+
+    let (client, server) = tokio::io::duplex(1024);
+    let mut client = AsyncBytesCodec::new(FuturesIoStream(FuturesIo(client)));
+    let mut server = AsyncBytesCodec::new(server);
+    client.send(&b"hello"[..]).await.unwrap();
+    assert_eq!(server.receive().await.unwrap().data.as_ref(), b"hello");
+    server.send(&b"world"[..]).await.unwrap();
+    assert_eq!(client.receive().await.unwrap().data.as_ref(), b"world");
+
+    // handshake is driven by io traits only
+    let (client, server) = tokio::io::duplex(1024);
+    let server = tokio::spawn(async move {
+        crate::ServerBuilder::async_accept(
+            FuturesIoStream(FuturesIo(server)),
+            crate::codec::default_handshake_handler,
+            AsyncBytesCodec::factory,
+        )
+        .await
+        .unwrap()
+        .send(&b"hello"[..])
+        .await
+        .unwrap();
+    });
+    let mut client = crate::ClientBuilder::new()
+        .async_with_stream(
+            "ws://localhost".parse().unwrap(),
+            FuturesIoStream(FuturesIo(client)),
+            AsyncBytesCodec::check_fn,
+        )
+        .await
+        .unwrap();
+    assert_eq!(client.receive().await.unwrap().data.as_ref(), b"hello");
+    server.await.unwrap();
+}
@@ -0,0 +1,206 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::Sleep,
+};
+
+use crate::codec::Split;
+
+/// misbehavior injected by `ChaosStream`
+#[derive(Debug, Clone, Default)]
+pub struct ChaosConfig {
+    /// max bytes returned by a single read, 0 means no limit
+    pub read_chunk: usize,
+    /// max bytes accepted by a single write, 0 means no limit
+    pub write_chunk: usize,
+    /// delay before every read
+    pub read_delay: Option<Duration>,
+    /// delay before every write
+    pub write_delay: Option<Duration>,
+    /// every n-th read fails with `ErrorKind::Interrupted`, 0 means never
+    pub read_error_every: usize,
+    /// every n-th write fails with `ErrorKind::Interrupted`, 0 means never
+    pub write_error_every: usize,
+}
+
+/// stream wrapper which injects delay, tiny chunks and transient errors
+/// into reads and writes of inner stream
+///
+/// used to test codec against adversarial stream behavior, such as trickle
+/// reads and partial writes
+pub struct ChaosStream<S> {
+    inner: S,
+    config: ChaosConfig,
+    read_sleep: Option<Pin<Box<Sleep>>>,
+    write_sleep: Option<Pin<Box<Sleep>>>,
+    read_count: usize,
+    write_count: usize,
+}
+
+impl<S> ChaosStream<S> {
+    /// wrap stream with chaos config
+    pub fn new(inner: S, config: ChaosConfig) -> Self {
+        Self {
+            inner,
+            config,
+            read_sleep: None,
+            write_sleep: None,
+            read_count: 0,
+            write_count: 0,
+        }
+    }
+
+    /// get ref of inner stream
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// get mutable ref of inner stream
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// consume wrapper and return inner stream
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+/// wait for delay if any, sleep is reset after it's fired
+fn poll_delay(
+    sleep: &mut Option<Pin<Box<Sleep>>>,
+    delay: Option<Duration>,
+    cx: &mut Context<'_>,
+) -> Poll<()> {
+    if let Some(delay) = delay {
+        let s = sleep.get_or_insert_with(|| Box::pin(tokio::time::sleep(delay)));
+        ready!(s.as_mut().poll(cx));
+        *sleep = None;
+    }
+    Poll::Ready(())
+}
+
+fn should_fail(count: &mut usize, every: usize) -> bool {
+    if every == 0 {
+        return false;
+    }
+    *count += 1;
+    if *count == every {
+        *count = 0;
+        true
+    } else {
+        false
+    }
+}
+
+fn transient_error() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Interrupted, "chaos stream error")
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ChaosStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        ready!(poll_delay(&mut this.read_sleep, this.config.read_delay, cx));
+        if should_fail(&mut this.read_count, this.config.read_error_every) {
+            return Poll::Ready(Err(transient_error()));
+        }
+        let chunk = this.config.read_chunk;
+        if chunk == 0 || buf.remaining() <= chunk {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+        let mut tmp = vec![0; chunk];
+        let mut tmp_buf = ReadBuf::new(&mut tmp);
+        ready!(Pin::new(&mut this.inner).poll_read(cx, &mut tmp_buf))?;
+        buf.put_slice(tmp_buf.filled());
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ChaosStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(poll_delay(
+            &mut this.write_sleep,
+            this.config.write_delay,
+            cx
+        ));
+        if should_fail(&mut this.write_count, this.config.write_error_every) {
+            return Poll::Ready(Err(transient_error()));
+        }
+        let chunk = this.config.write_chunk;
+        let len = if chunk == 0 {
+            buf.len()
+        } else {
+            buf.len().min(chunk)
+        };
+        Pin::new(&mut this.inner).poll_write(cx, &buf[..len])
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Split for ChaosStream<S> {
+    type R = tokio::io::ReadHalf<Self>;
+
+    type W = tokio::io::WriteHalf<Self>;
+
+    fn split(self) -> (Self::R, Self::W) {
+        tokio::io::split(self)
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_chaos_stream() {
+    use crate::codec::AsyncBytesCodec;
+    use crate::frame::OpCode;
+
+    let (client, server) = tokio::io::duplex(1024);
+    let config = ChaosConfig {
+        read_chunk: 1,
+        write_chunk: 3,
+        read_delay: Some(Duration::from_millis(1)),
+        ..Default::default()
+    };
+    let mut client = AsyncBytesCodec::new(ChaosStream::new(client, config.clone()));
+    let mut server = AsyncBytesCodec::new(ChaosStream::new(server, config));
+
+    let payload = [7u8; 200];
+    client.send(&payload[..]).await.unwrap();
+    let msg = server.receive().await.unwrap();
+    assert_eq!(msg.code, OpCode::Binary);
+    assert_eq!(msg.data.as_ref(), &payload[..]);
+
+    let config = ChaosConfig {
+        read_error_every: 1,
+        ..Default::default()
+    };
+    let (_client, server) = tokio::io::duplex(1024);
+    let mut server = AsyncBytesCodec::new(ChaosStream::new(server, config));
+    match server.receive().await {
+        Err(crate::errors::WsError::IOError(e)) => {
+            assert_eq!(e.kind(), std::io::ErrorKind::Interrupted)
+        }
+        other => panic!("expect transient error, got {:?}", other.map(|_| ())),
+    }
+}
@@ -0,0 +1,31 @@
+//! make sure frame module compiles with `core` and `alloc` only
+#![no_std]
+
+extern crate alloc;
+
+#[path = "../src/frame.rs"]
+#[allow(dead_code)]
+mod frame;
+
+use alloc::vec::Vec;
+use frame::{BorrowedFrame, OpCode, OwnedFrame};
+
+#[test]
+fn test_no_std_round_trip() {
+    let frame = OwnedFrame::new(OpCode::Text, [1, 2, 3, 4], &[b'x'; 300]);
+    let mut buf = Vec::new();
+    buf.extend_from_slice(frame.header().as_bytes());
+    buf.extend_from_slice(frame.payload());
+    buf.extend_from_slice(&[0x89]);
+
+    let (parsed, len) = BorrowedFrame::parse(&buf).unwrap();
+    assert_eq!(len, buf.len() - 1);
+    assert_eq!(parsed.header().opcode(), OpCode::Text);
+    assert_eq!(parsed.header().payload_len(), 300);
+    let mut owned = parsed.to_owned();
+    assert_eq!(owned.unmask(), Some([1, 2, 3, 4]));
+    assert_eq!(&owned.payload()[..], &[b'x'; 300][..]);
+
+    assert!(BorrowedFrame::parse(&buf[..len - 1]).is_none());
+    assert!(BorrowedFrame::parse(&buf[len..]).is_none());
+}
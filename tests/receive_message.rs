@@ -0,0 +1,73 @@
+#![cfg(feature = "sync")]
+
+use std::net::TcpListener;
+
+use ws_tool::{
+    codec::{FrameCodec, FrameConfig, StringCodec},
+    frame::OpCode,
+    CloseEvent, Event,
+};
+
+#[test]
+fn test_receive_message_session() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let config = FrameConfig {
+            mask_send_frame: false,
+            ..Default::default()
+        };
+        let mut server = FrameCodec::new_with(stream, config);
+        server.send(OpCode::Ping, b"1").unwrap();
+        server.send(OpCode::Text, b"hello").unwrap();
+        server.send_frame(OpCode::Text, b"frag", false).unwrap();
+        server.send(OpCode::Ping, b"2").unwrap();
+        server.send(OpCode::Pong, b"unsolicited").unwrap();
+        server.send_frame(OpCode::Continue, b"ment", true).unwrap();
+        server.send(OpCode::Binary, b"bin").unwrap();
+        server.send(OpCode::Close, &1000u16.to_be_bytes()).unwrap();
+
+        // client answers every ping and echoes close
+        let mut replies = vec![];
+        loop {
+            let (header, data) = server.receive().unwrap();
+            replies.push((header.code, data.to_vec()));
+            if header.code == OpCode::Close {
+                break;
+            }
+        }
+        replies
+    });
+
+    let stream = std::net::TcpStream::connect(addr).unwrap();
+    let mut client = StringCodec::new(stream);
+    let mut events = vec![];
+    loop {
+        let event = client.receive_message().unwrap().map(|s| s.to_string());
+        let closed = matches!(event, Event::Closed(_));
+        events.push(event);
+        if closed {
+            break;
+        }
+    }
+    assert_eq!(
+        events,
+        vec![
+            Event::Text("hello".to_string()),
+            Event::Text("fragment".to_string()),
+            Event::Binary("bin".to_string()),
+            Event::Closed(CloseEvent::Status(1000)),
+        ]
+    );
+
+    let replies = server.join().unwrap();
+    assert_eq!(
+        replies,
+        vec![
+            (OpCode::Pong, b"1".to_vec()),
+            (OpCode::Pong, b"2".to_vec()),
+            (OpCode::Close, 1000u16.to_be_bytes().to_vec()),
+        ]
+    );
+}